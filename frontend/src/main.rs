@@ -1,17 +1,493 @@
 use core::time;
-use std::{fs::File, io::{BufReader, Read}, thread};
+use std::{fs::File, io::{BufReader, BufWriter, Read}, thread};
 use serde_json::{Value, Result};
 use minifb;
 
 use nes_components::*;
 
+// Keyboard layout for controller one
+const KEY_MAP: [(minifb::Key, u8); 8] = [
+    (minifb::Key::X, BUTTON_A),
+    (minifb::Key::Z, BUTTON_B),
+    (minifb::Key::RightShift, BUTTON_SELECT),
+    (minifb::Key::Enter, BUTTON_START),
+    (minifb::Key::Up, BUTTON_UP),
+    (minifb::Key::Down, BUTTON_DOWN),
+    (minifb::Key::Left, BUTTON_LEFT),
+    (minifb::Key::Right, BUTTON_RIGHT),
+];
+
+// Extra keys that press A/B with auto-fire
+const TURBO_KEY_MAP: [(minifb::Key, u8); 2] = [
+    (minifb::Key::S, BUTTON_A),
+    (minifb::Key::A, BUTTON_B),
+];
+const TURBO_RATE: u8 = 15; // Presses per second
+
+const VS_COIN_KEY: minifb::Key = minifb::Key::C; // Drops a coin into slot 1 on VS System games
+
+const RECORD_KEY: minifb::Key = minifb::Key::F9; // Starts/stops recording a GIF clip
+const RECORD_FRAME_STEP: u64 = 2; // Only every other frame is kept (30 FPS is plenty for a clip and halves the file size)
+const RECORD_FRAME_DELAY: u16 = 3; // GIF frame delays are in hundredths of a second, so 3 is as close to 30 FPS as it gets
+
+const NAMETABLE_KEY: minifb::Key = minifb::Key::F10; // Saves all four nametables to a PNG
+const CHR_DUMP_GREYSCALE: [u32; 4] = [0x000000, 0x555555, 0xAAAAAA, 0xFFFFFF]; // --dump-chr colors for pixel values 0-3
+
+const PAUSE_KEY: minifb::Key = minifb::Key::F5; // Pauses/resumes emulation
+const STEP_KEY: minifb::Key = minifb::Key::F6; // Runs one instruction while paused (hold to keep stepping)
+const STEP_FRAME_KEY: minifb::Key = minifb::Key::F7; // Runs the rest of the frame while paused
+
+const REWIND_KEY: minifb::Key = minifb::Key::Backspace; // Hold to go back through the last few seconds
+const REWIND_INTERVAL: u64 = 5; // Frames between the states rewinding goes back through
+const REWIND_CAPACITY: usize = 600; // States kept — 600 of them 5 frames apart is about 50 seconds
+
+// Gamepad layout for controller one, matching where A and B sit on a real NES pad — --gamepad-map changes it
+#[cfg(feature = "gamepad")]
+const GAMEPAD_MAP: [(gilrs::Button, u8); 8] = [
+    (gilrs::Button::East, BUTTON_A),
+    (gilrs::Button::South, BUTTON_B),
+    (gilrs::Button::Select, BUTTON_SELECT),
+    (gilrs::Button::Start, BUTTON_START),
+    (gilrs::Button::DPadUp, BUTTON_UP),
+    (gilrs::Button::DPadDown, BUTTON_DOWN),
+    (gilrs::Button::DPadLeft, BUTTON_LEFT),
+    (gilrs::Button::DPadRight, BUTTON_RIGHT),
+];
+#[cfg(feature = "gamepad")]
+const STICK_THRESHOLD: f32 = 0.5; // How far the left stick has to be pushed before it counts as a d-pad press
+
+// Collects frames while recording and writes them out as an animated GIF when recording stops
+struct Recorder {
+    frames: Vec<Vec<u32>>,
+    recording: bool,
+    clips_saved: u32, // Used to give each clip its own file name
+}
+
+impl Recorder {
+    fn new() -> Self {
+        Recorder { frames: vec![], recording: false, clips_saved: 0 }
+    }
+
+    // Starts or stops recording when the record key is pressed, and grabs the latest frame while recording
+    fn update(&mut self, cpu: &CPU) {
+        let toggled = match cpu.cpu_bus.ppu.window() {
+            Some(window) => window.is_key_pressed(RECORD_KEY, minifb::KeyRepeat::No),
+            None => false
+        };
+
+        if toggled {
+            if self.recording {
+                let path = format!("recording_{}.gif", self.clips_saved);
+
+                match write_gif(&path, &self.frames) {
+                    Ok(()) => println!("Saved {} frames to {}", self.frames.len(), path),
+                    Err(e) => println!("Could not save the recording: {}", e)
+                }
+
+                self.clips_saved += 1;
+                self.frames.clear();
+            } else {
+                println!("Recording started");
+            }
+
+            self.recording = !self.recording;
+        }
+
+        if self.recording && cpu.cpu_bus.ppu.frame_count().is_multiple_of(RECORD_FRAME_STEP) {
+            self.frames.push(cpu.cpu_bus.ppu.frame_buffer().to_vec());
+        }
+    }
+}
+
+// Encodes 0RGB frames as a looping GIF
+fn write_gif(path: &str, frames: &[Vec<u32>]) -> std::result::Result<(), String> {
+    let file = File::create(path).map_err(|e| e.to_string())?;
+    let mut encoder = gif::Encoder::new(file, SCREEN_WIDTH as u16, SCREEN_HEIGHT as u16, &[]).map_err(|e| e.to_string())?;
+    encoder.set_repeat(gif::Repeat::Infinite).map_err(|e| e.to_string())?;
+
+    for pixels in frames {
+        let mut rgba: Vec<u8> = pixels.iter()
+            .flat_map(|pixel| [(pixel >> 16) as u8, (pixel >> 8) as u8, *pixel as u8, 0xFF])
+            .collect();
+
+        let mut frame = gif::Frame::from_rgba_speed(SCREEN_WIDTH as u16, SCREEN_HEIGHT as u16, &mut rgba, 10);
+        frame.delay = RECORD_FRAME_DELAY;
+
+        encoder.write_frame(&frame).map_err(|e| e.to_string())?;
+    }
+
+    Ok(())
+}
+
+// Writes a nametable out as a 256x240 PNG, or all four laid out like the PPU's address space (512x480) when index is None
+fn export_nametable_png(ppu: &PPU, index: Option<u8>, path: &str) -> std::result::Result<(), String> {
+    let (width, height, pixels) = match index {
+        Some(index) => (SCREEN_WIDTH, SCREEN_HEIGHT, ppu.render_nametable(index)),
+        None => {
+            let mut pixels = vec![0; SCREEN_WIDTH * 2 * SCREEN_HEIGHT * 2];
+
+            for index in 0..4 {
+                let nametable = ppu.render_nametable(index);
+                let (left, top) = ((index as usize % 2) * SCREEN_WIDTH, (index as usize / 2) * SCREEN_HEIGHT);
+
+                for (y, row) in nametable.chunks(SCREEN_WIDTH).enumerate() {
+                    let start = (top + y) * SCREEN_WIDTH * 2 + left;
+                    pixels[start..(start + SCREEN_WIDTH)].copy_from_slice(row);
+                }
+            }
+
+            (SCREEN_WIDTH * 2, SCREEN_HEIGHT * 2, pixels)
+        }
+    };
+
+    write_png(path, width, height, &pixels)
+}
+
+// Writes every tile in the CHR data out as a PNG sheet, CHR_SHEET_COLUMNS tiles wide
+fn export_chr_png(chr: &[u8], colors: [u32; 4], path: &str) -> std::result::Result<(), String> {
+    let (width, height, pixels) = PPU::render_chr_sheet(chr, colors);
+
+    write_png(path, width, height, &pixels)
+}
+
+// Writes 0RGB pixels out as an 8 bit RGB PNG
+fn write_png(path: &str, width: usize, height: usize, pixels: &[u32]) -> std::result::Result<(), String> {
+    let file = File::create(path).map_err(|e| e.to_string())?;
+    let mut encoder = png::Encoder::new(file, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let rgb: Vec<u8> = pixels.iter()
+        .flat_map(|pixel| [(pixel >> 16) as u8, (pixel >> 8) as u8, *pixel as u8])
+        .collect();
+
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer.write_image_data(&rgb).map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+// Colors for the CHR dump — greyscale unless --chr-palette gives four system palette indices (e.g. 0F,16,27,30)
+fn chr_dump_colors(args: &[String], palette: &[u8]) -> [u32; 4] {
+    let indices: Option<Vec<usize>> = flag_value(args, "--chr-palette").map(|value| {
+        value.split(',').filter_map(|index| usize::from_str_radix(index.trim(), 16).ok()).collect()
+    });
+
+    match indices {
+        Some(indices) if indices.len() == 4 && indices.iter().all(|&index| (index + 1) * 3 <= palette.len()) => {
+            let mut colors = [0; 4];
+            for (color, index) in colors.iter_mut().zip(indices) {
+                let rgb = &palette[(index * 3)..(index * 3 + 3)];
+                *color = (rgb[0] as u32) << 16 | (rgb[1] as u32) << 8 | rgb[2] as u32;
+            }
+            colors
+        },
+        Some(_) => {
+            println!("--chr-palette wants four hex palette indices separated by commas, using greyscale");
+            CHR_DUMP_GREYSCALE
+        },
+        None => CHR_DUMP_GREYSCALE
+    }
+}
+
+// Dumps the nametables when the export key is pressed
+fn poll_nametable_export(cpu: &CPU) {
+    let pressed = match cpu.cpu_bus.ppu.window() {
+        Some(window) => window.is_key_pressed(NAMETABLE_KEY, minifb::KeyRepeat::No),
+        None => false
+    };
+
+    if pressed {
+        let path = format!("nametables_{}.png", cpu.cpu_bus.ppu.frame_count());
+
+        match export_nametable_png(&cpu.cpu_bus.ppu, None, &path) {
+            Ok(()) => println!("Saved the nametables to {}", path),
+            Err(e) => println!("Could not save the nametables: {}", e)
+        }
+    }
+}
+
+// Reads controller one from the first connected gamepad, alongside the keyboard
+#[cfg(feature = "gamepad")]
+struct Gamepad {
+    gilrs: gilrs::Gilrs,
+    map: Vec<(gilrs::Button, u8)>,
+}
+
+#[cfg(feature = "gamepad")]
+impl Gamepad {
+    fn new(map: Vec<(gilrs::Button, u8)>) -> std::result::Result<Self, String> {
+        let gilrs = gilrs::Gilrs::new().map_err(|e| e.to_string())?;
+        Ok(Gamepad { gilrs, map })
+    }
+
+    // Buttons held on the gamepad, with the left stick standing in for the d-pad
+    fn pressed(&mut self) -> u8 {
+        // Drains the event queue — gilrs only updates the gamepad state as events are read
+        while self.gilrs.next_event().is_some() {}
+
+        let Some((_, gamepad)) = self.gilrs.gamepads().next() else { return 0 };
+        let mut pressed = 0;
+
+        for (button, nes_button) in &self.map {
+            if gamepad.is_pressed(*button) {
+                pressed |= nes_button;
+            }
+        }
+
+        pressed | axis_to_dpad(gamepad.value(gilrs::Axis::LeftStickX), gamepad.value(gilrs::Axis::LeftStickY), STICK_THRESHOLD)
+    }
+}
+
+// Turns a stick position (-1.0 to 1.0 on each axis, up is positive y) into d-pad buttons
+// Each axis only presses a direction once it's past the threshold, so a resting stick doesn't drift
+#[cfg(feature = "gamepad")]
+fn axis_to_dpad(x: f32, y: f32, threshold: f32) -> u8 {
+    let mut pressed = 0;
+
+    if x <= -threshold {
+        pressed |= BUTTON_LEFT;
+    } else if x >= threshold {
+        pressed |= BUTTON_RIGHT;
+    }
+
+    if y >= threshold {
+        pressed |= BUTTON_UP;
+    } else if y <= -threshold {
+        pressed |= BUTTON_DOWN;
+    }
+
+    pressed
+}
+
+#[cfg(feature = "gamepad")]
+fn parse_gamepad_button(name: &str) -> Option<gilrs::Button> {
+    use gilrs::Button;
+
+    match name {
+        "south" => Some(Button::South),
+        "east" => Some(Button::East),
+        "north" => Some(Button::North),
+        "west" => Some(Button::West),
+        "lb" => Some(Button::LeftTrigger),
+        "lt" => Some(Button::LeftTrigger2),
+        "rb" => Some(Button::RightTrigger),
+        "rt" => Some(Button::RightTrigger2),
+        "select" => Some(Button::Select),
+        "start" => Some(Button::Start),
+        "up" => Some(Button::DPadUp),
+        "down" => Some(Button::DPadDown),
+        "left" => Some(Button::DPadLeft),
+        "right" => Some(Button::DPadRight),
+        _ => None
+    }
+}
+
+#[cfg(feature = "gamepad")]
+fn parse_nes_button(name: &str) -> Option<u8> {
+    match name {
+        "a" => Some(BUTTON_A),
+        "b" => Some(BUTTON_B),
+        "select" => Some(BUTTON_SELECT),
+        "start" => Some(BUTTON_START),
+        "up" => Some(BUTTON_UP),
+        "down" => Some(BUTTON_DOWN),
+        "left" => Some(BUTTON_LEFT),
+        "right" => Some(BUTTON_RIGHT),
+        _ => None
+    }
+}
+
+// Applies a remapping like "a=south,b=west" on top of the default layout
+// Each entry replaces every pad button that was bound to that NES button
+#[cfg(feature = "gamepad")]
+fn parse_gamepad_map(value: &str) -> std::result::Result<Vec<(gilrs::Button, u8)>, String> {
+    let mut map = GAMEPAD_MAP.to_vec();
+
+    for entry in value.split(',') {
+        let (nes_name, pad_name) = entry.split_once('=').ok_or(format!("Expected <nes button>=<pad button>, got {}", entry))?;
+        let nes_button = parse_nes_button(nes_name.trim()).ok_or(format!("Unknown NES button {}", nes_name))?;
+        let pad_button = parse_gamepad_button(pad_name.trim()).ok_or(format!("Unknown gamepad button {}", pad_name))?;
+
+        map.retain(|(_, bound)| *bound != nes_button);
+        map.push((pad_button, nes_button));
+    }
+
+    Ok(map)
+}
+
 pub fn nes_start() {
 
 }
 
+// Key is held now, or was tapped at some point since the last frame (so presses shorter than a frame aren't dropped)
+fn key_active(window: &minifb::Window, key: minifb::Key) -> bool {
+    window.is_key_down(key) || window.is_key_pressed(key, minifb::KeyRepeat::No)
+}
+
+// Copies the keyboard state, plus any buttons held on a gamepad, into controller one
+// Only called between frames, so the game sees one consistent button state for a whole frame however many times it strobes
+fn poll_controller(cpu: &mut CPU, gamepad_pressed: u8) {
+    let mut pressed = gamepad_pressed;
+    let mut turbo = 0;
+
+    if let Some(window) = cpu.cpu_bus.ppu.window() {
+        for (key, button) in KEY_MAP {
+            if key_active(window, key) {
+                pressed |= button;
+            }
+        }
+
+        for (key, button) in TURBO_KEY_MAP {
+            if key_active(window, key) {
+                turbo |= button;
+            }
+        }
+
+        let coin = key_active(window, VS_COIN_KEY);
+        cpu.cpu_bus.set_vs_coin(0, coin);
+    }
+
+    // A button held down normally wins over its turbo key, so holding both keeps it pressed instead of auto-firing
+    turbo &= !pressed;
+    pressed |= turbo;
+
+    let controller = &mut cpu.cpu_bus.input.controllers_mut()[0];
+    controller.set_buttons(pressed);
+    controller.set_turbo(!turbo, 0);
+    controller.set_turbo(turbo, TURBO_RATE);
+}
+
+// Aims the Zapper (when one is plugged in) at the mouse pointer, with the left mouse button as the trigger
+fn poll_zapper(cpu: &mut CPU) {
+    let (aim, trigger) = match cpu.cpu_bus.ppu.window() {
+        Some(window) => (
+            window.get_mouse_pos(minifb::MouseMode::Discard).map(|(x, y)| (x as usize, y as usize)),
+            window.get_mouse_down(minifb::MouseButton::Left)
+        ),
+        None => (None, false)
+    };
+
+    if let Some(zapper) = cpu.cpu_bus.input.zapper_mut() {
+        zapper.set_aim(aim);
+        zapper.set_trigger(trigger);
+    }
+}
+
+// Shows the emulation speed in the window title
+fn show_stats(cpu: &mut CPU) {
+    let title = format!("NES - {:.1} FPS, {:.0} cycles/frame", cpu.stats().fps, cpu.stats().average_cycles_per_frame());
+
+    if let Some(window) = cpu.cpu_bus.ppu.window_mut() {
+        window.set_title(&title);
+    }
+}
+
+fn print_registers(cpu: &CPU) {
+    println!(
+        "A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PC:{:04X} frame {}",
+        cpu.accumulator, cpu.x, cpu.y, cpu.status, cpu.sp, cpu.pc, cpu.cpu_bus.ppu.frame_count()
+    );
+}
+
+fn key_pressed(cpu: &CPU, key: minifb::Key, repeat: minifb::KeyRepeat) -> bool {
+    match cpu.cpu_bus.ppu.window() {
+        Some(window) => window.is_key_pressed(key, repeat),
+        None => false
+    }
+}
+
+// Single step debugger used while paused — the window is kept responsive and the registers are printed after each step
+fn step_paused(cpu: &mut CPU) -> std::result::Result<(), RunError> {
+    if let Some(window) = cpu.cpu_bus.ppu.window_mut() {
+        window.update();
+    }
+
+    if key_pressed(cpu, STEP_KEY, minifb::KeyRepeat::Yes) {
+        cpu.decode()?;
+        print_registers(cpu);
+    } else if key_pressed(cpu, STEP_FRAME_KEY, minifb::KeyRepeat::No) {
+        cpu.run_frame()?;
+        print_registers(cpu);
+    }
+
+    Ok(())
+}
+
+// Goes back one captured state while the rewind key is held, then runs a frame from it so the window shows where it landed
+fn rewind(cpu: &mut CPU) -> std::result::Result<(), RunError> {
+    if cpu.rewind_step() {
+        return cpu.run_frame()
+    }
+
+    // Nothing older to go back to — just keep the window responsive
+    if let Some(window) = cpu.cpu_bus.ppu.window_mut() {
+        window.update();
+    }
+
+    Ok(())
+}
+
+// Logs why the emulator stopped along with the CPU registers, and saves a state to dig into if asked to
+fn report_run_error(cpu: &CPU, error: &RunError, dump_state: bool) {
+    println!("Emulation stopped: {}", error);
+    print_registers(cpu);
+
+    if dump_state {
+        let path = format!("crash_{}.state", cpu.cpu_bus.ppu.frame_count());
+
+        match std::fs::write(&path, cpu.save_state()) {
+            Ok(()) => println!("Saved the state to {}", path),
+            Err(e) => println!("Could not save the state: {}", e)
+        }
+    }
+}
+
+// Keeps the last frame on screen until the window is closed
+fn halt(cpu: &mut CPU) {
+    while let Some(window) = cpu.cpu_bus.ppu.window_mut() {
+        if !window.is_open() {
+            break
+        }
+
+        window.update();
+    }
+}
+
 // pub fn nes_tick(cpu: &mut CPU) {
 // }
 
+// Rate to present at when drawing every (frame_skip + 1)th frame — rounded to the nearest whole rate so skips that
+// don't divide 60 don't slow the game down, and never 0 (minifb treats that as unlimited)
+fn presented_fps(frame_skip: usize) -> usize {
+    let drawn_every = frame_skip.saturating_add(1);
+    ((60 + drawn_every / 2) / drawn_every).max(1)
+}
+
+// Value following a command line flag (e.g. --patch <file>)
+fn flag_value<'a>(args: &'a [String], flag: &str) -> Option<&'a String> {
+    args.iter().position(|arg| arg == flag).and_then(|i| args.get(i + 1))
+}
+
+fn parse_mirroring(value: &str) -> Option<Mirroring> {
+    match value {
+        "h" => Some(Mirroring::HORIZONTAL),
+        "v" => Some(Mirroring::VERTICAL),
+        "4" => Some(Mirroring::FOUR_SCREEN),
+        _ => None
+    }
+}
+
+// --mirroring <h|v|4> overrides the header's mirroring for dumps that have the wrong bit set (scrolling tears otherwise)
+fn override_mirroring(rom: &mut Rom, args: &[String]) {
+    if let Some(value) = flag_value(args, "--mirroring") {
+        match parse_mirroring(value) {
+            Some(mirroring) => rom.screen_mirroring = mirroring,
+            None => println!("Unknown mirroring {} — expected h, v or 4, keeping the header's", value)
+        }
+    }
+}
+
 fn main() -> Result<()> {
     let mut nes_file = match File::open("C:/Users/Jasper Davidson/Documents/Programming/Rust/nes/frontend/roms/donkey_kong.nes") {
         Ok(file) => file,
@@ -24,11 +500,54 @@ fn main() -> Result<()> {
 
     nes_file.read_to_end(&mut buffer).expect("Could not load the rom");
 
-    let rom = match Rom::new(&buffer) {
+    let args: Vec<String> = std::env::args().collect();
+
+    let mut rom = match Rom::new(&buffer) {
         Ok(rom) => rom,
+
+        // Headerless dump — it can still be loaded if the user says what the header would have
+        Err(RomError::NoHeader) => {
+            let mapper = flag_value(&args, "--mapper").and_then(|value| value.parse::<u8>().ok());
+            let mirroring = flag_value(&args, "--mirroring").and_then(|value| parse_mirroring(value));
+
+            match (mapper, mirroring) {
+                (Some(mapper), Some(mirroring)) => match Rom::from_headerless(&buffer, mapper, mirroring) {
+                    Ok(rom) => rom,
+                    Err(e) => panic!("Error: {}", e)
+                },
+                _ => panic!("The ROM has no iNES header — pass --mapper <number> and --mirroring <h|v|4> to load it anyway")
+            }
+        },
+
         Err(e) => panic!("Error: {}", e)
     };
 
+    // --patch <file> soft-patches the ROM with an IPS patch, leaving the file on disk alone
+    if let Some(patch_path) = flag_value(&args, "--patch") {
+        let patch = std::fs::read(patch_path).unwrap_or_else(|e| panic!("Problem opening patch file: {:?}", e));
+        if let Err(e) = rom.apply_patch(&patch) {
+            panic!("Could not apply patch {}: {:?}", patch_path, e);
+        }
+    }
+
+    // --mirroring overrides the header's mirroring — done after patching, since a patch re-reads the header
+    override_mirroring(&mut rom, &args);
+
+    // --dump-chr <file> saves every CHR ROM tile as a PNG sprite sheet and exits, without starting the game
+    if let Some(path) = flag_value(&args, "--dump-chr") {
+        if rom.chr_rom.is_empty() {
+            println!("The ROM has no CHR ROM — its tiles are drawn into CHR RAM while it runs, so there's nothing to dump");
+            return Ok(())
+        }
+
+        match export_chr_png(&rom.chr_rom, chr_dump_colors(&args, &palette_buffer), path) {
+            Ok(()) => println!("Saved {} tiles to {}", rom.chr_rom.len() / 16, path),
+            Err(e) => println!("Could not save the CHR tiles: {}", e)
+        }
+
+        return Ok(())
+    }
+
     // CPU testing json extraction
     // let json_path = r"C:\Users\Jasper Davidson\Documents\Programming\Rust\nes\frontend\tests\6502SstepTests\16.json";
 
@@ -43,18 +562,6 @@ fn main() -> Result<()> {
     // // Try to parse the JSON and print detailed errors
     // let json: Value = serde_json::from_str(&contents)?;
 
-    let mut window = minifb::Window::new(
-        "NES",
-        SCREEN_WIDTH,
-        SCREEN_HEIGHT,
-        minifb::WindowOptions {
-            scale: minifb::Scale::X2,
-            ..Default::default()
-        }
-    ).unwrap_or_else(|e| panic!("{}", e));
-
-    window.set_target_fps(60);
-
     // Testing loop for CPU Instructions
     // for i in 0..10000 {
     //     let (pc, status, a, x, y, sp) = (json[i]["initial"]["pc"].as_i64().expect("Could not extract pc"), 
@@ -116,19 +623,312 @@ fn main() -> Result<()> {
     //     }
     // }
 
-    let ppu = PPU::init_ppu(rom.chr_rom.clone(), rom.screen_mirroring, palette_buffer.clone(), window);
-    let mut cpu = CPU::init_cpu(rom.clone().prg_rom, ppu);
+    let mapper = match rom.create_mapper() {
+        Ok(mapper) => mapper,
+        Err(e) => panic!("Error: {}", e)
+    };
 
-    loop {
-        cpu.decode();
-        // println!("dot: {}", cpu.cpu_bus.ppu.state.dots);
-        println!("scanline: {}", cpu.cpu_bus.ppu.state.scanline);
+    // The window only opens once the game is booted (see --skip-frames and --load-state below)
+    let ppu = PPU::init_ppu(mapper.clone(), palette_buffer.clone(), None);
+    for problem in ppu.palette_problems() {
+        println!("Warning: {}", problem);
+    }
+    let mut cpu = CPU::init_cpu(mapper, ppu);
+    let mut recorder = Recorder::new();
+    cpu.set_rewind(REWIND_INTERVAL, REWIND_CAPACITY);
+
+    // VS System games get the arcade palette and cabinet inputs, --vs-dip <hex> sets their DIP switches (all off otherwise)
+    cpu.cpu_bus.set_system(rom.system);
+    if let Some(value) = flag_value(&args, "--vs-dip") {
+        match u8::from_str_radix(value.trim_start_matches("0x"), 16) {
+            Ok(switches) => cpu.cpu_bus.set_vs_dip_switches(switches),
+            Err(_) => println!("--vs-dip wants the eight switches as a hex byte (e.g. 0x03), leaving them off")
+        }
+    }
+
+    // --bios <file> maps a boot ROM over the top of $8000-$FFFF for boards that boot from one instead of the cartridge
+    if let Some(bios_path) = flag_value(&args, "--bios") {
+        let bios = std::fs::read(bios_path).unwrap_or_else(|e| panic!("Problem opening BIOS file: {:?}", e));
+        if let Err(e) = cpu.set_bios(bios) {
+            panic!("Could not load BIOS {}: {}", bios_path, e);
+        }
+    }
+
+    // --trace <file> logs every instruction executed (this gets big fast)
+    if let Some(trace_path) = flag_value(&args, "--trace") {
+        let trace_file = File::create(trace_path).unwrap_or_else(|e| panic!("Problem creating trace file: {:?}", e));
+        cpu.set_trace_sink(Some(Box::new(BufWriter::new(trace_file))));
+    }
+
+    // --gamepad reads controller one from the first gamepad as well as the keyboard
+    // --gamepad-map <nes button>=<pad button>,... changes its layout (e.g. a=south,b=west)
+    #[cfg(feature = "gamepad")]
+    let mut gamepad = if args.iter().any(|arg| arg == "--gamepad") {
+        let map = match flag_value(&args, "--gamepad-map") {
+            Some(value) => parse_gamepad_map(value).unwrap_or_else(|e| panic!("Bad --gamepad-map: {}", e)),
+            None => GAMEPAD_MAP.to_vec()
+        };
+
+        match Gamepad::new(map) {
+            Ok(gamepad) => Some(gamepad),
+            Err(e) => {
+                println!("Could not start gamepad support, using the keyboard only: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    #[cfg(not(feature = "gamepad"))]
+    if args.iter().any(|arg| arg == "--gamepad") {
+        println!("This build has no gamepad support (build with --features gamepad), using the keyboard only");
+    }
+
+    // --zapper plugs a Zapper into port two (controller one stays on the keyboard)
+    if args.iter().any(|arg| arg == "--zapper") {
+        cpu.cpu_bus.input = InputBackend::Zapper(Controller::new(), Zapper::new());
+    }
+
+    // --restrict-dpad cancels out Left+Right and Up+Down like a real controller, for games that break when they see both
+    if args.iter().any(|arg| arg == "--restrict-dpad") {
+        for controller in cpu.cpu_bus.input.controllers_mut() {
+            controller.set_dpad_restriction(true);
+        }
     }
 
+    // A frame that gets more than one NMI usually means the game (or the emulator) has gone wrong, so say when it happens
+    cpu.set_on_double_nmi(Some(Box::new(|frame| println!("Warning: more than one NMI serviced during frame {}", frame))));
+
+    // --on-error reset powers the console back on when the emulator hits something it can't run, instead of halting
+    // --dump-on-error also saves a state at that point so it can be reloaded and looked at
+    let reset_on_error = flag_value(&args, "--on-error").is_some_and(|value| value == "reset");
+    let dump_on_error = args.iter().any(|arg| arg == "--dump-on-error");
+
+    // --load-state <file> boots straight into a save state, --skip-frames <n> then runs n frames headless before the window
+    // opens — together or on their own they get past intros that would otherwise be sat through every time
+    if let Some(state_path) = flag_value(&args, "--load-state") {
+        let state = std::fs::read(state_path).unwrap_or_else(|e| panic!("Problem opening state file: {:?}", e));
+        if let Err(e) = cpu.load_state(&state) {
+            panic!("Could not load state {}: {}", state_path, e);
+        }
+    }
+
+    if let Some(value) = flag_value(&args, "--skip-frames") {
+        match value.parse::<usize>() {
+            Ok(frames) => {
+                if let Err(e) = cpu.skip_frames(frames) {
+                    report_run_error(&cpu, &e, dump_on_error);
+                    return Ok(())
+                }
+            },
+            Err(_) => println!("--skip-frames wants a number of frames, starting from the beginning")
+        }
+    }
+
+    let mut window = minifb::Window::new(
+        "NES",
+        SCREEN_WIDTH,
+        SCREEN_HEIGHT,
+        minifb::WindowOptions {
+            scale: minifb::Scale::X2,
+            ..Default::default()
+        }
+    ).unwrap_or_else(|e| panic!("{}", e));
+
+    // --frame-skip <n> only draws every (n + 1)th frame, for machines that can't keep up with presenting all of them
+    // The target rate drops to match so the game still runs at full speed
+    let frame_skip = flag_value(&args, "--frame-skip").and_then(|value| value.parse::<usize>().ok()).unwrap_or(0);
+    window.set_target_fps(presented_fps(frame_skip));
+
+    cpu.cpu_bus.ppu.set_window(Some(window));
+
+    // --break-on-reset starts paused on the first instruction of the reset handler, so the boot code can be stepped through
+    let mut paused = args.iter().any(|arg| arg == "--break-on-reset");
+    if paused {
+        println!("Paused at the reset vector — {:?} steps an instruction, {:?} a frame, {:?} resumes", STEP_KEY, STEP_FRAME_KEY, PAUSE_KEY);
+        print_registers(&cpu);
+    }
+
+    loop {
+        if key_pressed(&cpu, PAUSE_KEY, minifb::KeyRepeat::No) {
+            paused = !paused;
+            println!("{}", if paused { "Paused" } else { "Resumed" });
+        }
+
+        let rewinding = !paused && cpu.cpu_bus.ppu.window().is_some_and(|window| window.is_key_down(REWIND_KEY));
+
+        let result = if paused {
+            step_paused(&mut cpu)
+        } else if rewinding {
+            rewind(&mut cpu)
+        } else {
+            cpu.run_frame_skip(frame_skip)
+        };
+
+        if let Err(e) = result {
+            report_run_error(&cpu, &e, dump_on_error);
+
+            if !reset_on_error {
+                halt(&mut cpu);
+                break
+            }
+
+            println!("Resetting the console");
+            cpu.insert_cartridge(rom.clone()).unwrap_or_else(|e| panic!("Error: {}", e));
+            continue
+        }
+
+        #[cfg(feature = "gamepad")]
+        let gamepad_pressed = gamepad.as_mut().map_or(0, |gamepad| gamepad.pressed());
+        #[cfg(not(feature = "gamepad"))]
+        let gamepad_pressed = 0;
+
+        poll_controller(&mut cpu, gamepad_pressed);
+        poll_zapper(&mut cpu);
+        poll_nametable_export(&cpu);
+
+        // Paused loops don't finish frames, so there's nothing new to time or record
+        if !paused {
+            show_stats(&mut cpu);
+            recorder.update(&cpu);
+        }
+    }
 
     Ok(())
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    // A file in the system temp directory, named per test so tests running in parallel don't share one
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("nes_frontend_{}_{}", std::process::id(), name))
+    }
+
+    // The PPU holds its frame buffers inline, which is too big for the default test thread stack in debug builds
+    fn run_with_big_stack(test: fn()) {
+        thread::Builder::new()
+            .stack_size(64 * 1024 * 1024)
+            .spawn(test)
+            .expect("Could not spawn the test thread")
+            .join()
+            .unwrap_or_else(|e| std::panic::resume_unwind(e));
+    }
+
+    // Width and height of a PNG, deleting it once read
+    fn png_dimensions(path: &PathBuf) -> (usize, usize) {
+        let reader = png::Decoder::new(File::open(path).unwrap()).read_info().expect("Not a valid PNG");
+        let info = reader.info();
+        let dimensions = (info.width as usize, info.height as usize);
+        std::fs::remove_file(path).unwrap();
+
+        dimensions
+    }
+
+    #[test]
+    fn recordings_are_written_as_a_multi_frame_gif() {
+        let path = temp_path("recording.gif");
+        let frames: Vec<Vec<u32>> = [0x000000, 0xFF0000, 0x00FF00].iter().map(|color| vec![*color; SCREEN_WIDTH * SCREEN_HEIGHT]).collect();
+
+        write_gif(path.to_str().unwrap(), &frames).expect("Could not write the GIF");
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::RGBA);
+        let mut decoder = options.read_info(File::open(&path).unwrap()).expect("Not a valid GIF");
+        assert_eq!((decoder.width() as usize, decoder.height() as usize), (SCREEN_WIDTH, SCREEN_HEIGHT));
+
+        let mut decoded = vec![];
+        while let Some(frame) = decoder.read_next_frame().expect("Bad frame in the GIF") {
+            assert_eq!(frame.delay, RECORD_FRAME_DELAY);
+            decoded.push(frame.buffer[0..3].to_vec());
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(decoded, [[0x00, 0x00, 0x00], [0xFF, 0x00, 0x00], [0x00, 0xFF, 0x00]]);
+    }
+
+    #[cfg(feature = "gamepad")]
+    #[test]
+    fn the_stick_only_presses_a_direction_past_the_threshold() {
+        // (x, y, pressed) — up is positive y
+        let cases = [
+            (0.0, 0.0, 0),
+            (0.49, -0.49, 0),
+            (0.5, 0.0, BUTTON_RIGHT),
+            (-0.5, 0.0, BUTTON_LEFT),
+            (0.0, 0.5, BUTTON_UP),
+            (0.0, -0.5, BUTTON_DOWN),
+            (-1.0, 1.0, BUTTON_LEFT | BUTTON_UP),
+            (0.8, -0.8, BUTTON_RIGHT | BUTTON_DOWN),
+        ];
+
+        for (x, y, pressed) in cases {
+            assert_eq!(axis_to_dpad(x, y, 0.5), pressed, "Stick at ({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn chr_dumps_are_16_tiles_wide_with_a_row_for_every_16() {
+        // (tiles, rows) — a partly filled last row still gets drawn
+        for (tiles, rows) in [(512, 32), (40, 3), (1, 1)] {
+            let path = temp_path("chr.png");
+            export_chr_png(&vec![0x55; tiles * 16], CHR_DUMP_GREYSCALE, path.to_str().unwrap()).expect("Could not dump the CHR");
+
+            assert_eq!(png_dimensions(&path), (CHR_SHEET_COLUMNS * 8, rows * 8), "{} tiles", tiles);
+        }
+    }
+
+    #[test]
+    fn frame_skip_rates_round_to_the_nearest_and_never_reach_0() {
+        for (frame_skip, fps) in [(0, 60), (1, 30), (3, 15), (6, 9), (7, 8), (59, 1), (200, 1), (usize::MAX, 1)] {
+            assert_eq!(presented_fps(frame_skip), fps, "--frame-skip {}", frame_skip);
+        }
+    }
+
+    #[test]
+    fn the_mirroring_flag_overrides_the_header() {
+        run_with_big_stack(|| {
+            // Horizontal in the header
+            let mut file = b"NES\x1A\x01\x01".to_vec();
+            file.resize(16 + 0x4000 + 0x2000, 0);
+
+            for (flag, mirroring, (same, different)) in [("v", Mirroring::VERTICAL, (0x2800, 0x2400)), ("h", Mirroring::HORIZONTAL, (0x2400, 0x2800))] {
+                let mut rom = Rom::new(&file).unwrap();
+                override_mirroring(&mut rom, &["frontend".to_string(), "--mirroring".to_string(), flag.to_string()]);
+                assert_eq!(rom.screen_mirroring, mirroring);
+
+                let mut ppu = PPU::init_ppu(rom.create_mapper().unwrap(), vec![0; 192], None);
+                ppu.poke_vram(0x2000, 0xAB);
+                assert_eq!((ppu.peek_vram(same), ppu.peek_vram(different)), (0xAB, 0x00), "--mirroring {}", flag);
+            }
+
+            // Anything else keeps the header's
+            let mut rom = Rom::new(&file).unwrap();
+            override_mirroring(&mut rom, &["frontend".to_string(), "--mirroring".to_string(), "x".to_string()]);
+            assert_eq!(rom.screen_mirroring, Mirroring::HORIZONTAL);
+        });
+    }
+
+    #[test]
+    fn nametable_exports_are_one_screen_or_all_four() {
+        run_with_big_stack(|| {
+            let mapper = Rom::from_parts(vec![0; 0x4000], vec![0; 0x2000], 0, Mirroring::VERTICAL).create_mapper().unwrap();
+            let ppu = PPU::init_ppu(mapper, vec![0; 192], None);
+
+            let path = temp_path("nametable.png");
+            export_nametable_png(&ppu, Some(2), path.to_str().unwrap()).expect("Could not export the nametable");
+            assert_eq!(png_dimensions(&path), (SCREEN_WIDTH, SCREEN_HEIGHT));
+
+            let path = temp_path("nametables.png");
+            export_nametable_png(&ppu, None, path.to_str().unwrap()).expect("Could not export the nametables");
+            assert_eq!(png_dimensions(&path), (SCREEN_WIDTH * 2, SCREEN_HEIGHT * 2));
+        });
+    }
+}
+
 // // Testing suite to ensure proper addressing/instruction functionality
 // #[cfg(test)]
 // mod tests {
@@ -401,25 +1201,6 @@ fn main() -> Result<()> {
 //         assert_eq!(cpu.pc, 10);
 //     }
 
-//     #[test]
-//     fn jsr() {
-//         let rom = match Rom::new(&vec![0]) {
-//             Ok(rom) => rom,
-//             Err(e) => panic!("Error: {}", e)
-//         };
-
-//         let mut cpu = Cpu::init_cpu(rom);
-//         cpu.pc = 0;
-
-//         cpu.write_byte(0, 0x20);
-//         cpu.write_byte(1, 10);
-//         cpu.write_byte(2, 0);
-//         cpu.decode();
-
-//         assert_eq!(cpu.pc, 10);
-//         assert_eq!(cpu.read_byte(cpu.sp as u16 + 1 + STACK_BASE as u16), 2)
-//     }
-
 //     #[test]
 //     fn lsr() {
 //         let rom = match Rom::new(&vec![0]) {
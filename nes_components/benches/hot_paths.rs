@@ -0,0 +1,125 @@
+// Benchmarks for the CPU and PPU hot paths — run with `cargo bench`
+// Everything runs Donkey Kong's title screen (or a small instruction loop for decode) so results are comparable between changes
+
+use std::cell::RefCell;
+use std::hint::black_box;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use nes_components::*;
+
+const NTSC_FRAME_RATE: f64 = 60.0988;
+const REAL_TIME_FRAMES: u32 = 600; // 10 seconds of NES time
+const DECODES_PER_ITER: u64 = 1000;
+const PPU_DOTS_PER_FRAME: u64 = 341 * 262;
+
+// Loops over a page doing loads, adds, shifts, stores, stack ops and a branch
+const INSTRUCTION_MIX: [u8; 22] = [
+    0xA2, 0x00,       // $8000 LDX #$00
+    0xBD, 0x00, 0x03, // $8002 LDA $0300,X
+    0x18,             // $8005 CLC
+    0x69, 0x01,       // $8006 ADC #$01
+    0x9D, 0x00, 0x04, // $8008 STA $0400,X
+    0x0A,             // $800B ASL A
+    0x45, 0x10,       // $800C EOR $10
+    0x48,             // $800E PHA
+    0x68,             // $800F PLA
+    0xE8,             // $8010 INX
+    0xD0, 0xEF,       // $8011 BNE $8002
+    0x4C, 0x00, 0x80, // $8013 JMP $8000
+];
+
+fn frontend_file(path: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../frontend").join(path)
+}
+
+// Donkey Kong with the title screen up, so the PPU is rendering both layers
+fn donkey_kong() -> CPU {
+    let rom_bytes = std::fs::read(frontend_file("roms/donkey_kong.nes")).expect("Could not read the Donkey Kong ROM");
+    let palette = std::fs::read(frontend_file("palettes/ntsc_palette.pal")).expect("Could not read the palette");
+
+    let rom = Rom::new(&rom_bytes).expect("Could not parse the Donkey Kong ROM");
+    let mapper: Rc<RefCell<dyn Mapper>> = rom.create_mapper().expect("Unsupported mapper");
+    let ppu = PPU::init_ppu(mapper.clone(), palette, None);
+
+    let mut cpu = CPU::init_cpu(mapper, ppu);
+    for _ in 0..120 {
+        cpu.run_frame().expect("Donkey Kong stopped running");
+    }
+
+    cpu
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let mut cpu = CPU::with_flat_memory();
+    for (offset, byte) in INSTRUCTION_MIX.iter().enumerate() {
+        cpu.cpu_bus.poke(0x8000 + offset as u16, *byte);
+    }
+    cpu.pc = 0x8000;
+
+    let mut group = c.benchmark_group("cpu");
+    group.throughput(Throughput::Elements(DECODES_PER_ITER));
+    group.bench_function("decode_instruction_mix", |b| {
+        b.iter(|| {
+            for _ in 0..DECODES_PER_ITER {
+                cpu.decode().expect("Decoding the instruction mix failed");
+            }
+        })
+    });
+    group.finish();
+}
+
+fn bench_ppu_frame(c: &mut Criterion) {
+    let mut cpu = donkey_kong();
+
+    let mut group = c.benchmark_group("ppu");
+    group.throughput(Throughput::Elements(PPU_DOTS_PER_FRAME));
+    group.bench_function("ppu_tick_full_frame", |b| {
+        b.iter(|| {
+            for _ in 0..PPU_DOTS_PER_FRAME {
+                cpu.cpu_bus.ppu.ppu_tick();
+            }
+            black_box(cpu.cpu_bus.ppu.frame_buffer());
+        })
+    });
+    group.finish();
+}
+
+fn bench_run_frame(c: &mut Criterion) {
+    let mut cpu = donkey_kong();
+
+    // Throughput is reported in CPU cycles, so the summary reads as cycles emulated per second
+    let start_cycles = cpu.cycles();
+    cpu.run_frame().expect("Donkey Kong stopped running");
+    let cycles_per_frame = (cpu.cycles() - start_cycles) as u64;
+
+    let mut group = c.benchmark_group("system");
+    group.throughput(Throughput::Elements(cycles_per_frame));
+    group.bench_function("run_frame", |b| b.iter(|| cpu.run_frame()));
+    group.finish();
+}
+
+// Not a criterion measurement — fails the bench run if the emulator can't keep up with a real NES
+fn bench_real_time(_c: &mut Criterion) {
+    let mut cpu = donkey_kong();
+
+    let start = Instant::now();
+    for _ in 0..REAL_TIME_FRAMES {
+        cpu.run_frame().expect("Donkey Kong stopped running");
+    }
+    let elapsed = start.elapsed();
+
+    let real_time = Duration::from_secs_f64(REAL_TIME_FRAMES as f64 / NTSC_FRAME_RATE);
+    println!(
+        "real_time: {} frames in {:?} ({:.1}x real time)",
+        REAL_TIME_FRAMES,
+        elapsed,
+        real_time.as_secs_f64() / elapsed.as_secs_f64()
+    );
+    assert!(elapsed < real_time, "Emulating {} frames took {:?}, a real NES takes {:?}", REAL_TIME_FRAMES, elapsed, real_time);
+}
+
+criterion_group!(benches, bench_decode, bench_ppu_frame, bench_run_frame, bench_real_time);
+criterion_main!(benches);
@@ -1,4 +1,9 @@
 use num::{signum, zero};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::rc::Rc;
+use std::time::Instant;
 
 pub const CPU_SPEED: usize = 1790000; // 1.79 Mhz
 pub const STACK_BASE: usize = 0x100;
@@ -14,32 +19,124 @@ const PPU_REGISTERS_MIRRORS_END: u16 = 0x3FFF;
 const NUM_PPU_MIRRORS: u16 = 1024;
 const APU_IO_REGISTERS: u16 = 0x4000;
 const APU_IO_REGISTERS_END: u16 = 0x4017;
+const OAM_DMA: u16 = 0x4014;
+const APU_STATUS: u16 = 0x4015;
+const CONTROLLER_ONE: u16 = 0x4016;
+const CONTROLLER_TWO: u16 = 0x4017;
+const CONTROLLER_OPEN_BUS: u8 = 0b1110_0000; // Bits of $4016/$4017 nothing drives — they keep what was last on the bus (usually $40 from the address)
+const APU_FRAME_COUNTER: u16 = 0x4017; // Shares its address with controller two — writes go to the APU, reads to the controller
+const CARTRIDGE_EXPANSION: u16 = 0x4020; // $4020-$7FFF is wired to the cartridge (mapper registers, extra RAM)
+const CARTRIDGE_EXPANSION_END: u16 = 0x7FFF;
+
+// Controller button masks — in the same order the buttons are shifted out of $4016/$4017
+pub const BUTTON_A: u8 = 0b0000_0001;
+pub const BUTTON_B: u8 = 0b0000_0010;
+pub const BUTTON_SELECT: u8 = 0b0000_0100;
+pub const BUTTON_START: u8 = 0b0000_1000;
+pub const BUTTON_UP: u8 = 0b0001_0000;
+pub const BUTTON_DOWN: u8 = 0b0010_0000;
+pub const BUTTON_LEFT: u8 = 0b0100_0000;
+pub const BUTTON_RIGHT: u8 = 0b1000_0000;
+const FRAME_RATE: u8 = 60; // NTSC frames per second, used to time turbo buttons
+const FOUR_SCORE_SIGNATURES: [u8; 2] = [0b0001_0000, 0b0010_0000]; // Shifted out (msb first) after the 16 button bits of each port
+const ZAPPER_LIGHT_THRESHOLD: u8 = 0xC0; // Brightness the Zapper's photodiode needs to see — white targets pass, the sky in Duck Hunt doesn't
+const ZAPPER_LIGHT_SCANLINES: usize = 26; // How long the photodiode keeps sensing a pixel after the beam draws it (the phosphor fades)
+
+// APU constants
+// Frame sequencer steps in CPU cycles (NTSC) — the real steps land on half cycles, these are rounded down
+const FRAME_STEP_ONE: u16 = 7457;
+const FRAME_STEP_TWO: u16 = 14913;
+const FRAME_STEP_THREE: u16 = 22371;
+const FRAME_STEP_FOUR: u16 = 29829;
+const FRAME_FOUR_STEP_END: u16 = 29830;
+const FRAME_STEP_FIVE: u16 = 37281;
+const FRAME_FIVE_STEP_END: u16 = 37282;
+const CPU_CLOCK_RATE: f64 = 1_789_773.0; // NTSC CPU cycles per second — the APU's output can change every cycle
+const RESAMPLER_OVERSAMPLE: f64 = 4.0; // The first stage averages down to no less than this many times the output rate
+const RESAMPLER_CUTOFF: f64 = 0.45; // Passband edge as a fraction of the output rate (Nyquist is 0.5, the gap is the transition band)
+const RESAMPLER_ZERO_CROSSINGS: f64 = 16.0; // Sinc lobes each side of the filter kernel — more is a sharper cutoff
+const RESAMPLER_PHASES: usize = 256; // Kernels precomputed between two input samples, the ones in between are interpolated
+const DMC_DMA_HALT_CYCLES: u32 = 3; // Halt, dummy and alignment cycles before a DMC DMA reads its sample
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
 
 // PPU memory constants
 const PATTERN_TABLES_BEGIN: u16 = 0x0000;
 const PATTERN_TABLES_END: u16 = 0x1FFF;
 const NAME_TABLES_BEGIN: u16 = 0x2000;
 const NAME_TABLE_SIZE: u16 = 0x400;
-const NAME_TABLES_END: u16 = 0x2FFF;
+const NAME_TABLE_MIRRORS_END: u16 = 0x3EFF; // $3000-$3EFF mirrors $2000-$2EFF
 const PALETTE_RAM_BEGIN: u16 = 0x3F00;
 const PALETTE_RAM_END: u16 = 0x3FFF;
 const NUM_PALETTE_REGISTERS: usize = 32;
+const A12_FILTER_DOTS: u64 = 10; // A12 has to stay low this long (just over 3 CPU cycles) before a rise is passed on to the mapper
+const NUM_SYSTEM_COLORS: usize = 64; // Colors the PPU can output — .pal files hold one RGB triple per color (some add emphasis variants after)
+// The VS System's RGB PPU (2C03) puts out these colors directly, 3 bits per channel, instead of NTSC video
+const VS_PALETTE: [u16; NUM_SYSTEM_COLORS] = [
+    0o333, 0o014, 0o006, 0o326, 0o403, 0o503, 0o510, 0o420, 0o320, 0o120, 0o031, 0o040, 0o022, 0o000, 0o000, 0o000,
+    0o555, 0o036, 0o027, 0o407, 0o507, 0o704, 0o700, 0o630, 0o430, 0o140, 0o040, 0o053, 0o044, 0o000, 0o000, 0o000,
+    0o777, 0o357, 0o447, 0o637, 0o707, 0o737, 0o740, 0o750, 0o660, 0o360, 0o070, 0o276, 0o077, 0o000, 0o000, 0o000,
+    0o777, 0o567, 0o657, 0o757, 0o747, 0o755, 0o764, 0o772, 0o773, 0o572, 0o473, 0o276, 0o467, 0o000, 0o000, 0o000,
+];
 
 // Screen constants
 pub const SCREEN_WIDTH: usize = 256; // Both in pixels
 pub const SCREEN_HEIGHT: usize = 240;
 const NUM_SCANLINES: usize = 261;
-const SPRITE_HEIGHT: usize = 8; // 8 pixels/scanlines
+const SPRITE_HEIGHT: u16 = 8; // 8 pixels/scanlines (16 when PPUCTRL bit 5 is set)
+const HARDWARE_SPRITE_LIMIT: u8 = 8; // Sprites per scanline the real PPU can show
+const NMI_DELAY_DOTS: u8 = 2; // PPU dots between the VBlank flag being set and the NMI line going low
+const NUM_SPRITES: usize = 64; // Sprites in OAM, the most that can ever be on one scanline
+// Solid colors PPU::set_debug_layers draws each layer in, instead of the palette — the backdrop goes black
+pub const DEBUG_TINT_BACKGROUND: (u8, u8, u8) = (0x30, 0x60, 0xFF);
+pub const DEBUG_TINT_SPRITE_FRONT: (u8, u8, u8) = (0xFF, 0x30, 0x30);
+pub const DEBUG_TINT_SPRITE_BACK: (u8, u8, u8) = (0x30, 0xE0, 0x30);
 
 pub const NES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
+const FDS_TAG: [u8; 4] = [0x46, 0x44, 0x53, 0x1A]; // "FDS" — fwNES disk images, which boot from the FDS BIOS
+const IPS_TAG: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
 const PRG_PAGE_SIZE: usize = 16384;
 const CHR_PAGE_SIZE: usize = 8192;
+const PRG_RAM: u16 = 0x6000; // $6000-$7FFF, battery backed on some boards (and where test ROMs report their results)
+const PRG_RAM_SIZE: usize = 8192;
+const CHR_TILE_SIZE: usize = 16; // Bytes per 8x8 tile — 8 rows of the low bit plane, then 8 of the high one
+pub const CHR_SHEET_COLUMNS: usize = 16; // Tiles per row of PPU::render_chr_sheet, matching how a pattern table is laid out
+const UNROM_512_CHR_RAM_SIZE: usize = 32768; // Four 8KB banks
+const GTROM_PRG_BANK_SIZE: usize = 32768;
+const GTROM_CHR_RAM_SIZE: usize = 16384; // Two 8KB banks
+const GTROM_REGISTER: u16 = 0x5000; // $5000-$5FFF, mirrored at $7000-$7FFF
+const COLOR_DREAMS_PRG_BANK_SIZE: usize = 32768; // The whole of $8000-$FFFF switches at once
+
+// Save states — a major version bump means old states can't be loaded, a minor one only appends fields (see CPU::load_state)
+const STATE_TAG: [u8; 4] = *b"NESS";
+const STATE_VERSION_MAJOR: u8 = 1;
+const STATE_VERSION_MINOR: u8 = 5; // 1.1 — CPUBus RDY hold, 1.2 — PPU bus A12 filter, 1.3 — NMI delay, 1.4 — DMC DMA,
+                                   // 1.5 — NROM PRG RAM
+const MMC5_PRG_WINDOW_SIZE: usize = 8192;
+const MMC5_CHR_WINDOW_SIZE: usize = 1024;
+const MMC5_EXRAM: u16 = 0x5C00;
+const MMC5_EXRAM_END: u16 = 0x5FFF;
+const MMC5_EXRAM_SIZE: usize = 1024;
 
 #[derive(Debug, PartialEq, Clone, Copy)]
+#[allow(non_camel_case_types)]
 pub enum Mirroring {
    VERTICAL,
    HORIZONTAL,
    FOUR_SCREEN,
+   SINGLE_SCREEN_LOWER, // Every nametable address maps to the first 1KB of VRAM
+   SINGLE_SCREEN_UPPER, // Every nametable address maps to the second 1KB of VRAM
+}
+
+// Hardware the cartridge was made for — VS System arcade boards have an RGB PPU with its own palette, and coin slots and
+// DIP switches on the controller ports
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum System {
+   Nes,
+   Vs,
 }
 
 #[derive(Clone)]
@@ -48,20 +145,215 @@ pub struct Rom {
    pub chr_rom: Vec<u8>,
    pub mapper: u8,
    pub screen_mirroring: Mirroring,
+   pub system: System, // Bit 0 of header byte 7
+   raw: Vec<u8>, // The whole file as loaded, kept around so patches can be applied and the header parsed again
+}
+
+// Reasons a patch couldn't be applied to a ROM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatchError {
+    UnknownFormat, // Doesn't start with the IPS "PATCH" tag
+    Truncated(usize), // The patch ends in the middle of the record starting at the given patch offset
+    InvalidRom(RomError), // The patched file no longer parses as a ROM
+}
+
+// Reasons a file couldn't be loaded as a ROM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RomError {
+    NoHeader, // Doesn't start with the iNES tag — possibly a headerless dump, which Rom::from_headerless can load
+    BadSize(usize), // Headerless dump that isn't whole 16KB PRG banks plus an optional 8KB of CHR
+    TruncatedHeader(usize), // Shorter than the 16 byte iNES header, so there's nothing to check
+    DiskImage, // Famicom Disk System image — the boot code is in the FDS BIOS and the disk drive isn't emulated
+}
+
+// Reasons a BIOS image couldn't be mapped in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BiosError {
+    Empty,
+    TooBig(usize), // Bigger than the 32KB at $8000-$FFFF it's mapped into
+}
+
+// Problems with a .pal file found when the PPU is built — it still runs, with the missing colors black
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PaletteError {
+    NotTriples(usize), // Size isn't a whole number of RGB triples
+    TooShort(usize), // Doesn't cover all 64 system colors
+}
+
+impl std::fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PaletteError::NotTriples(size) => write!(f, "Palette is {} bytes, which isn't a whole number of RGB triples", size),
+            PaletteError::TooShort(size) => write!(f, "Palette only has {} bytes, missing colors will be black", size),
+        }
+    }
+}
+
+impl std::fmt::Display for BiosError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BiosError::Empty => write!(f, "BIOS image is empty"),
+            BiosError::TooBig(size) => write!(f, "A {} byte BIOS doesn't fit in the 32KB at $8000-$FFFF", size),
+        }
+    }
+}
+
+impl std::fmt::Display for RomError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RomError::NoHeader => write!(f, "File is not in iNES file format"), // Pretty obvious...
+            RomError::BadSize(size) => write!(f, "A {} byte headerless dump doesn't split into 16KB PRG banks and 8KB of CHR", size),
+            RomError::TruncatedHeader(size) => write!(f, "A {} byte file is too short to hold an iNES header", size),
+            RomError::DiskImage => write!(f, "Famicom Disk System images need the FDS BIOS (--bios) and disk drive, which aren't supported yet"),
+        }
+    }
+}
+
+// Reasons a save state couldn't be loaded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    NotAState, // Doesn't start with the save state tag
+    Incompatible { major: u8, minor: u8 }, // Saved by a version of the emulator with a different state layout
+    Truncated, // Ends partway through the state
+}
+
+impl std::fmt::Display for StateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            StateError::NotAState => write!(f, "File is not a save state"),
+            StateError::Incompatible { major, minor } => write!(
+                f, "Save state version {}.{} can't be loaded by this version (expects {}.x)", major, minor, STATE_VERSION_MAJOR
+            ),
+            StateError::Truncated => write!(f, "Save state is cut short"),
+        }
+    }
+}
+
+// Little endian writer for save states
+// Each component goes in its own length prefixed section, so a section that gains fields doesn't move the ones after it
+struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    fn new() -> Self {
+        StateWriter { data: vec![] }
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    fn bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    // Length prefixed, for sections and buffers whose size isn't fixed
+    fn section(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.data.extend_from_slice(bytes);
+    }
+}
+
+struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        StateReader { data, pos: 0 }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        let bytes = self.data.get(self.pos..self.pos + len).ok_or(StateError::Truncated)?;
+        self.pos += len;
+
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, StateError> {
+        Ok(self.u8()? != 0)
+    }
+
+    fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn section(&mut self) -> Result<&'a [u8], StateError> {
+        let len = self.u32()? as usize;
+
+        self.bytes(len)
+    }
+
+    // Whether everything has been read — a section from an older minor version ends before the fields added since
+    fn at_end(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+}
+
+impl Mirroring {
+    fn from_state(value: u8) -> Mirroring {
+        match value {
+            0 => Mirroring::VERTICAL,
+            1 => Mirroring::HORIZONTAL,
+            2 => Mirroring::FOUR_SCREEN,
+            3 => Mirroring::SINGLE_SCREEN_LOWER,
+            _ => Mirroring::SINGLE_SCREEN_UPPER,
+        }
+    }
 }
 
 impl Rom {
-    pub fn new(raw: &Vec<u8>) -> Result<Rom, String> {
+    pub fn new(raw: &Vec<u8>) -> Result<Rom, RomError> {
+        if raw.len() < 16 {
+            return Err(RomError::TruncatedHeader(raw.len()))
+        }
+
+        if raw[0..4] == FDS_TAG {
+            return Err(RomError::DiskImage)
+        }
+
         if &raw[0..4] != NES_TAG {
-            return Err("File is not in iNES file format".to_string()) // Pretty obvious...
+            return Err(RomError::NoHeader)
         }
 
         // Sets various initial states based on the control bytes
-        let mapper = (raw[7] & 0b1111_0000) | raw[6];
+        let mapper = (raw[7] & 0b1111_0000) | (raw[6] >> 4);
         let four_screen = (raw[6] & 0b1000) != 0;
         let screen_mirroring = (raw[6] & 0x1) != 0;
 
         let mirroring = match(four_screen, screen_mirroring) {
+            // UNROM-512 reuses the four screen bit (with bit 0 clear) to mean the mapper picks a single screen
+            (true, false) if mapper == 30 => Mirroring::SINGLE_SCREEN_LOWER,
             (true, _) => Mirroring::FOUR_SCREEN,
             (false, true) => Mirroring::VERTICAL,
             (false, false) => Mirroring::HORIZONTAL,
@@ -71,6 +363,8 @@ impl Rom {
         let prg_rom_size = raw[4] as usize * PRG_PAGE_SIZE;
         let chr_rom_size = raw[5] as usize * CHR_PAGE_SIZE;
 
+        let system = if raw[7] & 0b1 != 0 { System::Vs } else { System::Nes };
+
         // Decides whether the trainer should be skipped from the control bytes
         let skip_trainer = (raw[6] & 0b100) != 0;
 
@@ -83,1024 +377,4388 @@ impl Rom {
             chr_rom: raw[chr_rom_start..(chr_rom_start + chr_rom_size)].to_vec(),
             mapper: mapper,
             screen_mirroring: mirroring,
+            system,
+            raw: raw.clone(),
         })
     }
-}
-
-pub trait Mem {
-    fn mem_read(&mut self, addr: u16) -> u8;
-    fn mem_write(&mut self, addr: u16, data: u8);
-    fn mem_read_u16(&self, addr: u16) -> u16;
-    fn mem_write_u16(&mut self, pos: u16, data: u16);
-}
-
-pub struct CPUBus {
-    cpu_ram: [u8; (0xFFFF + 1) as usize],
-    prg_rom: Vec<u8>, // Program ROM from the cartridge
-    halt_flag: bool, // The CPU halts when DMA units are in use
-    pub ppu: PPU, // Connecting the PPU to the CPU Bus
-    open_bus: u16, // Handles the open-bus case if attempting to access an area with no memory map
-    ppu_latch: u8 // The latch is loaded when a value is read/written to the PPU, extracted when read from a write-only latch
-}
-
-pub struct PPUBus {
-    chr_rom: Vec<u8>, // Character ROM from the cartridge
-    vram: [u8; 2048], // Used to lay out the background
-    mirroring: Mirroring, // Determines what kind of nametable mirroring is used (vertical, horizontal, four-screen, etc.)
-    palette_mem: [u8; 32], // Holds the background colors (low 16 bytes) and sprite colors (high 16 bytes)
-    palette_storage: Vec<u8>, // Holds 512 3 byte values, but we really only access the first 64
-}
 
-impl CPUBus {
-    pub fn new(prg_rom: Vec<u8>, ppu_connection: PPU) -> Self {
-        CPUBus {
-            cpu_ram: [0; (0xFFFF + 1) as usize],
-            prg_rom: prg_rom,
-            halt_flag: false,
-            ppu: ppu_connection,
-            open_bus: 0,
-            ppu_latch: 0
+    // Builds a ROM straight from its PRG and CHR (empty CHR means CHR RAM), for tests and tools that don't want to craft a header
+    // There's no file behind it, so apply_patch has nothing to patch
+    pub fn from_parts(prg: Vec<u8>, chr: Vec<u8>, mapper: u8, mirroring: Mirroring) -> Rom {
+        Rom {
+            prg_rom: prg,
+            chr_rom: chr,
+            mapper,
+            screen_mirroring: mirroring,
+            system: System::Nes,
+            raw: vec![],
         }
     }
 
-    pub fn read_prg_rom(&self, addr: &u16) -> u8 {
-        let mut mirrored_addr = addr - 0x8000;
-        
-        if self.prg_rom.len() == 0x4000 && mirrored_addr >= 0x4000 {
-            mirrored_addr %= 0x4000;
+    // Best effort load of a dump without an iNES header — the mapper and mirroring have to come from the user
+    // The PRG banks come first, followed by 8KB of CHR if the size leaves room for it (otherwise the cartridge has CHR RAM)
+    // A header is put back on the front so the rest of the loader (and patching) works the same as for any other ROM
+    pub fn from_headerless(raw: &[u8], mapper: u8, mirroring: Mirroring) -> Result<Rom, RomError> {
+        let chr_size = if raw.len() % PRG_PAGE_SIZE == CHR_PAGE_SIZE { CHR_PAGE_SIZE } else { 0 };
+        let prg_size = raw.len() - chr_size;
+
+        if prg_size == 0 || !prg_size.is_multiple_of(PRG_PAGE_SIZE) || prg_size / PRG_PAGE_SIZE > 255 {
+            return Err(RomError::BadSize(raw.len()))
         }
 
-        self.prg_rom[mirrored_addr as usize]
-    }
-}
+        let mirroring_flags = match mirroring {
+            Mirroring::VERTICAL => 0b0001,
+            Mirroring::FOUR_SCREEN => 0b1000,
+            _ => 0b0000,
+        };
 
-impl PPUBus {
-    pub fn new(chr_rom: Vec<u8>, mirroring: Mirroring, palette_mem: [u8; 32], palette_storage: Vec<u8>) -> Self {
-        PPUBus { 
-            chr_rom: chr_rom, 
-            vram: [0; 2048], 
-            mirroring: mirroring, 
-            palette_mem: palette_mem,
-            palette_storage
-        }
-    }
-}
+        let mut header = vec![0; 16];
+        header[0..4].copy_from_slice(&NES_TAG);
+        header[4] = (prg_size / PRG_PAGE_SIZE) as u8;
+        header[5] = (chr_size / CHR_PAGE_SIZE) as u8;
+        header[6] = (mapper << 4) | mirroring_flags;
+        header[7] = mapper & 0b1111_0000;
 
-impl Mem for CPUBus {
-    fn mem_read(&mut self, addr: u16) -> u8 {
-        match addr {
-            RAM..=RAM_MIRRORS_END => {
-                let mirrored_addr = addr & 0x07FF; // 0x07FF
-                self.cpu_ram[mirrored_addr as usize]
-            },
+        header.extend_from_slice(raw);
+        Rom::new(&header)
+    }
 
-            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
-                let mirrored_addr = addr & 0x2007;
+    // Soft-patches the ROM with an IPS patch — offsets are into the whole file (header included), so the
+    // patch is applied to the raw file and the header is parsed again afterwards
+    pub fn apply_patch(&mut self, patch: &[u8]) -> Result<(), PatchError> {
+        if !patch.starts_with(IPS_TAG) {
+            return Err(PatchError::UnknownFormat)
+        }
 
-                match mirrored_addr {
-                    // Returns the PPU status register and resets the write latch (w register)
-                    0x2002 => {
-                        self.ppu.w = 0;
-                        self.ppu_latch = self.ppu.status;
+        let mut patched = self.raw.clone();
+        let mut pos = IPS_TAG.len();
 
-                        return self.ppu.status
-                    }
+        loop {
+            let record_start = pos;
+            let take = |pos: &mut usize, len: usize| -> Result<&[u8], PatchError> {
+                let bytes = patch.get(*pos..(*pos + len)).ok_or(PatchError::Truncated(record_start))?;
+                *pos += len;
+                Ok(bytes)
+            };
 
-                    // Returns a byte from OAM
-                    0x2004 => {
-                        let return_value = self.ppu.oam[self.ppu.oam_addr as usize];
-                        self.ppu_latch = return_value;
+            let offset_bytes = take(&mut pos, 3)?;
+            if offset_bytes == IPS_EOF {
+                break
+            }
 
-                        return return_value
-                    }
+            let offset = (offset_bytes[0] as usize) << 16 | (offset_bytes[1] as usize) << 8 | offset_bytes[2] as usize;
+            let size_bytes = take(&mut pos, 2)?;
+            let size = (size_bytes[0] as usize) << 8 | size_bytes[1] as usize;
 
-                    // Sets the vram latch to a byte from VRAM
-                    // If the address is from palette memory it is instantly returned
-                    0x2007 => {
-                        let return_value;
-                        if (self.ppu.v <= 0x3FFF) & (self.ppu.v >= 0x3F00) {
-                            return_value = self.ppu.read_byte(self.ppu.v);
-                            // Still sets the ppu latch to the value "underneath"
-                            self.ppu.vram_latch = self.ppu.read_byte(self.ppu.v % 0x2000);
-                        } else {
-                            return_value = self.ppu.vram_latch;
-                            self.ppu.vram_latch = self.ppu.read_byte(self.ppu.v);
-                        }
+            // A size of 0 marks a run-length record: a 2 byte count followed by the byte to repeat
+            let data: Vec<u8> = if size == 0 {
+                let run = take(&mut pos, 3)?;
+                vec![run[2]; (run[0] as usize) << 8 | run[1] as usize]
+            } else {
+                take(&mut pos, size)?.to_vec()
+            };
 
-                        self.ppu.increment_vram();
+            // Records past the end of the file grow it
+            if offset + data.len() > patched.len() {
+                patched.resize(offset + data.len(), 0);
+            }
+            patched[offset..(offset + data.len())].copy_from_slice(&data);
+        }
 
-                        return return_value
-                    },
+        // Some patches put a 3 byte length after EOF that the file is cut down to
+        if let Some(length) = patch.get(pos..(pos + 3)) {
+            patched.truncate((length[0] as usize) << 16 | (length[1] as usize) << 8 | length[2] as usize);
+        }
 
-                    _ => { self.ppu_latch }
-                }
-            },
+        *self = Rom::new(&patched).map_err(PatchError::InvalidRom)?;
+        Ok(())
+    }
 
-            APU_IO_REGISTERS..=APU_IO_REGISTERS_END => {
-                // TODO: APU NOT IMPLEMENTED YET
-                return 0
-            },
+    // Builds the cartridge hardware for the mapper number in the header
+    // The CPU and PPU buses share the mapper since bank switches done by the CPU change what the PPU sees
+    pub fn create_mapper(&self) -> Result<Rc<RefCell<dyn Mapper>>, String> {
+        // Every mapper reads $8000-$FFFF out of the PRG ROM, so there has to be at least one byte of it
+        if self.prg_rom.is_empty() {
+            return Err("ROM has no PRG".to_string())
+        }
 
-            0x8000..=0xFFFF => { self.read_prg_rom(&addr) },
-            
-            _ => { self.open_bus as u8 }
+        match self.mapper {
+            0 => Ok(Rc::new(RefCell::new(Nrom::new(self)))),
+            5 => Ok(Rc::new(RefCell::new(Mmc5::new(self)))),
+            11 if self.chr_rom.is_empty() => Err("Color Dreams boards need CHR ROM, but this ROM has none".to_string()),
+            11 => Ok(Rc::new(RefCell::new(ColorDreams::new(self)))),
+            30 => Ok(Rc::new(RefCell::new(Unrom512::new(self)))),
+            111 => Ok(Rc::new(RefCell::new(Gtrom::new(self)))),
+            _ => Err(format!("Mapper {} is not supported", self.mapper)),
         }
     }
+}
 
-    fn mem_read_u16(&self, addr: u16) -> u16 {
-        match addr {
-            RAM..=RAM_MIRRORS_END => {
-                let unmirrored_addr = addr & 0x07FF;
-                let low_byte = self.cpu_ram[unmirrored_addr as usize];
-                let high_byte = self.cpu_ram[(unmirrored_addr.wrapping_add(1)) as usize];
+// Cartridge hardware — decides where CPU accesses to $8000-$FFFF and PPU accesses to $0000-$1FFF end up
+pub trait Mapper {
+    fn cpu_read(&self, addr: u16) -> u8;
+    fn cpu_write(&mut self, addr: u16, data: u8);
+    fn ppu_read(&self, addr: u16) -> u8;
+    fn ppu_write(&mut self, addr: u16, data: u8);
+    fn mirroring(&self) -> Mirroring;
+
+    // Banking layout — $8000-$FFFF and $0000-$1FFF are split into equal windows, each showing one bank of PRG/CHR
+    fn prg_window_size(&self) -> usize { PRG_PAGE_SIZE }
+    fn chr_window_size(&self) -> usize { CHR_PAGE_SIZE }
+    fn prg_len(&self) -> usize;
+    fn chr_len(&self) -> usize;
+    fn prg_bank(&self, window: usize) -> usize; // Bank currently switched into the given window (wraps if too big)
+    fn chr_bank(&self, window: usize) -> usize;
+
+    // $4020-$7FFF — registers and RAM some cartridges put below the ROM (None leaves the data bus open)
+    fn expansion_read(&self, _addr: u16) -> Option<u8> { None }
+    fn expansion_write(&mut self, _addr: u16, _data: u8) {}
+
+    // Stores into cartridge memory at a CPU address ($4020-$FFFF) without touching any registers, for debuggers and
+    // cheats — only memory that could be written anyway (PRG RAM, ExRAM) takes it
+    fn cpu_poke(&mut self, _addr: u16, _data: u8) {}
+
+    // Palette bits for the background tile at a nametable address when the cartridge overrides the attribute table
+    fn extended_attribute(&self, _nametable_addr: u16) -> Option<u8> { None }
+
+    // Rising edge of PPU address line A12 (pattern table $1000-$1FFF) — MMC3 style mappers clock their scanline counter
+    // with it. Rises right after a short low are filtered out before this is called (see PPUBus::watch_a12)
+    fn on_ppu_a12_rise(&mut self) {}
+
+    // Whether $0000-$1FFF is CHR RAM — writes to CHR ROM are dropped by ppu_write and counted as illegal by the PPU bus
+    fn chr_writable(&self) -> bool { false }
+
+    // Whether the ROM keeps driving the data bus when the CPU writes to $8000-$FFFF — if it does, the register sees the
+    // written value ANDed with the ROM byte at that address (games write to a byte that already holds the value)
+    fn bus_conflicts(&self) -> bool { false }
+
+    // Bank registers and cartridge RAM for save states — the ROM itself is never saved
+    fn save_state(&self) -> Vec<u8> { vec![] }
+    fn load_state(&mut self, _data: &[u8]) -> Result<(), StateError> { Ok(()) }
+
+    // Index into the PRG ROM for a CPU address in $8000-$FFFF
+    // PRG smaller than a window (e.g. a 16KB dump on a 32KB board) is mirrored through it, the same as CHR below
+    fn prg_offset(&self, addr: u16) -> usize {
+        let window_size = self.prg_window_size();
+        let rom_addr = (addr - ROM_BEGIN) as usize;
+        let num_banks = (self.prg_len() / window_size).max(1);
+        let offset = (self.prg_bank(rom_addr / window_size) % num_banks) * window_size + rom_addr % window_size;
+
+        offset % self.prg_len().max(1)
+    }
 
-                (high_byte as u16) << 8 | low_byte as u16
-            },
+    // Index into CHR ROM/RAM for a PPU address in $0000-$1FFF
+    // CHR smaller than a window (e.g. a 4KB NROM dump) is mirrored through it, the cartridge just doesn't wire up the
+    // top address lines — so reads past the end wrap around instead of indexing out of bounds
+    fn chr_offset(&self, addr: u16) -> usize {
+        let window_size = self.chr_window_size();
+        let num_banks = (self.chr_len() / window_size).max(1);
+        let offset = (self.chr_bank(addr as usize / window_size) % num_banks) * window_size + addr as usize % window_size;
 
-            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
-                return self.ppu_latch as u16
-            },
+        offset % self.chr_len().max(1)
+    }
+}
 
-            APU_IO_REGISTERS..=APU_IO_REGISTERS_END => {
-                // TODO: APU NOT IMPLEMENTED YET
-                return 0
-            },
+// Mapper 0 — no bank switching, a 16KB PRG ROM is mirrored into $C000-$FFFF
+// Only Family BASIC shipped with PRG RAM, but it's always there so test ROMs can report through $6000
+pub struct Nrom {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>, // CHR ROM, or 8KB of CHR RAM if the cartridge doesn't have any
+    chr_ram: bool,
+    mirroring: Mirroring,
+}
 
-            0x8000..=0xFFFF => { ((self.read_prg_rom(&(addr + 1)) as u16) << 8) | self.read_prg_rom(&addr) as u16 },
-            
-            _ => { self.open_bus }
+impl Nrom {
+    pub fn new(rom: &Rom) -> Self {
+        let chr_ram = rom.chr_rom.is_empty();
+
+        Nrom {
+            prg_rom: rom.prg_rom.clone(),
+            prg_ram: vec![0; PRG_RAM_SIZE],
+            chr: if chr_ram { vec![0; CHR_PAGE_SIZE] } else { rom.chr_rom.clone() },
+            chr_ram,
+            mirroring: rom.screen_mirroring,
         }
     }
+}
 
-    fn mem_write(&mut self, addr: u16, data: u8) {
-        //println!("write addr: {}", addr);
-
-        match addr {
-            RAM..=RAM_MIRRORS_END => {
-                let unmirrored_addr = addr & 0x07FF;
-                self.cpu_ram[unmirrored_addr as usize] = data;
-            },
-
-            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
-                let mirrored_addr = addr & 0x2007;
-                match mirrored_addr {
-                    // Sets the control register of the PPU
-                    0x2000 => {
-                        self.ppu.ctrl = data;
-                        self.ppu_latch = data;
-                    },
+impl Mapper for Nrom {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        self.prg_rom[self.prg_offset(addr)]
+    }
 
-                    // Writes to the ppu mask register
-                    0x2001 => {
-                        self.ppu.mask = data;
-                        self.ppu_latch = data;
-                    }
+    fn cpu_write(&mut self, _addr: u16, _data: u8) {
+        // No registers, writes to ROM go nowhere
+    }
 
-                    // Sets open bus
-                    0x2002 => {
-                        self.ppu_latch = data;
-                    }
+    fn expansion_read(&self, addr: u16) -> Option<u8> {
+        (addr >= PRG_RAM).then(|| self.prg_ram[(addr - PRG_RAM) as usize])
+    }
 
-                    // Writes an OAM address
-                    0x2003 => {
-                        self.ppu.oam_addr = data;
+    fn expansion_write(&mut self, addr: u16, data: u8) {
+        self.cpu_poke(addr, data);
+    }
 
-                        self.ppu_latch = data;
-                    }
+    fn cpu_poke(&mut self, addr: u16, data: u8) {
+        if let PRG_RAM..=CARTRIDGE_EXPANSION_END = addr {
+            self.prg_ram[(addr - PRG_RAM) as usize] = data;
+        }
+    }
 
-                    // Writes to OAM and increments the OAM adder - very dangerous (normally) due to not finishing during VBLANK
-                    0x2004 => {
-                        self.ppu.oam_data = data;
-                        self.ppu.oam_data_set();
-                        self.ppu.oam_addr += 1;
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr[self.chr_offset(addr)]
+    }
 
-                        self.ppu_latch = data;
-                    }
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = data;
+        }
+    }
 
-                    // Loads the scroll register with the scroll data
-                    0x2005 => {
-                        self.ppu.load_scroll(data);
+    fn chr_writable(&self) -> bool {
+        self.chr_ram
+    }
 
-                        self.ppu_latch = data;
-                    }
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
 
-                    // Loads the given byte into either the high or low position depending on the w register
-                    0x2006 => {
-                        self.ppu.load_addr_byte(data as u16);
+    fn prg_len(&self) -> usize {
+        self.prg_rom.len()
+    }
 
-                        self.ppu_latch = data;
-                    },
+    fn chr_len(&self) -> usize {
+        self.chr.len()
+    }
 
-                    // Sets the value of the data register and moves vram forward
-                    0x2007 => {
-                        self.ppu.cpu_write_byte(data);
+    // Fixed banks — a 16KB ROM only has bank 0, so the $C000 window wraps around to mirror it
+    fn prg_bank(&self, window: usize) -> usize {
+        window
+    }
 
-                        self.ppu_latch = data;
-                    }
+    fn chr_bank(&self, _window: usize) -> usize {
+        0
+    }
 
-                    // OAM DMA Call... pretty tricky to get right
-                    // God damn it this sucks ngl
-                    // Mfw I have to refactor my code: _-_
-                    0x4014 => {
-                        self.ppu.oam_data = data;
-                        self.halt_flag = true;
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        if self.chr_ram {
+            state.bytes(&self.chr);
+        }
+        state.bytes(&self.prg_ram); // 1.5
 
-                        self.ppu_latch = data;
-                    }
+        state.data
+    }
 
-                    _ => { self.ppu_latch = data; }
-                }
-            },
+    fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut state = StateReader::new(data);
 
-            APU_IO_REGISTERS..=APU_IO_REGISTERS_END => {
-                // TODO: APU NOT IMPLEMENTED YET
-            },
+        if self.chr_ram {
+            let chr = state.bytes(self.chr.len())?;
+            self.chr.copy_from_slice(chr);
+        }
 
-            _ => { self.open_bus = data as u16; }
+        if state.at_end() {
+            self.prg_ram.fill(0);
+        } else {
+            let prg_ram = state.bytes(self.prg_ram.len())?;
+            self.prg_ram.copy_from_slice(prg_ram);
         }
+
+        Ok(())
     }
+}
 
-    fn mem_write_u16(&mut self, addr: u16, data: u16) {
-        match addr {
-            RAM..=RAM_MIRRORS_END => {
-                let unmirrored_addr = addr & 0x07FF;
-                let low_byte = (data & 0x00FF) as u8;
-                let high_byte = (data & 0xFF00) as u8;
+// Mapper 30 (UNROM-512) — homebrew board with a switchable 16KB PRG bank at $8000 (the last bank is fixed at $C000),
+// four 8KB CHR RAM banks, and optionally a single screen mirroring select
+// A write anywhere in $8000-$FFFF sets the bank register: MCCP PPPP (M = single screen page, C = CHR bank, P = PRG bank)
+pub struct Unrom512 {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>, // 32KB of CHR RAM, or the CHR ROM for the rare boards that have one
+    chr_ram: bool,
+    prg_bank: u8,
+    chr_bank: u8,
+    single_screen: bool, // Set when the header hands mirroring control to the mapper
+    mirroring: Mirroring,
+    bus_conflicts: bool, // Only the flashable boards (battery bit set in the header) keep the ROM off the bus during writes
+}
 
-                self.cpu_ram[unmirrored_addr as usize] = low_byte;
-                self.cpu_ram[(unmirrored_addr + 1) as usize] = high_byte;
-            },
+impl Unrom512 {
+    pub fn new(rom: &Rom) -> Self {
+        let chr_ram = rom.chr_rom.is_empty();
+
+        Unrom512 {
+            prg_rom: rom.prg_rom.clone(),
+            chr: if chr_ram { vec![0; UNROM_512_CHR_RAM_SIZE] } else { rom.chr_rom.clone() },
+            chr_ram,
+            prg_bank: 0,
+            chr_bank: 0,
+            single_screen: rom.screen_mirroring == Mirroring::SINGLE_SCREEN_LOWER,
+            mirroring: rom.screen_mirroring,
+            bus_conflicts: rom.raw.get(6).is_none_or(|flags| flags & 0b10 == 0),
+        }
+    }
+}
 
-            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
-                let unmirrored_addr = addr & 0x2007;
+impl Mapper for Unrom512 {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        self.prg_rom[self.prg_offset(addr)]
+    }
 
-                match unmirrored_addr {
-                    0x2002 => {
-                        self.open_bus = data;
-                    },
+    fn cpu_write(&mut self, _addr: u16, data: u8) {
+        self.prg_bank = data & 0b1_1111;
+        self.chr_bank = (data >> 5) & 0b11;
 
-                    _ => {
-                        self.open_bus = data;
-                    }
-                }
-            },
+        if self.single_screen {
+            self.mirroring = if data & 0b1000_0000 != 0 {
+                Mirroring::SINGLE_SCREEN_UPPER
+            } else {
+                Mirroring::SINGLE_SCREEN_LOWER
+            };
+        }
+    }
 
-            APU_IO_REGISTERS..=APU_IO_REGISTERS_END => {
-                // TODO: APU NOT IMPLEMENTED YET
-            },
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr[self.chr_offset(addr)]
+    }
 
-            _ => { self.open_bus = data; }
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = data;
         }
     }
-}
 
-// impl Mem later
-impl PPUBus {
-    pub fn mem_read(&self, addr: u16) -> u8 {
-        match addr {
-            PATTERN_TABLES_BEGIN..=PATTERN_TABLES_END => {
-                self.chr_rom[addr as usize]
-            },
+    fn chr_writable(&self) -> bool {
+        self.chr_ram
+    }
 
-            NAME_TABLES_BEGIN..=NAME_TABLES_END => {
-                //println!("The addr is: {}", addr);
-                //println!("The mirrored addr is: {}", (addr % 0x400));
+    fn bus_conflicts(&self) -> bool {
+        self.bus_conflicts
+    }
 
-                let base_addr: u16 = addr & (NAME_TABLE_SIZE - 1);
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
 
-                let vram_index: u16 = match (self.mirroring, addr) {
-                    (Mirroring::HORIZONTAL, 0x2000..0x2800) |
-                    (Mirroring::VERTICAL, 0x2000..0x2400) |
-                    (Mirroring::VERTICAL, 0x2800..0x2C00) => base_addr,
+    fn prg_len(&self) -> usize {
+        self.prg_rom.len()
+    }
 
-                    _ => { base_addr + (NAME_TABLE_SIZE - 1) }
-                };
+    fn chr_len(&self) -> usize {
+        self.chr.len()
+    }
 
-                return self.vram[vram_index as usize]
-            },
+    // Switchable bank at $8000, last bank fixed at $C000
+    fn prg_bank(&self, window: usize) -> usize {
+        if window == 0 {
+            self.prg_bank as usize
+        } else {
+            (self.prg_rom.len() / PRG_PAGE_SIZE).saturating_sub(1)
+        }
+    }
 
-            PALETTE_RAM_BEGIN..=PALETTE_RAM_END => {
-                // 3 times this value is what points to the red byte in palette_storage (+1 -> green and +2 -> blue)
-                return (addr - PALETTE_RAM_BEGIN) as u8 % NUM_PALETTE_REGISTERS as u8
-            }
+    fn chr_bank(&self, _window: usize) -> usize {
+        self.chr_bank as usize
+    }
 
-            // Open bus behavior — multiplexed with pins 31-38, accesses the low byte of the address
-            _ => { (addr & 0b1111_1111) as u8 }
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.u8(self.prg_bank);
+        state.u8(self.chr_bank);
+        state.u8(self.mirroring as u8);
+        if self.chr_ram {
+            state.bytes(&self.chr);
         }
+
+        state.data
     }
 
-    pub fn mem_write(&mut self, addr: u16, data: u8) {
-        match addr {
-            NAME_TABLES_BEGIN..=NAME_TABLES_END => {
-                let base_addr: u16 = addr & (NAME_TABLE_SIZE - 1);
+    fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut state = StateReader::new(data);
 
-                let vram_index: u16 = match (self.mirroring, addr) {
-                    (Mirroring::HORIZONTAL, 0x2000..0x2800) |
-                    (Mirroring::VERTICAL, 0x2000..0x2400) |
-                    (Mirroring::VERTICAL, 0x2800..0x2C00) => base_addr,
-    
-                    _ => { base_addr + (NAME_TABLE_SIZE - 1) }
-                };
+        self.prg_bank = state.u8()?;
+        self.chr_bank = state.u8()?;
+        self.mirroring = Mirroring::from_state(state.u8()?);
 
-                self.vram[vram_index as usize] = data;
-            },
+        if self.chr_ram {
+            let chr = state.bytes(self.chr.len())?;
+            self.chr.copy_from_slice(chr);
+        }
+
+        Ok(())
+    }
+}
+
+// Mapper 111 (GTROM) — homebrew board with 32KB PRG banks, two 8KB CHR RAM banks and four screen nametables
+// The register sits in $5000-$5FFF (and $7000-$7FFF): --NC PPPP (N = nametable bank, C = CHR bank, P = PRG bank), the
+// top two bits drive LEDs. The board's 8KB of nametable RAM isn't emulated — both nametable banks share the PPU's four
+// screens — and neither is flashing the PRG from the game
+pub struct Gtrom {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    register: u8,
+}
+
+impl Gtrom {
+    pub fn new(rom: &Rom) -> Self {
+        Gtrom {
+            prg_rom: rom.prg_rom.clone(),
+            chr: vec![0; GTROM_CHR_RAM_SIZE],
+            register: 0,
+        }
+    }
+
+    fn is_register(addr: u16) -> bool {
+        addr & 0xD000 == GTROM_REGISTER
+    }
+}
+
+impl Mapper for Gtrom {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        self.prg_rom[self.prg_offset(addr)]
+    }
+
+    fn cpu_write(&mut self, _addr: u16, _data: u8) {
+        // Flash writes aren't emulated
+    }
+
+    fn expansion_write(&mut self, addr: u16, data: u8) {
+        if Gtrom::is_register(addr) {
+            self.register = data;
+        }
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr[self.chr_offset(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr[offset] = data;
+    }
+
+    fn chr_writable(&self) -> bool {
+        true
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::FOUR_SCREEN
+    }
+
+    fn prg_window_size(&self) -> usize {
+        GTROM_PRG_BANK_SIZE
+    }
+
+    fn prg_len(&self) -> usize {
+        self.prg_rom.len()
+    }
+
+    fn chr_len(&self) -> usize {
+        self.chr.len()
+    }
+
+    fn prg_bank(&self, _window: usize) -> usize {
+        (self.register & 0b1111) as usize
+    }
+
+    fn chr_bank(&self, _window: usize) -> usize {
+        ((self.register >> 4) & 0b1) as usize
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.u8(self.register);
+        state.bytes(&self.chr);
+
+        state.data
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut state = StateReader::new(data);
+
+        self.register = state.u8()?;
+        let chr = state.bytes(self.chr.len())?;
+        self.chr.copy_from_slice(chr);
+
+        Ok(())
+    }
+}
+
+// Mapper 11 (Color Dreams) — unlicensed board that switches all 32KB of PRG and all 8KB of CHR ROM with one register
+// A write anywhere in $8000-$FFFF sets it: CCCC LLPP (C = CHR bank, P = PRG bank, L = lockout defeat, not emulated)
+pub struct ColorDreams {
+    prg_rom: Vec<u8>,
+    chr_rom: Vec<u8>,
+    prg_bank: u8,
+    chr_bank: u8,
+    mirroring: Mirroring, // Soldered on the board, so it comes from the header
+}
+
+impl ColorDreams {
+    pub fn new(rom: &Rom) -> Self {
+        ColorDreams {
+            prg_rom: rom.prg_rom.clone(),
+            chr_rom: rom.chr_rom.clone(),
+            prg_bank: 0,
+            chr_bank: 0,
+            mirroring: rom.screen_mirroring,
+        }
+    }
+}
+
+impl Mapper for ColorDreams {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        self.prg_rom[self.prg_offset(addr)]
+    }
+
+    fn cpu_write(&mut self, _addr: u16, data: u8) {
+        self.prg_bank = data & 0b11;
+        self.chr_bank = data >> 4;
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr_rom[self.chr_offset(addr)]
+    }
+
+    fn ppu_write(&mut self, _addr: u16, _data: u8) {
+        // CHR ROM only
+    }
+
+    // The register is a plain latch with nothing keeping the ROM off the bus
+    fn bus_conflicts(&self) -> bool {
+        true
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_window_size(&self) -> usize {
+        COLOR_DREAMS_PRG_BANK_SIZE
+    }
+
+    fn prg_len(&self) -> usize {
+        self.prg_rom.len()
+    }
+
+    fn chr_len(&self) -> usize {
+        self.chr_rom.len()
+    }
+
+    fn prg_bank(&self, _window: usize) -> usize {
+        self.prg_bank as usize
+    }
+
+    fn chr_bank(&self, _window: usize) -> usize {
+        self.chr_bank as usize
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.u8(self.prg_bank);
+        state.u8(self.chr_bank);
+
+        state.data
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut state = StateReader::new(data);
+
+        self.prg_bank = state.u8()?;
+        self.chr_bank = state.u8()?;
+
+        Ok(())
+    }
+}
+
+// Mapper 5 (MMC5) — only the first slice of it: the 1KB of ExRAM at $5C00-$5FFF and its extended attribute mode, where
+// each ExRAM byte picks the palette of the background tile at the same nametable position (bits 6-7)
+// Banking is fixed to PRG mode 3 (four 8KB banks, $5114-$5117) and CHR mode 3 (eight 1KB banks, $5120-$5127), and
+// $5105 only takes the nametable layouts that match a standard mirroring — no scanline IRQ, PRG RAM, fill mode,
+// split screen or the extended attribute CHR bank bits
+pub struct Mmc5 {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>, // CHR ROM, or 8KB of CHR RAM if the cartridge doesn't have any
+    chr_ram: bool,
+    prg_banks: [u8; 4], // $5114-$5117, bit 7 selects ROM (always treated as ROM here)
+    chr_banks: [u8; 8],
+    exram: [u8; MMC5_EXRAM_SIZE],
+    exram_mode: u8, // $5104 — 0/1 nametable/extended attributes (CPU can't read it), 2 plain RAM, 3 read only RAM
+    mirroring: Mirroring,
+}
+
+impl Mmc5 {
+    pub fn new(rom: &Rom) -> Self {
+        let chr_ram = rom.chr_rom.is_empty();
+
+        Mmc5 {
+            prg_rom: rom.prg_rom.clone(),
+            chr: if chr_ram { vec![0; CHR_PAGE_SIZE] } else { rom.chr_rom.clone() },
+            chr_ram,
+            prg_banks: [0, 0, 0, 0xFF], // $E000 powers on with the last bank, where the reset vector is
+            chr_banks: [0, 1, 2, 3, 4, 5, 6, 7],
+            exram: [0; MMC5_EXRAM_SIZE],
+            exram_mode: 0,
+            mirroring: rom.screen_mirroring,
+        }
+    }
+
+    // $5105 gives each nametable its own source — only the layouts a standard mirroring can express are supported
+    fn set_nametable_mapping(&mut self, data: u8) {
+        self.mirroring = match data {
+            0x44 => Mirroring::VERTICAL,
+            0x50 => Mirroring::HORIZONTAL,
+            0x00 => Mirroring::SINGLE_SCREEN_LOWER,
+            0x55 => Mirroring::SINGLE_SCREEN_UPPER,
+            // Mixed mappings and the fill mode nametable don't fit a Mirroring — keep the last one that did
+            _ => self.mirroring,
+        };
+    }
+}
+
+impl Mapper for Mmc5 {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        self.prg_rom[self.prg_offset(addr)]
+    }
+
+    fn cpu_write(&mut self, _addr: u16, _data: u8) {
+        // The registers all live in $5000-$5FFF
+    }
+
+    fn expansion_read(&self, addr: u16) -> Option<u8> {
+        match addr {
+            MMC5_EXRAM..=MMC5_EXRAM_END if self.exram_mode >= 2 => Some(self.exram[(addr - MMC5_EXRAM) as usize]),
+            _ => None,
+        }
+    }
+
+    fn expansion_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x5104 => { self.exram_mode = data & 0b11; },
+            0x5105 => { self.set_nametable_mapping(data); },
+            0x5114..=0x5117 => { self.prg_banks[(addr - 0x5114) as usize] = data; },
+            0x5120..=0x5127 => { self.chr_banks[(addr - 0x5120) as usize] = data; },
+            MMC5_EXRAM..=MMC5_EXRAM_END if self.exram_mode != 3 => {
+                self.exram[(addr - MMC5_EXRAM) as usize] = data;
+            },
+            _ => {}
+        }
+    }
+
+    // ExRAM takes pokes in every mode, even mode 3 where the CPU can only read it
+    fn cpu_poke(&mut self, addr: u16, data: u8) {
+        if let MMC5_EXRAM..=MMC5_EXRAM_END = addr {
+            self.exram[(addr - MMC5_EXRAM) as usize] = data;
+        }
+    }
+
+    fn extended_attribute(&self, nametable_addr: u16) -> Option<u8> {
+        if self.exram_mode == 1 {
+            Some(self.exram[(nametable_addr & 0x3FF) as usize] >> 6)
+        } else {
+            None
+        }
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr[self.chr_offset(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = data;
+        }
+    }
+
+    fn chr_writable(&self) -> bool {
+        self.chr_ram
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_window_size(&self) -> usize {
+        MMC5_PRG_WINDOW_SIZE
+    }
+
+    fn chr_window_size(&self) -> usize {
+        MMC5_CHR_WINDOW_SIZE
+    }
+
+    fn prg_len(&self) -> usize {
+        self.prg_rom.len()
+    }
+
+    fn chr_len(&self) -> usize {
+        self.chr.len()
+    }
+
+    fn prg_bank(&self, window: usize) -> usize {
+        (self.prg_banks[window] & 0b0111_1111) as usize
+    }
+
+    fn chr_bank(&self, window: usize) -> usize {
+        self.chr_banks[window] as usize
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.bytes(&self.prg_banks);
+        state.bytes(&self.chr_banks);
+        state.bytes(&self.exram);
+        state.u8(self.exram_mode);
+        state.u8(self.mirroring as u8);
+        if self.chr_ram {
+            state.bytes(&self.chr);
+        }
+
+        state.data
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut state = StateReader::new(data);
+
+        self.prg_banks.copy_from_slice(state.bytes(4)?);
+        self.chr_banks.copy_from_slice(state.bytes(8)?);
+        self.exram.copy_from_slice(state.bytes(MMC5_EXRAM_SIZE)?);
+        self.exram_mode = state.u8()?;
+        self.mirroring = Mirroring::from_state(state.u8()?);
+
+        if self.chr_ram {
+            let chr = state.bytes(self.chr.len())?;
+            self.chr.copy_from_slice(chr);
+        }
+
+        Ok(())
+    }
+}
+
+pub trait Mem {
+    fn mem_read(&mut self, addr: u16) -> u8;
+    fn mem_write(&mut self, addr: u16, data: u8);
+    fn mem_read_u16(&self, addr: u16) -> u16;
+    fn mem_write_u16(&mut self, pos: u16, data: u16);
+    fn peek(&self, addr: u16) -> u8; // Reads without side effects or ticking, for debuggers and cheats
+    fn poke(&mut self, addr: u16, data: u8); // Writes straight into memory without triggering register behavior
+}
+
+// What the CPU needs from its bus on top of memory accesses — clocking the rest of the system and the interrupt lines
+// The defaults fit a bus with nothing but memory on it (mocks in tests, tools wrapping a plain memory)
+pub trait Bus: Mem {
+    fn tick(&mut self) {} // Runs everything else on the bus for one CPU cycle
+    fn take_nmi(&mut self) -> bool { false } // Acknowledges a pending NMI, returning whether there was one
+    fn nmi_pending(&self) -> bool { false }
+    fn irq(&self) -> bool { false } // Whether anything on the bus is holding the IRQ line low
+    fn rdy(&self) -> bool { true } // RDY line — while it's low the CPU halts on its next read cycle
+    fn take_dma_request(&mut self) -> Option<u8> { None } // CPU page of a requested OAM DMA, cleared once taken
+    fn take_dmc_dma_request(&mut self) -> Option<u16> { None } // Address of a requested DMC sample fetch, cleared once taken
+    fn dmc_sample_fetched(&mut self, _sample: u8) {} // Hands the byte a DMC DMA read back to whatever asked for it
+    fn frame_count(&self) -> u64 { 0 }
+    fn ppu_position(&self) -> (u16, u16) { (0, 0) } // PPU (scanline, dots), for debugging raster timing
+}
+
+// Standard NES controller — an 8 bit shift register read one button at a time through $4016/$4017
+#[derive(Clone, Copy, Default)]
+pub struct Controller {
+    strobe: bool, // While set the shift register keeps reloading with the current button state
+    shift_register: u8, // Button state latched on the last strobe, shifted out bit by bit on reads
+    button_status: u8, // Buttons currently held down, one bit per button (see the BUTTON_* masks)
+    turbo_mask: u8, // Buttons that auto-fire while held down
+    turbo_rate: u8, // Press/release cycles per second for the turbo buttons
+    dpad_restriction: bool, // Cancels out Left+Right and Up+Down, which a real d-pad can't press together
+}
+
+impl Controller {
+    pub fn new() -> Self {
+        Controller {
+            strobe: false,
+            shift_register: 0,
+            button_status: 0,
+            turbo_mask: 0,
+            turbo_rate: 0,
+            dpad_restriction: false,
+        }
+    }
+
+    pub fn set_button_state(&mut self, button: u8, pressed: bool) {
+        if pressed {
+            self.button_status |= button;
+        } else {
+            self.button_status &= !button;
+        }
+    }
+
+    // Replaces every button at once — what a frame of keyboard, gamepad or scripted input sets
+    pub fn set_buttons(&mut self, buttons: u8) {
+        self.button_status = buttons;
+    }
+
+    pub fn buttons(&self) -> u8 {
+        self.button_status
+    }
+
+    // Makes the masked buttons toggle between pressed and released rate_hz times a second while held, on top of any
+    // buttons that already have turbo (they all share the latest rate) — a rate of 0 turns turbo off for the masked buttons
+    pub fn set_turbo(&mut self, button_mask: u8, rate_hz: u8) {
+        if rate_hz == 0 {
+            self.turbo_mask &= !button_mask;
+        } else {
+            self.turbo_mask |= button_mask;
+            self.turbo_rate = rate_hz;
+        }
+    }
+
+    // Off by default — keyboards happily press both, and some games glitch (or crash) when they see opposite directions
+    // together, so turning it on releases both directions of a pair whenever they're held at the same time
+    pub fn set_dpad_restriction(&mut self, enabled: bool) {
+        self.dpad_restriction = enabled;
+    }
+
+    // Returns the buttons as seen by the console on the given frame (turbo buttons are released every other half period)
+    fn effective_state(&self, frame: u64) -> u8 {
+        let mut state = self.button_status;
+
+        if self.turbo_mask != 0 {
+            let half_period = (FRAME_RATE / self.turbo_rate.saturating_mul(2)).max(1) as u64;
+
+            if (frame / half_period) % 2 == 1 {
+                state &= !self.turbo_mask;
+            }
+        }
+
+        if self.dpad_restriction {
+            for pair in [BUTTON_LEFT | BUTTON_RIGHT, BUTTON_UP | BUTTON_DOWN] {
+                if state & pair == pair {
+                    state &= !pair;
+                }
+            }
+        }
+
+        state
+    }
+
+    // Writes to $4016 — bit 0 high keeps reloading the shift register, going low latches the buttons for reading
+    fn write_strobe(&mut self, data: u8, frame: u64) {
+        self.strobe = data & 0b1 != 0;
+        self.shift_register = self.effective_state(frame);
+    }
+
+    // Returns the button the next read will see without shifting it out
+    fn peek(&self) -> u8 {
+        self.shift_register & 0b1
+    }
+
+    // Returns the next button in bit 0; after all eight buttons have been read an official controller returns 1
+    fn read(&mut self) -> u8 {
+        let button = self.peek();
+
+        if !self.strobe {
+            self.shift_register = (self.shift_register >> 1) | 0b1000_0000;
+        }
+
+        button
+    }
+}
+
+// NES Zapper light gun — a trigger and a photodiode that senses whether the spot it's aimed at is lit
+#[derive(Clone, Copy, Default)]
+pub struct Zapper {
+    aim: Option<(usize, usize)>, // Screen pixel the Zapper points at, None when it's aimed off screen
+    trigger: bool,
+    light: bool, // Whether the photodiode saw light — updated from the PPU just before each read
+}
+
+impl Zapper {
+    pub fn new() -> Self {
+        Zapper { aim: None, trigger: false, light: false }
+    }
+
+    pub fn set_aim(&mut self, aim: Option<(usize, usize)>) {
+        self.aim = aim;
+    }
+
+    pub fn set_trigger(&mut self, pulled: bool) {
+        self.trigger = pulled;
+    }
+
+    // Bit 3 reads 0 while light is sensed, bit 4 reads 1 while the trigger is pulled
+    fn read(&self) -> u8 {
+        let light = if self.light { 0 } else { 0b0000_1000 };
+        let trigger = if self.trigger { 0b0001_0000 } else { 0 };
+
+        light | trigger
+    }
+}
+
+// What is plugged into the controller ports
+// The Four Score multitap puts controllers 1 & 3 on $4016 and 2 & 4 on $4017, followed by a signature byte
+// The Zapper goes in port two, next to a normal controller in port one
+pub enum InputBackend {
+    Standard([Controller; 2]),
+    FourScore([Controller; 4]),
+    Zapper(Controller, Zapper),
+}
+
+impl InputBackend {
+    pub fn controllers_mut(&mut self) -> &mut [Controller] {
+        match self {
+            InputBackend::Standard(controllers) => controllers,
+            InputBackend::FourScore(controllers) => controllers,
+            InputBackend::Zapper(controller, _) => std::slice::from_mut(controller),
+        }
+    }
+
+    pub fn zapper_mut(&mut self) -> Option<&mut Zapper> {
+        match self {
+            InputBackend::Zapper(_, zapper) => Some(zapper),
+            _ => None
+        }
+    }
+
+    // Lets the Zapper look at what the PPU has drawn where it's aimed, right before the game reads it
+    fn sense_light(&mut self, ppu: &PPU) {
+        if let InputBackend::Zapper(_, zapper) = self {
+            zapper.light = zapper.aim.is_some_and(|(x, y)| ppu.light_at(x, y));
+        }
+    }
+
+    fn write_strobe(&mut self, data: u8, frame: u64) {
+        for controller in self.controllers_mut() {
+            controller.write_strobe(data, frame);
+        }
+    }
+
+    // Reads the next bit from a port, read_count being the number of reads from that port since the last strobe
+    fn read(&mut self, port: usize, read_count: u8) -> u8 {
+        match self {
+            InputBackend::Standard(controllers) => controllers[port].read(),
+
+            InputBackend::Zapper(controller, zapper) => if port == 0 { controller.read() } else { zapper.read() },
+
+            InputBackend::FourScore(controllers) => {
+                match read_count {
+                    0..=7 => controllers[port].read(),
+                    8..=15 => controllers[port + 2].read(),
+                    16..=23 => (FOUR_SCORE_SIGNATURES[port] >> (23 - read_count)) & 0b1,
+                    _ => 1,
+                }
+            }
+        }
+    }
+
+    // Same as read but nothing gets shifted
+    fn peek(&self, port: usize, read_count: u8) -> u8 {
+        match self {
+            InputBackend::Standard(controllers) => controllers[port].peek(),
+
+            InputBackend::Zapper(controller, zapper) => if port == 0 { controller.peek() } else { zapper.read() },
+
+            InputBackend::FourScore(controllers) => {
+                match read_count {
+                    0..=7 => controllers[port].peek(),
+                    8..=15 => controllers[port + 2].peek(),
+                    16..=23 => (FOUR_SCORE_SIGNATURES[port] >> (23 - read_count)) & 0b1,
+                    _ => 1,
+                }
+            }
+        }
+    }
+}
+
+// Scripted input — the buttons held on each controller, one entry per frame, for headless runs and movie playback
+// Goes through the same Controller::set_buttons the frontend uses for keys, so the game reads them out identically
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InputLog {
+    frames: Vec<[u8; 4]>, // Buttons for controllers 1-4 (only the first two are used without a Four Score)
+}
+
+impl InputLog {
+    pub fn new() -> Self {
+        InputLog { frames: vec![] }
+    }
+
+    // Adds a frame with controller one holding the given buttons and everything else released
+    pub fn push(&mut self, buttons: u8) {
+        self.frames.push([buttons, 0, 0, 0]);
+    }
+
+    pub fn push_controllers(&mut self, buttons: [u8; 4]) {
+        self.frames.push(buttons);
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    // Buttons for a frame, None once the log has run out
+    pub fn frame(&self, frame: usize) -> Option<[u8; 4]> {
+        self.frames.get(frame).copied()
+    }
+
+    // Sets every plugged in controller to what the log holds for the frame — past the end all buttons are released
+    pub fn apply(&self, frame: usize, input: &mut InputBackend) {
+        let buttons = self.frame(frame).unwrap_or([0; 4]);
+
+        for (controller, buttons) in input.controllers_mut().iter_mut().zip(buttons) {
+            controller.set_buttons(buttons);
+        }
+    }
+}
+
+// One CPU access to $2000-$2007, recorded when the PPU access log is turned on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PpuAccess {
+    pub addr: u16, // Register address, mirrored down to $2000-$2007
+    pub value: u8, // Byte written, or the byte the CPU got back
+    pub write: bool,
+    pub scanline: u16, // Where the PPU was when the access happened
+    pub dot: u16,
+    pub v: u16, // v and t after the access
+    pub t: u16,
+}
+
+// A write to $8000-$FFFF that disagreed with the ROM byte under it — games that write to a bus conflict board have to
+// write a byte the ROM already holds, so these are usually bugs that only show up on real hardware (or on boards where
+// the mapper doesn't mask the value, where they hide entirely)
+#[cfg(feature = "bus-conflicts")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BusConflict {
+    pub addr: u16,
+    pub written: u8, // What the CPU put on the bus
+    pub rom: u8, // What the ROM put on the bus at the same time
+    pub latched: u8, // What the mapper ended up with (written ANDed with rom, or just written if the board has no conflicts)
+}
+
+#[cfg(feature = "bus-conflicts")]
+impl std::fmt::Display for BusConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Bus conflict: wrote {:02X} to {:04X} over ROM byte {:02X}, mapper latched {:02X}", self.written, self.addr, self.rom, self.latched)
+    }
+}
+
+pub struct CPUBus {
+    cpu_ram: [u8; (0xFFFF + 1) as usize],
+    mapper: Rc<RefCell<dyn Mapper>>, // The cartridge — program ROM and any bank switching hardware
+    oam_dma_pending: bool, // $4014 was written — the OAM DMA unit pulls RDY low until the CPU halts and hands it the bus
+    rdy_hold_cycles: u32, // CPU cycles left that something else (mapper, DMC, tests) holds RDY low for
+    dmc_dma: Option<u16>, // Address of a DMC sample fetch waiting on the CPU to halt (RDY is low until it's taken)
+    dmc_sample: Option<u8>, // Byte the last DMC DMA fetched, until it's collected
+    pub ppu: PPU, // Connecting the PPU to the CPU Bus
+    pub apu: APU, // Connecting the APU to the CPU Bus
+    pub input: InputBackend, // Controllers plugged into $4016 and $4017
+    input_reads: [u8; 2], // Reads from each controller port since the last strobe
+    open_bus: u16, // Last value on the data bus — what reads from an area with no memory map (and undriven register bits) see
+    ppu_latch: u8, // The latch is loaded when a value is read/written to the PPU, extracted when read from a write-only latch
+    ppu_access_log: Option<Vec<PpuAccess>>, // Every PPU register access while logging is on (None when it's off)
+    flat_memory: bool, // Testing mode where all 64KB is plain RAM — no mirroring, registers or cartridge
+    system: System,
+    vs_dip_switches: u8, // VS System DIP switches 1-8 (bit 0 is switch 1), read back through $4016/$4017
+    vs_coins: u8, // Coin slots 1 and 2 (bits 0 and 1) while a coin is going through
+    vs_service: bool, // The cabinet's service button
+    bios: Option<Vec<u8>>, // Boot ROM mapped over the top of $8000-$FFFF, in front of the cartridge (None on a plain NES)
+    #[cfg(feature = "bus-conflicts")]
+    bus_conflict_count: u64, // Writes to ROM space whose value got changed by the ROM byte under it
+    #[cfg(feature = "bus-conflicts")]
+    on_bus_conflict: Option<Box<dyn FnMut(BusConflict)>>, // Told about each conflict (None prints them instead)
+}
+
+pub struct PPUBus {
+    mapper: Rc<RefCell<dyn Mapper>>, // The cartridge — character ROM/RAM, also determines the nametable mirroring
+    vram: [u8; 4096], // Used to lay out the background — the console has 2KB, four screen cartridges add the other 2KB
+    palette_mem: [u8; 32], // Holds the background colors (low 16 bytes) and sprite colors (high 16 bytes)
+    palette_storage: Vec<u8>, // Holds 512 3 byte values, but we really only access the first 64
+    cycle: u64, // PPU dots since power on, to time how long A12 has been low
+    a12_high: bool, // Level of address line A12 on the last access
+    a12_low_since: u64, // Dot A12 last went low
+    illegal_writes: u64, // Writes to CHR ROM or past $3FFF, which the PPU drops
+    on_illegal_ppu_write: Option<Box<dyn FnMut(u16, u8)>>, // Told the address and data of each dropped write (None when nobody's listening)
+}
+
+impl CPUBus {
+    pub fn new(mapper: Rc<RefCell<dyn Mapper>>, ppu_connection: PPU) -> Self {
+        CPUBus {
+            cpu_ram: [0; (0xFFFF + 1) as usize],
+            mapper,
+            oam_dma_pending: false,
+            rdy_hold_cycles: 0,
+            dmc_dma: None,
+            dmc_sample: None,
+            ppu: ppu_connection,
+            apu: APU::new(),
+            input: InputBackend::Standard([Controller::new(); 2]),
+            input_reads: [0; 2],
+            open_bus: 0,
+            ppu_latch: 0,
+            ppu_access_log: None,
+            flat_memory: false,
+            system: System::Nes,
+            vs_dip_switches: 0,
+            vs_coins: 0,
+            vs_service: false,
+            bios: None,
+            #[cfg(feature = "bus-conflicts")]
+            bus_conflict_count: 0,
+            #[cfg(feature = "bus-conflicts")]
+            on_bus_conflict: None,
+        }
+    }
+
+    // Switches between the NES and VS System wiring — the PPU's palette and what the controller ports' upper bits hold
+    pub fn set_system(&mut self, system: System) {
+        self.system = system;
+        self.ppu.set_system(system);
+    }
+
+    pub fn system(&self) -> System {
+        self.system
+    }
+
+    // Games read their settings (difficulty, lives, coins per credit) from these, so they're left up to the player
+    pub fn set_vs_dip_switches(&mut self, switches: u8) {
+        self.vs_dip_switches = switches;
+    }
+
+    // Slot is 0 or 1 — the game counts a credit when it sees a coin go from inserted back to not
+    pub fn set_vs_coin(&mut self, slot: usize, inserted: bool) {
+        let bit = 1 << (slot & 1);
+
+        if inserted { self.vs_coins |= bit } else { self.vs_coins &= !bit }
+    }
+
+    pub fn set_vs_service(&mut self, pressed: bool) {
+        self.vs_service = pressed;
+    }
+
+    // Bits 1-7 of a controller port read — open bus on the NES, while a VS System drives the service button, DIP
+    // switches 1-2 and the coin slots on $4016 (bits 2-6) and DIP switches 3-8 on $4017 (bits 2-7)
+    fn controller_upper_bits(&self, port: usize) -> u8 {
+        match (self.system, port) {
+            (System::Nes, _) => self.open_bus as u8 & CONTROLLER_OPEN_BUS,
+            (System::Vs, 0) => {
+                (self.vs_service as u8) << 2 | (self.vs_dip_switches & 0b11) << 3 | (self.vs_coins & 0b11) << 5
+                    | (self.open_bus as u8 & 0b1000_0000)
+            },
+            (System::Vs, _) => self.vs_dip_switches & 0b1111_1100,
+        }
+    }
+
+    // Writes to ROM space that disagreed with the ROM byte there, since power on
+    #[cfg(feature = "bus-conflicts")]
+    pub fn bus_conflicts(&self) -> u64 {
+        self.bus_conflict_count
+    }
+
+    // Calls back with every conflict counted by bus_conflicts instead of printing it — None goes back to printing
+    #[cfg(feature = "bus-conflicts")]
+    pub fn set_on_bus_conflict(&mut self, callback: Option<Box<dyn FnMut(BusConflict)>>) {
+        self.on_bus_conflict = callback;
+    }
+
+    #[cfg(feature = "bus-conflicts")]
+    fn report_bus_conflict(&mut self, conflict: BusConflict) {
+        self.bus_conflict_count += 1;
+
+        match self.on_bus_conflict.as_mut() {
+            Some(callback) => callback(conflict),
+            None => println!("{}", conflict),
+        }
+    }
+
+    pub fn read_prg_rom(&self, addr: &u16) -> u8 {
+        if let Some(bios) = self.bios.as_ref() {
+            // The BIOS sits at the top of the address space (an 8KB FDS BIOS covers $E000-$FFFF)
+            let start = 0x10000 - bios.len();
+            if *addr as usize >= start {
+                return bios[*addr as usize - start]
+            }
+        }
+
+        self.mapper.borrow().cpu_read(*addr)
+    }
+
+    // Pulls RDY low for the next given number of CPU cycles (a longer hold already in progress isn't cut short)
+    // The CPU only stops on read cycles, so writes that fall in the hold still happen
+    pub fn hold_rdy(&mut self, cycles: u32) {
+        self.rdy_hold_cycles = self.rdy_hold_cycles.max(cycles);
+    }
+
+    // Asks for a DMC sample fetch from the given address — the DMA unit halts the CPU on its next read and takes the bus
+    pub fn request_dmc_dma(&mut self, sample_addr: u16) {
+        self.dmc_dma = Some(sample_addr);
+    }
+
+    // The byte the last DMC DMA read, None if there hasn't been one since the last call
+    pub fn take_dmc_sample(&mut self) -> Option<u8> {
+        self.dmc_sample.take()
+    }
+
+    // Only the 2KB of work RAM is saved (all 64KB with flat memory) — the controllers belong to the frontend, so they aren't
+    fn save_state(&self, state: &mut StateWriter) {
+        let ram_size = if self.flat_memory { self.cpu_ram.len() } else { 0x800 };
+
+        state.section(&self.cpu_ram[..ram_size]);
+        state.bool(self.oam_dma_pending);
+        state.bytes(&self.input_reads);
+        state.u16(self.open_bus);
+        state.u8(self.ppu_latch);
+        state.u32(self.rdy_hold_cycles); // 1.1
+        state.bool(self.dmc_dma.is_some()); // 1.4
+        state.u16(self.dmc_dma.unwrap_or(0));
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let ram = state.section()?;
+        let ram_size = ram.len().min(self.cpu_ram.len());
+        self.cpu_ram[..ram_size].copy_from_slice(&ram[..ram_size]);
+
+        self.oam_dma_pending = state.bool()?;
+        self.input_reads.copy_from_slice(state.bytes(2)?);
+        self.open_bus = state.u16()?;
+        self.ppu_latch = state.u8()?;
+        self.rdy_hold_cycles = if state.at_end() { 0 } else { state.u32()? };
+
+        self.dmc_dma = None;
+        if !state.at_end() {
+            let pending = state.bool()?;
+            let sample_addr = state.u16()?;
+            self.dmc_dma = pending.then_some(sample_addr);
+        }
+
+        Ok(())
+    }
+
+    // Starts (or stops and throws away) the PPU register access log — off by default since it grows every access
+    pub fn set_ppu_access_logging(&mut self, enabled: bool) {
+        self.ppu_access_log = if enabled { Some(vec![]) } else { None };
+    }
+
+    pub fn ppu_access_log(&self) -> &[PpuAccess] {
+        self.ppu_access_log.as_deref().unwrap_or(&[])
+    }
+
+    // Hands back the accesses logged so far and starts a fresh log
+    pub fn take_ppu_access_log(&mut self) -> Vec<PpuAccess> {
+        match self.ppu_access_log.as_mut() {
+            Some(log) => std::mem::take(log),
+            None => vec![]
+        }
+    }
+
+    fn log_ppu_access(&mut self, addr: u16, value: u8, write: bool) {
+        if let Some(log) = self.ppu_access_log.as_mut() {
+            log.push(PpuAccess {
+                addr,
+                value,
+                write,
+                scanline: self.ppu.state.scanline,
+                dot: self.ppu.state.dots,
+                v: self.ppu.v,
+                t: self.ppu.t,
+            });
+        }
+    }
+
+    // $2000-$2007 reads, addr is already mirrored down
+    fn read_ppu_register(&mut self, mirrored_addr: u16) -> u8 {
+        match mirrored_addr {
+            // Returns the PPU status register and resets the write latch (w register)
+            // Only bits 5-7 are driven — bits 0-4 are open bus, whatever the latch still holds from the last PPU access
+            0x2002 => {
+                let status = (self.ppu.read_status() & 0b1110_0000) | (self.ppu_latch & 0b1_1111);
+                self.ppu_latch = status;
+
+                return status
+            }
+
+            // Returns a byte from OAM (or whatever sprite evaluation is doing when rendering)
+            0x2004 => {
+                let return_value = self.ppu.oam_data_read();
+                self.ppu_latch = return_value;
+
+                return return_value
+            }
+
+            // Sets the vram latch to a byte from VRAM
+            // If the address is from palette memory it is instantly returned
+            0x2007 => {
+                let return_value;
+                if (self.ppu.v <= 0x3FFF) & (self.ppu.v >= 0x3F00) {
+                    return_value = self.ppu.read_byte(self.ppu.v);
+                    // Still sets the ppu latch to the value "underneath" — the nametable mirror at $2F00-$2FFF
+                    self.ppu.vram_latch = self.ppu.read_byte(self.ppu.v & 0x2FFF);
+                } else {
+                    return_value = self.ppu.vram_latch;
+                    self.ppu.vram_latch = self.ppu.read_byte(self.ppu.v);
+                }
+
+                self.ppu.increment_vram();
+
+                return return_value
+            },
+
+            _ => { self.ppu_latch }
+        }
+    }
+
+    fn write_ppu_register(&mut self, mirrored_addr: u16, data: u8) {
+        // Right after power on these registers ignore writes (the value still lands on the latch)
+        if !self.ppu.warmed_up() && matches!(mirrored_addr, 0x2000 | 0x2001 | 0x2005 | 0x2006) {
+            self.ppu_latch = data;
+            return
+        }
+
+        match mirrored_addr {
+            // Sets the control register of the PPU
+            0x2000 => {
+                self.ppu.write_ctrl(data);
+                self.ppu_latch = data;
+            },
+
+            // Writes to the ppu mask register
+            0x2001 => {
+                self.ppu.mask = data;
+                self.ppu_latch = data;
+            }
+
+            // Sets open bus
+            0x2002 => {
+                self.ppu_latch = data;
+            }
+
+            // Writes an OAM address
+            0x2003 => {
+                self.ppu.oam_addr = data;
+
+                self.ppu_latch = data;
+            }
+
+            // Writes to OAM and increments the OAM adder - very dangerous (normally) due to not finishing during VBLANK
+            0x2004 => {
+                self.ppu.oam_data = data;
+                self.ppu.oam_data_set();
+                self.ppu.oam_addr = self.ppu.oam_addr.wrapping_add(1);
+
+                self.ppu_latch = data;
+            }
+
+            // Loads the scroll register with the scroll data
+            0x2005 => {
+                self.ppu.load_scroll(data);
+
+                self.ppu_latch = data;
+            }
+
+            // Loads the given byte into either the high or low position depending on the w register
+            0x2006 => {
+                self.ppu.load_addr_byte(data as u16);
+
+                self.ppu_latch = data;
+            },
+
+            // Sets the value of the data register and moves vram forward
+            0x2007 => {
+                self.ppu.cpu_write_byte(data);
+
+                self.ppu_latch = data;
+            }
+
+            _ => { self.ppu_latch = data; }
+        }
+    }
+}
+
+impl PPUBus {
+    pub fn new(mapper: Rc<RefCell<dyn Mapper>>, palette_mem: [u8; 32], palette_storage: Vec<u8>) -> Self {
+        PPUBus { 
+            mapper,
+            vram: [0; 4096], 
+            palette_mem: palette_mem,
+            palette_storage,
+            cycle: 0,
+            a12_high: false,
+            a12_low_since: 0,
+            illegal_writes: 0,
+            on_illegal_ppu_write: None,
+        }
+    }
+
+    // Counts a write the PPU has nowhere to put — some games do it by accident, which is worth knowing about but not crashing over
+    fn illegal_write(&mut self, addr: u16, data: u8) {
+        self.illegal_writes += 1;
+
+        if let Some(callback) = self.on_illegal_ppu_write.as_mut() {
+            callback(addr, data);
+        }
+    }
+
+    // Every address the PPU puts on its bus goes through here so the mapper sees A12 rise
+    // Like the MMC3's M2 filter, a rise only counts once A12 has been low for a few CPU cycles — otherwise the nametable
+    // fetches between sprite pattern fetches would clock the counter eight times a scanline instead of once
+    fn watch_a12(&mut self, addr: u16) {
+        let high = addr & 0x1000 != 0;
+
+        if high && !self.a12_high && self.cycle - self.a12_low_since >= A12_FILTER_DOTS {
+            self.mapper.borrow_mut().on_ppu_a12_rise();
+        } else if !high && self.a12_high {
+            self.a12_low_since = self.cycle;
+        }
+
+        self.a12_high = high;
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mapper.borrow().mirroring()
+    }
+
+    fn extended_attribute(&self, addr: u16) -> Option<u8> {
+        self.mapper.borrow().extended_attribute(addr)
+    }
+
+    // Index into palette_mem — $3F10/$3F14/$3F18/$3F1C are mirrors of the background entries at $3F00/$3F04/$3F08/$3F0C
+    fn palette_index(addr: u16) -> usize {
+        let index = ((addr - PALETTE_RAM_BEGIN) as usize) % NUM_PALETTE_REGISTERS;
+
+        if index >= 0x10 && index.is_multiple_of(4) {
+            index - 0x10
+        } else {
+            index
+        }
+    }
+
+    // Maps a nametable address onto VRAM — each of the four nametables is mirrored onto one of the 1KB VRAM pages
+    fn mirror_nametable_addr(&self, addr: u16) -> u16 {
+        let base_addr: u16 = addr & (NAME_TABLE_SIZE - 1);
+        let nametable = (addr & 0x0FFF) / NAME_TABLE_SIZE;
+
+        let page = match self.mirroring() {
+            Mirroring::HORIZONTAL => nametable / 2,
+            Mirroring::VERTICAL => nametable % 2,
+            Mirroring::SINGLE_SCREEN_LOWER => 0,
+            Mirroring::SINGLE_SCREEN_UPPER => 1,
+            Mirroring::FOUR_SCREEN => nametable,
+        };
+
+        base_addr + page * NAME_TABLE_SIZE
+    }
+}
+
+impl Mem for CPUBus {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        if self.flat_memory {
+            return self.cpu_ram[addr as usize]
+        }
+
+        let value = match addr {
+            RAM..=RAM_MIRRORS_END => {
+                let mirrored_addr = addr & 0x07FF; // 0x07FF
+                self.cpu_ram[mirrored_addr as usize]
+            },
+
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
+                let mirrored_addr = addr & 0x2007;
+                let value = self.read_ppu_register(mirrored_addr);
+                self.log_ppu_access(mirrored_addr, value, false);
+
+                value
+            },
+
+            CONTROLLER_ONE | CONTROLLER_TWO => {
+                let port = (addr - CONTROLLER_ONE) as usize;
+                self.input.sense_light(&self.ppu);
+                let bit = self.input.read(port, self.input_reads[port]);
+                self.input_reads[port] = self.input_reads[port].saturating_add(1);
+
+                bit | self.controller_upper_bits(port)
+            },
+
+            // $4015 is read inside the CPU so it never reaches the data bus — the undriven bit 5 is whatever was last on it
+            APU_STATUS => {
+                return (self.apu.read_status() & !0b0010_0000) | (self.open_bus as u8 & 0b0010_0000)
+            },
+
+            APU_IO_REGISTERS..=APU_IO_REGISTERS_END => {
+                // The rest of the APU registers are write only
+                0
+            },
+
+            CARTRIDGE_EXPANSION..=CARTRIDGE_EXPANSION_END => {
+                self.mapper.borrow().expansion_read(addr).unwrap_or(self.open_bus as u8)
+            },
+
+            0x8000..=0xFFFF => { self.read_prg_rom(&addr) },
+            
+            _ => { self.open_bus as u8 }
+        };
+
+        self.open_bus = value as u16;
+        value
+    }
+
+    // The 6502 never reads 16 bits at once, so this is just two side-effect free byte reads (little endian)
+    // The CPU itself uses fetch_word, which goes through read_byte so register side effects and cycles are counted
+    fn mem_read_u16(&self, addr: u16) -> u16 {
+        (self.peek(addr.wrapping_add(1)) as u16) << 8 | self.peek(addr) as u16
+    }
+
+    fn mem_write(&mut self, addr: u16, data: u8) {
+        //println!("write addr: {}", addr);
+
+        if self.flat_memory {
+            self.cpu_ram[addr as usize] = data;
+            return
+        }
+
+        self.open_bus = data as u16;
+
+        match addr {
+            RAM..=RAM_MIRRORS_END => {
+                let unmirrored_addr = addr & 0x07FF;
+                self.cpu_ram[unmirrored_addr as usize] = data;
+            },
+
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
+                let mirrored_addr = addr & 0x2007;
+                self.write_ppu_register(mirrored_addr, data);
+                self.log_ppu_access(mirrored_addr, data, true);
+            },
+
+            // Strobes every controller at once
+            CONTROLLER_ONE => {
+                self.input.write_strobe(data, self.ppu.frame_count);
+                self.input_reads = [0; 2];
+            },
+
+            // OAM DMA Call... pretty tricky to get right
+            // The CPU halts on its next read cycle and copies the whole page into OAM (see CPU::execute_oam_dma)
+            OAM_DMA => {
+                self.ppu.oam_dma = data;
+                self.oam_dma_pending = true;
+            },
+
+            APU_IO_REGISTERS..=APU_IO_REGISTERS_END => {
+                self.apu.write_register(addr, data);
+            },
+
+            CARTRIDGE_EXPANSION..=CARTRIDGE_EXPANSION_END => { self.mapper.borrow_mut().expansion_write(addr, data); },
+
+            // Writes to ROM space go to the mapper's registers — on boards with bus conflicts the ROM drives the bus at
+            // the same time, and since either chip can pull a line low the register only sees the bits both agree on
+            0x8000..=0xFFFF => {
+                let (rom, conflicts) = {
+                    let mapper = self.mapper.borrow();
+                    (mapper.cpu_read(addr), mapper.bus_conflicts())
+                };
+                let latched = if conflicts { data & rom } else { data };
+
+                #[cfg(feature = "bus-conflicts")]
+                if data & rom != data {
+                    self.report_bus_conflict(BusConflict { addr, written: data, rom, latched });
+                }
+
+                self.mapper.borrow_mut().cpu_write(addr, latched);
+            },
+
+            _ => {}
+        }
+    }
+
+    fn mem_write_u16(&mut self, addr: u16, data: u16) {
+        self.mem_write(addr, (data & 0x00FF) as u8);
+        self.mem_write(addr.wrapping_add(1), (data >> 8) as u8);
+    }
+
+    // Reads what the CPU would see at an address without any of the side effects (for debuggers)
+    // Nothing is ticked, $2002 doesn't clear VBlank, $2007 doesn't move VRAM, controllers don't shift, etc.
+    fn peek(&self, addr: u16) -> u8 {
+        if self.flat_memory {
+            return self.cpu_ram[addr as usize]
+        }
+
+        match addr {
+            RAM..=RAM_MIRRORS_END => { self.cpu_ram[(addr & 0x07FF) as usize] },
+
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
+                match addr & 0x2007 {
+                    0x2002 => { (self.ppu.status & 0b1110_0000) | (self.ppu_latch & 0b1_1111) },
+                    0x2004 => { self.ppu.oam_data_read() },
+                    0x2007 => { self.ppu.vram_latch },
+                    _ => { self.ppu_latch }
+                }
+            },
+
+            CONTROLLER_ONE | CONTROLLER_TWO => {
+                let port = (addr - CONTROLLER_ONE) as usize;
+                self.input.peek(port, self.input_reads[port]) | self.controller_upper_bits(port)
+            },
+
+            APU_STATUS => { (self.apu.status() & !0b0010_0000) | (self.open_bus as u8 & 0b0010_0000) },
+
+            APU_IO_REGISTERS..=APU_IO_REGISTERS_END => { 0 },
+
+            CARTRIDGE_EXPANSION..=CARTRIDGE_EXPANSION_END => {
+                self.mapper.borrow().expansion_read(addr).unwrap_or(self.open_bus as u8)
+            },
+
+            0x8000..=0xFFFF => { self.read_prg_rom(&addr) },
+
+            _ => { self.open_bus as u8 }
+        }
+    }
+
+    // Only RAM (the console's, and whatever the cartridge has) is poked — registers and ROM are left alone since there's
+    // no way to store into them without side effects
+    fn poke(&mut self, addr: u16, data: u8) {
+        if self.flat_memory {
+            self.cpu_ram[addr as usize] = data;
+            return
+        }
+
+        match addr {
+            RAM..=RAM_MIRRORS_END => { self.cpu_ram[(addr & 0x07FF) as usize] = data; },
+            CARTRIDGE_EXPANSION..=0xFFFF => { self.mapper.borrow_mut().cpu_poke(addr, data); },
+            _ => {}
+        }
+    }
+}
+
+impl Bus for CPUBus {
+    // 1 CPU cycle = 1 APU cycle = 3 PPU cycles
+    fn tick(&mut self) {
+        self.rdy_hold_cycles = self.rdy_hold_cycles.saturating_sub(1);
+        self.apu.tick();
+
+        for _ in 0..=2 {
+            self.ppu.ppu_tick();
+        }
+    }
+
+    fn take_nmi(&mut self) -> bool {
+        let pending = self.ppu.nmi == 1;
+        self.ppu.nmi = 0;
+
+        pending
+    }
+
+    fn nmi_pending(&self) -> bool {
+        self.ppu.nmi == 1
+    }
+
+    fn irq(&self) -> bool {
+        self.apu.frame_irq()
+    }
+
+    // RDY is wired-AND — anything can pull it low
+    fn rdy(&self) -> bool {
+        !self.oam_dma_pending && self.dmc_dma.is_none() && self.rdy_hold_cycles == 0
+    }
+
+    fn take_dma_request(&mut self) -> Option<u8> {
+        if self.oam_dma_pending {
+            self.oam_dma_pending = false;
+            return Some(self.ppu.oam_dma)
+        }
+
+        None
+    }
+
+    fn take_dmc_dma_request(&mut self) -> Option<u16> {
+        self.dmc_dma.take()
+    }
+
+    fn dmc_sample_fetched(&mut self, sample: u8) {
+        self.dmc_sample = Some(sample);
+    }
+
+    fn frame_count(&self) -> u64 {
+        self.ppu.frame_count()
+    }
+
+    fn ppu_position(&self) -> (u16, u16) {
+        (self.ppu.state.scanline, self.ppu.state.dots)
+    }
+}
+
+impl Mem for PPUBus {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        self.watch_a12(addr);
+        self.peek(addr)
+    }
+
+    fn mem_read_u16(&self, addr: u16) -> u16 {
+        (self.peek(addr.wrapping_add(1)) as u16) << 8 | self.peek(addr) as u16
+    }
+
+    fn mem_write_u16(&mut self, addr: u16, data: u16) {
+        self.mem_write(addr, (data & 0x00FF) as u8);
+        self.mem_write(addr.wrapping_add(1), (data >> 8) as u8);
+    }
+
+    // Nothing on the PPU bus has read side effects — those live in the $2007 buffer on the PPU itself
+    fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            PATTERN_TABLES_BEGIN..=PATTERN_TABLES_END => {
+                self.mapper.borrow().ppu_read(addr)
+            },
+
+            NAME_TABLES_BEGIN..=NAME_TABLE_MIRRORS_END => {
+                return self.vram[self.mirror_nametable_addr(addr) as usize]
+            },
+
+            // Palette RAM holds 6 bit color indexes — the PPU turns those into RGB through palette_storage (see color_for_index)
+            PALETTE_RAM_BEGIN..=PALETTE_RAM_END => {
+                self.palette_mem[PPUBus::palette_index(addr)] & 0b0011_1111
+            }
+
+            // Open bus behavior — multiplexed with pins 31-38, accesses the low byte of the address
+            _ => { (addr & 0b1111_1111) as u8 }
+        }
+    }
+
+    fn mem_write(&mut self, addr: u16, data: u8) {
+        self.watch_a12(addr);
+
+        // Pattern table writes only land if the cartridge has CHR RAM (the mapper drops writes to CHR ROM), and there's
+        // nothing past $3FFF
+        let dropped = match addr {
+            PATTERN_TABLES_BEGIN..=PATTERN_TABLES_END => !self.mapper.borrow().chr_writable(),
+            NAME_TABLES_BEGIN..=PALETTE_RAM_END => false,
+            _ => true
+        };
+
+        if dropped {
+            self.illegal_write(addr, data);
+        }
+
+        self.poke(addr, data);
+    }
+
+    // Stores straight into CHR, VRAM or palette RAM — A12 isn't watched (so the mapper's scanline counter doesn't see it)
+    // and dropped writes aren't counted as illegal
+    fn poke(&mut self, addr: u16, data: u8) {
+        match addr {
+            PATTERN_TABLES_BEGIN..=PATTERN_TABLES_END => {
+                self.mapper.borrow_mut().ppu_write(addr, data);
+            },
+
+            NAME_TABLES_BEGIN..=NAME_TABLE_MIRRORS_END => {
+                let vram_index = self.mirror_nametable_addr(addr);
+                self.vram[vram_index as usize] = data;
+            },
 
             PALETTE_RAM_BEGIN..=PALETTE_RAM_END => {
-                let mirrored_addr = (addr - PALETTE_RAM_BEGIN) & (NUM_PALETTE_REGISTERS - 1) as u16;
+                self.palette_mem[PPUBus::palette_index(addr)] = data & 0b0011_1111;
+            }
+
+            _ => {}
+        }
+    }
+}
+
+
+// PPU struct to hold registers and the PPUBus
+// All of the registers have extensive documentation available on the NESDev wiki... they are far too complicated to fully describe here
+pub struct PPU {
+    oam: [u8; 256], // Object Attribute Memory, used to store sprites
+    secondary_oam: Vec<u8>, // Buffer for the sprites currently being rendered on the *next* scanline (4 bytes per sprite up to the limit)
+    ctrl: u8, // > write - Important flags that control PPU operation
+    mask: u8, // > write - Handles different rendering states/color effects
+    status: u8, // < read - Reflects the current state of the PPU (useful for timing)
+    oam_addr: u8, // > write - CPU writes the address of OAM it wants to access here. Always returns to 0 after a frame
+    oam_data: u8, // <> read/write - (Writes increment oam_addr, reads do not). ONLY USE DURING VBLANK else corruption
+    scroll: u8, // >> write x2 - Tells PPU which pixel from nametable should be in top left - first is x scroll, second is y scroll
+    oam_dma: u8, // > write - Stores the page number of the CPU to start transferring data to OAM
+    v: u16, // During rendering, used for the scroll position. Outside of rendering, used as the current VRAM address
+    t: u16, // During rendering, specifies the starting coarse-x scroll and startying y scroll. Outside, holds scroll/VRAM addr --> v
+    x: u8, // The fine-x position of the current scroll, used during rendering alongside v
+    w: u8, // Indicates first or second write for PPUSCROLL or PPUADDR. Clears on reads of PPUSTATUS
+    low_pttrn_shift_reg: u16, // A group of shift registers, used to serialize bits for rendering
+    high_pttrn_shift_reg: u16,
+    low_attr_shift_reg: u8,
+    high_attr_shift_reg: u8,
+    ppu_latch: u8, // Serves as an address latch — the low 8 bits overlap with the data bus
+    vram_latch: u8, // Used to store read values when the PPU reads 0x2007
+    nmi: u8,
+    nmi_delay: u8, // Dots from VBlank being set to the NMI being raised
+    nmi_countdown: Option<u8>, // Dots left before a pending VBlank NMI is raised
+    vblank_suppressed: bool, // $2002 was read on the dot before VBlank starts, so the flag (and NMI) is skipped this frame
+    color_buffer: [u32; SCREEN_WIDTH * SCREEN_HEIGHT], // Stores the rgb colors of each pixel displayed each frame
+    frame_buffer: [u32; SCREEN_WIDTH * SCREEN_HEIGHT], // Copy of the last finished frame (color_buffer is cleared once it's sent out)
+    pub state: PpuState, // Keeps the PPU state when alternating between the CPU and PPU
+    sprite_y: u8,
+    sprite_tile_number: u8,
+    sprite_attribute: u8,
+    sprite_x: u8,
+    back_pixel: u8, // Variable to store the generated background pixel every 8 cycles
+    sprite_pixel_buffer: Vec<Sprite>, // Variable to store the generated sprite pixel from OAM (eight pixels for each sprite up to the limit)
+    sprite_limit: Option<u8>, // Sprites shown per scanline — 8 on hardware, None shows every sprite (no flicker)
+    pixel: u8, // Stores the current selected pixel between the background and sprite pixel
+    debug_layers: bool, // Draws each layer in a solid tint (see PixelLayer::debug_tint) to make priority bugs stand out
+    system: System, // VS System PPUs use VS_PALETTE instead of the .pal file
+    window: Option<minifb::Window>, // Window to display pixels to, None when running headless (e.g. tests). FINALLY!!! Feels so good having made it this far I love this project so much :)
+    oam_addr_overflow: bool,
+    frame_count: u64, // Number of frames finished since power on
+    present_frames: bool, // Finished frames go to the window — turned off for the frames frame skip leaves out
+    presented_count: u64, // Number of frames sent to the window since power on (counted headless too)
+    nmi_count: u64, // Number of NMIs raised since power on
+    warmed_up: bool, // Writes to PPUCTRL/PPUMASK/PPUSCROLL/PPUADDR are ignored until the first pre-render scanline (~29658 CPU cycles)
+    palette_problems: Vec<PaletteError>, // What was wrong with the .pal file the PPU was built with
+    ppu_bus: PPUBus // Bus to communicate with PPU memory like VRAM and the palette memory
+}
+
+#[derive(Default)]
+pub struct PpuState {
+    nametable_data: u8, // These four are just state registers
+    attribute_data: u8,
+    low_bitplane: u8,
+    high_bitplane: u8,
+    attribute_latch: u8, // Used to store the current attribute byte being shifted in
+    pub scanline: u16, // These two store the current scanline and dot (cycle) data
+    pub dots: u16,
+    sprite_counter: u8, // Tracks how many sprites have been found for the current scanline
+    valid_sprite: bool, // Tracks if the sprite detected is valid and the program should fetch the rest of its data
+    secondary_oam_addr: u16, // Tracks the current address in secondary_oam
+    sprite_addr: u8, // Address for indexing the bytes after the y address for OAM
+    sprite_zero_next: bool, // Sprite 0 was copied into secondary OAM, so it's in slot 0 on the next scanline
+    even_odd_frame: bool // Tracks whether the frame is even or odd (if even skip the very first cycle of every frame); true if even
+}
+
+// Where the pixel the PPU outputs came from — sprite pixels behind an opaque background pixel count as background
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PixelLayer {
+    Backdrop, // Both layers transparent (or rendering off), so it's the universal background color
+    Background,
+    SpriteFront,
+    SpriteBack, // A behind-background sprite showing through a transparent background pixel
+}
+
+impl PixelLayer {
+    fn debug_tint(self) -> (u8, u8, u8) {
+        match self {
+            PixelLayer::Backdrop => (0, 0, 0),
+            PixelLayer::Background => DEBUG_TINT_BACKGROUND,
+            PixelLayer::SpriteFront => DEBUG_TINT_SPRITE_FRONT,
+            PixelLayer::SpriteBack => DEBUG_TINT_SPRITE_BACK,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct Sprite {
+    x_coordinate: u8, // X coordinate of the sprite on the next scanline
+    pixel: u8, // Four bit pixel to encode pattern table and attribute table
+    priority: u8, // 0 if in front of background; 1 if behind background
+    horizontal_flip: u8, // 1 if sprite needs to be flipped horizontally
+    vertical_flip: u8, // 1 if sprite needs to be flipped vertically
+    sprite_zero: bool, // Pixel belongs to sprite 0, which sets the sprite zero hit flag when it overlaps the background
+}
+
+impl PpuState {
+    // Power on — scanline 0, dot 0, starting on an even frame
+    pub fn new() -> Self {
+        PpuState { even_odd_frame: true, ..Default::default() }
+    }
+}
+
+// How a pixel is packed into a u32 for the frontend
+// Xrgb is the PPU's own format (the value is 0x00RRGGBB, what minifb wants), Rgba and Bgra are the byte order in memory
+// (what RGBA8/BGRA8 textures expect), with alpha always 0xFF
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    Xrgb,
+    Rgba,
+    Bgra,
+}
+
+impl PixelFormat {
+    pub fn pack(self, r: u8, g: u8, b: u8) -> u32 {
+        match self {
+            PixelFormat::Xrgb => ((r as u32) << 16) | ((g as u32) << 8) | b as u32,
+            PixelFormat::Rgba => u32::from_ne_bytes([r, g, b, 0xFF]),
+            PixelFormat::Bgra => u32::from_ne_bytes([b, g, r, 0xFF]),
+        }
+    }
+
+    pub fn unpack(self, pixel: u32) -> (u8, u8, u8) {
+        match self {
+            PixelFormat::Xrgb => ((pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8),
+            PixelFormat::Rgba => {
+                let [r, g, b, _] = pixel.to_ne_bytes();
+                (r, g, b)
+            },
+            PixelFormat::Bgra => {
+                let [b, g, r, _] = pixel.to_ne_bytes();
+                (r, g, b)
+            },
+        }
+    }
+
+    // Repacks a pixel from one format into another
+    pub fn convert(self, pixel: u32, to: PixelFormat) -> u32 {
+        let (r, g, b) = self.unpack(pixel);
+        to.pack(r, g, b)
+    }
+}
+
+// Everything the PPU takes from outside — the rest of its state comes from power on
+pub struct PpuConfig {
+    pub mapper: Rc<RefCell<dyn Mapper>>, // Cartridge — the CHR ROM/RAM and the nametable mirroring both come from here
+    pub palette: Vec<u8>, // Contents of a .pal file (packed RGB triples)
+    pub window: Option<minifb::Window>, // Where finished frames go, None when running headless
+}
+
+impl PPU {
+    pub fn init_ppu(mapper: Rc<RefCell<dyn Mapper>>, palette_storage: Vec<u8>, window: Option<minifb::Window>) -> Self {
+        PPU::new(PpuConfig { mapper, palette: palette_storage, window })
+    }
+
+    pub fn new(config: PpuConfig) -> Self {
+        let PpuConfig { mapper, palette: mut palette_storage, window } = config;
+
+        // The .pal file is used as-is, so make sure it's really packed RGB triples covering every color the PPU can output
+        // Anything wrong is kept for the frontend to report (see palette_problems)
+        let mut palette_problems = Vec::new();
+        if !palette_storage.len().is_multiple_of(3) {
+            palette_problems.push(PaletteError::NotTriples(palette_storage.len()));
+        }
+
+        if palette_storage.len() < NUM_SYSTEM_COLORS * 3 {
+            palette_problems.push(PaletteError::TooShort(palette_storage.len()));
+            palette_storage.resize(NUM_SYSTEM_COLORS * 3, 0);
+        }
+
+        PPU { 
+              oam: [0; 256],
+              secondary_oam: vec![0; HARDWARE_SPRITE_LIMIT as usize * 4],
+              ctrl: 0,
+              mask: 0,
+              status: 0,
+              oam_addr: 0,
+              oam_data: 0,
+              scroll: 0,
+              oam_dma: 0,
+              v: 0,
+              t: 0,
+              x: 0,
+              w: 0,
+              low_pttrn_shift_reg: 0,
+              high_pttrn_shift_reg: 0,
+              low_attr_shift_reg: 0,
+              high_attr_shift_reg: 0,
+              color_buffer: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
+              frame_buffer: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
+              ppu_latch: 0,
+              vram_latch: 0,
+              nmi: 0,
+              nmi_delay: NMI_DELAY_DOTS,
+              nmi_countdown: None,
+              vblank_suppressed: false,
+              state: PpuState::new(),
+              sprite_y: 0,
+              sprite_tile_number: 0,
+              sprite_attribute: 0,
+              sprite_x: 0,
+              back_pixel: 0,
+              sprite_pixel_buffer: vec![Sprite::default(); HARDWARE_SPRITE_LIMIT as usize * 8],
+              sprite_limit: Some(HARDWARE_SPRITE_LIMIT),
+              pixel: 0,
+              debug_layers: false,
+              system: System::Nes,
+              window,
+              oam_addr_overflow: false,
+              frame_count: 0,
+              present_frames: true,
+              presented_count: 0,
+              nmi_count: 0,
+              warmed_up: false,
+              palette_problems,
+              ppu_bus: PPUBus::new(mapper, [0; NUM_PALETTE_REGISTERS], palette_storage) ,
+        }
+    }
+
+    // Anything wrong with the palette the PPU was given, empty if it covers every system color
+    pub fn palette_problems(&self) -> &[PaletteError] {
+        &self.palette_problems
+    }
+
+    // Gives the frontend access to the window (e.g. for polling the keyboard)
+    pub fn window(&self) -> Option<&minifb::Window> {
+        self.window.as_ref()
+    }
+
+    pub fn window_mut(&mut self) -> Option<&mut minifb::Window> {
+        self.window.as_mut()
+    }
+
+    // Attaches (or takes away) the window after power on, e.g. once a headless boot has got past a game's intro
+    pub fn set_window(&mut self, window: Option<minifb::Window>) {
+        self.window = window;
+    }
+
+    // Luminance (0-255) of a pixel drawn so far this frame — pixels the beam hasn't reached yet are black
+    pub fn pixel_brightness(&self, x: usize, y: usize) -> u8 {
+        if x >= SCREEN_WIDTH || y >= SCREEN_HEIGHT {
+            return 0
+        }
+
+        PPU::luminance(self.color_buffer[y * SCREEN_WIDTH + x])
+    }
+
+    // Rec. 601 luma of a 0RGB pixel
+    fn luminance(pixel: u32) -> u8 {
+        let (r, g, b) = ((pixel >> 16) & 0xFF, (pixel >> 8) & 0xFF, pixel & 0xFF);
+        ((299 * r + 587 * g + 114 * b) / 1000) as u8
+    }
+
+    // Whether a Zapper aimed at (x, y) sees light right now — the pixel has to be bright and drawn within the last few scanlines
+    fn light_at(&self, x: usize, y: usize) -> bool {
+        if x >= SCREEN_WIDTH || y >= SCREEN_HEIGHT {
+            return false
+        }
+
+        let (scanline, dots) = (self.state.scanline as usize, self.state.dots as usize);
+        let drawn = scanline > y || (scanline == y && dots > x);
+        if !drawn || scanline - y >= ZAPPER_LIGHT_SCANLINES {
+            return false
+        }
+
+        // The finished frame moves over to frame_buffer at the end of scanline 240, so the bottom rows are read from there
+        let brightness = if scanline > SCREEN_HEIGHT {
+            PPU::luminance(self.frame_buffer[y * SCREEN_WIDTH + x])
+        } else {
+            self.pixel_brightness(x, y)
+        };
+
+        brightness >= ZAPPER_LIGHT_THRESHOLD
+    }
+
+    // The last complete frame as 0RGB pixels, row by row
+    pub fn frame_buffer(&self) -> &[u32] {
+        &self.frame_buffer
+    }
+
+    // The last complete frame converted to another pixel format
+    pub fn frame_buffer_as(&self, format: PixelFormat) -> Vec<u32> {
+        let mut pixels = vec![0; self.frame_buffer.len()];
+        self.fill_frame_buffer(format, &mut pixels);
+
+        pixels
+    }
+
+    // Same as frame_buffer_as but into the frontend's own buffer, so nothing is allocated each frame
+    // Only as many pixels as both buffers hold are written
+    pub fn fill_frame_buffer(&self, format: PixelFormat, pixels: &mut [u32]) {
+        for (out, pixel) in pixels.iter_mut().zip(self.frame_buffer.iter()) {
+            *out = PixelFormat::Xrgb.convert(*pixel, format);
+        }
+    }
+
+    // Changes how many sprites can show on one scanline — Some(8) is the hardware behavior, None lifts the limit
+    // so games that flicker sprites to get around it draw them all every frame (it can't go below 8)
+    pub fn set_sprite_limit(&mut self, limit: Option<u8>) {
+        self.sprite_limit = limit.map(|limit| limit.clamp(HARDWARE_SPRITE_LIMIT, NUM_SPRITES as u8));
+
+        let slots = self.sprite_slots();
+        self.secondary_oam = vec![0xFF; slots * 4];
+        self.sprite_pixel_buffer = vec![Sprite::default(); slots * 8];
+    }
+
+    fn sprite_slots(&self) -> usize {
+        self.sprite_limit.map_or(NUM_SPRITES, |limit| limit as usize)
+    }
+
+    // Copy of all 64 sprites (y, tile, attributes, x), for debuggers and sprite viewers
+    pub fn oam_snapshot(&self) -> [u8; 256] {
+        self.oam
+    }
+
+    // The 32 palette entries as $3F00-$3F1F reads them, for palette viewers — the sprite backdrop entries ($3F10/$3F14/
+    // $3F18/$3F1C) show the background entries they mirror
+    pub fn palette_ram(&self) -> [u8; 32] {
+        std::array::from_fn(|entry| self.ppu_bus.palette_mem[PPUBus::palette_index(PALETTE_RAM_BEGIN + entry as u16)])
+    }
+
+    // Replaces all of OAM at once, like a DMA that takes no time — OAMADDR is left where it was
+    pub fn set_oam(&mut self, data: &[u8; 256]) {
+        self.oam = *data;
+    }
+
+    // (x, y, tile) of each sprite in secondary OAM — the ones sprite evaluation picked for the next scanline
+    // Only the slots evaluation filled count — the first free one still has the y byte of the last sprite it checked
+    pub fn active_sprites(&self) -> Vec<(u8, u8, u8)> {
+        self.secondary_oam.chunks(4)
+            .take(self.state.sprite_counter as usize)
+            .map(|sprite| (sprite[3], sprite[0], sprite[1]))
+            .collect()
+    }
+
+    // Debug view — background, front sprite and back sprite pixels come out in the DEBUG_TINT_* colors instead of
+    // their palette colors, so it's obvious which layer won at every pixel
+    pub fn set_debug_layers(&mut self, enabled: bool) {
+        self.debug_layers = enabled;
+    }
+
+    pub fn set_system(&mut self, system: System) {
+        self.system = system;
+    }
+
+    // Draws one of the four nametables ($2000, $2400, $2800, $2C00) as a full 256x240 0RGB image, ignoring scroll and sprites
+    // Uses the background pattern table and palettes currently selected, for tile viewers and level dumps
+    pub fn render_nametable(&self, index: u8) -> Vec<u32> {
+        let base = NAME_TABLES_BEGIN + (index as u16 % 4) * NAME_TABLE_SIZE;
+        let pattern_table = if self.ctrl & 0b0001_0000 != 0 { 0x1000 } else { 0x0000 };
+        let mut image = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT];
+
+        for tile_y in 0..30 {
+            for tile_x in 0..32 {
+                let tile = self.ppu_bus.peek(base + tile_y * 32 + tile_x) as u16;
+
+                // Each attribute byte covers a 4x4 tile area, two bits per 2x2 quadrant
+                let attribute = self.ppu_bus.peek(base + 0x3C0 + (tile_y / 4) * 8 + tile_x / 4);
+                let shift = ((tile_y % 4) / 2) * 4 + ((tile_x % 4) / 2) * 2;
+                let palette = (attribute >> shift) & 0b11;
+
+                for row in 0..8 {
+                    let low = self.ppu_bus.peek(pattern_table + tile * 16 + row);
+                    let high = self.ppu_bus.peek(pattern_table + tile * 16 + row + 8);
+
+                    for col in 0..8 {
+                        let pixel = PPU::tile_pixel(low, high, col as u8);
+
+                        // Transparent pixels show the universal background color
+                        let palette_addr = if pixel == 0 { PALETTE_RAM_BEGIN } else { PALETTE_RAM_BEGIN + (palette as u16) * 4 + pixel as u16 };
+                        let (r, g, b) = self.color_for_index(self.ppu_bus.peek(palette_addr));
+
+                        let x = (tile_x * 8 + col) as usize;
+                        let y = (tile_y * 8 + row) as usize;
+                        image[y * SCREEN_WIDTH + x] = ((r as u32) << 16) | ((g as u32) << 8) | b as u32;
+                    }
+                }
+            }
+        }
+
+        image
+    }
+
+    // Draws every tile in a CHR dump as a grid, CHR_SHEET_COLUMNS tiles wide, for ROM hackers and tile viewers
+    // colors are the 0RGB values for pixel values 0-3, since CHR data carries no palette of its own
+    // Returns (width, height, pixels) — a partly filled last row is padded with color 0
+    pub fn render_chr_sheet(chr: &[u8], colors: [u32; 4]) -> (usize, usize, Vec<u32>) {
+        let tiles = chr.len() / CHR_TILE_SIZE;
+        let width = CHR_SHEET_COLUMNS * 8;
+        let height = tiles.div_ceil(CHR_SHEET_COLUMNS) * 8;
+        let mut image = vec![colors[0]; width * height];
+
+        for (index, tile) in chr.chunks_exact(CHR_TILE_SIZE).enumerate() {
+            let (left, top) = ((index % CHR_SHEET_COLUMNS) * 8, (index / CHR_SHEET_COLUMNS) * 8);
+
+            for row in 0..8 {
+                for col in 0..8 {
+                    let pixel = PPU::tile_pixel(tile[row], tile[row + 8], col as u8);
+                    image[(top + row) * width + left + col] = colors[pixel as usize];
+                }
+            }
+        }
+
+        (width, height, image)
+    }
+
+    // 2 bit value of one pixel in a tile row, col 0 being the leftmost (the msb of each bit plane)
+    fn tile_pixel(low: u8, high: u8, col: u8) -> u8 {
+        ((low >> (7 - col)) & 0b1) | (((high >> (7 - col)) & 0b1) << 1)
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    // PPU dots run since power on — unlike the scanline and dot it doesn't wrap, so it's easy to time things with
+    pub fn dot_count(&self) -> u64 {
+        self.ppu_bus.cycle
+    }
+
+    pub fn nmi_count(&self) -> u64 {
+        self.nmi_count
+    }
+
+    pub fn presented_count(&self) -> u64 {
+        self.presented_count
+    }
+
+    pub fn warmed_up(&self) -> bool {
+        self.warmed_up
+    }
+
+    // Writes the PPU dropped because they went to CHR ROM or past $3FFF
+    pub fn illegal_ppu_writes(&self) -> u64 {
+        self.ppu_bus.illegal_writes
+    }
+
+    // Calls back with the address and data of every write counted by illegal_ppu_writes — None stops the callbacks
+    pub fn set_on_illegal_ppu_write(&mut self, callback: Option<Box<dyn FnMut(u16, u8)>>) {
+        self.ppu_bus.on_illegal_ppu_write = callback;
+    }
+
+    // Debugger access to the PPU's address space ($0000-$3FFF) — neither moves v or the read buffer like $2007 does
+    pub fn peek_vram(&self, addr: u16) -> u8 {
+        self.ppu_bus.peek(addr)
+    }
+
+    pub fn poke_vram(&mut self, addr: u16, data: u8) {
+        self.ppu_bus.poke(addr, data);
+    }
+
+    // The current VRAM address (v) — bits 12-14 are fine Y while rendering
+    pub fn vram_addr(&self) -> u16 {
+        self.v
+    }
+
+    // The temporary VRAM address (t) that $2000/$2005/$2006 writes build up before it's copied into v
+    pub fn temp_vram_addr(&self) -> u16 {
+        self.t
+    }
+
+    // The write toggle (w) shared by $2005 and $2006 — true when the next write is the second of a pair
+    pub fn write_toggle(&self) -> bool {
+        self.w != 0
+    }
+
+    // How many dots after VBlank is set the NMI is raised — 0 raises it on the same dot
+    pub fn set_nmi_delay(&mut self, dots: u8) {
+        self.nmi_delay = dots;
+    }
+
+    pub fn nmi_delay(&self) -> u8 {
+        self.nmi_delay
+    }
+
+    // Counts down a VBlank NMI — it's dropped if VBlank was cleared (a $2002 read) or NMIs were turned off in the meantime
+    fn nmi_delay_tick(&mut self) {
+        let Some(left) = self.nmi_countdown else { return };
+
+        if left > 1 {
+            self.nmi_countdown = Some(left - 1);
+            return
+        }
+
+        self.nmi_countdown = None;
+        if self.status & 0b1000_0000 > 0 && self.ctrl & 0b1000_0000 > 0 {
+            self.raise_nmi();
+        }
+    }
+
+    fn raise_nmi(&mut self) {
+        self.nmi = 1;
+        self.nmi_count += 1;
+    }
+
+    // Turning NMIs on while the VBlank flag is still set raises one straight away
+    fn write_ctrl(&mut self, data: u8) {
+        if self.status & 0b1000_0000 > 0 && self.ctrl & 0b1000_0000 == 0 && data & 0b1000_0000 > 0 {
+            self.raise_nmi();
+        }
+
+        // The base nametable select lands in bits 10-11 of t, just like the nametable bits of a $2006 write
+        self.t = (self.t & 0xF3FF) | ((data as u16 & 0x03) << 10);
+        self.ctrl = data;
+    }
+
+    // Reading PPUSTATUS clears VBlank and resets the write latch (w register)
+    // A read on the dot before VBlank starts (241,0) races the flag — it reads clear and the flag never goes up that
+    // frame. Reads on 241,1 and 241,2 see it set, but clear it before the NMI delay runs out so there's no NMI either
+    fn read_status(&mut self) -> u8 {
+        if self.state.scanline == 241 && self.state.dots == 0 {
+            self.vblank_suppressed = true;
+        }
+
+        let status = self.status;
+        self.status &= !0b1000_0000;
+        self.w = 0;
+
+        status
+    }
+
+    fn read_byte(&mut self, addr: u16) -> u8 {
+        self.ppu_bus.mem_read(addr)
+    }
+
+    // Doesn't increase dots because it's through the CPU
+    fn cpu_write_byte(&mut self, data: u8) {
+        self.ppu_bus.mem_write(self.v, data);
+        self.increment_vram();
+    }
+
+    // Increments the VRAM address. Used after accessing a VRAM element through the CPU (writing to 0x2007)
+    fn increment_vram(&mut self) {
+        if self.ctrl & 0x4 > 0 {
+            self.v = (self.v + 32) & 0x3FFF; // Going down
+        } else {
+            self.v = (self.v + 1) & 0x3FFF; // Going across
+        }
+    }
+
+    // Loads register t with VRAM address bytes through 0x2006 writes
+    fn load_addr_byte(&mut self, addr: u16) {
+        if self.w == 0 {
+            // Storing the upper byte — only 6 bits fit, and bit 14 (the top bit of fine Y) is cleared
+            // so after the second write fine Y is just bits 12-13 of the address
+            self.t = (self.t & 0b0000_0000_1111_1111) | ((addr & 0b0011_1111) << 8);
+            self.w = 1; // Setting the write latch (saying we want to write to the lower byte now)
+        } else {
+            self.t = (self.t & !0b1111_1111) | (addr & 0b1111_1111); // Storing the lower byte
+            self.v = self.t;
+            self.w = 0;
+        }
+    }
+
+    // Background or sprite rendering enabled in PPUMASK
+    fn rendering_enabled(&self) -> bool {
+        self.mask & 0b0001_1000 != 0
+    }
+
+    // Value returned by $2004 reads — while rendering the OAM bus belongs to sprite evaluation
+    // Dots 1-64 clear secondary OAM so the bus reads 0xFF, afterwards it holds the byte evaluation last read
+    fn oam_data_read(&self) -> u8 {
+        let rendering_scanline = self.state.scanline < 240 || self.state.scanline == 261;
+
+        if !self.rendering_enabled() || !rendering_scanline {
+            return self.oam[self.oam_addr as usize]
+        }
+
+        match self.state.dots {
+            1..=64 => 0xFF,
+            _ => self.oam_data,
+        }
+    }
+
+    // Sets oam data when called by CPU
+    fn oam_data_set(&mut self) {
+        self.oam[self.oam_addr as usize] = self.oam_data;
+    }
+
+    // Loading t with the scroll data
+    fn load_scroll(&mut self, scroll_data: u8) {
+        if self.w == 0 {
+            self.x = scroll_data & 0b111;
+            self.t = (self.t & !0b1_1111) | ((scroll_data as u16) >> 3);
+            self.w = 1;
+        } else {
+            self.t = (self.t & !0b11_1110_0000) | (((scroll_data as u16) << 2) & 0b11_1110_0000);
+            self.t = (self.t & !0b111_0000_0000_0000) | (((scroll_data as u16) << 12) & 0b111_0000_0000_0000);
+            self.w = 0;
+        }
+    }
+
+    // Returns the code of the palette table used for the current nametable address
+    fn fetch_attribute_data(&mut self, addr: u16) -> u8 {
+        // MMC5's extended attribute mode gives every tile its own palette, the attribute table isn't used at all
+        if let Some(palette_choice) = self.ppu_bus.extended_attribute(addr) {
+            return palette_choice
+        }
+
+        let x = addr & 0b1_1111;
+        let y = (addr >> 5) & 0b1_1111;
+
+        // The attribute byte sits in the same nametable as the tile (bits 10-11 of v), the bus applies the mirroring
+        // Coarse y 30 and 31 land in the last attribute row, just like the hardware
+        let attribute_addr = 0x23C0 | (addr & 0x0C00) | ((y / 4) << 3) | (x / 4);
+
+        let palette_value: u8 = self.read_byte(attribute_addr);
+        // Determine palette selection based on position within the 4x4 attribute block
+        let palette_choice: u8 = match ((x % 4) / 2, (y % 4) / 2) {
+            (0, 0) => palette_value & 0x3,  // Top-left quadrant
+            (1, 0) => (palette_value >> 2) & 0x3,  // Top-right quadrant
+            (0, 1) => (palette_value >> 4) & 0x3,  // Bottom-left quadrant
+            (1, 1) => (palette_value >> 6) & 0x3,  // Bottom-right quadrant
+            _ => 0, // This shouldn't happen
+        };
+
+        return palette_choice
+    }
+
+    /// Returns byte present in the pattern table, addr is id from the nametable
+    pub fn fetch_pattern_table(&mut self, high: bool, addr: u8) -> u8 {
+        let mut pattern_addr: u16 = 0;
+
+        if self.ctrl & 0b0001_0000 != 0 {
+            pattern_addr |= 0b1_0000_0000_0000;
+        }
+
+        if high {
+            pattern_addr |= 0b1000
+        }
+
+        pattern_addr |= (addr as u16) << 4;
+        pattern_addr |= (self.v >> 12) & 0b111; // Extracting fine y address
+
+        return self.read_byte(pattern_addr)
+    }
+
+    // Loads the address into the PPU latch (multiplexed with bottom 8 address bits)
+    fn load_latch(&mut self, addr: u16) {
+        self.ppu_latch = (addr & 0xFF) as u8;
+    }
+
+    fn nametable_fetch(&mut self, addr: u16) -> u8 {
+        let v = (addr & 0xFF00) | (self.ppu_latch as u16);
+        let tile_addr = 0x2000 | (v & 0x0FFF);
+
+        return self.read_byte(tile_addr)
+    }
+
+    // The two unused nametable fetches at the end of each rendering scanline (dots 338 and 340)
+    // Nothing is stored — v, the latches and the shift registers are left alone — but the address still goes out on
+    // the PPU bus, which mappers that watch it (e.g. MMC3's A12 counter) can see
+    fn dummy_nametable_fetch(&mut self) {
+        if self.state.dots == 338 || self.state.dots == 340 {
+            let _ = self.read_byte(0x2000 | (self.v & 0x0FFF));
+        }
+    }
+
+    fn shift(&mut self) {
+        self.low_pttrn_shift_reg <<= 1;
+
+        self.high_pttrn_shift_reg <<= 1;
+
+        // The attribute registers are only 8 bits — each shift feeds in the palette bit held by its 1 bit latch, so by the
+        // time a tile reaches the top of the pattern registers all 8 attribute bits belong to it
+        self.low_attr_shift_reg <<= 1;
+        self.low_attr_shift_reg |= self.state.attribute_latch & 0b1;
+
+        self.high_attr_shift_reg <<= 1;
+        self.high_attr_shift_reg |= (self.state.attribute_latch >> 1) & 0b1;
+    }
+
+    // Loads the next tile into the low byte of the pattern registers (the high byte is the tile still being drawn)
+    // and its palette bits into the attribute latches
+    fn shift_reload(&mut self) {
+        self.state.attribute_latch = self.state.attribute_data & 0b11;
+
+        self.low_pttrn_shift_reg = (self.low_pttrn_shift_reg & 0xFF00) | self.state.low_bitplane as u16;
+        self.high_pttrn_shift_reg = (self.high_pttrn_shift_reg & 0xFF00) | self.state.high_bitplane as u16;
+    }
+
+    fn continue_render(&mut self) {
+        // Nothing goes out on the PPU bus in forced blank, and v is only moved by the CPU ($2006/$2007)
+        if !self.rendering_enabled() {
+            return
+        }
+
+        // During the sprite fetches (dots 257-320) the background fetches are skipped so they don't override the nametable,
+        // attribute and pattern data (the pattern table slots belong to the sprites, see load_sprite_pixels) — the prefetch
+        // for the next line's first two tiles (dots 321-336) still goes through
+        let sprite_fetches = (257..=320).contains(&self.state.dots);
+
+        match self.state.dots % 8 {
+            // 1, 3, 5, 7 are all used to store the address in the latch
+            1 => {
+                self.load_latch(self.v);
+            },
+            // Fetches the pattern table address from the nametable
+            2 => {
+                if !sprite_fetches {
+                    self.state.nametable_data = self.nametable_fetch(self.v);
+                }
+            },
+            3 => {
+                self.load_latch(self.v);
+            },
+            // Fetches the attribute data from the attribute table
+            4 => {
+                if !sprite_fetches {
+                    self.state.attribute_data = self.fetch_attribute_data(self.v);
+                }
+            },
+            5 => {
+                self.load_latch(self.v);
+            },
+            // Fetches the low pattern bitplane from the pattern table
+            6 => {
+                if !sprite_fetches {
+                    self.state.low_bitplane = self.fetch_pattern_table(false, self.state.nametable_data);
+                }
+            },
+            7 => {
+                self.load_latch(self.v);
+            },
+            // Fetches the high pattern bitplane from the pattern table
+            _ => {
+                if !sprite_fetches {
+                    self.state.high_bitplane = self.fetch_pattern_table(true, self.state.nametable_data);
+                }
+            },
+        }
+
+        // Takes care of x scrolling — coarse x moves on once each tile's fetches are done (dot 8 of the tile), across
+        // the visible tiles (8, 16, ..., 256) and the two prefetched for the next scanline (328, 336), but not while sprites
+        // are fetched or on the idle dot 0
+        let tile_fetched = self.state.dots >= 8 && self.state.dots.is_multiple_of(8)
+            && (self.state.dots <= 256 || self.state.dots >= 328);
+
+        if tile_fetched {
+            // 31 is the horizontal limit of a nametable (5 bits), after which it wraps into the horizontally adjacent nametable
+            if (self.v & 0b1_1111) == 31 {
+                self.v &= !0b1_1111;
+                self.v ^= 0x0400;
+            } else {
+                self.v += 1;
+            }
+        }
+
+        // Handles y scrolling at the end of the scanline
+        if self.state.dots == 256 {
+            let mut fine_y = (self.v & 0b111_0000_0000_0000) >> 12;
+            
+            // Fine y overflows into coarse y
+            // Row 29 is the limit vertically, but coarse y can be set out of bounds
+            // Rows 30 and 31 fetch attribute bytes as tiles, and 31 wraps to 0 without switching nametables
+            if fine_y < 7 {
+                fine_y += 1;
+                self.v = (self.v & !0b111_0000_0000_0000) | (fine_y << 12);
+            } else {
+                // Resets fine y and overflows into coarse y
+                self.v &= !0b111_0000_0000_0000;
+                let mut coarse_y = (self.v & 0b11_1110_0000) >> 5;
+
+                if coarse_y == 29 {
+                    coarse_y = 0;
+                    self.v ^= 0x0800;
+                } else if coarse_y == 31 {
+                    coarse_y = 0;
+                } else {
+                    coarse_y += 1;
+                }
+
+                self.v = (self.v & !0b11_1110_0000) | (coarse_y << 5);
+            }
+        }
+
+        // Copies the horizontal scroll bits (coarse x and the horizontal nametable) from t back into v for the next scanline
+        if self.state.dots == 257 {
+            self.v = (self.v & !0x041F) | (self.t & 0x041F);
+        }
+    }
+
+    // 8x16 sprites are turned on by PPUCTRL bit 5
+    fn sprite_height(&self) -> u16 {
+        if self.ctrl & 0b0010_0000 != 0 {
+            SPRITE_HEIGHT * 2
+        } else {
+            SPRITE_HEIGHT
+        }
+    }
+
+    // Pattern table address of the low bitplane for one row of a sprite
+    // Vertical flip mirrors the row across the whole sprite, so on 8x16 sprites it also swaps which tile is the top half
+    fn sprite_pattern_addr(&self, tile: u8, attribute: u8, row: u16) -> u16 {
+        let height = self.sprite_height();
+        let row = if attribute & 0b1000_0000 != 0 { height - 1 - row } else { row };
+
+        if height == 16 {
+            // Bit 0 of the tile number picks the pattern table — the top half is the even tile and the bottom half the one after it
+            let table = (tile as u16 & 0b1) << 12;
+            let tile = (tile as u16 & !0b1) + (row >> 3);
+
+            table | (tile << 4) | (row & 0b111)
+        } else {
+            let table = if self.ctrl & 0b0000_1000 != 0 { 0x1000 } else { 0 };
+
+            table | ((tile as u16) << 4) | row
+        }
+    }
+
+    // Fills the eight sprite_pixel_buffer entries for the given secondary OAM slot from the sprite data latched this slot
+    fn load_sprite_pixels(&mut self, slot: usize) {
+        let row = self.state.scanline.wrapping_sub(self.sprite_y as u16);
+
+        // Unused slots (filled with 0xFF) land out of range and come out transparent — the pattern fetches still happen
+        // though (mappers watching A12 count on them)
+        let in_range = row < self.sprite_height();
+        let (low, high) = if self.rendering_enabled() {
+            let addr = self.sprite_pattern_addr(self.sprite_tile_number, self.sprite_attribute, if in_range { row } else { 0 });
+            (self.read_byte(addr), self.read_byte(addr + 8))
+        } else {
+            (0, 0)
+        };
+        let (low, high) = if in_range { (low, high) } else { (0, 0) };
+
+        let horizontal_flip = self.sprite_attribute & 0b0100_0000 != 0;
+
+        for i in 0..8 {
+            let bit = if horizontal_flip { i } else { 7 - i };
+
+            self.sprite_pixel_buffer[slot * 8 + i as usize] = Sprite {
+                x_coordinate: self.sprite_x.wrapping_add(i),
+                // Bit 4 selects the sprite palettes
+                pixel: ((low >> bit) & 0b1) | (((high >> bit) & 0b1) << 1) | ((self.sprite_attribute & 0b11) << 2) | 0b1_0000,
+                priority: ((self.sprite_attribute & 0b0010_0000) > 0) as u8,
+                horizontal_flip: horizontal_flip as u8,
+                vertical_flip: ((self.sprite_attribute & 0b1000_0000) > 0) as u8,
+                sprite_zero: slot == 0 && self.state.sprite_zero_next,
+            };
+        }
+    }
+
+    // What rendering does to OAMADDR on the pre-render and visible scanlines
+    // - When rendering starts (pre-render dot 1) with OAMADDR at 8 or above, the eight bytes at OAMADDR & $F8 are copied over
+    //   the first eight bytes of OAM (sprites 0 and 1) — a 2C02 quirk games avoid by setting OAMADDR to 0 before rendering
+    // - It's cleared on every dot of the sprite fetches (257-320), so evaluation always starts from sprite 0
+    fn oam_addr_rendering_tick(&mut self) {
+        if !self.rendering_enabled() {
+            return
+        }
+
+        if self.state.scanline == 261 && self.state.dots == 1 && self.oam_addr >= 8 {
+            let row = (self.oam_addr & 0xF8) as usize;
+            self.oam.copy_within(row..row + 8, 0);
+        }
+
+        if (257..=320).contains(&self.state.dots) {
+            self.oam_addr = 0;
+        }
+    }
+
+    // Function to handle sprite evaluation and loading the secondary OAM buffer
+    fn sprite_evaluation_tick(&mut self) {
+        if self.state.dots == 0 {
+            return
+        }
+
+        if self.state.dots <= 320 && self.state.dots > 256 {
+            if self.state.dots == 257 {
+                self.state.secondary_oam_addr = 0;
+            }
+
+            // println!("dots: {}", self.state.dots);
+            match self.state.dots % 8 {
+                1 => {
+                    self.sprite_y = self.secondary_oam[self.state.secondary_oam_addr as usize];
+                    self.state.secondary_oam_addr += 1;
+                },
+                2 => {
+                    self.sprite_tile_number = self.secondary_oam[self.state.secondary_oam_addr as usize];
+                    self.state.secondary_oam_addr += 1;
+                },
+                3 => {
+                    self.sprite_attribute = self.secondary_oam[self.state.secondary_oam_addr as usize];
+                    self.state.secondary_oam_addr += 1;
+                },
+                4..=7 => {
+                    self.sprite_x = self.secondary_oam[self.state.secondary_oam_addr as usize];
+                },
+                _ => {
+                    // Last dot of the slot — fetches the sprite's pattern row and unpacks it into the sprite pixel buffer
+                    self.load_sprite_pixels(((self.state.dots - 257) / 8) as usize);
+                    self.state.secondary_oam_addr += 1;
+                }
+            }
+
+            if self.state.dots == 320 {
+                // Hardware only has time to fetch 8 sprites — any past that (sprite limit raised) are fetched all at once
+                for slot in 8..self.sprite_slots() {
+                    self.sprite_y = self.secondary_oam[slot * 4];
+                    self.sprite_tile_number = self.secondary_oam[slot * 4 + 1];
+                    self.sprite_attribute = self.secondary_oam[slot * 4 + 2];
+                    self.sprite_x = self.secondary_oam[slot * 4 + 3];
+                    self.load_sprite_pixels(slot);
+                }
+
+                self.state.secondary_oam_addr = 0;
+            }
+
+            return
+        }
+
+        if self.state.dots <= 340 && self.state.dots > 320 {
+            // Reading the first byte in secondary OAM while the PPU fetches the first two background tiles
+            let _ = self.secondary_oam[self.state.secondary_oam_addr as usize];
+        }
+
+        if self.state.dots % 2 == 0 {
+            // In the first 64 cycles the secondary OAM buffer is filled with 0xFF regardless
+            if self.state.dots < 64 {
+                self.secondary_oam[self.state.secondary_oam_addr as usize] = 0xFF;
+                self.state.secondary_oam_addr += 1;
+                return
+            } else if self.state.dots == 64 {
+                // Hardware only clears 32 bytes, the rest of a bigger buffer (sprite limit raised) is cleared in one go
+                self.secondary_oam[self.state.secondary_oam_addr as usize..].fill(0xFF);
+                self.state.secondary_oam_addr = 0;
+                self.state.sprite_counter = 0; // Evaluation for the next scanline starts from scratch
+                self.state.sprite_zero_next = false;
+                self.oam_addr_overflow = false;
+                return
+            }
+
+            // Through cycle 256, it then loads the next eight sprites from the scanline into the secondary OAM buffer
+            // Ignores the write if there are already full 8 sprites in the buffer
+            if self.state.dots <= 256 && self.state.dots > 64 && !self.oam_addr_overflow {
+                // Checks if the y-byte received is in a valid range, and if so allow for the next bytes to be fetched
+                // The range check is against the current scanline, but the sprites are drawn on the *next* one — that's why
+                // sprites show up one line below their y
+                if self.oam_data as u16 <= self.state.scanline && self.state.scanline < self.oam_data as u16 + self.sprite_height() && !self.state.valid_sprite {
+                    self.state.valid_sprite = true;
+
+                    // The first sprite checked is sprite 0 (its y byte was read on dot 65)
+                    if self.state.dots == 66 {
+                        self.state.sprite_zero_next = true;
+                    }
+                }
+
+                if (self.state.sprite_counter as usize) < self.sprite_slots() {
+                    if (self.state.secondary_oam_addr as usize) < self.secondary_oam.len() {
+                        self.secondary_oam[self.state.secondary_oam_addr as usize] = self.oam_data;
+                    } else {
+                        // Reads the value in secondary_oam if it is full
+                        let _ = self.secondary_oam[0];
+                    }
+
+                    // The y byte is always copied, but it's only kept (the address moves on) if the sprite is on the scanline
+                    if self.state.valid_sprite {
+                        self.state.secondary_oam_addr += 1;
+                    } else {
+                        self.next_oam_sprite();
+                    }
+                } else if self.state.valid_sprite {
+                    // Evaluates once 8 sprites are stored in the secondary oam buffer — another sprite on the scanline
+                    // sets the overflow flag, and its other three bytes are read like a normal copy
+                    self.status |= 0b0010_0000;
+                } else {
+                    // IMPORTANT: This is a bug that renders the overflow sprite register unstable
+                    // Only doing this in order to stay accurate to the original NTSC PPU in the NES
+                    // Of course some masochist games actually *exploit* the fact that this bug exists
+                    // Moving on to the next sprite also moves onto its next byte, so tile numbers and x positions get checked as y
+                    self.next_oam_sprite();
+                    self.state.sprite_addr = (self.state.sprite_addr + 1) & 0b11;
+                }
+            }
+        } else if self.state.dots > 64 && self.state.dots <= 256 {
+            if self.state.valid_sprite {
+                self.state.sprite_addr += 1;
+            }
+
+            if self.state.sprite_addr == 4 {
+                self.state.valid_sprite = false;
+                self.state.sprite_counter += 1;
+                self.state.sprite_addr = 0;
+                self.next_oam_sprite();
+            }
+
+            // OAMADDR is a byte address — it points at the sprite's y byte and sprite_addr picks the byte within the sprite
+            self.oam_data = self.oam[self.oam_addr.wrapping_add(self.state.sprite_addr) as usize];
+        }
+    }
+
+    // Moves evaluation onto the next sprite in OAM — once it wraps past the last one every sprite has been checked,
+    // so nothing more is copied to secondary OAM for this scanline
+    fn next_oam_sprite(&mut self) {
+        let (next, wrapped) = self.oam_addr.overflowing_add(4);
+
+        self.oam_addr = next;
+        if wrapped {
+            self.oam_addr_overflow = true;
+        }
+    }
+
+    // Compares the background pixel with all the sprite pixels to see if there is an overlap
+    // Returns the layer the selected pixel came from
+    fn compare_against_sprites(&mut self) -> PixelLayer {
+        self.check_sprite_zero_hit();
+
+        for sprite in self.sprite_pixel_buffer.iter() {
+            // The first opaque sprite pixel wins, and shows unless it's behind an opaque background pixel
+            if sprite.x_coordinate == self.state.dots as u8 && sprite.pixel & 0b11 != 0 {
+                if sprite.priority == 0 || self.back_pixel & 0b11 == 0 {
+                    self.pixel = sprite.pixel;
+                    return if sprite.priority == 0 { PixelLayer::SpriteFront } else { PixelLayer::SpriteBack }
+                }
+
+                break
+            }
+        }
+
+        self.pixel = self.back_pixel;
+        self.background_layer()
+    }
+
+    fn background_layer(&self) -> PixelLayer {
+        if self.back_pixel & 0b11 == 0 { PixelLayer::Backdrop } else { PixelLayer::Background }
+    }
+
+    // Sprite zero hit — an opaque sprite 0 pixel over an opaque background pixel, whatever the sprite's priority
+    // Never happens at x = 255, or in the left 8 pixels while either layer is clipped there
+    fn check_sprite_zero_hit(&mut self) {
+        let both_layers_enabled = self.mask & 0b0001_1000 == 0b0001_1000;
+        let left_clipped = self.mask & 0b0000_0110 != 0b0000_0110;
+
+        if !both_layers_enabled || self.back_pixel & 0b11 == 0 {
+            return
+        }
+
+        for sprite in self.sprite_pixel_buffer.iter() {
+            if sprite.sprite_zero && sprite.x_coordinate == self.state.dots as u8 && sprite.pixel & 0b11 != 0 {
+                if sprite.x_coordinate != 255 && !(sprite.x_coordinate < 8 && left_clipped) {
+                    self.status |= 0b0100_0000;
+                }
+
+                return
+            }
+        }
+    }
+
+    // Looks the pixel's color index up in palette RAM, then maps it to RGB
+    // Transparent pixels (low two bits 0) all show the universal background color at $3F00
+    fn fetch_rgb(&self) -> (u8, u8, u8) {
+        let palette_entry = if self.pixel & 0b11 == 0 { 0 } else { self.pixel as u16 };
+        let color_index = self.ppu_bus.peek(PALETTE_RAM_BEGIN + palette_entry);
+
+        self.color_for_index(color_index)
+    }
+
+    // RGB for one of the 64 system colors (what palette RAM entries hold) — only the low 6 bits matter
+    pub fn color_for_index(&self, index: u8) -> (u8, u8, u8) {
+        if self.system == System::Vs {
+            let color = VS_PALETTE[index as usize % NUM_SYSTEM_COLORS];
+            let channel = |shift: u16| (((color >> shift) & 0b111) * 255 / 7) as u8;
+
+            return (channel(6), channel(3), channel(0))
+        }
+
+        let base = (index as usize % NUM_SYSTEM_COLORS) * 3;
+
+        let r = self.ppu_bus.palette_storage[base];
+        let g = self.ppu_bus.palette_storage[base + 1];
+        let b = self.ppu_bus.palette_storage[base + 2];
+
+        return (r, g, b)
+    }
+
+    // Registers, memory and the rendering pipeline for save states
+    // The frame buffers and sprite pixel buffer aren't saved — they're rebuilt by the next frame/scanline
+    fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.oam);
+        state.section(&self.secondary_oam);
+        state.bytes(&[self.ctrl, self.mask, self.status, self.oam_addr, self.oam_data, self.scroll, self.oam_dma]);
+        state.u16(self.v);
+        state.u16(self.t);
+        state.bytes(&[self.x, self.w]);
+        state.u16(self.low_pttrn_shift_reg);
+        state.u16(self.high_pttrn_shift_reg);
+        state.bytes(&[self.low_attr_shift_reg, self.high_attr_shift_reg, self.ppu_latch, self.vram_latch, self.nmi]);
+        state.bytes(&[self.sprite_y, self.sprite_tile_number, self.sprite_attribute, self.sprite_x, self.back_pixel, self.pixel]);
+        state.bool(self.oam_addr_overflow);
+        state.u64(self.frame_count);
+        state.u64(self.nmi_count);
+        state.bool(self.warmed_up);
+
+        state.bytes(&[
+            self.state.nametable_data,
+            self.state.attribute_data,
+            self.state.low_bitplane,
+            self.state.high_bitplane,
+            self.state.attribute_latch,
+        ]);
+        state.u16(self.state.scanline);
+        state.u16(self.state.dots);
+        state.u8(self.state.sprite_counter);
+        state.bool(self.state.valid_sprite);
+        state.u16(self.state.secondary_oam_addr);
+        state.u8(self.state.sprite_addr);
+        state.bool(self.state.sprite_zero_next);
+        state.bool(self.state.even_odd_frame);
+
+        state.bytes(&self.ppu_bus.vram);
+        state.bytes(&self.ppu_bus.palette_mem);
+        state.u64(self.ppu_bus.cycle); // 1.2
+        state.bool(self.ppu_bus.a12_high);
+        state.u64(self.ppu_bus.a12_low_since);
+        state.u8(self.nmi_delay); // 1.3
+        state.u8(self.nmi_countdown.unwrap_or(0));
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.oam.copy_from_slice(state.bytes(256)?);
+
+        // Only kept if it was saved with the same sprite limit, otherwise it's refilled on the next scanline
+        let secondary_oam = state.section()?;
+        if secondary_oam.len() == self.secondary_oam.len() {
+            self.secondary_oam.copy_from_slice(secondary_oam);
+        }
+
+        self.ctrl = state.u8()?;
+        self.mask = state.u8()?;
+        self.status = state.u8()?;
+        self.oam_addr = state.u8()?;
+        self.oam_data = state.u8()?;
+        self.scroll = state.u8()?;
+        self.oam_dma = state.u8()?;
+        self.v = state.u16()?;
+        self.t = state.u16()?;
+        self.x = state.u8()?;
+        self.w = state.u8()?;
+        self.low_pttrn_shift_reg = state.u16()?;
+        self.high_pttrn_shift_reg = state.u16()?;
+        self.low_attr_shift_reg = state.u8()?;
+        self.high_attr_shift_reg = state.u8()?;
+        self.ppu_latch = state.u8()?;
+        self.vram_latch = state.u8()?;
+        self.nmi = state.u8()?;
+        self.sprite_y = state.u8()?;
+        self.sprite_tile_number = state.u8()?;
+        self.sprite_attribute = state.u8()?;
+        self.sprite_x = state.u8()?;
+        self.back_pixel = state.u8()?;
+        self.pixel = state.u8()?;
+        self.oam_addr_overflow = state.bool()?;
+        self.frame_count = state.u64()?;
+        self.nmi_count = state.u64()?;
+        self.warmed_up = state.bool()?;
+
+        self.state.nametable_data = state.u8()?;
+        self.state.attribute_data = state.u8()?;
+        self.state.low_bitplane = state.u8()?;
+        self.state.high_bitplane = state.u8()?;
+        self.state.attribute_latch = state.u8()?;
+        self.state.scanline = state.u16()?;
+        self.state.dots = state.u16()?;
+        self.state.sprite_counter = state.u8()?;
+        self.state.valid_sprite = state.bool()?;
+        self.state.secondary_oam_addr = state.u16()?;
+        self.state.sprite_addr = state.u8()?;
+        self.state.sprite_zero_next = state.bool()?;
+        self.state.even_odd_frame = state.bool()?;
+
+        self.ppu_bus.vram.copy_from_slice(state.bytes(4096)?);
+        self.ppu_bus.palette_mem.copy_from_slice(state.bytes(NUM_PALETTE_REGISTERS)?);
+
+        if !state.at_end() {
+            self.ppu_bus.cycle = state.u64()?;
+            self.ppu_bus.a12_high = state.bool()?;
+            self.ppu_bus.a12_low_since = state.u64()?;
+        }
+
+        if !state.at_end() {
+            self.nmi_delay = state.u8()?;
+            self.nmi_countdown = Some(state.u8()?).filter(|left| *left > 0);
+        }
+
+        Ok(())
+    }
+
+    // Note that the data for the first two tiles should already be fetched from previous scanline
+    // Sprites cannot be rendered on the first scanline
+    pub fn ppu_tick(&mut self) {
+        // println!("Scanline: {}", self.state.scanline);
+        self.ppu_bus.cycle += 1;
+        self.nmi_delay_tick();
+   
+        // Visible scanlines
+        if self.state.scanline < 240 {
+            // Every other frame the very first cycle is skipped while rendering is on — helps with smoothness when not scrolling
+            // Slightly offsets the video signal
+            if self.state.even_odd_frame && self.rendering_enabled() && self.state.dots == 0 && self.state.scanline == 0 {
+                // No return statement because we *do* want it to run cycle 1 since cycle 0 is skipped
+                self.state.dots += 1;
+            } else if self.state.dots == 0 && self.state.scanline == 0 {
+                self.state.dots += 1;
+                return
+            }
+
+            if self.state.dots <= 256 {
+                self.continue_render();
+                self.sprite_evaluation_tick();
+                
+                // Every 8 cycles 8 new pixels can be constructed
+                if self.state.dots > 8 && (self.state.dots - 1) % 8 == 0 {
+                    // Loads the shift registers with values from last 8 cycles
+                    self.shift_reload();
+                }
+
+                // Assembling a four bit pixel based on the fine x register
+                // 0 bit: Low pattern table, 1 bit: High pattern table, 2 bit: Low palette bit, 3 bit: High palette bit
+                self.back_pixel = (((self.low_pttrn_shift_reg >> (15 - self.x)) & 0x1) as u8)
+                                    | ((((self.high_pttrn_shift_reg >> (15 - self.x)) & 0x1) << 1) as u8)
+                                    | (((self.low_attr_shift_reg >> (7 - self.x)) & 0x1) << 2)
+                                    | (((self.high_attr_shift_reg >> (7 - self.x)) & 0x1) << 3);
+
+                // println!("self.back_pixel: {:0b}", self.back_pixel);
+
+                let layer = if !self.rendering_enabled() {
+                    // Forced blank shows the backdrop color — nothing has been fetched
+                    self.pixel = 0;
+                    PixelLayer::Backdrop
+                } else if self.state.scanline > 0 {
+                    self.compare_against_sprites()
+                } else {
+                    self.pixel = self.back_pixel;
+                    self.background_layer()
+                };
+
+                let (r, g, b) = if self.debug_layers { layer.debug_tint() } else { self.fetch_rgb() };
+                // println!("r: {}, g: {}, b: {}", r, g, b);
+                let rgb_value = PixelFormat::Xrgb.pack(r, g, b);
+
+                // Stores the color output of the pixel in a buffer
+                let index = (self.state.scanline * 256 + self.state.dots) as usize;
+
+                if index < self.color_buffer.len() {
+                    self.color_buffer[index] = rgb_value;
+                }
+
+                self.shift();
+            } else if self.state.dots <= 320 {
+                // Garbage nametable fetches — allows for reusing circuitry in hardware
+                self.continue_render();
+                self.sprite_evaluation_tick();
+                self.oam_addr_rendering_tick();
+
+                if self.state.dots > 8 && (self.state.dots - 1) % 8 == 0 {
+                    self.shift_reload();
+                }
+
+                self.shift();
+            } else if self.state.dots <= 336 {
+                // This will load the pattern shift registers with two tiles worth of data
+                // The attribute registers will have their second tile data stored in the self.state.attribute_data latch
+                self.continue_render();
+
+                if self.state.dots == 329 {
+                    self.shift_reload();
+                }
+
+                self.shift();
+            } else if self.state.dots <= 340 {
+                // The second prefetched tile goes in behind the first one
+                if self.state.dots == 337 {
+                    self.shift_reload();
+                }
+
+                // Useless clock cycles spent accessing the third tiles nametable byte (only implemented to stay faithful)
+                self.dummy_nametable_fetch();
+                if self.state.dots == 340 {
+                    self.state.dots = 0;
+                    self.state.scanline += 1;
+
+                    return
+                }
+            }
+        }
+
+        // PPU just idles here (NMI is *not* set until scanline 241)
+        if self.state.scanline == 240 {
+            if self.state.dots == 340 {
+                self.state.scanline += 1;
+                self.state.dots = 0;
+
+                return
+            }
+        }
+
+        // Start of Vblank — Generate an NMI if requested by the CPU; also display the next frame
+        if self.state.scanline == 241 {
+            // Doesn't set VBlank or raise the NMI until the *second* PPU cycle — it goes up as dot 0 finishes, so a $2002
+            // read at 241,1 (the CPU reads before the PPU runs the rest of the cycle) is the first to see it
+            if self.state.dots == 0 {
+                self.oam_addr_overflow = false;
+
+                if !std::mem::take(&mut self.vblank_suppressed) {
+                    self.status |= 0b10000000;
+
+                    if self.ctrl & 0b10000000 > 0 {
+                        if self.nmi_delay == 0 {
+                            self.raise_nmi();
+                        } else {
+                            self.nmi_countdown = Some(self.nmi_delay);
+                        }
+                    }
+                }
+            }
+
+            if self.state.dots == 340 {
+                self.state.scanline += 1;
+                self.state.dots = 0;
+
+                // Update the screen
+                if self.present_frames {
+                    if let Some(window) = self.window.as_mut() {
+                        window.update_with_buffer(&self.color_buffer, SCREEN_WIDTH, SCREEN_HEIGHT).expect("Failed to update screen!");
+                    }
+
+                    self.presented_count += 1;
+                }
+
+                self.frame_buffer = self.color_buffer;
+                self.color_buffer.fill(0);
+                self.frame_count += 1;
+
+                return
+            }
+        }
+
+        // Rest of Vblank (through scanline 260)
+        // The PPU doesn't do anything during these scanlines — just increases the clock (allows the PPU to change memory during Vblank)
+        if self.state.scanline < 261 && self.state.scanline >= 241 && self.state.dots == 340 {
+            self.state.scanline += 1;
+            self.state.dots = 0;
+
+            return
+        }
+
+        // Also fetches the first two tiles of the first scanline for the next frame
+        if self.state.scanline == 261 {
+            // Clearing the 3 flags in PPUSTATUS (0x2002) — like setting VBlank, this happens as dot 0 finishes so reads
+            // from 261,1 on see them clear
+            // The first time through is also when the PPU finishes warming up after power on
+            if self.state.dots == 0 {
+                self.status &= 0b0001_1111;
+                self.warmed_up = true;
+            }
+
+            self.oam_addr_rendering_tick();
+
+            if self.state.dots <= 336 {
+                // This will load the pattern shift registers with two tiles worth of data
+                // The attribute registers will have their second tile data stored in the self.state.attribute_data latch
+                self.continue_render();
+
+                if self.state.dots == 329 {
+                    self.shift_reload();
+                }
+
+                // Sprites are fetched here too even though nothing is drawn with them (mappers watching A12 count on it)
+                if self.rendering_enabled() && (257..=320).contains(&self.state.dots) && self.state.dots.is_multiple_of(8) {
+                    let addr = self.sprite_pattern_addr(0xFF, 0, 0);
+                    let _ = (self.read_byte(addr), self.read_byte(addr + 8));
+                }
+
+                // Copies the vertical scroll bits (fine y, coarse y, and the vertical nametable) from t back into v for the new frame
+                if self.rendering_enabled() && self.state.dots >= 280 && self.state.dots <= 304 {
+                    self.v = (self.v & !0x7BE0) | (self.t & 0x7BE0);
+                }
+
+                self.shift();
+            } else {
+                // The second prefetched tile goes in behind the first one
+                if self.state.dots == 337 {
+                    self.shift_reload();
+                }
 
-                // This value is what points to the red byte in palette_storage (+1 -> green and +2 -> blue)
-                self.palette_mem[mirrored_addr as usize] = data;
+                // Useless clock cycles spent accessing the third tiles nametable byte (only implemented to stay faithful)
+                self.dummy_nametable_fetch();
             }
 
-            _ => { println!("Attempted to write to read-only ppu space at address {:X}!", addr) }
+            // Reset the dots and scanline for the next frame (also sets the next frame to be even/odd)
+            // Also resets various other things
+            if self.state.dots == 340 {
+
+                self.state.even_odd_frame = !self.state.even_odd_frame;
+
+                self.state.dots = 0;
+                self.state.scanline = 0;
+
+                // Clearning sprite overflow and sprite zero hit
+                self.status &= !0x20;
+                self.status &= !0x40;
+
+                // The write latch is left alone — only a $2002 read clears it, so a $2005/$2006 pair can straddle frames
+
+                return 
+            }
         }
+        
+        self.state.dots += 1;
     }
 }
 
+// Length counter shared by the pulse, triangle, and noise channels — silences the channel when it reaches zero
+#[derive(Clone, Copy, Default)]
+pub struct LengthCounter {
+    enabled: bool, // Set through $4015, a disabled counter is held at zero
+    halt: bool, // Stops the counter from decrementing (doubles as the envelope loop/linear counter control flag)
+    value: u8,
+}
 
-// PPU struct to hold registers and the PPUBus
-// All of the registers have extensive documentation available on the NESDev wiki... they are far too complicated to fully describe here
-pub struct PPU {
-    oam: [u8; 256], // Object Attribute Memory, used to store sprites
-    secondary_oam: [u8; 32], // Buffer for the sprites currently being rendered on the *next* scanline
-    ctrl: u8, // > write - Important flags that control PPU operation
-    mask: u8, // > write - Handles different rendering states/color effects
-    status: u8, // < read - Reflects the current state of the PPU (useful for timing)
-    oam_addr: u8, // > write - CPU writes the address of OAM it wants to access here. Always returns to 0 after a frame
-    oam_data: u8, // <> read/write - (Writes increment oam_addr, reads do not). ONLY USE DURING VBLANK else corruption
-    scroll: u8, // >> write x2 - Tells PPU which pixel from nametable should be in top left - first is x scroll, second is y scroll
-    oam_dma: u8, // > write - Stores the page number of the CPU to start transferring data to OAM
-    v: u16, // During rendering, used for the scroll position. Outside of rendering, used as the current VRAM address
-    t: u16, // During rendering, specifies the starting coarse-x scroll and startying y scroll. Outside, holds scroll/VRAM addr --> v
-    x: u8, // The fine-x position of the current scroll, used during rendering alongside v
-    w: u8, // Indicates first or second write for PPUSCROLL or PPUADDR. Clears on reads of PPUSTATUS
-    low_pttrn_shift_reg: u16, // A group of shift registers, used to serialize bits for rendering
-    high_pttrn_shift_reg: u16,
-    low_attr_shift_reg: u8,
-    high_attr_shift_reg: u8,
-    ppu_latch: u8, // Serves as an address latch — the low 8 bits overlap with the data bus
-    vram_latch: u8, // Used to store read values when the PPU reads 0x2007
-    nmi: u8,
-    color_buffer: [u32; SCREEN_WIDTH * SCREEN_HEIGHT], // Stores the rgb colors of each pixel displayed each frame
-    pub state: PpuState, // Keeps the PPU state when alternating between the CPU and PPU
-    sprite_y: u8,
-    sprite_tile_number: u8,
-    sprite_attribute: u8,
-    sprite_x: u8,
-    back_pixel: u8, // Variable to store the generated background pixel every 8 cycles
-    sprite_pixel_buffer: [Sprite; 8*8], // Variable to store the generated sprite pixel from OAM (eight possible eight-pixel sprites)
-    sprite_buffer_addr: u8, // Address to store what sprite pixel currently storing at
-    pixel: u8, // Stores the current selected pixel between the background and sprite pixel
-    window: minifb::Window, // Window to display pixels to. FINALLY!!! Feels so good having made it this far I love this project so much :)
-    oam_addr_overflow: bool,
-    ppu_bus: PPUBus // Bus to communicate with PPU memory like VRAM and the palette memory
+impl LengthCounter {
+    fn new() -> Self {
+        LengthCounter { enabled: false, halt: false, value: 0 }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+
+        if !enabled {
+            self.value = 0;
+        }
+    }
+
+    // Loads the counter from the top five bits written to the channel's fourth register
+    fn load(&mut self, data: u8) {
+        if self.enabled {
+            self.value = LENGTH_TABLE[(data >> 3) as usize];
+        }
+    }
+
+    fn clock(&mut self) {
+        if !self.halt && self.value > 0 {
+            self.value -= 1;
+        }
+    }
 }
 
-pub struct PpuState {
-    nametable_data: u8, // These four are just state registers
-    attribute_data: u8,
-    low_bitplane: u8,
-    high_bitplane: u8,
-    attribute_latch: u8, // Used to store the current attribute byte being shifted in
-    pub scanline: u16, // These two store the current scanline and dot (cycle) data
-    pub dots: u16,
-    sprite_counter: u8, // Tracks how many sprites have been found for the current scanline
-    valid_sprite: bool, // Tracks if the sprite detected is valid and the program should fetch the rest of its data
-    secondary_oam_addr: u8, // Tracks the current address in secondary_oam
-    sprite_addr: u8, // Address for indexing the bytes after the y address for OAM
-    even_odd_frame: bool // Tracks whether the frame is even or odd (if even skip the very first cycle of every frame); true if even
+// Extra sound hardware on the cartridge (VRC6, MMC5, ...) — the console mixes its output in with the APU's
+pub trait ExpansionAudio {
+    fn sample(&mut self, cpu_cycles: u32) -> f32; // Runs the chip for the CPU cycles since the last sample and returns its output
 }
 
-#[derive(Clone, Copy)]
-pub struct Sprite {
-    x_coordinate: u8, // X coordinate of the sprite on the next scanline
-    pixel: u8, // Four bit pixel to encode pattern table and attribute table
-    priority: u8, // 0 if in front of background; 1 if behind background
-    horizontal_flip: u8, // 1 if sprite needs to be flipped horizontally
-    vertical_flip: u8, // 1 if sprite needs to be flipped vertically
+// Band-limited resampler from the CPU clock rate down to an audio device's rate (44.1/48kHz)
+// Two stages: a box filter averages groups of cycles down to a few times the output rate, then a Blackman windowed sinc
+// filter (precomputed for RESAMPLER_PHASES fractional offsets) low passes under the output Nyquist and picks the samples
+pub struct Resampler {
+    decimation: u32, // Input samples the first stage averages into one
+    sum: f32, // First stage total so far
+    summed: u32, // Input samples in sum
+    step: f64, // First stage samples per output sample
+    half_width: usize, // Kernel reach either side of the output position, in first stage samples
+    kernels: Vec<f32>, // RESAMPLER_PHASES + 1 kernels of 2 * half_width taps, for fractional offsets 0 through 1
+    history: VecDeque<f32>, // First stage samples the kernel can still reach
+    position: f64, // Where the next output sample falls, in first stage samples from the front of history
+    output: Vec<f32>, // Resampled audio not collected yet
 }
 
-impl PPU {
-    // Stand in initialization function — NEEDS TO BE REDONE LATER (probably)
-    pub fn init_ppu(chr_rom: Vec<u8>, mirroring: Mirroring, palette_storage: Vec<u8>, window: minifb::Window) -> Self {
-        PPU { 
-              oam: [0; 256],
-              secondary_oam: [0; 32],
-              ctrl: 0,
-              mask: 0,
-              status: 0,
-              oam_addr: 0,
-              oam_data: 0,
-              scroll: 0,
-              oam_dma: 0,
-              v: 0,
-              t: 0,
-              x: 0,
-              w: 0,
-              low_pttrn_shift_reg: 0,
-              high_pttrn_shift_reg: 0,
-              low_attr_shift_reg: 0,
-              high_attr_shift_reg: 0,
-              color_buffer: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
-              ppu_latch: 0,
-              vram_latch: 0,
-              nmi: 0,
-              state: PpuState { nametable_data: 0, attribute_data: 0, low_bitplane: 0, high_bitplane: 0, attribute_latch: 0, scanline: 0, dots: 0, sprite_counter: 0, valid_sprite: false, secondary_oam_addr: 0, sprite_addr: 0, even_odd_frame: true },
-              sprite_y: 0,
-              sprite_tile_number: 0,
-              sprite_attribute: 0,
-              sprite_x: 0,
-              back_pixel: 0,
-              sprite_pixel_buffer: [Sprite { x_coordinate: 0, pixel: 0, priority: 0, horizontal_flip: 0, vertical_flip: 0 }; 64],
-              sprite_buffer_addr: 0,
-              pixel: 0,
-              window,
-              oam_addr_overflow: false,
-              ppu_bus: PPUBus::new(chr_rom, mirroring, [0; NUM_PALETTE_REGISTERS], palette_storage) ,
+impl Resampler {
+    pub fn new(input_rate: f64, output_rate: f64) -> Self {
+        let decimation = (input_rate / (output_rate * RESAMPLER_OVERSAMPLE)).floor().max(1.0) as u32;
+        let stage_rate = input_rate / decimation as f64;
+
+        // Cutoff in cycles per first stage sample, and the kernel long enough to reach the wanted number of zero crossings
+        let cutoff = RESAMPLER_CUTOFF * output_rate / stage_rate;
+        let half_width = (RESAMPLER_ZERO_CROSSINGS / (2.0 * cutoff)).ceil() as usize;
+        let taps = half_width * 2;
+
+        let mut kernels = Vec::with_capacity((RESAMPLER_PHASES + 1) * taps);
+        for phase in 0..=RESAMPLER_PHASES {
+            let offset = phase as f64 / RESAMPLER_PHASES as f64;
+            let kernel: Vec<f64> = (0..taps).map(|tap| {
+                let x = tap as f64 - (half_width - 1) as f64 - offset;
+                let sinc = if x == 0.0 { 1.0 } else { (std::f64::consts::PI * 2.0 * cutoff * x).sin() / (std::f64::consts::PI * 2.0 * cutoff * x) };
+                let w = (x / half_width as f64 + 1.0) / 2.0; // Window position, 0 to 1 across the kernel
+                let window = 0.42 - 0.5 * (2.0 * std::f64::consts::PI * w).cos() + 0.08 * (4.0 * std::f64::consts::PI * w).cos();
+
+                sinc * window
+            }).collect();
+
+            // Every phase passes DC at exactly unity gain, so a constant level comes out unchanged
+            let gain: f64 = kernel.iter().sum();
+            kernels.extend(kernel.iter().map(|tap| (tap / gain) as f32));
+        }
+
+        Resampler {
+            decimation,
+            sum: 0.0,
+            summed: 0,
+            step: stage_rate / output_rate,
+            half_width,
+            kernels,
+            history: std::iter::repeat_n(0.0, taps).collect(),
+            position: (half_width - 1) as f64,
+            output: vec![],
+        }
+    }
+
+    // Takes one input sample (one CPU cycle of the mixer)
+    pub fn push(&mut self, sample: f32) {
+        self.sum += sample;
+        self.summed += 1;
+
+        if self.summed == self.decimation {
+            self.history.push_back(self.sum / self.decimation as f32);
+            self.sum = 0.0;
+            self.summed = 0;
+
+            self.filter();
+        }
+    }
+
+    // Everything resampled since the last call
+    pub fn take_output(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.output)
+    }
+
+    // Makes every output sample the history now reaches far enough for, then drops what no kernel needs anymore
+    fn filter(&mut self) {
+        let taps = self.half_width * 2;
+
+        while (self.position as usize) + self.half_width < self.history.len() {
+            let start = self.position as usize + 1 - self.half_width;
+            let phase_position = self.position.fract() * RESAMPLER_PHASES as f64;
+            let phase = phase_position as usize;
+            let blend = phase_position.fract() as f32;
+
+            let (before, after) = (&self.kernels[phase * taps..(phase + 1) * taps], &self.kernels[(phase + 1) * taps..(phase + 2) * taps]);
+            let sample: f32 = (0..taps)
+                .map(|tap| self.history[start + tap] * (before[tap] + (after[tap] - before[tap]) * blend))
+                .sum();
+
+            self.output.push(sample);
+            self.position += self.step;
+        }
+
+        while self.position >= self.half_width as f64 {
+            self.history.pop_front();
+            self.position -= 1.0;
+        }
+    }
+}
+
+// APU struct — so far only the frame sequencer and the length counters it drives (no sound output yet)
+// Index order of the channels: pulse one, pulse two, triangle, noise
+#[derive(Default)]
+pub struct APU {
+    length_counters: [LengthCounter; 4],
+    five_step_mode: bool, // $4017 bit 7 — selects the 5 step sequence (no frame IRQ)
+    irq_inhibit: bool, // $4017 bit 6 — stops the frame IRQ from being raised
+    frame_irq: bool, // Set at the end of the 4 step sequence, cleared by reading $4015
+    frame_cycle: u16, // CPU cycles since the frame sequencer last restarted
+    frame_reset_delay: u8, // CPU cycles left until a $4017 write restarts the sequencer (0 if none pending)
+    cycles: u64, // CPU cycles since power on — the parity decides the $4017 reset delay
+    expansion_audio: Option<Box<dyn ExpansionAudio>>, // Cartridge sound chip, if the game has one
+    last_sample_cycle: u64, // Value of cycles when the mixer was last sampled
+    resampler: Option<Resampler>, // Turns the mixer's output every cycle into audio at the output rate (None until one is set)
+}
+
+impl APU {
+    pub fn new() -> Self {
+        APU {
+            length_counters: [LengthCounter::new(); 4],
+            five_step_mode: false,
+            irq_inhibit: false,
+            frame_irq: false,
+            frame_cycle: 0,
+            frame_reset_delay: 0,
+            cycles: 0,
+            expansion_audio: None,
+            last_sample_cycle: 0,
+            resampler: None,
+        }
+    }
+
+    // Starts resampling the mixer to the audio device's rate (0 turns it back off) — take_samples collects the result
+    pub fn set_output_rate(&mut self, hz: u32) {
+        self.resampler = if hz == 0 { None } else { Some(Resampler::new(CPU_CLOCK_RATE, hz as f64)) };
+    }
+
+    // Audio made since the last call, at the rate given to set_output_rate
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        self.resampler.as_mut().map_or(vec![], |resampler| resampler.take_output())
+    }
+
+    pub fn length_counter(&self, channel: usize) -> u8 {
+        self.length_counters[channel].value
+    }
+
+    pub fn frame_irq(&self) -> bool {
+        self.frame_irq
+    }
+
+    // Plugs in (or removes) the cartridge's sound chip — mappers with expansion audio hand theirs over here
+    pub fn set_expansion_audio(&mut self, audio: Option<Box<dyn ExpansionAudio>>) {
+        self.expansion_audio = audio;
+    }
+
+    // The expansion audio chip isn't saved, it belongs to the cartridge
+    fn save_state(&self, state: &mut StateWriter) {
+        for counter in self.length_counters.iter() {
+            state.bool(counter.enabled);
+            state.bool(counter.halt);
+            state.u8(counter.value);
+        }
+
+        state.bool(self.five_step_mode);
+        state.bool(self.irq_inhibit);
+        state.bool(self.frame_irq);
+        state.u16(self.frame_cycle);
+        state.u8(self.frame_reset_delay);
+        state.u64(self.cycles);
+        state.u64(self.last_sample_cycle);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        for counter in self.length_counters.iter_mut() {
+            counter.enabled = state.bool()?;
+            counter.halt = state.bool()?;
+            counter.value = state.u8()?;
+        }
+
+        self.five_step_mode = state.bool()?;
+        self.irq_inhibit = state.bool()?;
+        self.frame_irq = state.bool()?;
+        self.frame_cycle = state.u16()?;
+        self.frame_reset_delay = state.u8()?;
+        self.cycles = state.u64()?;
+        self.last_sample_cycle = state.u64()?;
+
+        Ok(())
+    }
+
+    // One output sample from the mixer — the APU's own channels don't make any sound yet, so for now this is
+    // only the expansion audio (which is just added on top once they do, like the cartridge audio pin)
+    pub fn sample(&mut self) -> f32 {
+        let elapsed = (self.cycles - self.last_sample_cycle) as u32;
+        self.last_sample_cycle = self.cycles;
+
+        let apu_output = 0.0;
+
+        match self.expansion_audio.as_mut() {
+            Some(audio) => apu_output + audio.sample(elapsed),
+            None => apu_output
+        }
+    }
+
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            // Halt flags live in each channel's first register (bit 5, bit 7 for the triangle)
+            0x4000 => { self.length_counters[0].halt = data & 0b10_0000 != 0; },
+            0x4004 => { self.length_counters[1].halt = data & 0b10_0000 != 0; },
+            0x4008 => { self.length_counters[2].halt = data & 0b1000_0000 != 0; },
+            0x400C => { self.length_counters[3].halt = data & 0b10_0000 != 0; },
+
+            // Length counter loads
+            0x4003 => { self.length_counters[0].load(data); },
+            0x4007 => { self.length_counters[1].load(data); },
+            0x400B => { self.length_counters[2].load(data); },
+            0x400F => { self.length_counters[3].load(data); },
+
+            // Channel enables
+            APU_STATUS => {
+                for (channel, counter) in self.length_counters.iter_mut().enumerate() {
+                    counter.set_enabled(data & (1 << channel) != 0);
+                }
+            },
+
+            // Frame counter — the sequencer restarts 3 or 4 CPU cycles later depending on where the write lands in
+            // the APU cycle, but the 5 step mode's extra quarter/half frame clock happens straight away
+            APU_FRAME_COUNTER => {
+                self.five_step_mode = data & 0b1000_0000 != 0;
+                self.irq_inhibit = data & 0b0100_0000 != 0;
+
+                if self.irq_inhibit {
+                    self.frame_irq = false;
+                }
+
+                self.frame_reset_delay = if self.cycles.is_multiple_of(2) { 3 } else { 4 };
+
+                if self.five_step_mode {
+                    self.clock_half_frame();
+                }
+            },
+
+            _ => {}
         }
     }
 
-    fn read_byte(&mut self, addr: u16) -> u8 {
-        self.ppu_bus.mem_read(addr)
-    }
+    // $4015 read — length counter status in bits 0-3 and the frame IRQ in bit 6 (which the read acknowledges)
+    pub fn read_status(&mut self) -> u8 {
+        let status = self.status();
+        self.frame_irq = false;
 
-    // Doesn't increase dots because it's through the CPU
-    fn cpu_write_byte(&mut self, data: u8) {
-        self.ppu_bus.mem_write(self.v, data);
-        self.increment_vram();
+        status
     }
 
-    // Increments the VRAM address. Used after accessing a VRAM element through the CPU (writing to 0x2007)
-    fn increment_vram(&mut self) {
-        if self.ctrl & 0x4 > 0 {
-            self.v = (self.v + 32) & 0x3FFF; // Going down
-        } else {
-            self.v = (self.v + 1) & 0x3FFF; // Going across
+    // $4015 without acknowledging the frame IRQ
+    pub fn status(&self) -> u8 {
+        let mut status = 0;
+
+        for (channel, counter) in self.length_counters.iter().enumerate() {
+            if counter.value > 0 {
+                status |= 1 << channel;
+            }
         }
-    }
 
-    // Loads register t with VRAM address bytes through 0x2006 writes
-    fn load_addr_byte(&mut self, addr: u16) {
-        if self.w == 0 {
-            self.t = (self.t & !0b1111_1111_0000_0000) | ((addr << 8) & 0b1111_1111_0000_0000); // Storing the upper byte
-            self.w = 1; // Setting the write latch (saying we want to write to the lower byte now)
-        } else {
-            self.t = (self.t & !0b1111_1111) | (addr & 0b1111_1111); // Storing the lower byte
-            self.v = self.t;
-            self.w = 0;
+        if self.frame_irq {
+            status |= 0b0100_0000;
         }
-    }
 
-    // Sets oam data when called by CPU
-    fn oam_data_set(&mut self) {
-        self.oam[self.oam_addr as usize] = self.oam_data;
+        status
     }
 
-    // Loading t with the scroll data
-    fn load_scroll(&mut self, scroll_data: u8) {
-        if self.w == 0 {
-            self.x = scroll_data & 0b111;
-            self.t = (self.t & !0b1_1111) | ((scroll_data as u16) >> 3);
-            self.w = 1;
-        } else {
-            self.t = (self.t & !0b11_1110_0000) | (((scroll_data as u16) << 2) & 0b11_1110_0000);
-            self.t = (self.t & !0b111_0000_0000_0000) | (((scroll_data as u16) << 12) & 0b111_0000_0000_0000);
-            self.w = 0;
+    // Envelopes and the triangle's linear counter would be clocked here once the channels exist
+    fn clock_quarter_frame(&mut self) {}
+
+    // Half frames clock the length counters (and sweep units) along with everything a quarter frame does
+    fn clock_half_frame(&mut self) {
+        self.clock_quarter_frame();
+
+        for counter in self.length_counters.iter_mut() {
+            counter.clock();
         }
     }
 
-    // Returns the code of the palette table used for the current nametable address
-    fn fetch_attribute_data(&mut self, addr: u16) -> u8 {
-        let x = (addr % 0x400) % 32;
-        let y = (addr % 0x400) / 32;
-        let mut attribute_addr = 0x23C0 + (x / 4) + ((y / 4) * 8);
-
-        // Adjusts for mirroring
-        if self.ppu_bus.mirroring == Mirroring::HORIZONTAL {
-            if self.v > 0x2400 {
-                attribute_addr += 0x400 * 2; // Mirroring adjustment for second nametable
-            }
-        } else if self.ppu_bus.mirroring == Mirroring::VERTICAL {
-            if (self.v % 0x800) > 0x400 {
-                attribute_addr += 0x400; // Vertical mirroring adjustment
+    // Called once per CPU cycle
+    pub fn tick(&mut self) {
+        self.cycles += 1;
+
+        if self.frame_reset_delay > 0 {
+            self.frame_reset_delay -= 1;
+
+            if self.frame_reset_delay == 0 {
+                self.frame_cycle = 0;
             }
         }
 
-        let palette_value: u8 = self.read_byte(attribute_addr);
-        // Determine palette selection based on position within the 4x4 attribute block
-        let palette_choice: u8 = match ((x % 4) / 2, (y % 4) / 2) {
-            (0, 0) => palette_value & 0x3,  // Top-left quadrant
-            (1, 0) => (palette_value >> 2) & 0x3,  // Top-right quadrant
-            (0, 1) => (palette_value >> 4) & 0x3,  // Bottom-left quadrant
-            (1, 1) => (palette_value >> 6) & 0x3,  // Bottom-right quadrant
-            _ => 0, // This shouldn't happen
-        };
+        self.frame_cycle += 1;
 
-        return palette_choice
-    }
+        match (self.five_step_mode, self.frame_cycle) {
+            (_, FRAME_STEP_ONE) | (_, FRAME_STEP_THREE) => { self.clock_quarter_frame(); },
+            (_, FRAME_STEP_TWO) => { self.clock_half_frame(); },
 
-    /// Returns byte present in the pattern table, addr is id from the nametable
-    pub fn fetch_pattern_table(&self, high: bool, addr: u8) -> u8 {
-        let mut pattern_addr: u16 = 0;
+            (false, FRAME_STEP_FOUR) => {
+                self.clock_half_frame();
 
-        if self.ctrl & 0b0001_0000 != 0 {
-            pattern_addr |= 0b1_0000_0000_0000;
+                if !self.irq_inhibit {
+                    self.frame_irq = true;
+                }
+            },
+
+            (false, FRAME_FOUR_STEP_END) => { self.frame_cycle = 0; },
+            (true, FRAME_STEP_FIVE) => { self.clock_half_frame(); },
+            (true, FRAME_FIVE_STEP_END) => { self.frame_cycle = 0; },
+
+            _ => {}
         }
 
-        if high {
-            pattern_addr |= 0b1000
+        if self.resampler.is_some() {
+            let level = self.sample();
+
+            if let Some(resampler) = self.resampler.as_mut() {
+                resampler.push(level);
+            }
         }
+    }
+}
 
-        pattern_addr |= (addr as u16) << 4;
-        pattern_addr |= (self.v >> 12) & 0b111; // Extracting fine y address
+// Frame timing numbers for the frontend, updated every run_frame
+pub struct Stats {
+    pub frame_count: u64, // Frames emulated since power on
+    pub fps: f64, // Frames emulated per second of wall-clock time, measured over the last second
+    pub total_cycles: u64, // CPU cycles spent in run_frame since power on
+    fps_window_start: Instant, // When the current one-second FPS measurement started
+    fps_window_frames: u32, // Frames finished in the current FPS measurement
+}
 
-        return self.ppu_bus.mem_read(pattern_addr)
+impl Stats {
+    pub fn new() -> Self {
+        Stats {
+            frame_count: 0,
+            fps: 0.0,
+            total_cycles: 0,
+            fps_window_start: Instant::now(),
+            fps_window_frames: 0,
+        }
     }
 
-    // Loads the address into the PPU latch (multiplexed with bottom 8 address bits)
-    fn load_latch(&mut self, addr: u16) {
-        self.ppu_latch = (addr & 0xFF) as u8;
+    pub fn average_cycles_per_frame(&self) -> f64 {
+        if self.frame_count == 0 {
+            return 0.0
+        }
+
+        self.total_cycles as f64 / self.frame_count as f64
     }
 
-    fn nametable_fetch(&mut self, addr: u16) -> u8 {
-        let v = (addr & 0xFF00) | (self.ppu_latch as u16);
-        let tile_addr = 0x2000 | (v & 0x0FFF);
+    fn record_frame(&mut self, cycles: u64) {
+        self.frame_count += 1;
+        self.total_cycles += cycles;
+        self.fps_window_frames += 1;
 
-        return self.read_byte(tile_addr)
+        let elapsed = self.fps_window_start.elapsed().as_secs_f64();
+        if elapsed >= 1.0 {
+            self.fps = self.fps_window_frames as f64 / elapsed;
+            self.fps_window_start = Instant::now();
+            self.fps_window_frames = 0;
+        }
     }
+}
 
-    fn shift(&mut self) {
-        self.low_pttrn_shift_reg <<= 1;
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-        self.high_pttrn_shift_reg <<= 1;
+// Recent save states for rewinding, captured every few frames and stepped back through newest first
+// Only the newest state is kept whole — each older one is stored as its XOR against the state after it, with the runs of
+// zeros (everything that didn't change between the two captures, which is most of the console) left out
+pub struct RewindBuffer {
+    interval: u64, // Frames between captures, 0 turns capturing off
+    capacity: usize, // Most states kept — past this the oldest is dropped
+    latest: Option<Vec<u8>>, // Newest state
+    deltas: VecDeque<Vec<u8>>, // Older states, oldest first — applying the last one to latest gives the state before it
+}
 
-        // Allows for the storage of 16 bits of attribute data!!!!!!!!
-        self.low_attr_shift_reg <<= 1;
-        self.low_attr_shift_reg |= self.state.attribute_latch & 0b1;
+impl RewindBuffer {
+    pub fn new(interval: u64, capacity: usize) -> Self {
+        RewindBuffer { interval, capacity, latest: None, deltas: VecDeque::new() }
+    }
 
-        self.high_attr_shift_reg <<= 1;
-        self.high_attr_shift_reg |= self.state.attribute_latch & 0b10;
+    // Number of states that can be rewound to
+    pub fn len(&self) -> usize {
+        self.latest.iter().count() + self.deltas.len()
     }
 
-    fn shift_reload(&mut self) {
-        self.state.attribute_latch = self.state.attribute_data;
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
 
-        self.low_pttrn_shift_reg = self.state.low_bitplane as u16;
-        self.high_pttrn_shift_reg = self.state.high_bitplane as u16;
+    // Bytes held by the stored states, to see what the delta encoding saves
+    pub fn memory_used(&self) -> usize {
+        self.latest.as_ref().map_or(0, |latest| latest.len()) + self.deltas.iter().map(|delta| delta.len()).sum::<usize>()
     }
 
-    fn continue_render(&mut self) {
-        // We need to make it so that the attribute and nametable data from the sprites doesn't get overriden by these garbage fetches
-        match self.state.dots % 8 {
-            // 1, 3, 5, 7 are all used to store the address in the latch
-            1 => {
-                self.load_latch(self.v);
-            },
-            // Fetches the pattern table address from the nametable
-            2 => {
-                // If statement to prevent overriding the nametable data stored by sprite evaluation
-                if self.state.dots <= 256 {
-                    self.state.nametable_data = self.nametable_fetch(self.v);
-                }
-            },
-            3 => {
-                self.load_latch(self.v);
-            },
-            // Fetches the attribute data from the attribute table
-            4 => {
-                // If statement to prevent overriding the attribute data stored by sprite evaluation
-                if self.state.dots <= 256 {
-                    self.state.attribute_data = self.fetch_attribute_data(self.v);
-                }
-            },
-            5 => {
-                self.load_latch(self.v);
-            },
-            // Fetches the low pattern bitplane from the pattern table
-            6 => {
-                self.state.low_bitplane = self.fetch_pattern_table(false, self.state.nametable_data);
-            },
-            7 => {
-                self.load_latch(self.v);
-            },
-            // Fetches the high pattern bitplane from the pattern table
-            _ => {
-                self.state.high_bitplane = self.fetch_pattern_table(true, self.state.nametable_data);
-            },
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+    }
+
+    // Whether a state should be captured at the end of the given frame
+    fn due(&self, frame: u64) -> bool {
+        self.interval != 0 && self.capacity != 0 && frame.is_multiple_of(self.interval)
+    }
+
+    fn push(&mut self, state: Vec<u8>) {
+        if self.capacity == 0 {
+            return
         }
 
-        if self.state.dots > 8 && (self.state.dots - 1) % 8 == 0 {
-            // Takes care of x scrolling
-            // 31 is the horizontal limit of a nametable (5 bits)
-            if (self.v & 0b1_1111) == 31 {
-                self.v &= !0b1_1111;
-                self.v ^= 0x0400;
-            } else {
-                self.v += 1;
+        if let Some(latest) = self.latest.take() {
+            self.deltas.push_back(RewindBuffer::encode_delta(&latest, &state));
+
+            if self.deltas.len() >= self.capacity {
+                self.deltas.pop_front();
             }
         }
 
-        // Handles y scrolling at the end of the scanline
-        if self.state.dots == 256 {
-            let mut fine_y = (self.v & 0b111_0000_0000_0000) >> 12;
-            
-            // Fine y overflows into coarse y
-            // Row 29 is the limit vertically, but coarse y can be set out of bounds
-            if fine_y < 7 {
-                fine_y += 1;
-                self.v = (self.v & !0b111_0000_0000_0000) | (fine_y << 12);
-            } else {
-                // Resets fine y and overflows into coarse y
-                self.v &= !0b111_0000_0000_0000;
-                let mut coarse_y = (self.v & 0b11_1110_0000) >> 5;
+        self.latest = Some(state);
+    }
 
-                if coarse_y == 29 {
-                    coarse_y = 0;
-                    self.v ^= 0x0800;
-                } else if coarse_y == 31 {
-                    coarse_y = 0;
-                } else {
-                    coarse_y += 1;
-                }
+    // Takes the newest state out, rebuilding the one before it from its delta
+    fn pop(&mut self) -> Option<Vec<u8>> {
+        let latest = self.latest.take()?;
+        self.latest = self.deltas.pop_back().and_then(|delta| RewindBuffer::apply_delta(&latest, &delta).ok());
 
-                self.v = (self.v & !0b11_1110_0000) | (coarse_y << 5);
+        Some(latest)
+    }
+
+    // The older state's length, then (zero run, length prefixed run of XORed bytes) pairs until the whole state is covered
+    fn encode_delta(older: &[u8], newer: &[u8]) -> Vec<u8> {
+        let xor = |i: usize| older[i] ^ newer.get(i).copied().unwrap_or(0);
+        let mut delta = StateWriter::new();
+        delta.u32(older.len() as u32);
+
+        let mut i = 0;
+        while i < older.len() {
+            let zeros_start = i;
+            while i < older.len() && xor(i) == 0 {
+                i += 1;
+            }
+
+            let changed_start = i;
+            while i < older.len() && xor(i) != 0 {
+                i += 1;
             }
 
-            let coarse_xt = self.t & 0b1_1111;
-            self.v = (self.v & !0b1_1111) | coarse_xt;
+            delta.u32((changed_start - zeros_start) as u32);
+            delta.section(&(changed_start..i).map(xor).collect::<Vec<u8>>());
         }
+
+        delta.data
     }
 
-    // Function to handle sprite evaluation and loading the secondary OAM buffer
-    fn sprite_evaluation_tick(&mut self) {
-        if self.state.dots == 0 {
-            return
-        }
+    fn apply_delta(newer: &[u8], delta: &[u8]) -> Result<Vec<u8>, StateError> {
+        let base = |i: usize| newer.get(i).copied().unwrap_or(0);
+        let mut delta = StateReader::new(delta);
+        let len = delta.u32()? as usize;
+        let mut older = Vec::with_capacity(len);
 
-        if self.state.dots <= 320 && self.state.dots > 256 {
-            if self.state.dots == 257 {
-                self.state.secondary_oam_addr = 0;
-            }
+        while !delta.at_end() {
+            let zeros = delta.u32()? as usize;
+            let start = older.len();
+            older.extend((start..start + zeros).map(base));
 
-            println!("dots: {}", self.state.dots);
-            match self.state.dots % 8 {
-                1 => {
-                    self.sprite_y = self.secondary_oam[self.state.secondary_oam_addr as usize];
-                    self.state.secondary_oam_addr += 1;
-                },
-                2 => {
-                    self.state.nametable_data = self.secondary_oam[self.state.secondary_oam_addr as usize];
-                    self.state.secondary_oam_addr += 1;
-                },
-                3 => {
-                    self.state.attribute_data = self.secondary_oam[self.state.secondary_oam_addr as usize];
-                    self.state.secondary_oam_addr += 1;
-                },
-                4 | 5 | 6 | 7 | 8 => {
-                    self.sprite_x = self.secondary_oam[self.state.secondary_oam_addr as usize];
-                },
-                _ => {
-                    self.state.secondary_oam_addr += 1;
-                }
+            for byte in delta.section()? {
+                older.push(base(older.len()) ^ byte);
             }
+        }
 
-            if self.state.dots == 320 {
-                self.state.secondary_oam_addr = 0;
-            }
+        Ok(older)
+    }
+}
+
+// Reasons the CPU stopped running a program — a bounded run giving up, or an instruction it couldn't execute
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunError {
+    InstructionLimit(usize), // Ran the given number of instructions without finishing (probably stuck in a loop)
+    Jammed(u16), // Hit a KIL opcode at the given address
+    UnsupportedOpcode(u8, u16), // Opcode the CPU doesn't implement, and the address it was fetched from
+}
+
+impl std::fmt::Display for RunError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RunError::InstructionLimit(limit) => write!(f, "Still running after {} instructions", limit),
+            RunError::Jammed(addr) => write!(f, "CPU jammed by the opcode at {:04X}", addr),
+            RunError::UnsupportedOpcode(opcode, addr) => write!(f, "Unsupported opcode {:02X} at {:04X}", opcode, addr),
+        }
+    }
+}
+
+// Register file compared between this CPU and a reference core in lockstep runs
+#[cfg(feature = "lockstep")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Registers {
+    pub accumulator: u8,
+    pub x: u8,
+    pub y: u8,
+    pub pc: u16,
+    pub sp: u8,
+    pub status: u8,
+}
+
+#[cfg(feature = "lockstep")]
+impl std::fmt::Display for Registers {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PC:{:04X}", self.accumulator, self.x, self.y, self.status, self.sp, self.pc)
+    }
+}
+
+// Another 6502 core (e.g. the mos6502 crate behind an adapter) to run next to this one
+// It has to be set up with the same program, memory and registers before the run starts
+#[cfg(feature = "lockstep")]
+pub trait ReferenceCpu {
+    fn step(&mut self); // Runs one instruction
+    fn registers(&self) -> Registers;
+    fn peek(&self, addr: u16) -> u8;
+}
+
+// First place the two cores disagreed — the instruction that caused it and both sides of the difference
+#[cfg(feature = "lockstep")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub instruction: usize, // How many instructions had run in lockstep before this one
+    pub pc: u16, // Address of the instruction that diverged
+    pub opcode: u8,
+    pub before: Registers, // Registers both cores agreed on going into the instruction
+    pub ours: Registers,
+    pub reference: Registers,
+    pub memory: Option<(u16, u8, u8)>, // First RAM address that differs, with our byte and the reference's
+}
+
+#[cfg(feature = "lockstep")]
+impl std::fmt::Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "Diverged on instruction {} — opcode {:02X} at {:04X}", self.instruction, self.opcode, self.pc)?;
+        writeln!(f, "  before:    {}", self.before)?;
+        writeln!(f, "  ours:      {}", self.ours)?;
+        write!(f, "  reference: {}", self.reference)?;
+
+        if let Some((addr, ours, reference)) = self.memory {
+            write!(f, "\n  memory:    {:04X} is {:02X}, reference has {:02X}", addr, ours, reference)?;
+        }
+
+        Ok(())
+    }
+}
+
+// One memory access by the CPU, handed to the bus access hook as it happens — the PPU position is from just before the
+// access, so a $2002 read shows exactly which dot it saw the status on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BusAccess {
+    pub addr: u16,
+    pub value: u8, // Byte written, or the byte the CPU got back
+    pub write: bool,
+    pub cycle: usize, // CPU cycles run since power on, counting this access
+    pub scanline: u16,
+    pub dot: u16,
+}
+
+// Where an instruction finds its operand — read-modify-write instructions (ASL, LSR, ROL, ROR, DEC, INC) decode it
+// from bbb, and read instructions get theirs from get_operand
+// Accumulator mode is the bbb == 2 slot of the read-modify-write opcodes — it works on A instead of memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AddressingMode {
+    Accumulator,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    IndirectX,
+    IndirectY,
+}
+
+impl AddressingMode {
+    // Decodes the bbb bits of a cc == 2 read-modify-write opcode — the other slots aren't shifts, rotates, DEC or INC
+    fn read_modify_write(bbb: u8) -> Option<AddressingMode> {
+        match bbb {
+            1 => Some(AddressingMode::ZeroPage),
+            2 => Some(AddressingMode::Accumulator),
+            3 => Some(AddressingMode::Absolute),
+            5 => Some(AddressingMode::ZeroPageX),
+            7 => Some(AddressingMode::AbsoluteX),
+            _ => None
+        }
+    }
+}
+
+// How an instruction's operand is written out by the disassembler — every 6502 addressing mode, unlike AddressingMode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OperandFormat {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    Indirect,
+    IndirectX,
+    IndirectY,
+    Relative,
+}
+
+impl OperandFormat {
+    // Bytes following the opcode
+    fn operand_bytes(self) -> u16 {
+        match self {
+            OperandFormat::Implied | OperandFormat::Accumulator => 0,
+            OperandFormat::Absolute | OperandFormat::AbsoluteX | OperandFormat::AbsoluteY | OperandFormat::Indirect => 2,
+            _ => 1
+        }
+    }
+
+    // Operand in the usual assembler syntax — addr is where the opcode is, so branches can show where they go
+    fn format(self, addr: u16, low: u8, high: u8) -> String {
+        let word = u16::from_le_bytes([low, high]);
+
+        match self {
+            OperandFormat::Implied => String::new(),
+            OperandFormat::Accumulator => "A".to_string(),
+            OperandFormat::Immediate => format!("#${:02X}", low),
+            OperandFormat::ZeroPage => format!("${:02X}", low),
+            OperandFormat::ZeroPageX => format!("${:02X},X", low),
+            OperandFormat::ZeroPageY => format!("${:02X},Y", low),
+            OperandFormat::Absolute => format!("${:04X}", word),
+            OperandFormat::AbsoluteX => format!("${:04X},X", word),
+            OperandFormat::AbsoluteY => format!("${:04X},Y", word),
+            OperandFormat::Indirect => format!("(${:04X})", word),
+            OperandFormat::IndirectX => format!("(${:02X},X)", low),
+            OperandFormat::IndirectY => format!("(${:02X}),Y", low),
+            OperandFormat::Relative => format!("${:04X}", addr.wrapping_add(2).wrapping_add(low as i8 as u16)),
+        }
+    }
+}
+
+// Mnemonic and operand format of an opcode, split up into aaa bbb cc the same way decode does
+// None for the opcodes decode doesn't implement (it hands those back as RunError::UnsupportedOpcode)
+fn disassembly_format(opcode: u8) -> Option<(&'static str, OperandFormat)> {
+    use OperandFormat::*;
+
+    let aaa = (opcode >> 5) as usize & 0b111;
+    let bbb = (opcode >> 2) as usize & 0b111;
+    let cc = opcode & 0b11;
+
+    Some(match (aaa, bbb, cc) {
+        // Unofficial opcodes — starred like they are in the nestest log
+        (0..=3, 0, 2) | (_, 4, 2) => ("*KIL", Implied),
+        (0..=3 | 6 | 7, 6, 2) => ("*NOP", Implied),
+        (4, 0, 0) | (4, 0, 2) | (4, 2, 1) | (6, 0, 2) | (7, 0, 2) => ("*NOP", Immediate),
+        (0 | 2 | 3, 1, 0) => ("*NOP", ZeroPage),
+        (0..=3 | 6 | 7, 5, 0) => ("*NOP", ZeroPageX),
+        (0, 3, 0) => ("*NOP", Absolute),
+        (0..=3 | 6 | 7, 7, 0) => ("*NOP", AbsoluteX),
+        (0 | 1, 2, 3) => ("*ANC", Immediate),
+        (2, 2, 3) => ("*ALR", Immediate),
+        (3, 2, 3) => ("*ARR", Immediate),
+        (6, 2, 3) => ("*AXS", Immediate),
+
+        // The rest of the cc == 3 opcodes, SHY ($9C) and SHX ($9E) aren't implemented
+        (_, _, 3) | (4, 7, 0) | (4, 7, 2) => return None,
+
+        (0, 0, 0) => ("BRK", Implied),
+        (1, 0, 0) => ("JSR", Absolute),
+        (2, 0, 0) => ("RTI", Implied),
+        (3, 0, 0) => ("RTS", Implied),
+        (_, 0, 0) => (["LDY", "CPY", "CPX"][aaa - 5], Immediate),
+        (_, 2, 0) => (["PHP", "PLP", "PHA", "PLA", "DEY", "TAY", "INY", "INX"][aaa], Implied),
+        (_, 4, 0) => (["BPL", "BMI", "BVC", "BVS", "BCC", "BCS", "BNE", "BEQ"][aaa], Relative),
+        (_, 6, 0) => (["CLC", "SEC", "CLI", "SEI", "TYA", "CLV", "CLD", "SED"][aaa], Implied),
+        (2, 3, 0) => ("JMP", Absolute),
+        (3, 3, 0) => ("JMP", Indirect),
+        (_, _, 0) => {
+            let format = match bbb { 1 => ZeroPage, 3 => Absolute, 5 => ZeroPageX, _ => AbsoluteX };
+            (["", "BIT", "", "", "STY", "LDY", "CPY", "CPX"][aaa], format)
+        },
+
+        (_, _, 1) => {
+            let format = match bbb {
+                0 => IndirectX,
+                1 => ZeroPage,
+                2 => Immediate,
+                3 => Absolute,
+                4 => IndirectY,
+                5 => ZeroPageX,
+                6 => AbsoluteY,
+                _ => AbsoluteX
+            };
+            (["ORA", "AND", "EOR", "ADC", "STA", "LDA", "CMP", "SBC"][aaa], format)
+        },
+
+        (5, 0, 2) => ("LDX", Immediate),
+        (0..=3, 2, 2) => (["ASL", "ROL", "LSR", "ROR"][aaa], Accumulator),
+        (_, 2, 2) => (["TXA", "TAX", "DEX", "NOP"][aaa - 4], Implied),
+        (4, 6, 2) => ("TXS", Implied),
+        (5, 6, 2) => ("TSX", Implied),
+        _ => {
+            // STX and LDX index with Y instead of X
+            let format = match (bbb, aaa) {
+                (1, _) => ZeroPage,
+                (3, _) => Absolute,
+                (5, 4 | 5) => ZeroPageY,
+                (5, _) => ZeroPageX,
+                (_, 5) => AbsoluteY,
+                _ => AbsoluteX
+            };
+            (["ASL", "ROL", "LSR", "ROR", "STX", "LDX", "DEC", "INC"][aaa], format)
+        },
+    })
+}
+
+// CPU struct to hold registers and the CPUBus
+// Generic over the bus so tests can drive it with a mock and tools can wrap the real bus — normally it's the console's CPUBus
+pub struct CPU<B: Bus = CPUBus> {
+    cpu_clk: usize, // Clock used to coordinate with the CPU, since they run in parallel (Usually 1 CPU Cycle = 3 PPU Cycles)
+    pub accumulator: u8, // Allows the use of the status register for overflow detection, carrying, etc.
+    pub x: u8, // Both x and y are used for addressing
+    pub y: u8,
+    pub pc: u16, // Program counter - allows for 65,536 memory locations
+    pub sp: u8, // Allows for indexing into a 256 byte stack,
+    pub status: u8, // Status register - 6 bits that each encode different meanings --> NV1B DIZC (Negative, Overflow, Decimal, Interrupt Disable, Zero, Carry) skipping B
+    pub cpu_bus: B, // 64 KiB of memory; stack goes from 0x100 to 0x1FF, descending (top to bottom), and empty (sp points to next available space)
+    ppu_span: ((u16, u16), (u16, u16)), // PPU (scanline, dots) when the last instruction started and finished — for debugging raster timing
+    nmi_serviced_count: u64, // Number of NMIs the CPU has jumped to the handler for
+    last_nmi_frame: u64, // PPU frame the last NMI was serviced in, used to catch more than one NMI per frame
+    nmis_this_frame: u32, // NMIs serviced so far in last_nmi_frame
+    double_nmi_frames: u64, // Frames that got more than one NMI serviced
+    on_double_nmi: Option<Box<dyn FnMut(u64)>>, // Told the frame number the first time a frame gets a second NMI (None when nobody's listening)
+    jammed: bool, // Set by the KIL opcodes — the CPU stops executing until it's reset
+    irq_line: bool, // IRQ held by something outside the CPU and APU (cartridge hardware, tests)
+    irq_pending: bool, // Result of the IRQ poll at the end of the last instruction — the IRQ is taken before the next one
+    stats: Stats, // Frame timing, updated by run_frame
+    dmc_read_glitch: bool, // Whether a DMC DMA repeats the read it halts (the controller bit deletion bug) — off by default
+    rewind: RewindBuffer, // States run_frame captures for rewind_step to go back to (off until set_rewind is called)
+    trace_sink: Option<Box<dyn Write>>, // Gets a line for every instruction executed (None when tracing is off)
+    on_bus_access: Option<Box<dyn FnMut(BusAccess)>>, // Told about every read and write as it happens, mid-instruction (None when nobody's listening)
+}
+
+impl CPU<CPUBus> {
+    pub fn init_cpu(mapper: Rc<RefCell<dyn Mapper>>, ppu: PPU) -> Self {
+        CPU::with_bus(CPUBus::new(mapper, ppu))
+    }
 
-            return
+    // Swaps in a different game — the mapper, bus and PPU are rebuilt and the console powers back on, but the window,
+    // palette, controllers and debugging hooks carry over so the frontend doesn't have to set everything up again
+    pub fn insert_cartridge(&mut self, rom: Rom) -> Result<(), String> {
+        let mapper = rom.create_mapper()?;
+
+        let palette_storage = self.cpu_bus.ppu.ppu_bus.palette_storage.clone();
+        let window = self.cpu_bus.ppu.window.take();
+        let ppu = PPU::init_ppu(mapper.clone(), palette_storage, window);
+
+        let mut cpu = CPU::init_cpu(mapper, ppu);
+        cpu.cpu_bus.set_system(rom.system);
+        cpu.cpu_bus.set_vs_dip_switches(self.cpu_bus.vs_dip_switches);
+        std::mem::swap(&mut cpu.cpu_bus.input, &mut self.cpu_bus.input);
+        std::mem::swap(&mut cpu.cpu_bus.bios, &mut self.cpu_bus.bios);
+        if cpu.has_bios() {
+            cpu.pc = cpu.cpu_bus.mem_read_u16(0xFFFC);
         }
+        std::mem::swap(&mut cpu.trace_sink, &mut self.trace_sink);
+        std::mem::swap(&mut cpu.on_bus_access, &mut self.on_bus_access);
+        std::mem::swap(&mut cpu.on_double_nmi, &mut self.on_double_nmi);
+        #[cfg(feature = "bus-conflicts")]
+        std::mem::swap(&mut cpu.cpu_bus.on_bus_conflict, &mut self.cpu_bus.on_bus_conflict);
+        std::mem::swap(&mut cpu.cpu_bus.ppu.ppu_bus.on_illegal_ppu_write, &mut self.cpu_bus.ppu.ppu_bus.on_illegal_ppu_write);
+        *self = cpu;
+
+        Ok(())
+    }
 
-        if self.state.dots <= 340 && self.state.dots > 320 {
-            // Reading the first byte in secondary OAM while the PPU fetches the first two background tiles
-            let _ = self.secondary_oam[self.state.secondary_oam_addr as usize];
+    // Maps a boot ROM over the top of $8000-$FFFF, for systems (FDS, arcade boards) that don't ship their boot code on
+    // the cartridge — reads there go to the BIOS instead of the mapper, writes still reach the mapper's registers
+    // It brings its own reset vector, so the CPU restarts from it
+    pub fn set_bios(&mut self, data: Vec<u8>) -> Result<(), BiosError> {
+        if data.is_empty() {
+            return Err(BiosError::Empty)
         }
 
-        if self.state.dots % 2 == 0 {
-            // In the first 64 cycles the secondary OAM buffer is filled with 0xFF regardless
-            if self.state.dots < 64 {
-                self.secondary_oam[self.state.secondary_oam_addr as usize] = 0xFF;
-                self.state.secondary_oam_addr += 1;
-                return
-            } else if self.state.dots == 64 {
-                self.secondary_oam[self.state.secondary_oam_addr as usize] = 0xFF;
-                self.state.secondary_oam_addr = 0;
-                return
-            }
+        if data.len() > 0x8000 {
+            return Err(BiosError::TooBig(data.len()))
+        }
 
-            // Through cycle 256, it then loads the next eight sprites from the scanline into the secondary OAM buffer
-            // Ignores the write if there are already full 8 sprites in the buffer
-            if self.state.dots <= 256 && self.state.dots > 64 && !self.oam_addr_overflow {
-                // Checks if the y-byte received is in a valid range, and if so allow for the next bytes to be fetched
-                // Evaluating sprites on the *next* scanline remember
-                if self.oam_data as u16 <= self.state.scanline + 1 && self.state.scanline + 1 < (self.oam_data as usize + SPRITE_HEIGHT) as u16 && !self.state.valid_sprite {
-                    self.state.valid_sprite = true;
-                }
+        self.cpu_bus.bios = Some(data);
+        self.pc = self.cpu_bus.mem_read_u16(0xFFFC);
 
-                if self.state.sprite_counter < 8 {
-                    if self.state.secondary_oam_addr < 32 {
-                        self.secondary_oam[self.state.secondary_oam_addr as usize] = self.oam_data;
+        Ok(())
+    }
 
-                        self.state.secondary_oam_addr += 1;
-                    } else {
-                        // Reads the value in secondary_oam if it is full
-                        let _ = self.secondary_oam[0];
-                    }
-                } else {
-                    // Evaluates once 8 sprites are stored in the secondary oam buffer
-                    self.state.sprite_addr = 0;
+    pub fn has_bios(&self) -> bool {
+        self.cpu_bus.bios.is_some()
+    }
 
-                    if self.state.valid_sprite {
-                        self.status |= 0b0010_0000;
-                        self.oam_addr = self.oam_addr.wrapping_add(1);
+    // CPU on a bus where every address is plain read/write RAM (how single instruction tests like Tom Harte's expect memory to behave)
+    // There's still a blank cartridge and PPU behind it so the clocks tick the same, they just can't be reached from the CPU
+    pub fn with_flat_memory() -> Self {
+        let blank_rom = Rom::from_parts(vec![0; PRG_PAGE_SIZE], vec![], 0, Mirroring::HORIZONTAL);
+        let mapper: Rc<RefCell<dyn Mapper>> = Rc::new(RefCell::new(Nrom::new(&blank_rom)));
+        let ppu = PPU::init_ppu(mapper.clone(), vec![0; 64 * 3], None);
 
-                        // IMPORTANT: This is a bug that renders the overflow sprite register unstable
-                        // Only doing this in order to stay accurate to the original NTSC PPU in the NES
-                        // Of course some masochist games actually *exploit* the fact that this bug exists
-                        self.state.sprite_addr += 1;
-                    }
-                }
-            }
-        } else {
-            if self.oam_addr == 64 {
-                self.oam_addr = 0;
-                self.oam_addr_overflow = true;
-            }
+        let mut cpu = CPU::init_cpu(mapper, ppu);
+        cpu.cpu_bus.flat_memory = true;
+        cpu
+    }
 
-            if self.state.valid_sprite {
-                self.state.sprite_addr += 1;
-            }
+    // Runs instructions until the PPU sends the next frame to the window
+    pub fn run_frame(&mut self) -> Result<(), RunError> {
+        let frame = self.cpu_bus.ppu.frame_count();
+        let start_clk = self.cpu_clk;
 
-            if self.state.sprite_addr == 4 {
-                self.state.valid_sprite = false;
-                self.state.sprite_counter += 1;
-            }
+        while self.cpu_bus.ppu.frame_count() == frame {
+            self.decode()?;
+        }
 
-            self.oam_data = self.oam[((4 * self.oam_addr as usize) + self.state.sprite_addr as usize) as usize];
+        self.stats.record_frame((self.cpu_clk - start_clk) as u64);
 
-            if !self.state.valid_sprite {
-                self.state.sprite_addr = 0;
-                self.oam_addr += 1;
-            }
+        if self.rewind.due(self.cpu_bus.ppu.frame_count()) {
+            self.capture_rewind_state();
         }
+
+        Ok(())
     }
 
-    // Compares the background pixel with all the sprite pixels to see if there is an overlap
-    // 0 if sprite pixel is selected; 1 if background pixel is selected
-    fn compare_against_sprites(&mut self) {
-        for sprite in self.sprite_pixel_buffer {
-            if sprite.x_coordinate == self.state.dots as u8 {
-                if sprite.pixel & 0b11 != 0 {
-                    if sprite.priority == 0 {
-                        // Logical and with 0b0001_0000 because a 5th bit of one acceses the sprite palette tables
-                        self.pixel = sprite.pixel & 0b0001_0000;
-                        return
-                    }
-                } else if self.back_pixel &0b11 == 0{
-                    self.pixel = sprite.pixel;
-                    return
-                }
-            }
-        }
+    // Captures a state every interval frames, keeping the last capacity of them for rewind_step — 0 for either turns it off
+    pub fn set_rewind(&mut self, interval: u64, capacity: usize) {
+        self.rewind = RewindBuffer::new(interval, capacity);
+    }
 
-        self.pixel = self.back_pixel;
+    pub fn rewind_buffer(&self) -> &RewindBuffer {
+        &self.rewind
     }
 
-    fn fetch_rgb(&self) -> (u8, u8, u8) {
-        let palette_addr = self.ppu_bus.mem_read(PALETTE_RAM_BEGIN as u16 + self.pixel as u16) as usize;
+    // Adds the console as it is now to the rewind buffer (run_frame does this by itself every interval frames)
+    pub fn capture_rewind_state(&mut self) {
+        let state = self.save_state();
+        self.rewind.push(state);
+    }
 
-        let r = self.ppu_bus.palette_storage[palette_addr * 3];
-        let g = self.ppu_bus.palette_storage[(palette_addr * 3) + 1];
-        let b = self.ppu_bus.palette_storage[(palette_addr * 3) + 2];
+    // Goes back to the most recently captured state, taking it out of the buffer — false once there's nothing left
+    pub fn rewind_step(&mut self) -> bool {
+        match self.rewind.pop() {
+            Some(state) => self.load_state(&state).is_ok(),
+            None => false
+        }
+    }
 
-        return (r, g, b)
+    // Runs skip + 1 frames but only sends the last one to the window — the emulation (and audio) carries on as normal,
+    // it just saves the cost of presenting frames nobody needs to see on a slow machine or while fast-forwarding
+    pub fn run_frame_skip(&mut self, skip: usize) -> Result<(), RunError> {
+        self.skip_frames(skip)?;
+        self.run_frame()
     }
 
-    // Note that the data for the first two tiles should already be fetched from previous scanline
-    // Sprites cannot be rendered on the first scanline
-    pub fn ppu_tick(&mut self) {
-        // println!("Scanline: {}", self.state.scanline);
-   
-        // Visible scanlines
-        if self.state.scanline < 240 {
-            // If it's an even frame, the very first cycle is skipped — helps with smoothness when not scrolling
-            // Slightly offsets the video signal
-            if self.state.even_odd_frame && self.state.dots == 0 && self.state.scanline == 0 {
-                // No return statement because we *do* want it to run cycle 1 since cycle 0 is skipped
-                self.state.dots += 1;
-            } else if self.state.dots == 0 && self.state.scanline == 0 {
-                self.state.dots += 1;
-                return
-            }
+    // Runs the given number of frames without sending any of them to the window — for getting through a game's boot and
+    // intro as fast as the emulator can go
+    pub fn skip_frames(&mut self, frames: usize) -> Result<(), RunError> {
+        self.cpu_bus.ppu.present_frames = false;
+        let skipped = (0..frames).try_for_each(|_| self.run_frame());
+        self.cpu_bus.ppu.present_frames = true;
 
-            if self.state.dots <= 256 {
-                self.continue_render();
-                self.sprite_evaluation_tick();
-                
-                // Every 8 cycles 8 new pixels can be constructed
-                if self.state.dots > 8 && (self.state.dots - 1) % 8 == 0 {
-                    // Loads the shift registers with values from last 8 cycles
-                    self.shift_reload();
-                }
+        skipped
+    }
 
-                // Assembling a four bit pixel based on the fine x register
-                // 0 bit: Low pattern table, 1 bit: High pattern table, 2 bit: Low palette bit, 3 bit: High palette bit
-                self.back_pixel = (((self.low_pttrn_shift_reg >> (15 - self.x)) & 0x1) as u8)
-                                    | ((((self.high_pttrn_shift_reg >> (15 - self.x)) & 0x1) << 1) as u8)
-                                    | (((self.low_attr_shift_reg >> (7 - self.x)) & 0x1) << 2)
-                                    | (((self.high_attr_shift_reg >> (7 - self.x)) & 0x1) << 3);
+    // Runs instructions until the PPU moves onto another scanline (useful for stepping through a frame in a debugger)
+    pub fn step_scanline(&mut self) -> Result<(), RunError> {
+        let scanline = self.cpu_bus.ppu.state.scanline;
 
-                // println!("self.back_pixel: {:0b}", self.back_pixel);
+        while self.cpu_bus.ppu.state.scanline == scanline {
+            self.decode()?;
+        }
 
-                if self.state.scanline > 0 {
-                    self.compare_against_sprites();
-                } else {
-                    self.pixel = self.back_pixel;
-                }
+        Ok(())
+    }
 
-                let (r, g, b) = self.fetch_rgb();
-                // println!("r: {}, g: {}, b: {}", r, g, b);
-                let rgb_value = ((r as u32) << 16) | ((g as u32) << 8) | b as u32;
+    pub fn step_frame(&mut self) -> Result<(), RunError> {
+        self.run_frame()
+    }
 
-                // Stores the color output of the pixel in a buffer
-                let index = (self.state.scanline * 256 + self.state.dots) as usize;
+    // Runs one frame per entry in the log, setting the controllers before each like the frontend does with the keyboard
+    // Doesn't need a window, so tests and movie playback can drive a headless console
+    pub fn play_input_log(&mut self, log: &InputLog) -> Result<(), RunError> {
+        for frame in 0..log.len() {
+            log.apply(frame, &mut self.cpu_bus.input);
+            self.run_frame()?;
+        }
 
-                if index < self.color_buffer.len() {
-                    self.color_buffer[index] = rgb_value;
-                }
+        Ok(())
+    }
 
-                self.shift();
-            } else if self.state.dots <= 320 {
-                // Garbage nametable fetches — allows for reusing circuitry in hardware
-                self.continue_render();
-                self.sprite_evaluation_tick();
+    // Runs instructions until the PPU enters VBlank (scanline 241 once the VBlank flag has gone up) — the point where games
+    // read the controllers, so input can be injected at the same moment every frame. Already in VBlank runs to the next one
+    pub fn run_to_vblank(&mut self) -> Result<(), RunError> {
+        while self.cpu_bus.ppu.state.scanline == 241 {
+            self.decode()?;
+        }
 
-                if self.state.dots > 8 && (self.state.dots - 1) % 8 == 0 {
-                    self.shift_reload();
-                }
+        while self.cpu_bus.ppu.state.scanline != 241 || self.cpu_bus.ppu.state.dots <= 1 {
+            self.decode()?;
+        }
 
-                self.sprite_pixel_buffer[self.sprite_buffer_addr as usize] = Sprite{ x_coordinate: self.sprite_x + self.x, 
-                    pixel: (((self.low_pttrn_shift_reg >> 15 - self.x) & 0x1) as u8)
-                         | ((((self.high_pttrn_shift_reg >> 15 - self.x) & 0x1) << 1) as u8)
-                         | (((self.low_attr_shift_reg >> 7 - self.x) & 0x1) << 2)
-                         | (((self.high_attr_shift_reg >> 7 - self.x) & 0x1) << 3),
-                    priority: ((self.state.attribute_data & 0b10_0000) > 0) as u8,
-                    horizontal_flip: ((self.state.attribute_data & 0b100_0000) > 0) as u8,
-                    vertical_flip: ((self.state.attribute_data & 0b100_0000) > 0) as u8 };
-                
-                self.sprite_buffer_addr += 1;
+        Ok(())
+    }
 
-                self.shift();
-            } else if self.state.dots <= 336 {
-                // This will load the pattern shift registers with two tiles worth of data
-                // The attribute registers will have their second tile data stored in the self.state.attribute_data latch
-                self.continue_render();
+    // Snapshot of the whole console (CPU, RAM, PPU, APU and the cartridge's banks/RAM) that load_state can go back to
+    // Starts with the state tag and version, then each component in its own length prefixed section
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut cpu = StateWriter::new();
+        cpu.u64(self.cpu_clk as u64);
+        cpu.bytes(&[self.accumulator, self.x, self.y]);
+        cpu.u16(self.pc);
+        cpu.bytes(&[self.sp, self.status]);
+        cpu.u64(self.nmi_serviced_count);
+        cpu.u64(self.last_nmi_frame);
+        cpu.bool(self.jammed);
+        cpu.bool(self.irq_line);
+        cpu.bool(self.irq_pending);
+
+        let mut bus = StateWriter::new();
+        self.cpu_bus.save_state(&mut bus);
+
+        let mut ppu = StateWriter::new();
+        self.cpu_bus.ppu.save_state(&mut ppu);
+
+        let mut apu = StateWriter::new();
+        self.cpu_bus.apu.save_state(&mut apu);
+
+        let mut state = StateWriter::new();
+        state.bytes(&STATE_TAG);
+        state.u8(STATE_VERSION_MAJOR);
+        state.u8(STATE_VERSION_MINOR);
+        state.section(&cpu.data);
+        state.section(&bus.data);
+        state.section(&ppu.data);
+        state.section(&apu.data);
+        state.section(&self.cpu_bus.mapper.borrow().save_state());
+
+        state.data
+    }
 
-                if self.state.dots == 329 {
-                    self.shift_reload();
-                }
+    // Restores a save_state snapshot taken with the same game loaded
+    // States from another major version are rejected. Minor versions only ever add fields to the end of a section: a
+    // newer state's extra fields are skipped, and an older state just ends its sections early — whatever loader reads
+    // a field added in a later minor version has to check for that and keep the power on value instead
+    // If the state turns out to be cut short the console is put back how it was
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut state = StateReader::new(data);
 
-                self.shift();
-            } else if self.state.dots <= 341 {
-                // Useless clock cycles spent accessing the third tiles nametable byte (only implemented to stay faithful)
-                self.read_byte(self.v);
-                if self.state.dots == 341 {
-                    self.state.dots = 0;
-                    self.state.scanline += 1;
-                    self.sprite_buffer_addr = 0;
+        if state.bytes(STATE_TAG.len()).ok() != Some(&STATE_TAG[..]) {
+            return Err(StateError::NotAState)
+        }
 
-                    return
-                }
-            }
+        let (major, minor) = (state.u8()?, state.u8()?);
+        if major != STATE_VERSION_MAJOR {
+            return Err(StateError::Incompatible { major, minor })
         }
 
-        // PPU just idles here (NMI is *not* set until scanline 241)
-        if self.state.scanline == 240 {
-            if self.state.dots == 341 {
-                self.state.scanline += 1;
-                self.state.dots = 0;
+        let backup = self.save_state();
 
-                return
-            }
+        let result = self.load_sections(&mut state);
+        if result.is_err() {
+            self.load_sections(&mut StateReader::new(&backup[STATE_TAG.len() + 2..]))?;
         }
 
-        // Start of Vblank — Generate an NMI if requested by the CPU; also display the next frame
-        if self.state.scanline == 241 {
-            self.status |= 0b10000000;
-            self.oam_addr_overflow = false;
-
-            // Doesn't active the NMI until the *second* PPU cycle
-            if self.ctrl & 0b10000000 > 0 && self.state.dots >= 1 {
-                self.nmi = 1;
-                self.ctrl &= !0b1000_0000;
+        result
+    }
 
-                self.state.even_odd_frame = !self.state.even_odd_frame;
-            }
+    fn load_sections(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let mut cpu = StateReader::new(state.section()?);
+        let mut bus = StateReader::new(state.section()?);
+        let mut ppu = StateReader::new(state.section()?);
+        let mut apu = StateReader::new(state.section()?);
+        let mapper = state.section()?;
+
+        self.cpu_clk = cpu.u64()? as usize;
+        self.accumulator = cpu.u8()?;
+        self.x = cpu.u8()?;
+        self.y = cpu.u8()?;
+        self.pc = cpu.u16()?;
+        self.sp = cpu.u8()?;
+        self.status = cpu.u8()?;
+        self.nmi_serviced_count = cpu.u64()?;
+        self.last_nmi_frame = cpu.u64()?;
+        self.nmis_this_frame = (self.nmi_serviced_count > 0) as u32; // Not saved — a state is taken between frames, after their one NMI
+        self.jammed = cpu.bool()?;
+        self.irq_line = cpu.bool()?;
+        self.irq_pending = cpu.bool()?;
+
+        self.cpu_bus.load_state(&mut bus)?;
+        self.cpu_bus.ppu.load_state(&mut ppu)?;
+        self.cpu_bus.apu.load_state(&mut apu)?;
+        self.cpu_bus.mapper.borrow_mut().load_state(mapper)
+    }
 
-            if self.state.dots == 341 {
-                self.state.scanline += 1;
-                self.state.dots = 0;
+}
 
-                // Update the screen
-                self.window.update_with_buffer(&self.color_buffer, SCREEN_WIDTH, SCREEN_HEIGHT).expect("Failed to update screen!");
-                self.color_buffer.fill(0);
+impl<B: Bus> CPU<B> {
+    // Powers on with the given bus — the reset vector is read from it without ticking anything
+    pub fn with_bus(bus: B) -> Self {
+        let reset_vector = bus.mem_read_u16(0xFFFC);
 
-                return
-            }
+        return CPU {
+            cpu_clk: 0,
+            accumulator: 0,
+            x: 0,
+            y: 0,
+            pc: reset_vector,
+            sp: 0xFD,
+            status: 0b0010_0100,
+            cpu_bus: bus,
+            ppu_span: ((0, 0), (0, 0)),
+            nmi_serviced_count: 0,
+            jammed: false,
+            irq_line: false,
+            irq_pending: false,
+            stats: Stats::new(),
+            dmc_read_glitch: false,
+            rewind: RewindBuffer::new(0, 0),
+            trace_sink: None,
+            on_bus_access: None,
+            last_nmi_frame: 0,
+            nmis_this_frame: 0,
+            double_nmi_frames: 0,
+            on_double_nmi: None,
         }
+    }
 
-        // End of Vblank (scanline 260) — Cancel the ability to create new NMI's and fill shift registers for next frame (first two tiles)
-        // The PPU doesn't do anything during these scanlines — just increases the clock (allows the PPU to change memory during Vblank)
-        if self.state.scanline < 261 && self.state.scanline >= 241 {
-            // Doesn't active the NMI until the *second* PPU cycle
-            // Resets the NMI register in control
-            if self.ctrl & 0b10000000 > 0 && self.state.dots >= 1 {
-                self.nmi = 1;
-                self.ctrl &= !0b1000_0000;
-            }
+    // Asserts or releases the IRQ line — IRQs are level triggered so whatever raised it has to release it once it's acknowledged
+    pub fn set_irq_line(&mut self, asserted: bool) {
+        self.irq_line = asserted;
+    }
 
-            if self.state.dots == 341{
-                self.state.scanline += 1;
-                self.state.dots = 0;
+    fn irq_asserted(&self) -> bool {
+        self.irq_line || self.cpu_bus.irq()
+    }
 
-                 return
-            }
-        }
+    pub fn nmi_serviced_count(&self) -> u64 {
+        self.nmi_serviced_count
+    }
 
-        // Also fetches the first two tiles of the first scanline for the next frame
-        if self.state.scanline == 261 {
-            // Clearing the 3 flags in PPUSTATUS (0x2002)
-            if self.state.dots == 1 {
-                self.status &= 0b0001_1111;
-            }
+    // Frames that got more than one NMI serviced — a well behaved game only gets one, at the start of VBlank
+    pub fn double_nmi_frames(&self) -> u64 {
+        self.double_nmi_frames
+    }
 
-            if self.state.dots <= 336 {
-                // This will load the pattern shift registers with two tiles worth of data
-                // The attribute registers will have their second tile data stored in the self.state.attribute_data latch
-                self.continue_render();
+    // Calls back with the frame number whenever a frame gets a second NMI (once per frame) — None stops the callbacks
+    pub fn set_on_double_nmi(&mut self, callback: Option<Box<dyn FnMut(u64)>>) {
+        self.on_double_nmi = callback;
+    }
 
-                if self.state.dots == 329 {
-                    self.shift_reload();
-                }
+    // CPU cycles since power on
+    pub fn cycles(&self) -> usize {
+        self.cpu_clk
+    }
 
-                if self.state.dots < 340 && self.state.even_odd_frame {
-                    // Useless clock cycles spent accessing the third tiles nametable byte (only implemented to stay faithful)
-                    self.read_byte(self.v);
-                }
+    pub fn jammed(&self) -> bool {
+        self.jammed
+    }
 
-                self.shift();
-            }
+    // Accuracy option — makes a DMC DMA that lands on a controller read drop a bit like the real console
+    pub fn set_dmc_read_glitch(&mut self, enabled: bool) {
+        self.dmc_read_glitch = enabled;
+    }
 
-            // Reset the dots and scanline for the next frame (also sets the next frame to be even/odd)
-            // Also resets various other things
-            if self.state.dots == 341 {
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
 
-                self.state.even_odd_frame = !self.state.even_odd_frame;
+    fn ppu_position(&self) -> (u16, u16) {
+        self.cpu_bus.ppu_position()
+    }
 
-                self.state.dots = 0;
-                self.state.scanline = 0;
+    // Returns the PPU (scanline, dots) at the start and end of the last decoded instruction
+    pub fn last_instruction_ppu_span(&self) -> ((u16, u16), (u16, u16)) {
+        self.ppu_span
+    }
 
-                // Clearning sprite overflow and sprite zero hit
-                self.status &= !0x20;
-                self.status &= !0x40;
+    // Snapshot of a range of CPU memory for a hex viewer — uses peek so nothing is ticked or acknowledged
+    pub fn read_range(&self, start: u16, len: usize) -> Vec<u8> {
+        (0..len).map(|offset| self.cpu_bus.peek(start.wrapping_add(offset as u16))).collect()
+    }
 
-                // Resets the write latch
-                self.w = 0;
+    // Runs the program until it executes a BRK (how test programs signal they're done), giving up after max_instructions
+    // Stops tests and sandboxed runs from hanging forever on a program that never finishes
+    pub fn run_with_limit(&mut self, max_instructions: usize) -> Result<(), RunError> {
+        for _ in 0..max_instructions {
+            // A pending NMI runs first, in which case the BRK (if any) hasn't been reached yet
+            let finished = !self.cpu_bus.nmi_pending() && !self.irq_pending && self.cpu_bus.peek(self.pc) == 0x00;
+            self.decode()?;
 
-                return 
+            if self.jammed {
+                return Err(RunError::Jammed(self.pc.wrapping_sub(1)))
+            }
+
+            if finished {
+                return Ok(())
             }
         }
-        
-        self.state.dots += 1;
+
+        Err(RunError::InstructionLimit(max_instructions))
     }
-}
 
-// CPU struct to hold registers and the CPUBus
-pub struct CPU {
-    cpu_clk: usize, // Clock used to coordinate with the CPU, since they run in parallel (Usually 1 CPU Cycle = 3 PPU Cycles)
-    pub accumulator: u8, // Allows the use of the status register for overflow detection, carrying, etc.
-    pub x: u8, // Both x and y are used for addressing
-    pub y: u8,
-    pub pc: u16, // Program counter - allows for 65,536 memory locations
-    pub sp: u8, // Allows for indexing into a 256 byte stack,
-    pub status: u8, // Status register - 6 bits that each encode different meanings --> NV1B DIZC (Negative, Overflow, Decimal, Interrupt Disable, Zero, Carry) skipping B
-    pub cpu_bus: CPUBus, // 64 KiB of memory; stack goes from 0x100 to 0x1FF, descending (top to bottom), and empty (sp points to next available space)
-}
+    // Runs one of blargg's test ROMs (instr_test-v5 and friends) until it reports a result through $6000-$7FFF
+    // Once $6001-$6003 hold the DE B0 61 signature, $6000 is 0x80 while the test runs and then its result code (0 is a pass),
+    // with the text it printed as a zero terminated string from $6004. A test that never finishes hits the instruction limit
+    pub fn run_test_rom(&mut self, max_instructions: usize) -> Result<(u8, String), RunError> {
+        for _ in 0..max_instructions {
+            self.decode()?;
 
-impl CPU {
-    pub fn init_cpu(prg_rom: Vec<u8>, ppu: PPU) -> Self {
-        //println!("{}", prg_rom[0xFFFC % 0x8000] as u16);
+            if self.jammed {
+                return Err(RunError::Jammed(self.pc.wrapping_sub(1)))
+            }
 
-        return CPU {
-            cpu_clk: 0,
-            accumulator: 0,
-            x: 0,
-            y: 0,
-            pc: ((prg_rom[0xFFFD % 0x8000] as u16) << 8) | (prg_rom[0xFFFC % 0x8000] as u16),
-            sp: 0xFD,
-            status: 0b0010_0100,
-            cpu_bus: CPUBus::new(prg_rom, ppu),
+            let signature = [self.cpu_bus.peek(0x6001), self.cpu_bus.peek(0x6002), self.cpu_bus.peek(0x6003)];
+            let status = self.cpu_bus.peek(0x6000);
+
+            // 0x81 asks for the reset button to be pressed, which none of the CPU tests do — it's left to run into the limit
+            if signature == [0xDE, 0xB0, 0x61] && status < 0x80 {
+                let text = (0x6004..=0x7FFF)
+                    .map(|addr| self.cpu_bus.peek(addr))
+                    .take_while(|byte| *byte != 0)
+                    .map(|byte| byte as char)
+                    .collect();
+
+                return Ok((status, text))
+            }
         }
+
+        Err(RunError::InstructionLimit(max_instructions))
     }
 
     pub fn load_testing_ram(&mut self, initial_state: &Vec<(i64, i64)>) {
@@ -1109,83 +4767,152 @@ impl CPU {
         }
     }
 
-    // Checks if an NMI has been requested and, if so, acts upon it — Acts just like BRK (which is for IRQ's) but is for NMI's (often Vblank)
-    // CPU checks for an NMI every cycle
+    // Acts on an NMI taken from the bus — Acts just like BRK (which is for IRQ's) but is for NMI's (often Vblank)
+    // CPU checks for an NMI before each instruction
     fn nmi(&mut self) {
-        if self.cpu_bus.ppu.nmi == 1 {
-            println!("ENTERED NMI");
-            self.cpu_bus.ppu.nmi = 0;
+        // println!("ENTERED NMI");
+
+        // A well behaved game only gets one NMI per frame (at the start of VBlank)
+        let frame = self.cpu_bus.frame_count();
+        if self.last_nmi_frame != frame {
+            self.nmis_this_frame = 0;
+        }
 
-            // padding byte
-            let _ = self.fetch_byte();
+        self.last_nmi_frame = frame;
+        self.nmi_serviced_count += 1;
+        self.nmis_this_frame += 1;
 
-            let low_pc = (self.pc & 0x00FF) as u8;
-            let high_pc = ((self.pc & 0xFF00) >> 8) as u8;
+        if self.nmis_this_frame == 2 {
+            self.double_nmi_frames += 1;
+            if let Some(callback) = self.on_double_nmi.as_mut() {
+                callback(frame);
+            }
+        }
 
-            self.push_stack(high_pc);
-            self.push_stack(low_pc);
-            self.push_stack(self.status);
+        // Two dummy reads of the next opcode (where BRK fetches its opcode and padding byte) — unlike BRK the pc isn't moved
+        // past it, the instruction runs after the handler returns
+        let _ = self.read_byte(self.pc);
+        let _ = self.read_byte(self.pc);
 
-            // Fetches the NMI handler address
-            let new_low_pc = self.read_byte(0xFFFA);
-            let new_high_pc = self.read_byte(0xFFFB);
+        self.enter_interrupt(0xFFFA, false);
+    }
 
-            self.pc = (new_high_pc as u16) << 8 | new_low_pc as u16;
+    // Same sequence as the NMI but through the IRQ/BRK vector — only taken if the poll at the end of the last instruction saw it
+    fn irq(&mut self) {
+        self.irq_pending = false;
 
-            self.status |= 0b100;
-        }
+        let _ = self.read_byte(self.pc);
+        let _ = self.read_byte(self.pc);
+
+        self.enter_interrupt(0xFFFE, false);
+    }
+
+    // The part BRK, NMI and IRQ share — pushes the pc and status (B only set for BRK), sets interrupt disable and jumps through the vector
+    fn enter_interrupt(&mut self, vector: u16, break_flag: bool) {
+        let low_pc = (self.pc & 0x00FF) as u8;
+        let high_pc = ((self.pc & 0xFF00) >> 8) as u8;
+
+        self.push_stack(high_pc);
+        self.push_stack(low_pc);
+        self.push_stack(self.status_for_push(break_flag));
+
+        self.status |= 0b100;
+
+        let new_low_pc = self.read_byte(vector);
+        let new_high_pc = self.read_byte(vector + 1);
+
+        self.pc = (new_high_pc as u16) << 8 | new_low_pc as u16;
     }
 
-    fn execute_oam_dma(&mut self, start_addr_high: u8) {
+    // Copies a page into OAM — 513 cycles, or 514 when the copy has to wait a cycle to line up
+    // halted_addr is the read the CPU was about to do, which gets repeated while the DMA takes over the bus
+    // Every cycle goes through read_byte/write_byte so the PPU keeps running (a DMA mid-frame doesn't knock rendering out
+    // of step), and the bytes are written through $2004 so the copy starts at whatever OAMADDR holds
+    fn execute_oam_dma(&mut self, start_addr_high: u8, halted_addr: u16) {
         let start_addr = (start_addr_high as u16) << 8;
         let end_addr = start_addr + 255;
 
-        println!("start addr: {}", start_addr);
-        println!("end addr: {}", end_addr);
+        // println!("start addr: {}", start_addr);
+        // println!("end addr: {}", end_addr);
+
+        // Halt cycle — the CPU's read still goes out on the bus (side effects included)
+        let _ = self.read_byte(halted_addr);
+
+        // The DMA reads on even (get) cycles and writes on odd (put) cycles, so starting on an odd cycle costs an alignment read
+        if self.cpu_clk % 2 == 1 {
+            let _ = self.read_byte(halted_addr);
+        }
 
         for addr in start_addr..=end_addr {
             let sprite_data = self.read_byte(addr);
-            self.write_oam(sprite_data);
+            self.write_byte(0x2004, sprite_data);
+        }
+    }
+
+    // DMC sample fetch — the halt, dummy and alignment cycles, then the DMA unit's read of the sample (4 cycles)
+    // On a 2A03 the controllers see the halted read of $4016/$4017 again after the fetch splits it up, so one more bit is
+    // shifted out and the game loses a button. With the glitch on the halted read repeats once with its side effects (it
+    // hits $2002/$2007 the same way), with it off the halted cycles leave the registers alone
+    fn execute_dmc_dma(&mut self, sample_addr: u16, halted_addr: u16) {
+        for cycle in 0..DMC_DMA_HALT_CYCLES {
+            self.cpu_clk += 1;
+            if cycle == 0 && self.dmc_read_glitch {
+                let _ = self.cpu_bus.mem_read(halted_addr);
+            }
+            self.cpu_bus.tick();
         }
+
+        self.cpu_clk += 1;
+        let sample = self.cpu_bus.mem_read(sample_addr);
+        self.cpu_bus.tick();
+
+        self.cpu_bus.dmc_sample_fetched(sample);
     }
 
     // Writes a byte to memory
     pub fn write_byte(&mut self, address: u16, data: u8) {
         // println!("write");
+        let position = self.ppu_position();
         self.cpu_clk += 1;
         self.cpu_bus.mem_write(address, data);
+        self.cpu_bus.tick();
 
-        for _ in 0..=2 {
-            // println!("ppu_tick write");
-            self.cpu_bus.ppu.ppu_tick();
-        }
-
-        self.nmi();
+        self.report_bus_access(address, data, true, position);
     }
 
     // Performs a write to oam data
     pub fn write_oam(&mut self, data: u8) {
-        // Does NOT call write_byte() because the CPU is halted so the clock doesn't tick up
+        // Straight to OAMDATA without using up a CPU cycle (OAM DMA goes through write_byte since each of its writes takes one)
         self.cpu_bus.mem_write(0x2004, data);
     }
 
     pub fn read_byte(&mut self, address: u16) -> u8 {
         // println!("read");
-        // If a halt has been requested, the CPU stops and executes the DMA — only allowed on read cycles
-        if self.cpu_bus.halt_flag {
-            self.cpu_bus.halt_flag = false;
-            self.execute_oam_dma(self.cpu_bus.ppu.oam_dma);
+        // RDY low halts the CPU, but only on read cycles — writes always go through
+        if !self.cpu_bus.rdy() {
+            // The OAM DMA unit takes the bus over while the CPU is halted
+            if let Some(page) = self.cpu_bus.take_dma_request() {
+                self.execute_oam_dma(page, address);
+            }
+
+            if let Some(sample_addr) = self.cpu_bus.take_dmc_dma_request() {
+                self.execute_dmc_dma(sample_addr, address);
+            }
+
+            // Any other hold just stalls the CPU, which keeps repeating the read (side effects included) until it's released
+            while !self.cpu_bus.rdy() {
+                self.cpu_clk += 1;
+                let _ = self.cpu_bus.mem_read(address);
+                self.cpu_bus.tick();
+            }
         }
 
+        let position = self.ppu_position();
         self.cpu_clk += 1;
         let rtrn = self.cpu_bus.mem_read(address);
+        self.cpu_bus.tick();
 
-        for _ in 0..=2 {
-            // println!("ppu_tick read");
-            self.cpu_bus.ppu.ppu_tick();
-        }
-        
-        self.nmi();
+        self.report_bus_access(address, rtrn, false, position);
 
         return rtrn
     }
@@ -1197,60 +4924,274 @@ impl CPU {
         byte
     }
 
+    // Two separate byte reads (low byte first) like the real CPU, so each one ticks the PPU/APU and hits any register side effects
     fn fetch_word(&mut self) -> u16 {
-        let word = self.cpu_bus.mem_read_u16(self.pc);
-        self.pc = self.pc.wrapping_add(2);
+        let low_byte = self.fetch_byte();
+        let high_byte = self.fetch_byte();
 
-        word
+        (high_byte as u16) << 8 | low_byte as u16
     }
 
-    // Helper functions for DEC and INC instructions
-    fn dec(&mut self, addr: u16) {
-        let data = self.read_byte(addr).wrapping_sub(1);
-        self.write_byte(addr, data);
-        
-        let at_addr = self.read_byte(addr);
-        self.set_zero_neg(at_addr);
+    // Shared by ASL, LSR, ROL, ROR, DEC and INC — works out where the operand is, applies the operation and sets Z/N from the result
+    // Memory operands go through the 6502's read, write back unchanged, write result pattern (the extra write is visible to registers)
+    fn read_modify_write(&mut self, mode: AddressingMode, operation: fn(&mut Self, u8) -> u8) {
+        let result = match mode {
+            AddressingMode::Accumulator => {
+                self.accumulator = operation(self, self.accumulator);
+                self.accumulator
+            },
+
+            _ => {
+                let addr = self.operand_address(mode);
+                let value = self.read_byte(addr);
+                self.write_byte(addr, value);
+
+                let result = operation(self, value);
+                self.write_byte(addr, result);
+                result
+            }
+        };
+
+        self.set_zero_neg(result);
+    }
+
+    // Address of a memory operand, including the dummy read indexed modes make while adding the index
+    fn operand_address(&mut self, mode: AddressingMode) -> u16 {
+        match mode {
+            AddressingMode::ZeroPage => self.fetch_byte() as u16,
+            AddressingMode::ZeroPageX | AddressingMode::ZeroPageY => {
+                let base = self.fetch_byte();
+                let _ = self.read_byte(base as u16);
+                base.wrapping_add(self.index_for(mode)) as u16
+            },
+            AddressingMode::Absolute => self.fetch_word(),
+            AddressingMode::AbsoluteX | AddressingMode::AbsoluteY => {
+                let base = self.fetch_word();
+                self.add_index(base, self.index_for(mode), true)
+            },
+            AddressingMode::IndirectX => {
+                let pointer = self.fetch_byte();
+                let _ = self.read_byte(pointer as u16);
+                self.read_zero_page_word(pointer.wrapping_add(self.x))
+            },
+            AddressingMode::IndirectY => {
+                let pointer = self.fetch_byte();
+                let base = self.read_zero_page_word(pointer);
+                self.add_index(base, self.y, true)
+            },
+            AddressingMode::Accumulator => unreachable!("Accumulator mode has no address"),
+        }
+    }
+
+    // Reads an operand the way read instructions do — unlike read-modify-write, absolute,X/Y and (indirect),Y only pay
+    // the dummy read when adding the index carries into the high byte
+    fn read_operand(&mut self, mode: AddressingMode) -> u8 {
+        let addr = match mode {
+            AddressingMode::AbsoluteX | AddressingMode::AbsoluteY => {
+                let base = self.fetch_word();
+                self.add_index(base, self.index_for(mode), false)
+            },
+            AddressingMode::IndirectY => {
+                let pointer = self.fetch_byte();
+                let base = self.read_zero_page_word(pointer);
+                self.add_index(base, self.y, false)
+            },
+            _ => self.operand_address(mode),
+        };
+
+        self.read_byte(addr)
+    }
+
+    fn index_for(&self, mode: AddressingMode) -> u8 {
+        match mode {
+            AddressingMode::ZeroPageY | AddressingMode::AbsoluteY | AddressingMode::IndirectY => self.y,
+            _ => self.x,
+        }
+    }
+
+    // Adds an index to a 16 bit base the way the 6502 does — the low byte first, with a dummy read from that address
+    // (before the carry into the high byte is fixed up) when the carry happens or the instruction always pays it
+    fn add_index(&mut self, base: u16, index: u8, always_dummy_read: bool) -> u16 {
+        let addr = base.wrapping_add(index as u16);
+
+        if always_dummy_read || addr & 0xFF00 != base & 0xFF00 {
+            let _ = self.read_byte((base & 0xFF00) | (addr & 0x00FF));
+        }
+
+        addr
     }
 
-    fn inc(&mut self, addr: u16) {
-        let data = self.read_byte(addr).wrapping_add(1);
-        self.write_byte(addr, data);
+    // Pointer stored in the zero page — the high byte wraps around to $00 instead of going on to $0100
+    fn read_zero_page_word(&mut self, pointer: u8) -> u16 {
+        let lsb = self.read_byte(pointer as u16);
+        let msb = self.read_byte(pointer.wrapping_add(1) as u16);
 
-        let at_addr = self.read_byte(addr);
-        self.set_zero_neg(at_addr);
+        (msb as u16) << 8 | lsb as u16
     }
 
-    // Helper functions to keep code more clean (L borrow checker)
-    fn or_and_store(&mut self, addr: u16, or_value: u8) {
-        let new_value = self.read_byte(addr) | or_value;
-        self.write_byte(addr, new_value);
+    // ASL - Bit 7 goes into the carry flag and bit 0 is filled with 0
+    fn asl(&mut self, value: u8) -> u8 {
+        self.set_carry(value & 0x80 != 0);
+        value << 1
     }
-    
-    fn and_and_store(&mut self, addr: u16, and_value: u8) {
-        let new_value = self.read_byte(addr) & and_value;
-        self.write_byte(addr, new_value);
+
+    // LSR - Bit 0 goes into the carry flag and bit 7 is filled with 0
+    fn lsr(&mut self, value: u8) -> u8 {
+        self.set_carry(value & 0x1 != 0);
+        value >> 1
     }
 
-    fn left_shift_and_store(&mut self, addr: u16, ls_value: u8) {
-        let new_value = self.read_byte(addr) << ls_value;
-        self.write_byte(addr, new_value);
+    // ROL - Bit 7 goes into the carry flag and bit 0 is filled with the old carry
+    fn rol(&mut self, value: u8) -> u8 {
+        let old_carry = self.status & 0x1;
+        self.set_carry(value & 0x80 != 0);
+        (value << 1) | old_carry
     }
-    
-    fn right_shift_and_store(&mut self, addr: u16, rs_value: u8) {
-        let new_value = self.read_byte(addr) >> rs_value;
-        self.write_byte(addr, new_value);
+
+    // ROR - Bit 0 goes into the carry flag and bit 7 is filled with the old carry
+    fn ror(&mut self, value: u8) -> u8 {
+        let old_carry = self.status & 0x1;
+        self.set_carry(value & 0x1 != 0);
+        (value >> 1) | (old_carry << 7)
     }
 
-    fn set_zero_neg_flags(&mut self, addr: u16) {
-        let result = self.read_byte(addr);
-        self.set_zero_neg(result);
+    // DEC - Subtracts one, wrapping $00 around to $FF
+    fn dec(&mut self, value: u8) -> u8 {
+        value.wrapping_sub(1)
+    }
+
+    // INC - Adds one, wrapping $FF around to $00
+    fn inc(&mut self, value: u8) -> u8 {
+        value.wrapping_add(1)
+    }
+
+    fn set_carry(&mut self, carry: bool) {
+        if carry {
+            self.status |= 0x1;
+        } else {
+            self.status &= !0x1;
+        }
+    }
+
+    // Adds a value and the carry bit to the accumulator, setting C on unsigned overflow and V when the sign comes out wrong
+    fn add_with_carry(&mut self, value: u8) {
+        let carry_bit = self.status & 0x1;
+        let previous = self.accumulator;
+        let result = previous as u16 + value as u16 + carry_bit as u16;
+
+        if result > 0xFF { self.status |= 0x1 } else { self.status &= !0x1 };
+
+        self.accumulator = result as u8;
+        self.set_zero_neg(self.accumulator);
+
+        // Overflow when both inputs share a sign and the result doesn't
+        if (previous ^ self.accumulator) & (value ^ self.accumulator) & 0x80 != 0 {
+            self.status |= 0x40;
+        } else {
+            self.status &= !0x40;
+        }
+    }
+
+    // Sends tracing to a file, a buffer, stdout, ... or turns it off with None
+    pub fn set_trace_sink(&mut self, sink: Option<Box<dyn Write>>) {
+        self.trace_sink = sink;
+    }
+
+    // Calls back on every CPU read and write with where the PPU was at that cycle — instructions run one bus access
+    // at a time, so this is how raster tricks (sprite zero polling, mapper IRQ timing) can be checked access by access
+    // OAM DMA goes through the same reads and writes, so its halt cycle, page reads and $2004 writes are reported too —
+    // DMC sample fetches aren't. None stops the callbacks
+    pub fn set_on_bus_access(&mut self, callback: Option<Box<dyn FnMut(BusAccess)>>) {
+        self.on_bus_access = callback;
+    }
+
+    fn report_bus_access(&mut self, addr: u16, value: u8, write: bool, (scanline, dot): (u16, u16)) {
+        if let Some(callback) = self.on_bus_access.as_mut() {
+            callback(BusAccess { addr, value, write, cycle: self.cpu_clk, scanline, dot });
+        }
+    }
+
+    // The count instructions starting at start as (address, text), e.g. (C000, "JMP $C5F5") — for a debugger's code window
+    // Memory is read with peek so registers don't see the reads. Opcodes the CPU doesn't implement come out as a single
+    // .db byte and the walk carries on after it, which also keeps it going when start lands in the middle of data
+    pub fn disassemble_range(&self, start: u16, count: usize) -> Vec<(u16, String)> {
+        let mut lines = Vec::with_capacity(count);
+        let mut addr = start;
+
+        for _ in 0..count {
+            let opcode = self.cpu_bus.peek(addr);
+
+            let (text, length) = match disassembly_format(opcode) {
+                Some((mnemonic, operand)) => {
+                    let (low, high) = (self.cpu_bus.peek(addr.wrapping_add(1)), self.cpu_bus.peek(addr.wrapping_add(2)));
+
+                    let text = match operand {
+                        OperandFormat::Implied => mnemonic.to_string(),
+                        _ => format!("{} {}", mnemonic, operand.format(addr, low, high)),
+                    };
+                    (text, 1 + operand.operand_bytes())
+                },
+                None => (format!(".db ${:02X}", opcode), 1)
+            };
+
+            lines.push((addr, text));
+            addr = addr.wrapping_add(length);
+        }
+
+        lines
+    }
+
+    // One line per instruction with the registers before it runs, in the same layout as the nestest log (minus the disassembly):
+    // C000  4C  A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+    fn trace_instruction(&mut self) {
+        if self.trace_sink.is_none() {
+            return
+        }
+
+        let opcode = self.cpu_bus.peek(self.pc);
+        let (scanline, dots) = self.ppu_position();
+        let line = format!(
+            "{:04X}  {:02X}  A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:3},{:3} CYC:{}",
+            self.pc, opcode, self.accumulator, self.x, self.y, self.status, self.sp, scanline, dots, self.cpu_clk
+        );
+
+        if let Some(sink) = self.trace_sink.as_mut() {
+            if let Err(e) = writeln!(sink, "{}", line) {
+                println!("Stopping the instruction trace, writing it failed: {}", e);
+                self.trace_sink = None;
+            }
+        }
     }
 
-    pub fn decode(&mut self) {
+    // Runs one instruction (taking a pending interrupt first) — an opcode the CPU doesn't implement, or one that jams it, is
+    // handed back as an error
+    pub fn decode(&mut self) -> Result<(), RunError> {
+        // A jammed CPU leaves $FFFF on the address bus forever and ignores interrupts — the rest of the system keeps running
+        if self.jammed {
+            let _ = self.read_byte(0xFFFF);
+            return Ok(())
+        }
+
+        // Interrupts are only taken between instructions — an NMI wins over an IRQ polled at the same time
+        if self.cpu_bus.take_nmi() {
+            self.irq_pending = false;
+            self.nmi();
+        } else if self.irq_pending {
+            self.irq();
+        }
+
+        let span_start = self.ppu_position();
+        let status_before = self.status;
+        self.trace_instruction();
+        let opcode_addr = self.pc;
         let instruction = self.fetch_byte();
-        println!("instruction: {:X}", instruction);
-        // println!("pc: {}, s: {}, a: {}, x: {}, y: {}, p: {}", self.pc.wrapping_sub(1), self.sp, self.accumulator, self.x, self.y, self.status);
+
+        // KIL - Illegal opcodes that lock the CPU up — reported once as an error, later calls just spin
+        if matches!(instruction, 0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 | 0x62 | 0x72 | 0x92 | 0xB2 | 0xD2 | 0xF2) {
+            self.jammed = true;
+            self.ppu_span = (span_start, self.ppu_position());
+            return Err(RunError::Jammed(opcode_addr))
+        }
 
         let aaa = (instruction >> 5) & 0b111;
         let bbb = (instruction >> 2) & 0b111;
@@ -1277,32 +5218,19 @@ impl CPU {
 
             // BRK - Forces the generation of an interrupt request
             // Loads the program counter and status flags onto the stack then IRQ interrupt vector is loaded into the pc
-            // Break status flag is set to 1 to prevent further interrupts
+            // The pushed copy has the break flag set, and the interrupt disable flag is then set to prevent further interrupts
+            // The decimal flag is pushed and kept as-is — unlike the 65C02, the 2A03 doesn't clear it when taking an interrupt
 
             (0, 0, 0) => {
                 // padding byte
                 let _ = self.fetch_byte();
 
-                let low_pc = (self.pc & 0x00FF) as u8;
-                let high_pc = ((self.pc & 0xFF00) >> 8) as u8;
-
-                self.push_stack(high_pc);
-                self.push_stack(low_pc);
-                self.push_stack(self.status | 0b1_0000);
-
-                self.status |= 0b100;
-
-                let new_low_pc = self.read_byte(0xFFFE);
-                let new_high_pc = self.read_byte(0xFFFF);
-
-                // println!("new low: {}, new high: {}", new_low_pc, new_high_pc);
-
-                self.pc = (new_high_pc as u16) << 8 | new_low_pc as u16;
+                self.enter_interrupt(0xFFFE, true);
             }
 
             // PHP - Pushes a copy of the status flags onto the stack
             (0, 2, 0) => {
-                self.push_stack(self.status | 0b1_0000);
+                self.push_stack(self.status_for_push(true));
             },
 
             // CLC - Clears the carry flag
@@ -1312,13 +5240,7 @@ impl CPU {
 
             // PLP - Pulls a byte of data from the stack and loads it into the status flags
             (1, 2, 0) => {
-                let mut popped_value = self.pop_stack();
-
-                popped_value |= 0b10_0000;
-                popped_value &= !0b1_0000;
-                // println!("popped: {}", popped_value);
-
-                self.status = popped_value;
+                self.pull_status();
             },
 
             // SEC - Sets the carry flag
@@ -1328,9 +5250,7 @@ impl CPU {
 
             // RTI - Return from interrupt - Pulls processor status flags from stack followed by the program counter
             (2, 0, 0) => {
-                self.status = self.pop_stack();
-                self.status |= 0b0010_0000; // Sets the unused bit
-                self.status &= !0b0001_0000; // Clears the break flag
+                self.pull_status();
 
                 let low_pc = self.pop_stack();
                 let high_pc = self.pop_stack();
@@ -1349,11 +5269,17 @@ impl CPU {
             }
 
             // RTS - Return from subroutine - Pulls program counter from stack
+            // 6 cycles: opcode, a dummy read of the next byte, a dummy stack read while S is incremented, the two pulls,
+            // then a read of the pulled address while it's incremented past the JSR's last operand byte
             (3, 0, 0) => {
+                let _ = self.read_byte(self.pc);
+                let _ = self.read_byte(self.sp as u16 + STACK_BASE as u16);
+
                 let low_pc = self.pop_stack() as u16;
                 let high_pc = self.pop_stack() as u16;
+                self.pc = (high_pc << 8) | low_pc;
 
-                self.pc = ((high_pc << 8) | low_pc) + 1;
+                let _ = self.fetch_byte();
             },
 
             // PLA - Pulls a byte value from the stack and loads it into the accumulator
@@ -1428,6 +5354,7 @@ impl CPU {
             },
 
             // CLD - Clears the decimal flag
+            // The 2A03 has no decimal mode so ADC/SBC ignore it, but the bit itself is still stored and pushed like any other flag
             (6, 6, 0) => {
                 self.status &= !0x8;
             },
@@ -1450,6 +5377,76 @@ impl CPU {
 
             // End implied instructions
 
+            // Unofficial NOPs — they go through their addressing mode like any other instruction (every operand byte,
+            // read and dummy read happens, so the cycles are paid) and then throw the value away. nestest runs them all
+            (0 | 1 | 2 | 3 | 6 | 7, 6, 2) => {
+                // Single byte ones ($1A, $3A, $5A, $7A, $DA, $FA), same as NOP
+            },
+
+            (4, 0, 0) | (4, 0, 2) | (4, 2, 1) | (6, 0, 2) | (7, 0, 2) => {
+                // Immediate ($80, $82, $89, $C2, $E2)
+                let _ = self.fetch_byte();
+            },
+
+            (0 | 2 | 3, 1, 0) => {
+                let _ = self.read_operand(AddressingMode::ZeroPage); // $04, $44, $64
+            },
+
+            (0 | 1 | 2 | 3 | 6 | 7, 5, 0) => {
+                let _ = self.read_operand(AddressingMode::ZeroPageX); // $14, $34, $54, $74, $D4, $F4
+            },
+
+            (0, 3, 0) => {
+                let _ = self.read_operand(AddressingMode::Absolute); // $0C
+            },
+
+            (0 | 1 | 2 | 3 | 6 | 7, 7, 0) => {
+                let _ = self.read_operand(AddressingMode::AbsoluteX); // $1C, $3C, $5C, $7C, $DC, $FC
+            },
+
+            // Unofficial immediate opcodes — an AND with the operand glued onto another operation, with their own flag quirks
+
+            // ANC ($0B, $2B) - ANDs the operand into the accumulator, then copies bit 7 (the new N flag) into carry
+            (0 | 1, 2, 3) => {
+                self.accumulator &= self.fetch_byte();
+                self.set_zero_neg(self.accumulator);
+                self.set_carry(self.accumulator & 0x80 != 0);
+            },
+
+            // ALR ($4B) - AND with the operand, then LSR the accumulator
+            (2, 2, 3) => {
+                let value = self.accumulator & self.fetch_byte();
+                self.accumulator = self.lsr(value);
+                self.set_zero_neg(self.accumulator);
+            },
+
+            // ARR ($6B) - AND with the operand, then ROR the accumulator — but C and V come from the adder the AND went
+            // through rather than the shift: C is bit 6 of the result and V is bit 6 XOR bit 5
+            (3, 2, 3) => {
+                let value = self.accumulator & self.fetch_byte();
+                self.accumulator = (value >> 1) | ((self.status & 0x1) << 7);
+                self.set_zero_neg(self.accumulator);
+
+                let bit_6 = (self.accumulator >> 6) & 0x1;
+                let bit_5 = (self.accumulator >> 5) & 0x1;
+                self.set_carry(bit_6 != 0);
+
+                if bit_6 ^ bit_5 != 0 {
+                    self.status |= 0x40;
+                } else {
+                    self.status &= !0x40;
+                }
+            },
+
+            // AXS ($CB) - X = (A AND X) - operand, with the flags of a CMP (no borrow in, V left alone)
+            (6, 2, 3) => {
+                let operand = self.fetch_byte();
+                let value = self.accumulator & self.x;
+
+                self.set_cmp_flags(value, operand);
+                self.x = value.wrapping_sub(operand);
+            },
+
             (0, bbb, diff) => {
                 match diff {
                     // BPL - Increment the program counter by the relative displacement if the negative flag is clear
@@ -1473,98 +5470,11 @@ impl CPU {
                     // Bit 0 is set to zero and the carry flag is set to bit 7 (if result is >0xFF then carry flag is set)
                     // Has special accumulator mode. Basically immediate, but acts on the accumulator and has other addressing modes
                     2 => {
-                        match bbb {
-                            // Zero page mode
-                            1 => {
-                                // println!("Going into asl: {}", self.pc);
-
-                                let zero_page_addr = self.fetch_byte() as u16;
-                                let carry_bit = self.read_byte(zero_page_addr) & 0x80;
-
-                                self.left_shift_and_store(zero_page_addr, 1);
-
-                                if carry_bit != 0 {
-                                    self.status |= 0x01;
-                                } else {
-                                    self.status &= !0x01;
-                                }
-
-                                let at_addr = self.read_byte(zero_page_addr);
-                                self.set_zero_neg(at_addr);
-                            },
-        
-                            // Special Accumulator mode
-                            2 => {
-                                let carry_bit = self.accumulator & 0x80;
-                                self.accumulator <<= 1;
-                                
-                                if carry_bit != 0 {
-                                    self.status |= 0x01;
-                                } else {
-                                    self.status &= !0x01;
-                                }
-        
-                                self.set_zero_neg(self.accumulator);
-                            },
-        
-                            // Absolute mode
-                            3 => {
-                                let addr = self.fetch_word();
-        
-                                let carry_bit = self.read_byte(addr) & 0x80;
-
-                                self.left_shift_and_store(addr, 1);
-        
-                                if carry_bit != 0 {
-                                    self.status |= 0x01;
-                                } else {
-                                    self.status &= !0x01;
-                                }
-
-                                let at_addr = self.read_byte(addr);
-                                self.set_zero_neg(at_addr);
-                            },
-
-                            // Zero page x mode
-                            5 => {
-                                let zero_page_addr = (self.fetch_byte().wrapping_add(self.x)) as u16;
-                                let carry_bit = self.read_byte(zero_page_addr) & 0x80;
-
-                                self.left_shift_and_store(zero_page_addr, 1);
-                                
-                                if carry_bit != 0 {
-                                    self.status |= 0x01;
-                                } else {
-                                    self.status &= !0x01;
-                                }
-        
-                                let at_addr = self.read_byte(zero_page_addr);
-                                self.set_zero_neg(at_addr);
-                            },
-        
-                            // Absolute x mode
-                            7 => {
-                                let addr = self.fetch_word().wrapping_add(self.x as u16);
-        
-                                let carry_bit = self.read_byte(addr) & 0x80;
-
-                                self.left_shift_and_store(addr, 1);
-        
-                                if carry_bit != 0 {
-                                    self.status |= 0x01;
-                                } else {
-                                    self.status &= !0x01;
-                                }
-        
-                                let at_addr = self.read_byte(addr);
-                                self.set_zero_neg(at_addr);
-                            },
-        
-                            _ => { panic!("Opcode not supported!") }
-                        }
+                        let mode = AddressingMode::read_modify_write(bbb).ok_or(RunError::UnsupportedOpcode(instruction, opcode_addr))?;
+                        self.read_modify_write(mode, Self::asl);
                     },
 
-                    _ => { panic!("Opcode not supported!") }
+                    _ => { return Err(RunError::UnsupportedOpcode(instruction, opcode_addr)) }
                 }
             },
 
@@ -1572,181 +5482,70 @@ impl CPU {
                 match diff {
                     0 => {
                         match bbb {
-                            // JSR - Pushes the address (-1) of the return point onto the stack then sets pc to absolute address
-                            0 => {
-                                let addr_low = self.fetch_byte();
-                                self.push_stack(((self.pc & 0xFF00) >> 8) as u8);
-                                self.push_stack((self.pc & 0x00FF) as u8);
-                                let addr_high = self.fetch_byte();
-
-                                self.pc = (addr_high as u16) << 8 | addr_low as u16;
-                            },
-
-                            // BIT - Checks A & M to set/clear the zero flag, stores bit 7 in the N flag and bit 6 in the V flag
-                            1 | 3 => {
-                                let operand = self.get_operand(bbb);
-
-                                if operand & 0x80 != 0 {
-                                    self.status |= 0x80;
-                                } else {
-                                    self.status &= !0x80;
-                                }
-
-                                if operand & 0x40 != 0 {
-                                    self.status |= 0x40;
-                                } else {
-                                    self.status &= !0x40;
-                                }
-
-                                if self.accumulator & operand == 0 {
-                                    self.status |= 0x2;
-                                } else {
-                                    self.status &= !0x2;
-                                }
-                            },
-
-                            // BMI - Adds the relative displacement to the program counter if the negative flag is set
-                            4 => {
-                                let displacement = self.fetch_byte() as i8;
-                        
-                                if self.status & 0x80 > 0 {
-                                    self.pc = (displacement as isize + self.pc as isize) as u16;
-                                }
-                            },
-
-                            _ => { panic!("Opcode not supported!") }
-                        }
-                    }
-
-                    // AND - Logical AND is performed on the accumulator register and a byte from memory
-                    // Sets the zero and negative flags as needed
-                    1 => {
-                        self.accumulator &= self.get_operand(bbb);
-                        self.set_zero_neg(self.accumulator);
-                    },
-
-                    // ROL - Move the bits in either A or M one place to the left
-                    // Bit 0 is filled with the carry flag, bit 7 becomes the new carry flag
-                    2 => {
-                        match bbb {
-                            // Zero page mode
-                            1 => {
-                                let old_carry = self.status & 0x1;
-                                let zero_page_addr = self.read_byte(self.pc) as u16;
-                                let new_carry = self.get_operand(bbb) & 0x80;
-
-                                self.left_shift_and_store(zero_page_addr, 1);
-
-                                if old_carry != 0 {
-                                    self.or_and_store(zero_page_addr, 0x1);
-                                } else {
-                                    self.and_and_store(zero_page_addr, !0x1);
-                                }
-
-                                if new_carry != 0 {
-                                    self.status |= 0x1;
-                                } else {
-                                    self.status &= !0x1;
-                                }
-
-                                self.set_zero_neg_flags(zero_page_addr);
-                            }
-
-                            // Special accumulator mode
-                            2 => {
-                                let old_carry = self.status & 0x1;
-                                let new_carry = self.accumulator & 0x80;
-                                self.accumulator <<= 1;
-
-                                if old_carry != 0 {
-                                    self.accumulator |= 0x1;
-                                } else {
-                                    self.accumulator &= !0x1;
-                                }
-
-                                if new_carry != 0 {
-                                    self.status |= 0x1;
-                                } else {
-                                    self.status &= !0x1;
-                                }
-
-                                self.set_zero_neg(self.accumulator);
-                            },
-
-                            // Absolute mode
-                            3 => {
-                                let old_carry = self.status & 0x1;
-                                let addr = self.cpu_bus.mem_read_u16(self.pc);
-                                let new_carry = self.get_operand(bbb) & 0x80;
-
-                                self.left_shift_and_store(addr, 1);
-
-                                if old_carry != 0 {
-                                    self.or_and_store(addr, 0x1);
-                                } else {
-                                    self.and_and_store(addr, !0x1);
-                                }
-
-                                if new_carry != 0 {
-                                    self.status |= 0x1;
-                                } else {
-                                    self.status &= !0x1;
-                                }
+                            // JSR - Pushes the address (-1) of the return point onto the stack then sets pc to absolute address
+                            // 6 cycles: opcode, low byte, a dummy stack read, the two pushes, then the high byte — so the
+                            // pushed pc points at the high byte (the last byte of the operand), which RTS adds 1 to
+                            0 => {
+                                let addr_low = self.fetch_byte();
+                                let _ = self.read_byte(self.sp as u16 + STACK_BASE as u16);
+                                self.push_stack(((self.pc & 0xFF00) >> 8) as u8);
+                                self.push_stack((self.pc & 0x00FF) as u8);
+                                let addr_high = self.fetch_byte();
 
-                                self.set_zero_neg_flags(addr);
+                                self.pc = (addr_high as u16) << 8 | addr_low as u16;
                             },
 
-                            // Zero page x mode
-                            5 => {
-                                let old_carry = self.status & 0x1;
-                                let zero_page_addr = self.fetch_byte().wrapping_add(self.x) as u16;
-                                let new_carry = self.read_byte(zero_page_addr) & 0x80;
-
-                                self.left_shift_and_store(zero_page_addr, 1);
+                            // BIT - Checks A & M to set/clear the zero flag, stores bit 7 in the N flag and bit 6 in the V flag
+                            1 | 3 => {
+                                let operand = self.get_operand(bbb);
 
-                                if old_carry != 0 {
-                                    self.or_and_store(zero_page_addr, 0x1);
+                                if operand & 0x80 != 0 {
+                                    self.status |= 0x80;
                                 } else {
-                                    self.and_and_store(zero_page_addr, !0x1);
+                                    self.status &= !0x80;
                                 }
 
-                                if new_carry != 0 {
-                                    self.status |= 0x1;
+                                if operand & 0x40 != 0 {
+                                    self.status |= 0x40;
                                 } else {
-                                    self.status &= !0x1;
+                                    self.status &= !0x40;
                                 }
 
-                                self.set_zero_neg_flags(zero_page_addr);
-                            },
-
-                            // Absolute x mode
-                            7 => {
-                                let old_carry = self.status & 0x1;
-                                let addr = self.cpu_bus.mem_read_u16(self.pc).wrapping_add(self.x as u16);
-                                let new_carry = self.get_operand(bbb) & 0x80;
-
-                                self.left_shift_and_store(addr, 1);
-
-                                if old_carry != 0 {
-                                    self.or_and_store(addr, 0x1);
+                                if self.accumulator & operand == 0 {
+                                    self.status |= 0x2;
                                 } else {
-                                    self.and_and_store(addr, !0x1);
+                                    self.status &= !0x2;
                                 }
+                            },
 
-                                if new_carry != 0 {
-                                    self.status |= 0x1;
-                                } else {
-                                    self.status &= !0x1;
+                            // BMI - Adds the relative displacement to the program counter if the negative flag is set
+                            4 => {
+                                let displacement = self.fetch_byte() as i8;
+                        
+                                if self.status & 0x80 > 0 {
+                                    self.pc = (displacement as isize + self.pc as isize) as u16;
                                 }
-
-                                self.set_zero_neg_flags(addr);
                             },
 
-                            _ => { panic!("Opcode not supported!") }   
+                            _ => { return Err(RunError::UnsupportedOpcode(instruction, opcode_addr)) }
                         }
+                    }
+
+                    // AND - Logical AND is performed on the accumulator register and a byte from memory
+                    // Sets the zero and negative flags as needed
+                    1 => {
+                        self.accumulator &= self.get_operand(bbb);
+                        self.set_zero_neg(self.accumulator);
+                    },
+
+                    // ROL - Move the bits in either A or M one place to the left
+                    // Bit 0 is filled with the carry flag, bit 7 becomes the new carry flag
+                    2 => {
+                        let mode = AddressingMode::read_modify_write(bbb).ok_or(RunError::UnsupportedOpcode(instruction, opcode_addr))?;
+                        self.read_modify_write(mode, Self::rol);
                     },
 
-                    _ => { panic!("Opcode not supported!") }
+                    _ => { return Err(RunError::UnsupportedOpcode(instruction, opcode_addr)) }
                 }
             },
 
@@ -1769,7 +5568,7 @@ impl CPU {
                                 }
                             },
 
-                            _ => { panic!("Opcode not supported!") }
+                            _ => { return Err(RunError::UnsupportedOpcode(instruction, opcode_addr)) }
                         }
                     },
 
@@ -1783,90 +5582,11 @@ impl CPU {
                     // LSR - Shifts all the bits of the operand to the right
                     // The bit in bit 0 is sent to the carry flag, bit 7 is set to 0
                     2 => {
-                        match bbb {
-                            // Zero page mode
-                            1 => {
-                                let zero_page_addr = self.fetch_byte() as u16;
-                                let zero_bit = self.read_byte(zero_page_addr) & 0x1;
-
-                                self.right_shift_and_store(zero_page_addr, 1);
-
-                                if zero_bit != 0 {
-                                    self.status |= 0x1;
-                                } else {
-                                    self.status &= !0x1;
-                                }
-
-                                self.set_zero_neg_flags(zero_page_addr);
-                            },
-
-                            // Special accumulator mode
-                            2 => {
-                                let zero_bit = self.accumulator & 0x1;
-                                self.accumulator >>= 1;
-
-                                if zero_bit != 0 {
-                                    self.status |= 0x1;
-                                } else {
-                                    self.status &= !0x1;
-                                }
-
-                                self.set_zero_neg(self.accumulator);
-                            },
-
-                            // Absolute mode
-                            3 => {
-                                let addr = self.fetch_word();
-                                let zero_bit = self.read_byte(addr) & 0x1;
-
-                                self.right_shift_and_store(addr, 1);
-
-                                if zero_bit != 0 {
-                                    self.status |= 0x1;
-                                } else {
-                                    self.status &= !0x1;
-                                }
-
-                                self.set_zero_neg_flags(addr);
-                            },
-
-                            // Zero page x mode
-                            5 => {
-                                let zero_page_addr = (self.fetch_byte().wrapping_add(self.x)) as u16;
-                                let zero_bit = self.read_byte(zero_page_addr) & 0x1;
-
-                                self.right_shift_and_store(zero_page_addr, 1);
-
-                                if zero_bit != 0 {
-                                    self.status |= 0x1;
-                                } else {
-                                    self.status &= !0x1;
-                                }
-
-                                self.set_zero_neg_flags(zero_page_addr);
-                            },
-
-                            // Absolute x mode
-                            7 => {
-                                let addr = self.fetch_word().wrapping_add(self.x as u16);
-                                let zero_bit = self.read_byte(addr) & 0x1;
-
-                                self.right_shift_and_store(addr, 1);
-
-                                if zero_bit != 0 {
-                                    self.status |= 0x1;
-                                } else {
-                                    self.status &= !0x1;
-                                }
-
-                                self.set_zero_neg_flags(addr);
-                            },
-
-                            _ => { panic!("Opcode not supported!") }
-                        }
+                        let mode = AddressingMode::read_modify_write(bbb).ok_or(RunError::UnsupportedOpcode(instruction, opcode_addr))?;
+                        self.read_modify_write(mode, Self::lsr);
                     },
 
-                    _ => { panic!("Opcode not supported!") }
+                    _ => { return Err(RunError::UnsupportedOpcode(instruction, opcode_addr)) }
                 }
             }
 
@@ -1887,7 +5607,7 @@ impl CPU {
                                 }
                             },
 
-                            _ => { panic!("Opcode not supported!") }
+                            _ => { return Err(RunError::UnsupportedOpcode(instruction, opcode_addr)) }
                         }
                     },
 
@@ -1895,148 +5615,18 @@ impl CPU {
                     // If overflow occurs the carry bit is set - this allows for multi-byte addition
                     // Sets the zero and negative flags as needed, also sets overflow flag if sign bit is incorrect
                     1 => {
-                        let operand= self.get_operand(bbb);
-
-                        let carry_bit = self.status & 0x1;
-                        let previous = self.accumulator;
-                        let result = self.accumulator as u16 + operand as u16 + carry_bit as u16;
-
-                        if result > 255 { self.status |= 0x1 } else { self.status &= !0x01 };
-
-                        self.accumulator = result as u8;
-                        self.set_zero_neg(self.accumulator);
-
-                        let prev_sign = previous & 0x80;
-                        let op_sign = operand & 0x80;
-                        let res_sign = self.accumulator & 0x80;
-
-                        if prev_sign == op_sign && prev_sign != res_sign {
-                            self.status |= 0x40;
-                        } else {
-                            self.status &= !0x40;
-                        }
+                        let operand = self.get_operand(bbb);
+                        self.add_with_carry(operand);
                     },
 
                     // ROR - Move the bits in either A or M one place to the right
                     // Bit 7 is filled with the carry flag, bit 0 becomes the new carry flag
                     2 => {
-                        match bbb {
-                            // Zero page mode
-                            1 => {
-                                let old_carry = self.status & 0x1;
-                                let zero_page_addr = self.fetch_byte() as u16;
-                                let new_carry = self.read_byte(zero_page_addr) & 0x1;
-
-                                self.right_shift_and_store(zero_page_addr, 1);
-
-                                if old_carry != 0 {
-                                    self.or_and_store(zero_page_addr, 0x80);
-                                } else {
-                                    self.and_and_store(zero_page_addr, !0x80);
-                                }
-
-                                if new_carry != 0 {
-                                    self.status |= 0x1;
-                                } else {
-                                    self.status &= !0x1;
-                                }
-
-                                self.set_zero_neg_flags(zero_page_addr);
-                            }
-
-                            // Special accumulator mode
-                            2 => {
-                                let old_carry = self.status & 0x1;
-                                let new_carry = self.accumulator & 0x1;
-                                self.accumulator >>= 1;
-
-                                if old_carry != 0 {
-                                    self.accumulator |= 0x80;
-                                }
-
-                                if new_carry != 0 {
-                                    self.status |= 0x1;
-                                } else {
-                                    self.status &= !0x1;
-                                }
-
-                                self.set_zero_neg(self.accumulator);
-                            },
-
-                            // Absolute mode
-                            3 => {
-                                let old_carry = self.status & 0x1;
-                                let addr = self.fetch_word();
-                                let new_carry = self.read_byte(addr) & 0x1;
-
-                                self.right_shift_and_store(addr, 1);
-
-                                if old_carry != 0 {
-                                    self.or_and_store(addr, 0x80);
-                                } else {
-                                    self.and_and_store(addr, !0x80);
-                                }
-
-                                if new_carry != 0 {
-                                    self.status |= 0x1;
-                                } else {
-                                    self.status &= !0x1;
-                                }
-
-                                self.set_zero_neg_flags(addr);
-                            },
-
-                            // Zero page x mode
-                            5 => {
-                                let old_carry = self.status & 0x1;
-                                let zero_page_addr = (self.fetch_byte().wrapping_add(self.x)) as u16;
-                                let new_carry = self.read_byte(zero_page_addr) & 0x1;
-
-                                self.right_shift_and_store(zero_page_addr, 1);
-
-                                if old_carry != 0 {
-                                    self.or_and_store(zero_page_addr, 0x80);
-                                } else {
-                                    self.and_and_store(zero_page_addr, !0x80);
-                                }
-
-                                if new_carry != 0 {
-                                    self.status |= 0x1;
-                                } else {
-                                    self.status &= !0x1;
-                                }
-
-                                self.set_zero_neg_flags(zero_page_addr);
-                            },
-
-                            // Absolute x mode
-                            7 => {
-                                let old_carry = self.status & 0x1;
-                                let addr = self.fetch_word().wrapping_add(self.x as u16);
-                                let new_carry = self.read_byte(addr) & 0x1;
-
-                                self.right_shift_and_store(addr, 1);
-
-                                if old_carry != 0 {
-                                    self.or_and_store(addr, 0x80);
-                                } else {
-                                    self.and_and_store(addr, !0x80);
-                                }
-
-                                if new_carry != 0 {
-                                    self.status |= 0x1;
-                                } else {
-                                    self.status &= !0x1;
-                                }
-
-                                self.set_zero_neg_flags(addr);
-                            },
-
-                            _ => { panic!("Opcode not supported!") }   
-                        }
+                        let mode = AddressingMode::read_modify_write(bbb).ok_or(RunError::UnsupportedOpcode(instruction, opcode_addr))?;
+                        self.read_modify_write(mode, Self::ror);
                     }
 
-                    _ => { panic!("Opcode not implemented!") }
+                    _ => { return Err(RunError::UnsupportedOpcode(instruction, opcode_addr)) }
                 }
             },
 
@@ -2074,7 +5664,7 @@ impl CPU {
                                 }
                             },
 
-                           _ => { panic!("Opcode not supported!") } 
+                           _ => { return Err(RunError::UnsupportedOpcode(instruction, opcode_addr)) } 
                         }
                     },
 
@@ -2137,7 +5727,7 @@ impl CPU {
                                 self.write_byte(addr, self.accumulator);
                             }
 
-                            _ => { panic!("Opcode not supported") }
+                            _ => { return Err(RunError::UnsupportedOpcode(instruction, opcode_addr)) }
                         }
                     },
 
@@ -2165,11 +5755,11 @@ impl CPU {
                                 self.write_byte(addr, self.x);
                             },
 
-                            _ => { panic!("Opcode not supported") }
+                            _ => { return Err(RunError::UnsupportedOpcode(instruction, opcode_addr)) }
                         }
                     },
 
-                    _ => { panic!("Opcode not supported!") }
+                    _ => { return Err(RunError::UnsupportedOpcode(instruction, opcode_addr)) }
                 }
             }
 
@@ -2200,15 +5790,13 @@ impl CPU {
 
                         // LDY - Zero-page x
                         5 => {
-                            let zero_page_addr = (self.fetch_byte().wrapping_add(self.x)) as u16;
-                            self.y = self.read_byte(zero_page_addr);
+                            self.y = self.read_operand(AddressingMode::ZeroPageX);
                             self.set_zero_neg(self.y);
                         }
 
                         // LDY - Absolute x
                         7 => {
-                            let addr = self.fetch_word().wrapping_add(self.x as u16);
-                            self.y = self.read_byte(addr);
+                            self.y = self.read_operand(AddressingMode::AbsoluteX);
                             self.set_zero_neg(self.y);
                         }
 
@@ -2220,7 +5808,7 @@ impl CPU {
                             }
                         },
 
-                        _ => { panic!("Opcode not supported!") }
+                        _ => { return Err(RunError::UnsupportedOpcode(instruction, opcode_addr)) }
                     }
 
                     // LDA - Loads a byte from memory into the accumulator and changes the zero and negative flags as needed
@@ -2255,23 +5843,21 @@ impl CPU {
     
                             // LDX - Zero-page y
                             5 => {
-                                let zero_page_addr = (self.fetch_byte().wrapping_add(self.y)) as u16;
-                                self.x = self.read_byte(zero_page_addr);
+                                self.x = self.read_operand(AddressingMode::ZeroPageY);
                                 self.set_zero_neg(self.x);
                             },
 
                             // LDX - Absolute y
                             7 => {
-                                let addr = self.fetch_word().wrapping_add(self.y as u16);
-                                self.x = self.read_byte(addr);
+                                self.x = self.read_operand(AddressingMode::AbsoluteY);
                                 self.set_zero_neg(self.x);
                             },
 
-                            _ => panic!("Opcode not supported!")
+                            _ => return Err(RunError::UnsupportedOpcode(instruction, opcode_addr))
                         }
                     },
 
-                    _ => { panic!("Opcode not supported!") }
+                    _ => { return Err(RunError::UnsupportedOpcode(instruction, opcode_addr)) }
                 }
             }
 
@@ -2306,7 +5892,7 @@ impl CPU {
                                 }
                             },
 
-                            _ => { panic!("Opcode not supported!") }
+                            _ => { return Err(RunError::UnsupportedOpcode(instruction, opcode_addr)) }
                         }
                     },
 
@@ -2318,42 +5904,15 @@ impl CPU {
                     },
 
                     // DEC - Subtracts one from a memory-held value - Sets the negative and zero flags as appropriate
-                    // Because DEC directly changes memory values we need to code each addressing mode
+                    // Memory only — the bbb == 2 slot that would be the accumulator is DEX, decoded with the implied instructions
                     2 => {
-                        match bbb {
-                            // Zero-page
-                            1 => {
-                                let addr = self.fetch_byte();
-
-                                self.dec(addr as u16);
-                            },
-
-                            // Absolute
-                            3 => {
-                                let addr = self.fetch_word();
-
-                                self.dec(addr as u16)
-                            },
-
-                            // Zero-page x
-                            5 => {
-                                let addr = self.fetch_byte().wrapping_add(self.x);
-
-                                self.dec(addr as u16);
-                            },
-
-                            // Absolute-x
-                            7 => {
-                                let addr = self.fetch_word().wrapping_add(self.x as u16);
-
-                                self.dec(addr);
-                            }
-
-                            _ => { panic!("Opcode not supported!") }
-                        }
+                        let mode = AddressingMode::read_modify_write(bbb)
+                            .filter(|mode| *mode != AddressingMode::Accumulator)
+                            .ok_or(RunError::UnsupportedOpcode(instruction, opcode_addr))?;
+                        self.read_modify_write(mode, Self::dec);
                     },
 
-                    _ => { panic!("Opcode not supported!") }
+                    _ => { return Err(RunError::UnsupportedOpcode(instruction, opcode_addr)) }
                 }
             },
 
@@ -2388,82 +5947,42 @@ impl CPU {
                                 }
                             },
 
-                            _ => { panic!("Opcode not implemented!") }
+                            _ => { return Err(RunError::UnsupportedOpcode(instruction, opcode_addr)) }
                         }
                     },
 
                     // SBC - Subtracts the contents of a memory location from the accumulator together with the not of the carry flag
                     // If overflow occurs the carry bit is clear - Allows for multi-byte subtraction
                     1 => {
-                        let carry_bit = self.status & 0x1;
-                        let prev = self.accumulator;
+                        // A - M - (1 - C) is the same as A + !M + C, so the carry and overflow logic is shared with ADC
                         let operand = self.get_operand(bbb);
-                        self.accumulator = self.accumulator.wrapping_sub(operand).wrapping_sub(1 - carry_bit);
-
-                        let sign = self.accumulator & 0x80;
-                        let op_sign = operand & 0x80;
-                        let prev_sign = prev & 0x80;
-
-                        // println!("after accumulator: {}", self.accumulator);
-                        // println!("prev: {}, carry_bit: {}, operand: {}, term: {}", prev, carry_bit, operand, operand.wrapping_add(1-carry_bit));
-
-                        if (prev as u16) < (operand as u16 + (1 - carry_bit as u16)) {
-                            self.status &= !0x01;
-                        } else {
-                            self.status |= 0x01;
-                        }
-
-                        if op_sign != prev_sign && prev_sign != sign {
-                            self.status |= 0x40;
-                        } else {
-                            self.status &= !0x40;
-                        }
-
-                        self.set_zero_neg(self.accumulator);
+                        self.add_with_carry(!operand);
                     },
 
                     // INC - Adds one to a memory-held value - Sets the negative and zero flags as appropriate
-                    // Because INC directly changes memory values we need to code each addressing mode
+                    // Memory only — the bbb == 2 slot that would be the accumulator is NOP, decoded with the implied instructions
                     2 => {
-                        match bbb {
-                            // Zero-page
-                            1 => {
-                                let addr = self.fetch_byte();
-
-                                self.inc(addr as u16);
-                            },
-
-                            // Absolute
-                            3 => {
-                                let addr = self.fetch_word();
-
-                                self.inc(addr as u16)
-                            },
-
-                            // Zero-page x
-                            5 => {
-                                let addr = self.fetch_byte().wrapping_add(self.x);
-
-                                self.inc(addr as u16);
-                            },
-
-                            // Absolute-x
-                            7 => {
-                                let addr = self.fetch_word().wrapping_add(self.x as u16);
-
-                                self.inc(addr);
-                            }
-
-                            _ => { panic!("Opcode not supported!") }
-                        }
+                        let mode = AddressingMode::read_modify_write(bbb)
+                            .filter(|mode| *mode != AddressingMode::Accumulator)
+                            .ok_or(RunError::UnsupportedOpcode(instruction, opcode_addr))?;
+                        self.read_modify_write(mode, Self::inc);
                     },
 
-                    _ => { panic!("Opcode not implemented!") }
+                    _ => { return Err(RunError::UnsupportedOpcode(instruction, opcode_addr)) }
                 }
             },
 
-            _ => { panic!("Opcode not supported!") }
+            _ => { return Err(RunError::UnsupportedOpcode(instruction, opcode_addr)) }
         }
+
+        // IRQs are polled before the last cycle of each instruction — CLI, SEI and PLP change the I flag on that last cycle,
+        // so the poll still sees the old flag and the change only affects interrupts after the next instruction (RTI's is immediate)
+        let interrupt_disable = if matches!(instruction, 0x58 | 0x78 | 0x28) { status_before } else { self.status } & 0b100 != 0;
+        self.irq_pending = self.irq_asserted() && !interrupt_disable;
+
+        self.ppu_span = (span_start, self.ppu_position());
+
+        Ok(())
     }
 
     fn set_zero_neg(&mut self, check: u8) {
@@ -2511,42 +6030,6 @@ impl CPU {
         self.fetch_byte()
     }
 
-    // Absolute addressing - returns the value specified in a 16 bit address
-    fn absolute(&mut self) -> u8 {
-        let addr = self.fetch_word();
-        self.read_byte(addr)
-    }
-
-    // Zero page addressing - returns the value specified in the first 256 bytes - requires one less byte
-    fn zero_page(&mut self) -> u8 {
-        let zero_page_addr = self.fetch_byte();
-        self.read_byte(zero_page_addr as u16)
-    }
-
-    // Zero page x addressing - adds the value of register x to the next byte and retrieves the value
-    fn zero_page_x(&mut self) -> u8 {
-        let zero_page_addr = self.fetch_byte().wrapping_add(self.x);
-        self.read_byte(zero_page_addr as u16)
-    }
-
-    // Zero page y addressing - adds the value of register y to the next byte and retrieves the value
-    fn zero_page_y(&mut self) -> u8 {
-        let zero_page_addr = self.fetch_byte().wrapping_add(self.y);
-        self.read_byte(zero_page_addr as u16)
-    }
-
-    // Absolute addressing with x - returns the value at the absolute address plus the value in the x register
-    fn absolute_x(&mut self) -> u8 {
-        let addr = self.fetch_word().wrapping_add(self.x as u16);
-        self.read_byte(addr)
-    }
-
-    // Absolute addressing with y - returns the value at the absolute address plus the value in the y register
-    fn absolute_y(&mut self) -> u8 {
-        let addr = self.fetch_word().wrapping_add(self.y as u16);
-        self.read_byte(addr)
-    }
-
     // Indirect addressing - Changes the pc to the next two bytes of the address provided (provides the lsb)
     // Quirk - If at page boundary the 6502 will actually take xx00 after xxFF instead of 0(x+1)00
     fn indirect(&mut self) {
@@ -2560,38 +6043,21 @@ impl CPU {
         self.pc = (msb as u16) << 8 | lsb as u16;
     }
 
-    // Indexed Indirect - Takes a zero page address, adds the value of x to it --> new address of lsb, combine with next byte to form new address
-    fn indexed_indirect(&mut self) -> u8 {
-        let zpage_addr = self.fetch_byte().wrapping_add(self.x);
-        let lsb = self.read_byte(zpage_addr as u16);
-        let msb = self.read_byte(zpage_addr.wrapping_add(1) as u16);
-
-        self.read_byte((msb as u16) << 8 | lsb as u16)
-    }
-
-    // Indirect Indexed - Slightly more sane younger brother
-    // Take a zero page address, find the msb/lsb to get new address, add y to that new address
-    fn indirect_indexed(&mut self) -> u8 {
-        let zpage_addr = self.fetch_byte();
-        let lsb = self.read_byte(zpage_addr as u16);
-        let msb = self.read_byte(zpage_addr.wrapping_add(1) as u16);
-        let addr = ((msb as u16) << 8 | lsb as u16).wrapping_add(self.y as u16);
-
-        self.read_byte(addr)
-    }
-
+    // Operand of a read instruction from the bbb bits of its opcode (cc == 1 layout)
     fn get_operand(&mut self, bbb_data: u8) -> u8 {
-        match bbb_data {
-            0 => { self.indexed_indirect() },
-            1 => { self.zero_page() },
-            2 => { self.immediate() },
-            3 => { self.absolute() },
-            4 => { self.indirect_indexed() },
-            5 => { self.zero_page_x() },
-            6 => { self.absolute_y() },
-            7 => { self.absolute_x() },
-            _ => { panic!("Addressing mode not supported") }, 
-        }
+        let mode = match bbb_data {
+            0 => AddressingMode::IndirectX,
+            1 => AddressingMode::ZeroPage,
+            2 => return self.immediate(),
+            3 => AddressingMode::Absolute,
+            4 => AddressingMode::IndirectY,
+            5 => AddressingMode::ZeroPageX,
+            6 => AddressingMode::AbsoluteY,
+            7 => AddressingMode::AbsoluteX,
+            _ => { panic!("Addressing mode not supported") },
+        };
+
+        self.read_operand(mode)
     }
 
     // Push and pop functions for the stack
@@ -2602,6 +6068,19 @@ impl CPU {
         self.sp = self.sp.wrapping_sub(1);
     }
 
+    // Bit 5 always reads as 1 in the register, and is pushed that way. B (bit 4) only exists in the copy pushed to the
+    // stack — 1 from PHP/BRK and 0 from an NMI/IRQ (how a handler tells them apart)
+    fn status_for_push(&self, break_flag: bool) -> u8 {
+        let status = (self.status | 0b10_0000) & !0b1_0000;
+
+        if break_flag { status | 0b1_0000 } else { status }
+    }
+
+    // PLP/RTI — N, V, D, I, Z and C come back exactly as pushed, bit 5 stays 1 and B stays 0 whatever the stack held
+    fn pull_status(&mut self) {
+        self.status = (self.pop_stack() | 0b10_0000) & !0b1_0000;
+    }
+
     fn pop_stack(&mut self) -> u8 {
         self.sp = self.sp.wrapping_add(1);
         let stack_addr = self.sp as u16 + STACK_BASE as u16;
@@ -2611,3 +6090,37 @@ impl CPU {
         rtrn_value
     }
 }
+
+#[cfg(feature = "lockstep")]
+impl<B: Bus> CPU<B> {
+    pub fn registers(&self) -> Registers {
+        Registers { accumulator: self.accumulator, x: self.x, y: self.y, pc: self.pc, sp: self.sp, status: self.status }
+    }
+
+    // Steps this CPU and the reference one instruction at a time, comparing the registers and internal RAM after each
+    // Stops at the first difference so the report points at the instruction that caused it
+    pub fn run_lockstep<R: ReferenceCpu>(&mut self, reference: &mut R, instructions: usize) -> Result<(), Divergence> {
+        for instruction in 0..instructions {
+            let before = self.registers();
+            let opcode = self.cpu_bus.peek(self.pc);
+
+            // An opcode we can't run is as much a divergence as a wrong result, so it's reported the same way
+            let _ = self.decode();
+            reference.step();
+
+            let (ours, theirs) = (self.registers(), reference.registers());
+
+            // Bits 4 and 5 only exist on the stack copy of the status register, so cores are free to keep them however they like
+            let registers_match = Registers { status: ours.status & 0b1100_1111, ..ours } == Registers { status: theirs.status & 0b1100_1111, ..theirs };
+            let memory = (RAM..0x800)
+                .find(|addr| self.cpu_bus.peek(*addr) != reference.peek(*addr))
+                .map(|addr| (addr, self.cpu_bus.peek(addr), reference.peek(addr)));
+
+            if !registers_match || memory.is_some() {
+                return Err(Divergence { instruction, pc: before.pc, opcode, before, ours, reference: theirs, memory })
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+// ADC and SBC share one adder — the overflow boundaries, carry in, and SBC being ADC of the inverted operand
+
+mod common;
+
+use common::*;
+use nes_components::*;
+
+const FLAGS: u8 = NEGATIVE | OVERFLOW | ZERO | CARRY;
+
+const ADC: u8 = 0x69;
+const SBC: u8 = 0xE9;
+
+#[test]
+fn adc_overflow_boundaries() {
+    run_with_big_stack(|| {
+        // (A, operand, carry in, result, flags)
+        let cases = [
+            (0x7F, 0x01, false, 0x80, NEGATIVE | OVERFLOW), // 127 + 1 overflows into negative
+            (0x7F, 0x00, true, 0x80, NEGATIVE | OVERFLOW), // Same again from the carry in
+            (0x80, 0xFF, false, 0x7F, OVERFLOW | CARRY), // -128 + -1 overflows into positive
+            (0xFF, 0x01, false, 0x00, ZERO | CARRY), // Unsigned wrap, no signed overflow (-1 + 1)
+            (0xFF, 0x00, true, 0x00, ZERO | CARRY),
+            (0x50, 0x50, false, 0xA0, NEGATIVE | OVERFLOW),
+            (0x50, 0x10, true, 0x61, 0),
+        ];
+
+        for (a, operand, carry, result, flags) in cases {
+            assert_eq!(run(ADC, a, operand, carry), (result, flags), "ADC #{:02X} with A = {:02X}, carry {}", operand, a, carry);
+        }
+    });
+}
+
+#[test]
+fn sbc_overflow_boundaries() {
+    run_with_big_stack(|| {
+        // (A, operand, carry in (clear means borrow), result, flags)
+        let cases = [
+            (0x80, 0x01, true, 0x7F, OVERFLOW | CARRY), // -128 - 1 overflows into positive
+            (0x80, 0x00, false, 0x7F, OVERFLOW | CARRY), // Same again from the borrow
+            (0x7F, 0xFF, true, 0x80, NEGATIVE | OVERFLOW), // 127 - -1 overflows into negative
+            (0x00, 0x01, true, 0xFF, NEGATIVE), // Borrows, no signed overflow (0 - 1)
+            (0x01, 0x01, true, 0x00, ZERO | CARRY),
+            (0x01, 0x00, false, 0x00, ZERO | CARRY),
+        ];
+
+        for (a, operand, carry, result, flags) in cases {
+            assert_eq!(run(SBC, a, operand, carry), (result, flags), "SBC #{:02X} with A = {:02X}, carry {}", operand, a, carry);
+        }
+    });
+}
+
+#[test]
+fn sbc_is_adc_of_the_inverted_operand() {
+    run_with_big_stack(|| {
+        for a in 0..=255u8 {
+            for operand in 0..=255u8 {
+                for carry in [false, true] {
+                    assert_eq!(run(SBC, a, operand, carry), run(ADC, a, !operand, carry), "A = {:02X}, operand {:02X}, carry {}", a, operand, carry);
+                }
+            }
+        }
+    });
+}
+
+// Runs ADC/SBC immediate once, giving back A and the N, V, Z and C flags
+fn run(opcode: u8, accumulator: u8, operand: u8, carry: bool) -> (u8, u8) {
+    thread_local! {
+        static CPU: std::cell::RefCell<CPU<CPUBus>> = std::cell::RefCell::new(CPU::with_flat_memory());
+    }
+
+    CPU.with_borrow_mut(|cpu| {
+        cpu.cpu_bus.poke(PROGRAM_START, opcode);
+        cpu.cpu_bus.poke(PROGRAM_START + 1, operand);
+        cpu.pc = PROGRAM_START;
+        cpu.accumulator = accumulator;
+        // The same CPU runs every case, which is long enough for the APU's frame IRQ to go off
+        cpu.status = INTERRUPT_DISABLE | if carry { CARRY } else { 0 };
+
+        cpu.decode().expect("The instruction didn't run");
+
+        (cpu.accumulator, cpu.status & FLAGS)
+    })
+}
@@ -0,0 +1,63 @@
+// The APU driven directly without a console around it — its registers, the frame sequencer and what comes out of the mixer
+
+use nes_components::*;
+use std::cell::Cell;
+use std::rc::Rc;
+
+// NTSC CPU cycles in a tenth of a second
+const TENTH_OF_A_SECOND: u32 = 178_977;
+
+#[test]
+fn a_5_step_frame_counter_write_clocks_the_length_counters_straight_away() {
+    for (data, clocked) in [(0x00, false), (0x80, true)] {
+        let mut apu = APU::new();
+        apu.write_register(0x4015, 0b0001);
+        apu.write_register(0x4003, 0x08);
+        let loaded = apu.length_counter(0);
+        assert!(loaded > 0, "The length counter didn't load");
+
+        apu.write_register(0x4017, data);
+
+        assert_eq!(apu.length_counter(0), loaded - clocked as u8, "$4017 write of {:02X}", data);
+    }
+}
+
+// Cartridge sound chip stub putting out a constant level, keeping count of the cycles it's been run for
+struct ConstantChip {
+    level: f32,
+    cycles: Rc<Cell<u32>>,
+}
+
+impl ExpansionAudio for ConstantChip {
+    fn sample(&mut self, cpu_cycles: u32) -> f32 {
+        self.cycles.set(self.cycles.get() + cpu_cycles);
+        self.level
+    }
+}
+
+#[test]
+fn expansion_audio_is_mixed_into_the_output() {
+    let mut apu = APU::new();
+    assert_eq!(apu.sample(), 0.0);
+
+    let cycles = Rc::new(Cell::new(0));
+    apu.set_expansion_audio(Some(Box::new(ConstantChip { level: 0.25, cycles: cycles.clone() })));
+    for _ in 0..10 {
+        apu.tick();
+    }
+
+    assert_eq!(apu.sample(), 0.25);
+    assert_eq!(cycles.get(), 10, "The chip wasn't run for the cycles since the last sample");
+
+    // It makes it through the resampler to the audio device too, once the filter has settled
+    apu.set_output_rate(44_100);
+    for _ in 0..TENTH_OF_A_SECOND {
+        apu.tick();
+    }
+    let samples = apu.take_samples();
+    assert!(!samples.is_empty());
+    assert!(samples[samples.len() / 2..].iter().all(|sample| (sample - 0.25).abs() < 0.01), "The chip's level didn't come out");
+
+    apu.set_expansion_audio(None);
+    assert_eq!(apu.sample(), 0.0);
+}
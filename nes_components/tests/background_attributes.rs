@@ -0,0 +1,53 @@
+// Attribute bytes — each one covers a 4x4 tile area, two bits per 2x2 quadrant picking the palette for those tiles
+
+mod common;
+
+use common::*;
+use nes_components::*;
+
+// Colors 1 and 3 of background palettes 0 and 1
+const PALETTE_0: [u8; 2] = [0x21, 0x23];
+const PALETTE_1: [u8; 2] = [0x25, 0x27];
+
+#[test]
+fn adjacent_tiles_in_different_quadrants_use_their_own_palettes() {
+    run_with_big_stack(|| {
+        // Tile 0 (all the background) is color 3 in its leftmost column and color 1 everywhere else, so tiles can be
+        // told apart on screen
+        let mut chr = vec![0; 0x2000];
+        chr[0x00..0x08].fill(0xFF);
+        chr[0x08..0x10].fill(0x80);
+        let mut cpu = booted_nrom(&JMP_SELF, chr, Mirroring::VERTICAL);
+        cpu.cpu_bus.ppu.set_oam(&[0xFF; 256]);
+        cpu.cpu_bus.ppu.poke_vram(0x3F01, PALETTE_0[0]);
+        cpu.cpu_bus.ppu.poke_vram(0x3F03, PALETTE_0[1]);
+        cpu.cpu_bus.ppu.poke_vram(0x3F05, PALETTE_1[0]);
+        cpu.cpu_bus.ppu.poke_vram(0x3F07, PALETTE_1[1]);
+
+        // $23C1 covers tile columns 4-7 of rows 0-3 — palette 0 for the top left quadrant (columns 4-5), palette 1 for the
+        // top right (columns 6-7)
+        cpu.cpu_bus.ppu.poke_vram(0x23C1, 0b0000_0100);
+        cpu.cpu_bus.mem_write(0x2001, 0b0000_1010);
+        let _ = cpu.run_frame();
+        let _ = cpu.run_frame();
+
+        let pixels = cpu.cpu_bus.ppu.frame_buffer_as(PixelFormat::Xrgb);
+        for line in [2, 10, 15] {
+            let row: Vec<u8> = pixels[line * 256..(line + 1) * 256].iter().map(|pixel| *pixel as u8).collect();
+
+            // Tile column n starts at the nth color 3 pixel
+            let edges: Vec<usize> = (0..256).filter(|x| row[*x] == PALETTE_0[1] || row[*x] == PALETTE_1[1]).collect();
+            assert_eq!(edges.len(), 32, "Couldn't find the tile edges on line {}", line);
+
+            // Every pixel of a tile uses the same palette, and the quadrants switch over exactly at a tile edge
+            let tile = |column: usize| &row[edges[column]..edges[column] + 8];
+            let drawn_with = |palette: [u8; 2]| [[palette[1]].as_slice(), &[palette[0]; 7]].concat();
+            for column in [4, 5] {
+                assert_eq!(tile(column), drawn_with(PALETTE_0), "Tile column {} on line {}", column, line);
+            }
+            for column in [6, 7] {
+                assert_eq!(tile(column), drawn_with(PALETTE_1), "Tile column {} on line {}", column, line);
+            }
+        }
+    });
+}
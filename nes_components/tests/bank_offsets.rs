@@ -0,0 +1,53 @@
+// Mapper::prg_offset — banks switched into a window land at the right place in the PRG, and PRG smaller than a window
+// mirrors through it instead of indexing past the end (CHR through Mapper::chr_offset the same way)
+
+use nes_components::*;
+
+#[test]
+fn banks_of_a_256kb_prg_map_to_their_file_offsets() {
+    // Sixteen 16KB banks on UNROM-512, every byte holding its bank number
+    let prg: Vec<u8> = (0..16u8).flat_map(|bank| vec![bank; 0x4000]).collect();
+    let mapper = Rom::from_parts(prg, vec![], 30, Mirroring::VERTICAL).create_mapper().expect("Mapper 30 isn't supported");
+
+    for bank in 0..16 {
+        mapper.borrow_mut().cpu_write(0x8000, bank as u8);
+
+        let mapper = mapper.borrow();
+        assert_eq!(mapper.prg_offset(0x8000), bank * 0x4000);
+        assert_eq!(mapper.prg_offset(0xBFFF), bank * 0x4000 + 0x3FFF);
+        assert_eq!(mapper.cpu_read(0x9234), bank as u8);
+        assert_eq!(mapper.prg_offset(0xC000), 15 * 0x4000, "The last bank should stay fixed at $C000");
+    }
+}
+
+#[test]
+fn prg_smaller_than_its_window_is_mirrored() {
+    // 8KB on NROM, whose windows are 16KB
+    let prg: Vec<u8> = (0..0x2000).map(|i| (i >> 8) as u8).collect();
+    let mapper = Rom::from_parts(prg, vec![0; 0x2000], 0, Mirroring::VERTICAL).create_mapper().expect("Could not create the mapper");
+    let mapper = mapper.borrow();
+
+    for addr in [0x8000, 0xA000, 0xC000, 0xE000] {
+        assert_eq!(mapper.prg_offset(addr + 0x1F00), 0x1F00, "${:04X} didn't mirror the 8KB", addr + 0x1F00);
+    }
+    assert_eq!(mapper.cpu_read(0xFFFF), 0x1F);
+}
+
+#[test]
+fn reads_past_the_end_of_a_short_chr_rom_are_mirrored() {
+    // 4KB of CHR on NROM, whose one window is 8KB
+    let chr: Vec<u8> = (0..0x1000).map(|i| (i >> 4) as u8).collect();
+    let mapper = Rom::from_parts(vec![0; 0x4000], chr, 0, Mirroring::VERTICAL).create_mapper().expect("Could not create the mapper");
+    let mapper = mapper.borrow();
+
+    assert_eq!(mapper.chr_offset(0x1FFF), 0x0FFF);
+    assert_eq!(mapper.ppu_read(0x1010), 0x01, "The upper pattern table didn't mirror the lower one");
+    assert_eq!(mapper.ppu_read(0x1FF0), 0xFF);
+}
+
+#[test]
+fn an_empty_prg_is_rejected() {
+    let rom = Rom::from_parts(vec![], vec![0; 0x2000], 0, Mirroring::VERTICAL);
+
+    assert!(rom.create_mapper().is_err());
+}
@@ -0,0 +1,37 @@
+// A BIOS image mapped over the top of the cartridge (CPU::set_bios), like the 8KB FDS BIOS at $E000-$FFFF
+
+mod common;
+
+use common::*;
+use nes_components::*;
+
+#[test]
+fn bios_bytes_read_back_at_the_top_of_the_address_space() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+
+        // Every byte holds the low byte of its address, with the reset vector pointing at $E000
+        let mut bios: Vec<u8> = (0xE000..=0xFFFFu32).map(|addr| addr as u8).collect();
+        bios[0x1FFC..0x1FFE].copy_from_slice(&[0x00, 0xE0]);
+        cpu.set_bios(bios.clone()).expect("The BIOS wasn't mapped");
+
+        for addr in [0xE000, 0xE123, 0xF800, 0xFFFF] {
+            assert_eq!(cpu.cpu_bus.mem_read(addr), bios[addr as usize - 0xE000], "${:04X}", addr);
+        }
+        assert_eq!(cpu.pc, 0xE000, "The CPU didn't restart from the BIOS reset vector");
+
+        // Below it is still the cartridge
+        assert_eq!(cpu.cpu_bus.mem_read(0xC000), JMP_SELF[0]);
+    });
+}
+
+#[test]
+fn empty_and_oversized_bioses_are_rejected() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+
+        assert_eq!(cpu.set_bios(vec![]), Err(BiosError::Empty));
+        assert_eq!(cpu.set_bios(vec![0; 0x8001]), Err(BiosError::TooBig(0x8001)));
+        assert!(!cpu.has_bios());
+    });
+}
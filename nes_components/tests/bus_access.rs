@@ -0,0 +1,136 @@
+// The bus access hook (CPU::set_on_bus_access) — a sprite zero polling loop, and the accesses an OAM DMA makes
+// Also the PPU dots an instruction covers (CPU::last_instruction_ppu_span), three for each of its accesses
+
+mod common;
+
+use common::*;
+use nes_components::*;
+use std::{cell::RefCell, rc::Rc};
+
+// Sprite 0 sits over a solid background, eight pixels wide from x = 100
+const SPRITE_Y: u8 = 50;
+const SPRITE_X: u8 = 100;
+const BACKGROUND_COLOR: u8 = 0x21;
+const SPRITE_COLOR: u8 = 0x30;
+
+// Polls $2002 until sprite zero hit goes up, then until it comes back down at the end of the frame
+const POLL_LOOP: [u8; 13] = [
+    0x2C, 0x02, 0x20, // $C000: BIT $2002
+    0x50, 0xFB, // BVC $C000
+    0x2C, 0x02, 0x20, // $C005: BIT $2002
+    0x70, 0xFB, // BVS $C005
+    0x4C, 0x00, 0xC0, // JMP $C000
+];
+
+#[test]
+fn sprite_zero_hit_shows_up_on_the_first_read_after_it() {
+    run_with_big_stack(|| {
+        let mut chr = vec![0; 0x2000];
+        chr[0..8].fill(0xFF); // Tile 0 is solid, both as the background and as sprite 0
+        let mut cpu = booted_nrom(&POLL_LOOP, chr, Mirroring::VERTICAL);
+        cpu.cpu_bus.ppu.poke_vram(0x3F01, BACKGROUND_COLOR);
+        cpu.cpu_bus.ppu.poke_vram(0x3F11, SPRITE_COLOR);
+
+        let mut oam = [0xFF; 256];
+        oam[0..4].copy_from_slice(&[SPRITE_Y, 0, 0, SPRITE_X]);
+        cpu.cpu_bus.ppu.set_oam(&oam);
+        cpu.cpu_bus.mem_write(0x2001, 0b0001_1110);
+
+        // Skip the frame rendering was turned on in the middle of
+        let _ = cpu.run_frame();
+
+        let reads = Rc::new(RefCell::new(Vec::new()));
+        let recorder = reads.clone();
+        cpu.set_on_bus_access(Some(Box::new(move |access: BusAccess| {
+            if access.addr == 0x2002 && !access.write {
+                recorder.borrow_mut().push(access);
+            }
+        })));
+
+        let _ = cpu.run_frame();
+
+        // The flag is still up from the last frame until the pre-render line, so look for where it goes back up
+        let reads = reads.borrow();
+        let (before, hit) = reads.windows(2)
+            .map(|pair| (pair[0], pair[1]))
+            .find(|(before, hit)| before.value & 0x40 == 0 && hit.value & 0x40 != 0)
+            .expect("The poll never saw the hit");
+
+        // The flag goes up on the dot the sprite's first pixel is drawn on, so the read right before that dot misses it
+        // and the next poll sees it
+        let pixels = cpu.cpu_bus.ppu.frame_buffer_as(PixelFormat::Xrgb);
+        let sprite_line = (0..240u16)
+            .find(|line| pixels[*line as usize * 256 + SPRITE_X as usize] & 0xFF == SPRITE_COLOR as u32)
+            .expect("Sprite 0 wasn't drawn");
+        let hit_dot = (sprite_line, SPRITE_X as u16);
+        assert!((before.scanline, before.dot) <= hit_dot, "Read at {},{} already saw the hit", before.scanline, before.dot);
+        assert!((hit.scanline, hit.dot) > hit_dot, "Read at {},{} saw the hit too early", hit.scanline, hit.dot);
+
+        // Consecutive polls, no more than a BIT and a taken BVC (7 cycles) apart
+        assert!(hit.cycle - before.cycle <= 7, "Polls {} cycles apart", hit.cycle - before.cycle);
+    });
+}
+
+#[test]
+fn oam_dma_accesses_are_reported() {
+    run_with_big_stack(|| {
+        // LDA #$02, STA $4014, then spin
+        let program = [0xA9, 0x02, 0x8D, 0x14, 0x40, 0x4C, 0x05, 0xC0];
+        let mut cpu = booted_nrom(&program, vec![0; 0x2000], Mirroring::VERTICAL);
+        cpu.pc = 0xC000;
+
+        let accesses = Rc::new(RefCell::new(Vec::new()));
+        let recorder = accesses.clone();
+        cpu.set_on_bus_access(Some(Box::new(move |access: BusAccess| recorder.borrow_mut().push(access))));
+
+        for _ in 0..3 {
+            cpu.decode().expect("The DMA program stopped running");
+        }
+
+        let accesses = accesses.borrow();
+        let page_reads = accesses.iter().filter(|access| !access.write && access.addr & 0xFF00 == 0x0200).count();
+        let oam_writes = accesses.iter().filter(|access| access.write && access.addr == 0x2004).count();
+        assert_eq!((page_reads, oam_writes), (256, 256));
+    });
+}
+
+#[test]
+fn an_operand_word_in_the_ppu_registers_is_two_reads() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+
+        // Fill the $2007 read buffer from $2105, then leave $0C (NOP abs) on the open bus for the opcode fetch from $2006
+        cpu.cpu_bus.ppu.poke_vram(0x2105, 0x34);
+        cpu.cpu_bus.mem_write(0x2006, 0x21);
+        cpu.cpu_bus.mem_write(0x2006, 0x05);
+        let _ = cpu.cpu_bus.mem_read(0x2007);
+        cpu.cpu_bus.mem_write(0x2003, 0x0C);
+
+        let accesses = Rc::new(RefCell::new(Vec::new()));
+        let recorder = accesses.clone();
+        cpu.set_on_bus_access(Some(Box::new(move |access: BusAccess| recorder.borrow_mut().push((access.addr, access.value)))));
+
+        cpu.pc = 0x2006;
+        cpu.decode().expect("NOP abs didn't run");
+
+        // The low byte comes out of the read buffer (moving v on), and the high byte is open bus again
+        let accesses = accesses.borrow();
+        assert_eq!(accesses[..3], [(0x2006, 0x0C), (0x2007, 0x34), (0x2008, 0x0C)]);
+        assert_eq!(accesses[3].0, 0x0C34, "NOP read the wrong address");
+        assert_eq!(cpu.cpu_bus.ppu.vram_addr(), 0x2107, "The $2007 read should have moved v on once");
+    });
+}
+
+#[test]
+fn the_ppu_span_of_a_four_cycle_instruction_is_twelve_dots() {
+    run_with_big_stack(|| {
+        // $C003: LDA $0200 — opcode, two address bytes and the read
+        let mut cpu = booted_nrom(&[0x4C, 0x00, 0xC0, 0xAD, 0x00, 0x02], vec![0; 0x2000], Mirroring::VERTICAL);
+        tick_to(&mut cpu, 100, 50);
+        cpu.pc = 0xC003;
+
+        cpu.decode().expect("LDA didn't run");
+
+        assert_eq!(cpu.last_instruction_ppu_span(), ((100, 50), (100, 62)));
+    });
+}
@@ -0,0 +1,41 @@
+// Bus conflict reporting (cargo test --features bus-conflicts) — a write to ROM space that disagrees with the ROM byte
+// under it is counted and handed to the callback, with the value the mapper actually latched
+
+mod common;
+
+use common::*;
+use nes_components::*;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[test]
+fn a_conflicting_write_is_reported() {
+    run_with_big_stack(|| {
+        // The masking itself is checked in color_dreams.rs — this is what gets reported about it
+        let mut cpu = color_dreams_cpu();
+
+        let conflicts = Rc::new(RefCell::new(vec![]));
+        let seen = conflicts.clone();
+        cpu.cpu_bus.set_on_bus_conflict(Some(Box::new(move |conflict| seen.borrow_mut().push(conflict))));
+
+        cpu.cpu_bus.mem_write(0x8010, 0x92);
+
+        assert_eq!(cpu.cpu_bus.bus_conflicts(), 1);
+        assert_eq!(*conflicts.borrow(), [BusConflict { addr: 0x8010, written: 0x92, rom: 0x33, latched: 0x12 }]);
+    });
+}
+
+#[test]
+fn a_write_the_rom_agrees_with_isnt_reported() {
+    run_with_big_stack(|| {
+        let chr: Vec<u8> = (0..16u8).flat_map(|bank| vec![bank; 0x2000]).collect();
+        let mapper = Rom::from_parts(vec![0xFF; 0x8000], chr, 11, Mirroring::VERTICAL).create_mapper().expect("Mapper 11 isn't supported");
+        let ppu = PPU::init_ppu(mapper.clone(), vec![0; 192], None);
+        let mut cpu = CPU::init_cpu(mapper, ppu);
+
+        cpu.cpu_bus.mem_write(0x8000, 0x30);
+
+        assert_eq!(cpu.cpu_bus.bus_conflicts(), 0);
+        assert_eq!(cpu.cpu_bus.ppu.peek_vram(0x0000), 3);
+    });
+}
@@ -0,0 +1,43 @@
+// Coarse X (the low 5 bits of v) has to move on exactly once per fetched tile — 32 visible tiles plus the two prefetched
+// for the next scanline at dots 328 and 336
+
+mod common;
+
+use common::*;
+use nes_components::*;
+
+#[test]
+fn coarse_x_increments_34_times_per_scanline() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+        cpu.cpu_bus.mem_write(0x2001, 0b0000_1000);
+
+        for scanline in [1, 100, 239, 261] {
+            tick_to(&mut cpu, scanline, 0);
+
+            let mut increments = 0;
+            let mut v = cpu.cpu_bus.ppu.vram_addr();
+
+            while cpu.cpu_bus.ppu.state.scanline == scanline {
+                cpu.cpu_bus.ppu.ppu_tick();
+
+                let next = cpu.cpu_bus.ppu.vram_addr();
+                if next & 0x041F == coarse_x_increment(v) & 0x041F && next & 0x041F != v & 0x041F {
+                    increments += 1;
+                }
+                v = next;
+            }
+
+            assert_eq!(increments, 34, "Coarse X moved on {} times on scanline {}", increments, scanline);
+        }
+    });
+}
+
+// v with coarse X moved on one tile, wrapping into the next nametable over after tile 31
+fn coarse_x_increment(v: u16) -> u16 {
+    if v & 0x1F == 31 {
+        (v & !0x1F) ^ 0x0400
+    } else {
+        v + 1
+    }
+}
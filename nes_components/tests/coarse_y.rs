@@ -0,0 +1,38 @@
+// Coarse Y (bits 5-9 of v) moving on at dot 256 — row 29 wraps into the nametable below, while an out of bounds row 31
+// (only reachable by writing it) wraps to 0 in the same nametable
+
+mod common;
+
+use common::*;
+use nes_components::*;
+
+#[test]
+fn coarse_y_31_wraps_to_0_without_switching_nametables() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+        cpu.cpu_bus.mem_write(0x2001, 0b0000_1000);
+
+        // (coarse Y, whether bit 11 flips at the wrap)
+        for (coarse_y, switches) in [(29u16, true), (31, false)] {
+            tick_to(&mut cpu, 100, 200);
+            set_v_mid_frame(&mut cpu, coarse_y);
+            assert_eq!(cpu.cpu_bus.ppu.vram_addr() & 0x7BE0, 0x7000 | (coarse_y << 5));
+
+            tick_to(&mut cpu, 100, 257);
+
+            let v = cpu.cpu_bus.ppu.vram_addr();
+            assert_eq!(v & 0x73E0, 0, "Fine Y and coarse Y didn't wrap from row {}", coarse_y);
+            assert_eq!(v & 0x0800 != 0, switches, "Bit 11 after wrapping from row {}", coarse_y);
+        }
+    });
+}
+
+// The $2006/$2005/$2005/$2006 sequence games use to scroll mid frame — puts v in nametable 0 at fine Y 7 of the given
+// row, which a $2006 pair alone can't do (it always clears bit 14)
+fn set_v_mid_frame(cpu: &mut CPU<CPUBus>, coarse_y: u16) {
+    let _ = cpu.cpu_bus.mem_read(0x2002);
+    cpu.cpu_bus.mem_write(0x2006, 0x00);
+    cpu.cpu_bus.mem_write(0x2005, (coarse_y << 3) as u8 | 0b111);
+    cpu.cpu_bus.mem_write(0x2005, 0x00);
+    cpu.cpu_bus.mem_write(0x2006, ((coarse_y & 0b111) << 5) as u8);
+}
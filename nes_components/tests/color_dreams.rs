@@ -0,0 +1,59 @@
+// Mapper 11 (Color Dreams) — one register write switches the PRG and CHR banks together, fighting the ROM on the bus
+
+mod common;
+
+use common::*;
+use nes_components::*;
+
+#[test]
+fn one_write_selects_prg_and_chr_banks() {
+    // Four 32KB PRG banks and sixteen 8KB CHR banks, every byte holding its bank number
+    let prg: Vec<u8> = (0..4u8).flat_map(|bank| vec![bank; 0x8000]).collect();
+    let chr: Vec<u8> = (0..16u8).flat_map(|bank| vec![bank; 0x2000]).collect();
+
+    let rom = Rom::from_parts(prg, chr, 11, Mirroring::VERTICAL);
+    let mapper = rom.create_mapper().expect("Mapper 11 isn't supported");
+    assert_eq!((mapper.borrow().cpu_read(0x8000), mapper.borrow().ppu_read(0x0000)), (0, 0));
+
+    // CHR bank 9, PRG bank 2
+    mapper.borrow_mut().cpu_write(0xC123, 0x92);
+
+    let mapper = mapper.borrow();
+    assert_eq!((mapper.cpu_read(0x8000), mapper.cpu_read(0xFFFF)), (2, 2), "The whole 32KB should switch to bank 2");
+    assert_eq!((mapper.ppu_read(0x0000), mapper.ppu_read(0x1FFF)), (9, 9), "The whole 8KB should switch to bank 9");
+}
+
+#[test]
+fn a_16kb_prg_is_mirrored_through_the_32kb_window() {
+    let mut prg = vec![0xEA; 0x4000];
+    prg[0x3FFF] = 0x60;
+
+    let rom = Rom::from_parts(prg, vec![0; 0x2000], 11, Mirroring::VERTICAL);
+    let mapper = rom.create_mapper().expect("Mapper 11 isn't supported");
+    assert_eq!((mapper.borrow().cpu_read(0xC000), mapper.borrow().cpu_read(0xFFFF)), (0xEA, 0x60));
+
+    // Bank 3 doesn't exist either, and wraps back to the same 16KB
+    mapper.borrow_mut().cpu_write(0x8000, 0x03);
+    assert_eq!((mapper.borrow().cpu_read(0x8000), mapper.borrow().cpu_read(0xBFFF)), (0xEA, 0x60));
+}
+
+#[test]
+fn a_rom_without_chr_is_rejected() {
+    let rom = Rom::from_parts(vec![0; 0x8000], vec![], 11, Mirroring::VERTICAL);
+
+    assert!(rom.create_mapper().is_err());
+}
+
+#[test]
+fn register_writes_are_anded_with_the_rom_byte() {
+    run_with_big_stack(|| {
+        let rom = Rom::from_parts(vec![0; 0x8000], vec![0; 0x2000], 11, Mirroring::VERTICAL);
+        assert!(rom.create_mapper().expect("Mapper 11 isn't supported").borrow().bus_conflicts());
+
+        let mut cpu = color_dreams_cpu();
+
+        // CHR bank 9, PRG bank 2 over the $33 at $8010 — only CHR bank 1 and PRG bank 2 survive
+        cpu.cpu_bus.mem_write(0x8010, 0x92);
+        assert_eq!((cpu.cpu_bus.mem_read(0x8000), cpu.cpu_bus.ppu.peek_vram(0x0000)), (2, 1));
+    });
+}
@@ -0,0 +1,156 @@
+// Helpers shared by the integration tests — not every test uses all of them
+#![allow(dead_code)]
+
+use nes_components::*;
+use std::path::PathBuf;
+
+// Paths to ROMs and palettes that live in the frontend crate
+pub fn frontend_file(path: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../frontend").join(path)
+}
+
+// The CPU/PPU structs hold all of memory inline, which is too big for the default test thread stack in debug builds
+pub fn run_with_big_stack(test: fn()) {
+    std::thread::Builder::new()
+        .stack_size(64 * 1024 * 1024)
+        .spawn(test)
+        .expect("Could not spawn the test thread")
+        .join()
+        .unwrap_or_else(|e| std::panic::resume_unwind(e));
+}
+
+// Flat memory CPU (no registers or ROM in the way) with the program loaded at $0600 and the pc pointing at it
+pub fn cpu_with_program(program: &[u8]) -> CPU<CPUBus> {
+    let mut cpu = CPU::with_flat_memory();
+    for (i, byte) in program.iter().enumerate() {
+        cpu.cpu_bus.poke(PROGRAM_START + i as u16, *byte);
+    }
+    cpu.pc = PROGRAM_START;
+
+    cpu
+}
+
+pub const PROGRAM_START: u16 = 0x0600;
+
+// Status register flags — NV1B DIZC
+pub const CARRY: u8 = 0b0000_0001;
+pub const ZERO: u8 = 0b0000_0010;
+pub const INTERRUPT_DISABLE: u8 = 0b0000_0100;
+pub const DECIMAL: u8 = 0b0000_1000;
+pub const BREAK: u8 = 0b0001_0000;
+pub const UNUSED: u8 = 0b0010_0000;
+pub const OVERFLOW: u8 = 0b0100_0000;
+pub const NEGATIVE: u8 = 0b1000_0000;
+
+// $C000: JMP $C000 — keeps a console busy without touching anything
+pub const JMP_SELF: [u8; 3] = [0x4C, 0x00, 0xC0];
+
+// NROM console past the power on warm up, running the program from $C000 (empty CHR means CHR RAM)
+// The reset vector is set after the program is copied in, so a full 16KB program can bring its own NMI/IRQ vectors
+// Color n of the system palette is (n, n, n), so pixels read back as their palette index
+pub fn booted_nrom(program: &[u8], chr: Vec<u8>, mirroring: Mirroring) -> CPU<CPUBus> {
+    let mut prg = vec![0; 0x4000];
+    prg[..program.len()].copy_from_slice(program);
+    prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0xC0]);
+
+    let rom = Rom::from_parts(prg, chr, 0, mirroring);
+    let mapper = rom.create_mapper().expect("Could not create the mapper");
+    let ppu = PPU::init_ppu(mapper.clone(), (0..192).map(|i| (i / 3) as u8).collect(), None);
+    let mut cpu = CPU::init_cpu(mapper, ppu);
+
+    for _ in 0..3 {
+        let _ = cpu.run_frame();
+    }
+
+    cpu
+}
+
+// Color Dreams (mapper 11) console with four 32KB PRG banks and sixteen 8KB CHR banks, every byte holding its bank number,
+// except for a $33 at $8010 of bank 0 for register writes there to fight with
+pub fn color_dreams_cpu() -> CPU<CPUBus> {
+    let mut prg: Vec<u8> = (0..4u8).flat_map(|bank| vec![bank; 0x8000]).collect();
+    prg[0x0010] = 0x33;
+    let chr: Vec<u8> = (0..16u8).flat_map(|bank| vec![bank; 0x2000]).collect();
+
+    let mapper = Rom::from_parts(prg, chr, 11, Mirroring::VERTICAL).create_mapper().expect("Mapper 11 isn't supported");
+    let ppu = PPU::init_ppu(mapper.clone(), vec![0; 192], None);
+
+    CPU::init_cpu(mapper, ppu)
+}
+
+// Runs the PPU on its own (without the CPU) until it's about to run the given dot
+pub fn tick_to(cpu: &mut CPU<CPUBus>, scanline: u16, dot: u16) {
+    while (cpu.cpu_bus.ppu.state.scanline, cpu.cpu_bus.ppu.state.dots) != (scanline, dot) {
+        cpu.cpu_bus.ppu.ppu_tick();
+    }
+}
+
+// Sets the VRAM address through $2006 and writes one byte through $2007
+pub fn write_vram(cpu: &mut CPU<CPUBus>, addr: u16, data: u8) {
+    cpu.cpu_bus.mem_write(0x2006, (addr >> 8) as u8);
+    cpu.cpu_bus.mem_write(0x2006, addr as u8);
+    cpu.cpu_bus.mem_write(0x2007, data);
+}
+
+// FNV-1a, so reference hashes don't depend on the standard library's hasher
+pub fn hash_frame(frame: &[u32]) -> u64 {
+    let mut hash: u64 = 0xCBF2_9CE4_8422_2325;
+
+    for pixel in frame {
+        for byte in pixel.to_le_bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01B3);
+        }
+    }
+
+    hash
+}
+
+// Number of pixels that differ between two frames (pixels past the end of the shorter frame all count as different)
+pub fn frame_diff(a: &[u32], b: &[u32]) -> usize {
+    let mismatched = a.iter().zip(b).filter(|(pixel_a, pixel_b)| pixel_a != pixel_b).count();
+
+    mismatched + a.len().abs_diff(b.len())
+}
+
+// Lets a few pixels differ so timing jitter (e.g. a sprite landing a scanline late) doesn't fail a visual test
+pub fn assert_frames_close(a: &[u32], b: &[u32], tolerance: usize) {
+    let diff = frame_diff(a, b);
+    assert!(diff <= tolerance, "Frames differ in {} pixels (tolerance is {})", diff, tolerance);
+}
+
+// Runs every ROM in one of blargg's rom_singles directories (under frontend/tests) until it reports a result through
+// $6000, giving back how many ran and a message for each one that failed
+// None when the directory isn't there — the ROMs aren't shipped with every checkout
+pub fn run_blargg_singles(dir: &str, max_instructions: usize) -> Option<(usize, Vec<String>)> {
+    let rom_dir = frontend_file(dir);
+    let mut rom_paths: Vec<_> = std::fs::read_dir(&rom_dir)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "nes"))
+        .collect();
+    rom_paths.sort();
+
+    let palette = std::fs::read(frontend_file("palettes/ntsc_palette.pal")).expect("Unable to read palette file");
+    let mut failures = vec![];
+
+    for path in &rom_paths {
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        let rom_bytes = std::fs::read(path).expect("Could not read the test ROM");
+
+        let result = Rom::new(&rom_bytes).map_err(|e| e.to_string()).and_then(|rom| rom.create_mapper()).and_then(|mapper| {
+            let ppu = PPU::init_ppu(mapper.clone(), palette.clone(), None);
+            let mut cpu = CPU::init_cpu(mapper, ppu);
+
+            cpu.run_test_rom(max_instructions).map_err(|e| e.to_string())
+        });
+
+        match result {
+            Ok((0, _)) => println!("{}: passed", name),
+            Ok((status, text)) => failures.push(format!("{}: failed with status {}\n{}", name, status, text.trim_end())),
+            Err(e) => failures.push(format!("{}: {}", name, e)),
+        }
+    }
+
+    Some((rom_paths.len(), failures))
+}
@@ -0,0 +1,137 @@
+// Standard controllers as the game sees them through $4016 — strobing, then shifting the buttons out one bit per read
+// Buttons from a scripted InputLog shift out the same as the key presses they stand for
+// With the d-pad restriction on, opposite directions held together cancel out like they can't be pressed on a real pad
+// A Four Score shifts out two controllers per port, then a signature saying which port it is
+
+mod common;
+
+use common::*;
+use nes_components::*;
+
+#[test]
+fn turbo_buttons_alternate_between_frames() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+        let controller = &mut cpu.cpu_bus.input.controllers_mut()[0];
+        controller.set_buttons(BUTTON_A | BUTTON_B | BUTTON_START);
+        // 30 presses a second at 60 frames a second — pressed one frame, released the next
+        controller.set_turbo(BUTTON_A, 30);
+        controller.set_turbo(BUTTON_B, 30);
+
+        let mut seen = Vec::new();
+        for _ in 0..6 {
+            let _ = cpu.run_frame();
+            seen.push(read_buttons(&mut cpu, 0));
+        }
+
+        // Setting B's turbo kept A's, and START never had any
+        for pair in seen.windows(2) {
+            assert_eq!((pair[0] ^ pair[1]) & (BUTTON_A | BUTTON_B), BUTTON_A | BUTTON_B, "Turbo buttons didn't alternate: {:?}", seen);
+        }
+        assert!(seen.iter().all(|buttons| buttons & BUTTON_START != 0), "START flickered without turbo");
+        assert!(seen.iter().all(|buttons| (buttons & BUTTON_A != 0) == (buttons & BUTTON_B != 0)), "A and B turbo went out of step");
+
+        // Turning B's turbo off leaves A's running
+        cpu.cpu_bus.input.controllers_mut()[0].set_turbo(BUTTON_B, 0);
+        let mut seen = Vec::new();
+        for _ in 0..4 {
+            let _ = cpu.run_frame();
+            seen.push(read_buttons(&mut cpu, 0));
+        }
+        assert!(seen.iter().all(|buttons| buttons & BUTTON_B != 0), "B still has turbo: {:?}", seen);
+        assert!(seen.windows(2).all(|pair| (pair[0] ^ pair[1]) & BUTTON_A != 0), "A lost its turbo: {:?}", seen);
+    });
+}
+
+#[test]
+fn the_last_button_state_before_the_strobe_is_what_gets_read() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+        let controller = &mut cpu.cpu_bus.input.controllers_mut()[0];
+        controller.set_button_state(BUTTON_A, true);
+        controller.set_button_state(BUTTON_UP, true);
+        controller.set_button_state(BUTTON_A, false);
+        controller.set_button_state(BUTTON_B, true);
+
+        assert_eq!(read_buttons(&mut cpu, 0), BUTTON_B | BUTTON_UP);
+    });
+}
+
+#[test]
+fn scripted_input_reads_the_same_as_key_presses() {
+    run_with_big_stack(|| {
+        let mut scripted = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+        let mut keyed = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+        let frames = [BUTTON_A | BUTTON_START, BUTTON_A | BUTTON_RIGHT, 0, BUTTON_B | BUTTON_UP];
+
+        let mut held = 0u8;
+        for buttons in frames {
+            let mut log = InputLog::new();
+            log.push(buttons);
+            scripted.play_input_log(&log).expect("The scripted frame didn't run");
+
+            // A key down for every newly held button and a key up for every let go one
+            for bit in 0..8 {
+                let button = 1 << bit;
+                if (held ^ buttons) & button != 0 {
+                    keyed.cpu_bus.input.controllers_mut()[0].set_button_state(button, buttons & button != 0);
+                }
+            }
+            held = buttons;
+            keyed.run_frame().expect("The keyed frame didn't run");
+
+            let read = read_buttons(&mut scripted, 0);
+            assert_eq!(read, buttons);
+            assert_eq!(read_buttons(&mut keyed, 0), read);
+        }
+    });
+}
+
+#[test]
+fn opposite_directions_cancel_out_with_the_dpad_restriction_on() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+        let controller = &mut cpu.cpu_bus.input.controllers_mut()[0];
+        controller.set_buttons(BUTTON_LEFT | BUTTON_RIGHT | BUTTON_UP | BUTTON_A);
+
+        // Off by default, so both directions get through
+        assert_eq!(read_buttons(&mut cpu, 0), BUTTON_LEFT | BUTTON_RIGHT | BUTTON_UP | BUTTON_A);
+
+        cpu.cpu_bus.input.controllers_mut()[0].set_dpad_restriction(true);
+        assert_eq!(read_buttons(&mut cpu, 0), BUTTON_UP | BUTTON_A);
+    });
+}
+
+#[test]
+fn a_four_score_shifts_out_four_controllers_and_its_signature() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+        cpu.cpu_bus.input = InputBackend::FourScore(std::array::from_fn(|_| Controller::new()));
+        let buttons = [BUTTON_A | BUTTON_UP, BUTTON_B, BUTTON_START | BUTTON_RIGHT, BUTTON_SELECT | BUTTON_DOWN | BUTTON_LEFT];
+        for (controller, buttons) in cpu.cpu_bus.input.controllers_mut().iter_mut().zip(buttons) {
+            controller.set_buttons(buttons);
+        }
+
+        cpu.cpu_bus.mem_write(0x4016, 1);
+        cpu.cpu_bus.mem_write(0x4016, 0);
+
+        // Controllers 1 and 3 on $4016 with signature $10, 2 and 4 on $4017 with $20 — buttons A first, signatures MSB first
+        for (port, signature) in [(0u16, 0b0001_0000u8), (1, 0b0010_0000)] {
+            let bits: Vec<u8> = (0..24).map(|_| cpu.cpu_bus.mem_read(0x4016 + port) & 0b1).collect();
+            let byte = |chunk: &[u8]| chunk.iter().enumerate().fold(0, |byte, (bit, value)| byte | value << bit);
+
+            assert_eq!(byte(&bits[0..8]), buttons[port as usize], "First controller on port {}", port);
+            assert_eq!(byte(&bits[8..16]), buttons[port as usize + 2], "Second controller on port {}", port);
+            assert_eq!(byte(&bits[16..24]).reverse_bits(), signature, "Signature on port {}", port);
+            assert_eq!(cpu.cpu_bus.mem_read(0x4016 + port) & 0b1, 1, "Reads past the signature on port {}", port);
+        }
+    });
+}
+
+// Strobes the controllers and shifts the eight buttons of one port out, A first
+fn read_buttons(cpu: &mut CPU<CPUBus>, port: u16) -> u8 {
+    cpu.cpu_bus.mem_write(0x4016, 1);
+    cpu.cpu_bus.mem_write(0x4016, 0);
+
+    (0..8).fold(0, |buttons, bit| buttons | (cpu.cpu_bus.mem_read(0x4016 + port) & 0b1) << bit)
+}
@@ -0,0 +1,30 @@
+// The debug layer view (PPU::set_debug_layers) — pixels come out in the tint of the layer that won them
+
+mod common;
+
+use common::*;
+use nes_components::*;
+
+#[test]
+fn a_front_priority_sprite_comes_out_in_the_front_sprite_tint() {
+    run_with_big_stack(|| {
+        let mut chr = vec![0; 0x2000];
+        chr[16..24].fill(0xFF); // Tile 1 is solid, the background stays on the blank tile 0
+        let mut cpu = booted_nrom(&JMP_SELF, chr, Mirroring::VERTICAL);
+
+        let mut oam = [0xFF; 256];
+        oam[0..4].copy_from_slice(&[50, 0x01, 0x00, 100]);
+        cpu.cpu_bus.ppu.set_oam(&oam);
+        cpu.cpu_bus.ppu.set_debug_layers(true);
+        cpu.cpu_bus.mem_write(0x2001, 0b0001_1110);
+        let _ = cpu.run_frame();
+        let _ = cpu.run_frame();
+
+        let pixels = cpu.cpu_bus.ppu.frame_buffer_as(PixelFormat::Xrgb);
+        let (r, g, b) = DEBUG_TINT_SPRITE_FRONT;
+        assert_eq!(pixels[55 * 256 + 104] & 0xFF_FFFF, PixelFormat::Xrgb.pack(r, g, b));
+
+        // The backdrop next to it isn't any of the layers
+        assert_ne!(pixels[55 * 256 + 140] & 0xFF_FFFF, PixelFormat::Xrgb.pack(r, g, b));
+    });
+}
@@ -0,0 +1,50 @@
+// Disassembling a short program with CPU::disassemble_range, the way a debugger's code window would
+
+mod common;
+
+use common::*;
+use nes_components::*;
+
+const PROGRAM: [u8; 22] = [
+    0xA2, 0x08, // LDX #$08
+    0xBD, 0x00, 0x02, // LDA $0200,X
+    0x91, 0x10, // STA ($10),Y
+    0xCA, // DEX
+    0xD0, 0xF8, // BNE $0602
+    0x6C, 0xFC, 0xFF, // JMP ($FFFC)
+    0x03, // Not implemented (SLO)
+    0x04, 0x44, // Unofficial NOP $44
+    0x0A, // ASL A
+    0xB6, 0x20, // LDX $20,Y
+    0x20, 0x34, 0x12, // JSR $1234
+];
+
+#[test]
+fn disassembles_a_known_program() {
+    run_with_big_stack(|| {
+        let mut cpu = CPU::with_flat_memory();
+        for (i, byte) in PROGRAM.iter().enumerate() {
+            cpu.cpu_bus.poke(0x0600 + i as u16, *byte);
+        }
+
+        let lines = cpu.disassemble_range(0x0600, 11);
+        let expected = [
+            (0x0600, "LDX #$08"),
+            (0x0602, "LDA $0200,X"),
+            (0x0605, "STA ($10),Y"),
+            (0x0607, "DEX"),
+            (0x0608, "BNE $0602"),
+            (0x060A, "JMP ($FFFC)"),
+            (0x060D, ".db $03"),
+            (0x060E, "*NOP $44"),
+            (0x0610, "ASL A"),
+            (0x0611, "LDX $20,Y"),
+            (0x0613, "JSR $1234"),
+        ];
+
+        assert_eq!(lines, expected.map(|(addr, text)| (addr, text.to_string())));
+
+        // Nothing ran — the walk only peeks
+        assert_eq!(cpu.cycles(), 0);
+    });
+}
@@ -0,0 +1,48 @@
+// End to end smoke test — boots Donkey Kong headless and checks the title screen still comes out the same
+// Skipped when the ROM isn't there
+
+mod common;
+
+use common::*;
+use nes_components::*;
+
+const FRAMES_TO_TITLE: usize = 300;
+
+// Reference hash of the title screen — if a change is meant to alter it, check the new frame by eye, then run the test with
+// --nocapture and paste the printed hash in here
+const TITLE_SCREEN_HASH: u64 = 0xAAE41DA4E236FEDA;
+
+#[test]
+fn donkey_kong_boots_to_title_screen() {
+    run_with_big_stack(boot_donkey_kong);
+}
+
+fn boot_donkey_kong() {
+    let rom_path = frontend_file("roms/donkey_kong.nes");
+    if !rom_path.exists() {
+        println!("Skipping: frontend/roms/donkey_kong.nes isn't in this checkout");
+        return
+    }
+
+    let rom_bytes = std::fs::read(rom_path).expect("Unable to read frontend/roms/donkey_kong.nes");
+
+    let palette = std::fs::read(frontend_file("palettes/ntsc_palette.pal")).expect("Unable to read palette file");
+
+    let rom = Rom::new(&rom_bytes).expect("Could not parse the ROM");
+    let mapper = rom.create_mapper().expect("Could not create the mapper");
+
+    let ppu = PPU::init_ppu(mapper.clone(), palette, None);
+    let mut cpu = CPU::init_cpu(mapper, ppu);
+
+    for _ in 0..FRAMES_TO_TITLE {
+        cpu.run_frame().expect("Donkey Kong stopped running");
+    }
+
+    let frame = cpu.cpu_bus.ppu.frame_buffer();
+    assert!(frame.iter().any(|&pixel| pixel != frame[0]), "Frame is blank after {} frames", FRAMES_TO_TITLE);
+
+    let hash = hash_frame(frame);
+    println!("Title screen hash: {:#018X}", hash);
+
+    assert_eq!(hash, TITLE_SCREEN_HASH, "Title screen doesn't match the reference");
+}
@@ -0,0 +1,63 @@
+// The two garbage nametable fetches at dots 338 and 340 — they go out on the PPU bus, but nothing the PPU holds
+// (v, the shift registers, the $2007 read buffer) takes anything from them
+
+mod common;
+
+use common::*;
+use nes_components::*;
+
+const FIRST_TILES_COLOR: u8 = 0x21;
+const REST_COLOR: u8 = 0x22;
+
+#[test]
+fn dots_337_to_340_leave_v_the_shifters_and_the_read_buffer_alone() {
+    run_with_big_stack(|| {
+        // Tile 1 is solid color 1, tile 2 solid color 2
+        let mut chr = vec![0; 0x2000];
+        chr[0x10..0x18].fill(0xFF);
+        chr[0x28..0x30].fill(0xFF);
+        let mut cpu = booted_nrom(&JMP_SELF, chr, Mirroring::VERTICAL);
+        cpu.cpu_bus.ppu.set_oam(&[0xFF; 256]);
+        cpu.cpu_bus.ppu.poke_vram(0x3F01, FIRST_TILES_COLOR);
+        cpu.cpu_bus.ppu.poke_vram(0x3F02, REST_COLOR);
+
+        // Every row starts with the two tiles prefetched at the end of the scanline before it, and the dummy fetches read
+        // the third — if they were taken for real, the start of the next line would be drawn with tile 2
+        for row in 0..30 {
+            for column in 0..32 {
+                cpu.cpu_bus.ppu.poke_vram(0x2000 + row * 32 + column, if column < 2 { 1 } else { 2 });
+            }
+        }
+
+        // $5A in the read buffer
+        cpu.cpu_bus.ppu.poke_vram(0x2400, 0x5A);
+        cpu.cpu_bus.mem_write(0x2006, 0x24);
+        cpu.cpu_bus.mem_write(0x2006, 0x00);
+        let _ = cpu.cpu_bus.mem_read(0x2007);
+
+        cpu.cpu_bus.mem_write(0x2006, 0x20);
+        cpu.cpu_bus.mem_write(0x2006, 0x00);
+        cpu.cpu_bus.mem_write(0x2001, 0b0000_1010);
+        let _ = cpu.run_frame();
+
+        for scanline in [0, 100, 239, 261] {
+            tick_to(&mut cpu, scanline, 338);
+            let v = cpu.cpu_bus.ppu.vram_addr();
+            tick_to(&mut cpu, (scanline + 1) % 262, 0);
+
+            assert_eq!(cpu.cpu_bus.ppu.vram_addr(), v, "The dummy fetches on scanline {} moved v", scanline);
+        }
+
+        let _ = cpu.run_frame();
+        let pixels = cpu.cpu_bus.ppu.frame_buffer_as(PixelFormat::Xrgb);
+        for line in [10, 100, 200] {
+            let color_at = |x: usize| pixels[line * 256 + x] & 0xFF;
+
+            assert!((8..16).all(|x| color_at(x) == FIRST_TILES_COLOR as u32), "Line {} didn't start with the prefetched tiles", line);
+            assert!((17..32).all(|x| color_at(x) == REST_COLOR as u32), "Line {} lost its third tile", line);
+        }
+
+        // Still in VBlank, so the read doesn't fight rendering for v
+        assert_eq!(cpu.cpu_bus.mem_read(0x2007), 0x5A, "The dummy fetches went into the read buffer");
+    });
+}
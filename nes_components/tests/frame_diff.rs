@@ -0,0 +1,33 @@
+// Self-test for the frame comparison helpers used by the visual tests
+
+mod common;
+
+use common::*;
+use nes_components::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+#[test]
+fn identical_frames_have_no_diff() {
+    let frame = vec![0x00FF_8800; SCREEN_WIDTH * SCREEN_HEIGHT];
+
+    assert_eq!(frame_diff(&frame, &frame.clone()), 0);
+    assert_frames_close(&frame, &frame.clone(), 0);
+}
+
+#[test]
+fn one_changed_pixel_diffs_to_one() {
+    let frame = vec![0x00FF_8800; SCREEN_WIDTH * SCREEN_HEIGHT];
+    let mut changed = frame.clone();
+    changed[SCREEN_WIDTH * 100 + 37] = 0;
+
+    assert_eq!(frame_diff(&frame, &changed), 1);
+    assert_frames_close(&frame, &changed, 1);
+}
+
+#[test]
+#[should_panic]
+fn frames_past_the_tolerance_fail() {
+    let frame = vec![0; 16];
+    let changed = vec![1; 16];
+
+    assert_frames_close(&frame, &changed, 15);
+}
@@ -0,0 +1,75 @@
+// Mappers 30 (UNROM-512) and 111 (GTROM) — bank and mirroring registers written the way homebrew games write them
+
+use nes_components::*;
+
+#[test]
+fn unrom_512_switches_the_prg_bank_and_single_screen_page() {
+    // Four 16KB PRG banks, every byte holding its bank number
+    let prg: Vec<u8> = (0..4u8).flat_map(|bank| vec![bank; 0x4000]).collect();
+    let rom = Rom::from_parts(prg, vec![], 30, Mirroring::SINGLE_SCREEN_LOWER);
+    let mapper = rom.create_mapper().expect("Mapper 30 isn't supported");
+    assert_eq!((mapper.borrow().cpu_read(0x8000), mapper.borrow().cpu_read(0xC000)), (0, 3));
+    assert_eq!(mapper.borrow().mirroring(), Mirroring::SINGLE_SCREEN_LOWER);
+
+    // Upper screen, PRG bank 2
+    mapper.borrow_mut().cpu_write(0x8000, 0b1000_0010);
+    assert_eq!((mapper.borrow().cpu_read(0x8000), mapper.borrow().cpu_read(0xC000)), (2, 3), "Only $8000 should switch");
+    assert_eq!(mapper.borrow().mirroring(), Mirroring::SINGLE_SCREEN_UPPER);
+
+    // And back to the lower screen with bank 1
+    mapper.borrow_mut().cpu_write(0x8000, 0b0000_0001);
+    assert_eq!(mapper.borrow().cpu_read(0xBFFF), 1);
+    assert_eq!(mapper.borrow().mirroring(), Mirroring::SINGLE_SCREEN_LOWER);
+}
+
+#[test]
+fn unrom_512_with_a_tiny_prg_does_not_panic() {
+    let rom = Rom::from_parts(vec![0xEA; 0x2000], vec![], 30, Mirroring::VERTICAL);
+    let mapper = rom.create_mapper().expect("Mapper 30 isn't supported");
+
+    assert_eq!(mapper.borrow().prg_bank(1), 0);
+    assert_eq!(mapper.borrow().cpu_read(0xC000), 0xEA);
+}
+
+#[test]
+fn gtrom_with_a_tiny_prg_does_not_panic() {
+    // 16KB in a 32KB window — $C000-$FFFF mirrors $8000-$BFFF
+    let mut prg = vec![0xEA; 0x4000];
+    prg[0x3FFF] = 0x60;
+    let rom = Rom::from_parts(prg, vec![], 111, Mirroring::VERTICAL);
+    let mapper = rom.create_mapper().expect("Mapper 111 isn't supported");
+
+    assert_eq!(mapper.borrow().cpu_read(0xC000), 0xEA);
+    assert_eq!(mapper.borrow().cpu_read(0xFFFF), 0x60);
+
+    // Banks past the end wrap around too
+    mapper.borrow_mut().expansion_write(0x5000, 0b0000_0011);
+    assert_eq!(mapper.borrow().cpu_read(0xFFFF), 0x60);
+}
+
+#[test]
+fn gtrom_register_selects_prg_and_chr_banks() {
+    // Four 32KB PRG banks, every byte holding its bank number
+    let prg: Vec<u8> = (0..4u8).flat_map(|bank| vec![bank; 0x8000]).collect();
+    let rom = Rom::from_parts(prg, vec![], 111, Mirroring::HORIZONTAL);
+    let mapper = rom.create_mapper().expect("Mapper 111 isn't supported");
+    assert_eq!(mapper.borrow().mirroring(), Mirroring::FOUR_SCREEN, "GTROM always has four screens");
+
+    // A tile in each CHR RAM bank
+    mapper.borrow_mut().ppu_write(0x0010, 0xAA);
+    mapper.borrow_mut().expansion_write(0x5000, 0b0001_0000);
+    mapper.borrow_mut().ppu_write(0x0010, 0xBB);
+
+    // PRG bank 3 through the $7000 mirror, CHR bank 0
+    mapper.borrow_mut().expansion_write(0x7FFF, 0b0000_0011);
+    assert_eq!((mapper.borrow().cpu_read(0x8000), mapper.borrow().cpu_read(0xFFFF)), (3, 3), "The whole 32KB should switch");
+    assert_eq!(mapper.borrow().ppu_read(0x0010), 0xAA);
+
+    mapper.borrow_mut().expansion_write(0x5000, 0b0001_0001);
+    assert_eq!(mapper.borrow().cpu_read(0x8000), 1);
+    assert_eq!(mapper.borrow().ppu_read(0x0010), 0xBB);
+
+    // $6000 is outside the register
+    mapper.borrow_mut().expansion_write(0x6000, 0b0000_0010);
+    assert_eq!(mapper.borrow().cpu_read(0x8000), 1);
+}
@@ -0,0 +1,118 @@
+// The unofficial opcodes — the KIL opcodes jam the CPU until it's reset, the NOPs with operands read them and move on,
+// and ANC and ARR set carry (and ARR overflow) their own way — the ones that aren't implemented come back as an error
+
+mod common;
+
+use common::*;
+use nes_components::*;
+
+#[test]
+fn kil_jams_the_cpu_and_later_decodes_do_nothing() {
+    run_with_big_stack(|| {
+        // KIL, then an LDA #$42 that must never run
+        let mut cpu = cpu_with_program(&[0x02, 0xA9, 0x42]);
+
+        // The jam is reported once, by the decode that ran into it
+        assert_eq!(cpu.decode(), Err(RunError::Jammed(PROGRAM_START)));
+        assert!(cpu.jammed());
+        let registers = (cpu.pc, cpu.accumulator, cpu.status);
+
+        for _ in 0..10 {
+            let cycles = cpu.cycles();
+            cpu.decode().expect("Decoding on a jammed CPU failed");
+            assert_eq!((cpu.pc, cpu.accumulator, cpu.status), registers, "A jammed CPU kept executing");
+            assert!(cpu.cycles() > cycles, "The clock stopped with the CPU jammed");
+        }
+        assert!(cpu.jammed());
+    });
+}
+
+#[test]
+fn an_unsupported_opcode_is_returned_instead_of_panicking() {
+    run_with_big_stack(|| {
+        // SLO (zp,X) isn't implemented
+        let mut cpu = cpu_with_program(&[0x03, 0x10]);
+
+        assert_eq!(cpu.decode(), Err(RunError::UnsupportedOpcode(0x03, PROGRAM_START)));
+        assert!(!cpu.jammed());
+    });
+}
+
+#[test]
+fn nops_with_operands_skip_them_and_pay_for_the_read() {
+    run_with_big_stack(|| {
+        // (name, program, X, length, cycles)
+        let cases: [(&str, &[u8], u8, u16, usize); 5] = [
+            ("NOP zp", &[0x04, 0x10], 0, 2, 3),
+            ("NOP zp,X", &[0x14, 0x10], 1, 2, 4),
+            ("NOP abs", &[0x0C, 0x00, 0x02], 0, 3, 4),
+            ("NOP abs,X", &[0x1C, 0x00, 0x02], 1, 3, 4),
+            ("NOP abs,X across a page", &[0x1C, 0xFF, 0x02], 1, 3, 5),
+        ];
+
+        for (name, program, x, length, cycles) in cases {
+            let mut cpu = cpu_with_program(program);
+            cpu.x = x;
+            let (accumulator, status) = (cpu.accumulator, cpu.status);
+
+            let start = cpu.cycles();
+            cpu.decode().unwrap_or_else(|e| panic!("{} didn't run: {}", name, e));
+
+            assert_eq!(cpu.pc, PROGRAM_START + length, "{} left the pc in the wrong place", name);
+            assert_eq!(cpu.cycles() - start, cycles, "{} took the wrong number of cycles", name);
+            assert_eq!((cpu.accumulator, cpu.status), (accumulator, status), "{} changed a register", name);
+        }
+    });
+}
+
+#[test]
+fn anc_copies_bit_7_of_the_result_into_carry() {
+    run_with_big_stack(|| {
+        // (accumulator, operand, carry in, result, flags out)
+        let cases = [
+            (0xFF, 0x80, false, 0x80, NEGATIVE | CARRY),
+            (0xFF, 0x7F, true, 0x7F, 0),
+            (0x0F, 0xF0, true, 0x00, ZERO),
+            (0x81, 0xC1, false, 0x81, NEGATIVE | CARRY),
+        ];
+
+        for opcode in [0x0B, 0x2B] {
+            for (accumulator, operand, carry, result, flags) in cases {
+                let (accumulator_out, flags_out) = run_immediate(opcode, accumulator, operand, carry);
+                assert_eq!((accumulator_out, flags_out), (result, flags), "ANC ({:02X}) of {:02X} and {:02X}", opcode, accumulator, operand);
+            }
+        }
+    });
+}
+
+#[test]
+fn arr_takes_carry_and_overflow_from_bits_6_and_5() {
+    run_with_big_stack(|| {
+        // (accumulator, operand, carry in, result, flags out) — the AND'd value is rotated right through the carry, then C is
+        // bit 6 of the result and V is bit 6 XOR bit 5
+        let cases = [
+            (0xFF, 0x00, true, 0x80, NEGATIVE),
+            (0xFF, 0x00, false, 0x00, ZERO),
+            (0xFF, 0x40, false, 0x20, OVERFLOW),
+            (0xFF, 0x80, false, 0x40, CARRY | OVERFLOW),
+            (0xFF, 0xC0, false, 0x60, CARRY),
+            (0xFF, 0xFF, true, 0xFF, NEGATIVE | CARRY),
+            (0x0F, 0xFF, true, 0x87, NEGATIVE),
+        ];
+
+        for (accumulator, operand, carry, result, flags) in cases {
+            let (accumulator_out, flags_out) = run_immediate(0x6B, accumulator, operand, carry);
+            assert_eq!((accumulator_out, flags_out), (result, flags), "ARR of {:02X} and {:02X}, carry {}", accumulator, operand, carry);
+        }
+    });
+}
+
+// Runs an immediate mode opcode, giving back the accumulator and the N, V, Z and C flags
+fn run_immediate(opcode: u8, accumulator: u8, operand: u8, carry: bool) -> (u8, u8) {
+    let mut cpu = cpu_with_program(&[opcode, operand]);
+    cpu.accumulator = accumulator;
+    cpu.status = (cpu.status & !(NEGATIVE | OVERFLOW | ZERO | CARRY)) | carry as u8;
+
+    cpu.decode().expect("The opcode didn't run");
+    (cpu.accumulator, cpu.status & (NEGATIVE | OVERFLOW | ZERO | CARRY))
+}
@@ -0,0 +1,72 @@
+// Writes through $2007 into the pattern tables — dropped and counted on CHR ROM, stored on CHR RAM where rendering
+// fetches them from
+
+mod common;
+
+use common::*;
+use nes_components::*;
+use std::{cell::RefCell, rc::Rc};
+
+#[test]
+fn chr_rom_writes_are_counted_and_reported() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+
+        let reported = Rc::new(RefCell::new(Vec::new()));
+        let recorder = reported.clone();
+        cpu.cpu_bus.ppu.set_on_illegal_ppu_write(Some(Box::new(move |addr, data| recorder.borrow_mut().push((addr, data)))));
+
+        write_vram(&mut cpu, 0x0010, 0xAB);
+        write_vram(&mut cpu, 0x1FFF, 0xCD);
+
+        assert_eq!(cpu.cpu_bus.ppu.illegal_ppu_writes(), 2);
+        assert_eq!(*reported.borrow(), [(0x0010, 0xAB), (0x1FFF, 0xCD)]);
+        assert_eq!(cpu.cpu_bus.ppu.peek_vram(0x0010), 0, "The write reached CHR ROM");
+    });
+}
+
+#[test]
+fn chr_ram_writes_are_stored_and_not_counted() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, Vec::new(), Mirroring::VERTICAL);
+        cpu.cpu_bus.ppu.set_on_illegal_ppu_write(Some(Box::new(|addr, _| panic!("CHR RAM write to {:04X} was reported", addr))));
+
+        write_vram(&mut cpu, 0x0010, 0xAB);
+
+        assert_eq!(cpu.cpu_bus.ppu.illegal_ppu_writes(), 0);
+        assert_eq!(cpu.cpu_bus.ppu.peek_vram(0x0010), 0xAB);
+    });
+}
+
+#[test]
+fn a_tile_written_to_chr_ram_reads_back_and_renders() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, Vec::new(), Mirroring::VERTICAL);
+
+        // Low plane of tile 0 solid, so every background pixel is color 1
+        cpu.cpu_bus.mem_write(0x2006, 0x00);
+        cpu.cpu_bus.mem_write(0x2006, 0x00);
+        for _ in 0..8 {
+            cpu.cpu_bus.mem_write(0x2007, 0xFF);
+        }
+
+        // The first read only fills the buffer
+        cpu.cpu_bus.mem_write(0x2006, 0x00);
+        cpu.cpu_bus.mem_write(0x2006, 0x00);
+        let _ = cpu.cpu_bus.mem_read(0x2007);
+        let read_back: Vec<u8> = (0..16).map(|_| cpu.cpu_bus.mem_read(0x2007)).collect();
+        assert_eq!(read_back, [[0xFF; 8], [0; 8]].concat());
+
+        // The pattern fetches see the same bytes
+        cpu.cpu_bus.ppu.poke_vram(0x3F01, 0x21);
+        cpu.cpu_bus.mem_write(0x2006, 0x00);
+        cpu.cpu_bus.mem_write(0x2006, 0x00);
+        cpu.cpu_bus.mem_write(0x2001, 0b0000_1010);
+        let _ = cpu.run_frame();
+        let _ = cpu.run_frame();
+
+        let pixels = cpu.cpu_bus.ppu.frame_buffer_as(PixelFormat::Xrgb);
+        let tile_row: Vec<u32> = pixels[100 * 256 + 64..100 * 256 + 72].iter().map(|pixel| pixel & 0xFF).collect();
+        assert_eq!(tile_row, [0x21; 8], "The tile didn't render from CHR RAM");
+    });
+}
@@ -0,0 +1,58 @@
+// Official read instructions with indexed operands — the dummy read while the index is added costs them a cycle on the
+// same terms as the unofficial NOPs: always for zero page indexing, only across a page for absolute and (indirect),Y
+
+mod common;
+
+use common::*;
+use nes_components::*;
+
+#[test]
+fn indexed_reads_pay_for_the_dummy_read_like_the_unofficial_nops() {
+    run_with_big_stack(|| {
+        // (name, program, cycles) — X and Y are 1, the pointer at $10 holds $0200 and the one at $12 holds $02FF
+        let cases: [(&str, &[u8], usize); 12] = [
+            ("LDA zp,X", &[0xB5, 0x10], 4),
+            ("LDX zp,Y", &[0xB6, 0x10], 4),
+            ("LDA abs,X", &[0xBD, 0x00, 0x02], 4),
+            ("LDA abs,X across a page", &[0xBD, 0xFF, 0x02], 5),
+            ("LDA abs,Y", &[0xB9, 0x00, 0x02], 4),
+            ("LDA abs,Y across a page", &[0xB9, 0xFF, 0x02], 5),
+            ("LDY abs,X across a page", &[0xBC, 0xFF, 0x02], 5),
+            ("LDX abs,Y across a page", &[0xBE, 0xFF, 0x02], 5),
+            ("LDA (zp,X)", &[0xA1, 0x0F], 6),
+            ("LDA (zp),Y", &[0xB1, 0x10], 5),
+            ("LDA (zp),Y across a page", &[0xB1, 0x12], 6),
+            ("NOP abs,X across a page", &[0x1C, 0xFF, 0x02], 5),
+        ];
+
+        for (name, program, cycles) in cases {
+            let mut cpu = cpu_with_program(program);
+            cpu.cpu_bus.poke(0x0010, 0x00);
+            cpu.cpu_bus.poke(0x0011, 0x02);
+            cpu.cpu_bus.poke(0x0012, 0xFF);
+            cpu.cpu_bus.poke(0x0013, 0x02);
+            cpu.cpu_bus.poke(0x0300, 0x42);
+            (cpu.x, cpu.y) = (1, 1);
+
+            let start = cpu.cycles();
+            cpu.decode().unwrap_or_else(|e| panic!("{} didn't run: {}", name, e));
+
+            assert_eq!(cpu.cycles() - start, cycles, "{} took the wrong number of cycles", name);
+        }
+    });
+}
+
+#[test]
+fn a_page_crossing_read_still_reads_the_fixed_up_address() {
+    run_with_big_stack(|| {
+        // LDA $02FF,X with X = 1 — the dummy read is from $0200, the real one from $0300
+        let mut cpu = cpu_with_program(&[0xBD, 0xFF, 0x02]);
+        cpu.cpu_bus.poke(0x0200, 0x11);
+        cpu.cpu_bus.poke(0x0300, 0x42);
+        cpu.x = 1;
+
+        cpu.decode().expect("LDA didn't run");
+
+        assert_eq!(cpu.accumulator, 0x42);
+    });
+}
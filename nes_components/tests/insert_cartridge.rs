@@ -0,0 +1,40 @@
+// Hot-swapping cartridges — the console powers back on with the new game, keeping what the frontend set up
+// (a window can't be opened in a headless test, but it's carried over through the same path as the palette)
+
+mod common;
+
+use common::*;
+use nes_components::*;
+
+#[test]
+fn the_new_cartridge_is_read_and_the_setup_carries_over() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+        cpu.cpu_bus.input.controllers_mut()[0].set_buttons(BUTTON_START);
+        let palette: Vec<_> = (0..64).map(|index| cpu.cpu_bus.ppu.color_for_index(index)).collect();
+
+        // The second game starts at $D000 with $42 at $8010
+        let mut prg = vec![0; 0x8000];
+        prg[0x0010] = 0x42;
+        prg[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0xD0]);
+        cpu.insert_cartridge(Rom::from_parts(prg, vec![0; 0x2000], 0, Mirroring::HORIZONTAL)).expect("The cartridge didn't go in");
+
+        assert_eq!(cpu.pc, 0xD000, "The console didn't reset into the new game");
+        assert_eq!((cpu.cpu_bus.mem_read(0x8010), cpu.cpu_bus.mem_read(0xC000)), (0x42, 0x00));
+        assert_eq!(cpu.cpu_bus.ppu.frame_count(), 0);
+
+        assert!(cpu.cpu_bus.ppu.window().is_none());
+        assert_eq!((0..64).map(|index| cpu.cpu_bus.ppu.color_for_index(index)).collect::<Vec<_>>(), palette);
+        assert_eq!(cpu.cpu_bus.input.controllers_mut()[0].buttons(), BUTTON_START);
+    });
+}
+
+#[test]
+fn a_cartridge_that_cant_be_built_leaves_the_old_one_in() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+
+        assert!(cpu.insert_cartridge(Rom::from_parts(vec![], vec![0; 0x2000], 0, Mirroring::VERTICAL)).is_err());
+        assert_eq!(cpu.cpu_bus.mem_read(0xC000), JMP_SELF[0]);
+    });
+}
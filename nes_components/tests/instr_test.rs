@@ -0,0 +1,34 @@
+// Runs blargg's instr_test-v5 ROMs, which check every official opcode (and the common illegal ones) against real hardware
+// The ROMs aren't shipped with every checkout — put them in frontend/tests/instr_test-v5/rom_singles, and without them
+// the test is skipped
+
+mod common;
+
+use common::*;
+
+// The longest single test finishes in well under this many instructions, so hitting it means the test hung
+const MAX_INSTRUCTIONS: usize = 100_000_000;
+
+// Singles that also run illegal opcodes decode doesn't implement — SLO, RLA, SRE, RRA, SAX, LAX, DCP, ISC, $EB SBC, SHY
+// and SHX — so they stop with an unsupported opcode before they can pass
+const UNSUPPORTED_ILLEGAL_SINGLES: [&str; 7] = ["03-immediate", "04-zero_page", "05-zp_xy", "06-absolute", "07-abs_xy", "08-ind_x", "09-ind_y"];
+
+#[test]
+fn instr_test_v5_passes() {
+    run_with_big_stack(run_instr_tests);
+}
+
+fn run_instr_tests() {
+    let Some((count, failures)) = run_blargg_singles("tests/instr_test-v5/rom_singles", MAX_INSTRUCTIONS) else {
+        println!("Skipping: blargg's instr_test-v5 ROMs aren't in frontend/tests/instr_test-v5/rom_singles");
+        return
+    };
+
+    let (expected, failures): (Vec<String>, Vec<String>) = failures.into_iter()
+        .partition(|failure| UNSUPPORTED_ILLEGAL_SINGLES.iter().any(|single| failure.starts_with(single)));
+    for failure in &expected {
+        println!("Expected failure — {}", failure.lines().next().unwrap_or_default());
+    }
+
+    assert!(failures.is_empty(), "{} of {} instr_test ROMs failed:\n{}", failures.len(), count, failures.join("\n"));
+}
@@ -0,0 +1,46 @@
+// IPS soft-patching — records write bytes at offsets into the whole file (header included), run-length records
+// repeat one byte, and the ROM is parsed again afterwards
+
+use nes_components::*;
+
+const HEADER_LEN: usize = 16;
+
+// NROM with one 16KB PRG bank and one 8KB CHR bank, every byte 0
+fn blank_rom() -> Rom {
+    let mut file = b"NES\x1A\x01\x01".to_vec();
+    file.resize(HEADER_LEN + 0x4000 + 0x2000, 0);
+
+    Rom::new(&file).expect("The test ROM didn't parse")
+}
+
+#[test]
+fn a_small_patch_changes_only_the_bytes_it_names() {
+    let mut rom = blank_rom();
+
+    let mut patch = b"PATCH".to_vec();
+    // $AB $CD at PRG offset $10
+    patch.extend_from_slice(&[0x00, 0x00, 0x20, 0x00, 0x02, 0xAB, 0xCD]);
+    // Four $77s at the start of CHR
+    patch.extend_from_slice(&[0x00, 0x40, 0x10, 0x00, 0x00, 0x00, 0x04, 0x77]);
+    patch.extend_from_slice(b"EOF");
+
+    rom.apply_patch(&patch).expect("The patch didn't apply");
+
+    let mut prg = vec![0; 0x4000];
+    prg[0x10..0x12].copy_from_slice(&[0xAB, 0xCD]);
+    let mut chr = vec![0; 0x2000];
+    chr[0..4].fill(0x77);
+    assert_eq!(rom.prg_rom, prg);
+    assert_eq!(rom.chr_rom, chr);
+}
+
+#[test]
+fn bad_patches_are_rejected_and_leave_the_rom_alone() {
+    let mut rom = blank_rom();
+
+    assert_eq!(rom.apply_patch(b"PTCH\x00\x00\x20\x00\x01\xFF EOF"), Err(PatchError::UnknownFormat));
+    // The record says 2 bytes but only has 1
+    assert_eq!(rom.apply_patch(b"PATCH\x00\x00\x20\x00\x02\xFF"), Err(PatchError::Truncated(5)));
+
+    assert!(rom.prg_rom.iter().all(|byte| *byte == 0));
+}
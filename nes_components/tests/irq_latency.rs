@@ -0,0 +1,43 @@
+// IRQ polling happens before the last cycle of an instruction, so the I flag CLI clears only lets an IRQ in after the next
+// instruction
+
+mod common;
+
+use common::*;
+use nes_components::*;
+
+const HANDLER: u16 = 0x0700;
+
+#[test]
+fn cli_lets_a_pending_irq_in_after_the_next_instruction() {
+    run_with_big_stack(|| {
+        let mut cpu = cpu_with_program(&[
+            0x58, // CLI
+            0xA9, 0x01, // LDA #$01
+            0xA9, 0x02, // LDA #$02
+        ]);
+        cpu.cpu_bus.poke(HANDLER, 0xA2); // LDX #$FF
+        cpu.cpu_bus.poke(HANDLER + 1, 0xFF);
+        cpu.cpu_bus.poke(0xFFFE, (HANDLER & 0xFF) as u8);
+        cpu.cpu_bus.poke(0xFFFF, (HANDLER >> 8) as u8);
+
+        cpu.status |= 0b100;
+        cpu.set_irq_line(true);
+
+        // CLI, then the LDA right after it still runs
+        cpu.decode().unwrap();
+        cpu.decode().unwrap();
+        assert_eq!((cpu.pc, cpu.accumulator), (PROGRAM_START + 3, 0x01), "The IRQ was taken right after CLI");
+
+        // The IRQ goes in before the second LDA — 7 cycles of entry, then the handler's LDX
+        let cycles = cpu.cycles();
+        cpu.decode().unwrap();
+        assert_eq!((cpu.pc, cpu.accumulator, cpu.x), (HANDLER + 2, 0x01, 0xFF), "The IRQ wasn't taken after the next instruction");
+        assert_eq!(cpu.cycles() - cycles, 7 + 2);
+
+        // The second LDA is where the handler returns to
+        let stack = 0x0100 + cpu.sp as u16;
+        let return_addr = cpu.cpu_bus.peek(stack + 2) as u16 | (cpu.cpu_bus.peek(stack + 3) as u16) << 8;
+        assert_eq!(return_addr, PROGRAM_START + 3);
+    });
+}
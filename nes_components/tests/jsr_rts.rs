@@ -0,0 +1,43 @@
+// JSR and RTS — six cycles each, with JSR pushing the address of its own last byte and RTS adding one back on
+
+mod common;
+
+use common::*;
+use nes_components::*;
+
+const SUBROUTINE: u16 = 0x0700;
+
+#[test]
+fn jsr_pushes_its_last_byte_and_takes_6_cycles() {
+    run_with_big_stack(|| {
+        let mut cpu = cpu_with_program(&[0x20, 0x00, 0x07]); // JSR $0700
+        let sp = cpu.sp;
+
+        let start = cpu.cycles();
+        cpu.decode().unwrap();
+
+        assert_eq!(cpu.cycles() - start, 6);
+        assert_eq!(cpu.pc, SUBROUTINE);
+        assert_eq!(cpu.sp, sp.wrapping_sub(2));
+
+        let pushed = u16::from_le_bytes([cpu.cpu_bus.peek(0x0100 + sp as u16 - 1), cpu.cpu_bus.peek(0x0100 + sp as u16)]);
+        assert_eq!(pushed, PROGRAM_START + 2, "JSR should push the address of its last byte");
+    });
+}
+
+#[test]
+fn rts_returns_after_the_jsr_and_takes_6_cycles() {
+    run_with_big_stack(|| {
+        let mut cpu = cpu_with_program(&[0x20, 0x00, 0x07]); // JSR $0700
+        cpu.cpu_bus.poke(SUBROUTINE, 0x60); // RTS
+        let sp = cpu.sp;
+
+        cpu.decode().unwrap();
+        let start = cpu.cycles();
+        cpu.decode().unwrap();
+
+        assert_eq!(cpu.cycles() - start, 6);
+        assert_eq!(cpu.pc, PROGRAM_START + 3);
+        assert_eq!(cpu.sp, sp);
+    });
+}
@@ -0,0 +1,111 @@
+// Runs a short program on this CPU and on the mos6502 crate's 2A03 side by side (cargo test --features lockstep)
+// The program loops through adds, shifts, compares and the stack, so a flag bug shows up within a few hundred instructions
+// SBC is left out — mos6502 0.10.1 runs it without decimal mode on the 2A03 through a path that gets the carry backwards
+
+mod common;
+
+use common::*;
+use mos6502::cpu;
+use mos6502::instruction::Ricoh2a03;
+use mos6502::memory::Bus as ReferenceBus;
+use mos6502::registers::{StackPointer, Status};
+use nes_components::*;
+
+const START: u16 = 0x0600;
+const PROGRAM: [u8; 29] = [
+    0xA2, 0x00, // $0600: LDX #$00
+    0xA9, 0x37, // LDA #$37
+    0x18, // $0604: CLC
+    0x65, 0x10, // ADC $10
+    0x95, 0x10, // STA $10,X
+    0x49, 0xA5, // EOR #$A5
+    0x2A, // ROL A
+    0x69, 0x13, // ADC #$13
+    0x48, // PHA
+    0x20, 0x40, 0x06, // JSR $0640
+    0x68, // PLA
+    0xE8, // INX
+    0xE0, 0x40, // CPX #$40
+    0xD0, 0xEC, // BNE $0604
+    0xA2, 0x00, // LDX #$00
+    0x4C, 0x04, 0x06, // JMP $0604
+];
+const SUBROUTINE: u16 = 0x0640;
+const SUBROUTINE_PROGRAM: [u8; 12] = [
+    0x06, 0x11, // $0640: ASL $11
+    0x4A, // LSR A
+    0x66, 0x12, // ROR $12
+    0x24, 0x10, // BIT $10
+    0xC5, 0x13, // CMP $13
+    0xC6, 0x14, // DEC $14
+    0x60, // RTS
+];
+
+// The whole 64KB as plain memory, like CPU::with_flat_memory
+struct FlatMemory([u8; 0x10000]);
+
+impl ReferenceBus for FlatMemory {
+    fn get_byte(&mut self, address: u16) -> u8 {
+        self.0[address as usize]
+    }
+
+    fn set_byte(&mut self, address: u16, value: u8) {
+        self.0[address as usize] = value;
+    }
+}
+
+// Adapter putting the mos6502 core behind ReferenceCpu
+struct Mos6502(cpu::CPU<FlatMemory, Ricoh2a03>);
+
+impl ReferenceCpu for Mos6502 {
+    fn step(&mut self) {
+        self.0.single_step();
+    }
+
+    fn registers(&self) -> Registers {
+        let registers = &self.0.registers;
+
+        Registers {
+            accumulator: registers.accumulator,
+            x: registers.index_x,
+            y: registers.index_y,
+            pc: registers.program_counter,
+            sp: registers.stack_pointer.0,
+            status: registers.status.bits(),
+        }
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.0.memory.0[addr as usize]
+    }
+}
+
+#[test]
+fn simple_program_stays_in_lockstep_for_1000_instructions() {
+    run_with_big_stack(|| {
+        let mut cpu = CPU::with_flat_memory();
+        let mut memory = FlatMemory([0; 0x10000]);
+
+        for (start, program) in [(START, &PROGRAM[..]), (SUBROUTINE, &SUBROUTINE_PROGRAM[..])] {
+            for (i, byte) in program.iter().enumerate() {
+                cpu.cpu_bus.poke(start + i as u16, *byte);
+                memory.0[start as usize + i] = *byte;
+            }
+        }
+        cpu.pc = START;
+
+        // Both cores start from our power on registers
+        let mut reference = Mos6502(cpu::CPU::new(memory, Ricoh2a03));
+        let registers = cpu.registers();
+        reference.0.registers.accumulator = registers.accumulator;
+        reference.0.registers.index_x = registers.x;
+        reference.0.registers.index_y = registers.y;
+        reference.0.registers.stack_pointer = StackPointer(registers.sp);
+        reference.0.registers.program_counter = registers.pc;
+        reference.0.registers.status = Status::from_bits_truncate(registers.status);
+
+        if let Err(divergence) = cpu.run_lockstep(&mut reference, 1000) {
+            panic!("{}", divergence);
+        }
+    });
+}
@@ -0,0 +1,69 @@
+// MMC5 nametable mapping ($5105) and extended attribute mode ($5104 = 1), where each tile's palette comes from the top
+// two bits of its ExRAM byte
+
+mod common;
+
+use common::*;
+use nes_components::*;
+
+#[test]
+fn extended_attributes_pick_each_tiles_palette() {
+    run_with_big_stack(|| {
+        // $E000 (the last 8KB bank at power on) spins in place
+        let mut prg = vec![0; 0x8000];
+        prg[0x6000..0x6003].copy_from_slice(&[0x4C, 0x00, 0xE0]); // JMP $E000
+        prg[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0xE0]);
+
+        // Tile 0 is solid color 1
+        let mut chr = vec![0; 0x2000];
+        chr[0..8].fill(0xFF);
+
+        // Color n of the system palette is (n, n, n), so pixels read back as their palette index
+        let palette_storage = (0..192).map(|i| (i / 3) as u8).collect();
+
+        let rom = Rom::from_parts(prg, chr, 5, Mirroring::VERTICAL);
+        let mapper = rom.create_mapper().expect("Mapper 5 isn't supported");
+        let ppu = PPU::init_ppu(mapper.clone(), palette_storage, None);
+        let mut cpu = CPU::init_cpu(mapper.clone(), ppu);
+
+        for _ in 0..3 {
+            let _ = cpu.run_frame();
+        }
+
+        // The attribute table says palette 0 everywhere, ExRAM gives the second tile of the third row palette 3
+        cpu.cpu_bus.mem_write(0x5104, 1);
+        cpu.cpu_bus.mem_write(0x5C41, 0b1100_0000);
+        assert_eq!(mapper.borrow().extended_attribute(0x2041), Some(3));
+        assert_eq!(mapper.borrow().extended_attribute(0x2040), Some(0));
+
+        cpu.cpu_bus.ppu.poke_vram(0x3F01, 0x21);
+        cpu.cpu_bus.ppu.poke_vram(0x3F0D, 0x16);
+        cpu.cpu_bus.mem_write(0x2000, 0);
+        cpu.cpu_bus.mem_write(0x2001, 0b0000_1010);
+
+        for _ in 0..2 {
+            let _ = cpu.run_frame();
+        }
+
+        let pixels = cpu.cpu_bus.ppu.frame_buffer_as(PixelFormat::Xrgb);
+        assert_eq!(pixels[4 + 20 * 256] & 0xFF, 0x21, "The first tile didn't use palette 0");
+        assert_eq!(pixels[12 + 20 * 256] & 0xFF, 0x16, "The second tile didn't use its ExRAM palette");
+
+        // Back in mode 0 the attribute table is used again
+        cpu.cpu_bus.mem_write(0x5104, 0);
+        assert_eq!(mapper.borrow().extended_attribute(0x2041), None);
+    });
+}
+
+#[test]
+fn unsupported_nametable_mappings_keep_the_last_mirroring() {
+    let rom = Rom::from_parts(vec![0; 0x8000], vec![0; 0x2000], 5, Mirroring::VERTICAL);
+    let mapper = rom.create_mapper().expect("Mapper 5 isn't supported");
+
+    mapper.borrow_mut().expansion_write(0x5105, 0x50);
+    assert_eq!(mapper.borrow().mirroring(), Mirroring::HORIZONTAL);
+
+    // Nametable 0 from CIRAM page 0, the rest from ExRAM — no Mirroring for that
+    mapper.borrow_mut().expansion_write(0x5105, 0xA8);
+    assert_eq!(mapper.borrow().mirroring(), Mirroring::HORIZONTAL);
+}
@@ -0,0 +1,89 @@
+// The CPU on a bus of our own — a plain 64KB memory that records every access the CPU makes through it
+
+mod common;
+
+use common::*;
+use nes_components::*;
+
+// (address, value, whether it was a write)
+type Access = (u16, u8, bool);
+
+struct RecordingBus {
+    memory: Vec<u8>,
+    accesses: Vec<Access>,
+    ticks: usize,
+}
+
+impl RecordingBus {
+    fn with_program(program: &[u8]) -> Self {
+        let mut memory = vec![0; 0x10000];
+        memory[PROGRAM_START as usize..PROGRAM_START as usize + program.len()].copy_from_slice(program);
+        memory[0xFFFC..0xFFFE].copy_from_slice(&PROGRAM_START.to_le_bytes());
+
+        RecordingBus { memory, accesses: Vec::new(), ticks: 0 }
+    }
+}
+
+impl Mem for RecordingBus {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        let value = self.memory[addr as usize];
+        self.accesses.push((addr, value, false));
+        value
+    }
+
+    fn mem_write(&mut self, addr: u16, data: u8) {
+        self.accesses.push((addr, data, true));
+        self.memory[addr as usize] = data;
+    }
+
+    fn mem_read_u16(&self, addr: u16) -> u16 {
+        u16::from_le_bytes([self.peek(addr), self.peek(addr.wrapping_add(1))])
+    }
+
+    fn mem_write_u16(&mut self, pos: u16, data: u16) {
+        let [low, high] = data.to_le_bytes();
+        self.mem_write(pos, low);
+        self.mem_write(pos.wrapping_add(1), high);
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.memory[addr as usize]
+    }
+
+    fn poke(&mut self, addr: u16, data: u8) {
+        self.memory[addr as usize] = data;
+    }
+}
+
+impl Bus for RecordingBus {
+    fn tick(&mut self) {
+        self.ticks += 1;
+    }
+}
+
+#[test]
+fn a_mock_bus_sees_every_cpu_access() {
+    let mut cpu = CPU::with_bus(RecordingBus::with_program(&[
+        0xA5, 0x10, // LDA $10
+        0x8D, 0x00, 0x02, // STA $0200
+        0xEE, 0x00, 0x02, // INC $0200
+    ]));
+    assert_eq!(cpu.pc, PROGRAM_START, "The reset vector wasn't read from the bus");
+    cpu.cpu_bus.poke(0x0010, 0x41);
+
+    for _ in 0..3 {
+        cpu.decode().expect("The program stopped running");
+    }
+
+    assert_eq!(cpu.cpu_bus.accesses, [
+        (0x0600, 0xA5, false), (0x0601, 0x10, false), (0x0010, 0x41, false),
+        (0x0602, 0x8D, false), (0x0603, 0x00, false), (0x0604, 0x02, false), (0x0200, 0x41, true),
+        // Read-modify-write instructions write the unmodified value back before the result
+        (0x0605, 0xEE, false), (0x0606, 0x00, false), (0x0607, 0x02, false), (0x0200, 0x41, false),
+        (0x0200, 0x41, true), (0x0200, 0x42, true),
+    ]);
+
+    // One cycle per access, and none of them went around the bus
+    assert_eq!(cpu.cpu_bus.ticks, cpu.cpu_bus.accesses.len());
+    assert_eq!(cpu.cycles(), cpu.cpu_bus.ticks);
+}
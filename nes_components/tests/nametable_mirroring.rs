@@ -0,0 +1,38 @@
+// Which of the four nametables ($2000, $2400, $2800, $2C00) share memory under vertical and horizontal mirroring
+
+mod common;
+
+use common::*;
+use nes_components::*;
+
+const NAMETABLES: [u16; 4] = [0x2000, 0x2400, 0x2800, 0x2C00];
+
+#[test]
+fn nametables_mirror_by_the_cartridge_layout() {
+    run_with_big_stack(|| {
+        // Which 1KB page of VRAM each nametable lands on
+        for (mirroring, pages) in [(Mirroring::VERTICAL, [0, 1, 0, 1]), (Mirroring::HORIZONTAL, [0, 0, 1, 1])] {
+            let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], mirroring);
+
+            for (i, nametable) in NAMETABLES.into_iter().enumerate() {
+                // The first and last byte of each nametable, written through $2006/$2007 like a game would
+                for offset in [0x000, 0x3FF] {
+                    let marker = ((i as u8) << 4) | (offset >> 8) as u8 | 0x80;
+                    write_vram(&mut cpu, nametable + offset, marker);
+
+                    for (j, other) in NAMETABLES.into_iter().enumerate() {
+                        let shared = pages[i] == pages[j];
+                        let value = cpu.cpu_bus.ppu.peek_vram(other + offset);
+
+                        let expected = if shared { "share" } else { "not share" };
+                        assert_eq!(value == marker, shared, "{:?}: {:04X} and {:04X} should {} memory", mirroring, nametable + offset, other + offset, expected);
+                        // $3000-$3EFF mirrors $2000-$2EFF
+                        if other + offset < 0x2F00 {
+                            assert_eq!(cpu.cpu_bus.ppu.peek_vram(other + offset + 0x1000), value);
+                        }
+                    }
+                }
+            }
+        }
+    });
+}
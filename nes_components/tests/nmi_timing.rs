@@ -0,0 +1,131 @@
+// When the VBlank NMI handler starts, counted in CPU cycles from the cycle VBlank goes up (and how a longer NMI delay moves
+// it), how many NMIs a frame gets, and frames that get more than one being reported
+
+mod common;
+
+use common::*;
+use nes_components::*;
+use std::{cell::RefCell, rc::Rc};
+
+const HANDLER: u16 = 0xC010;
+
+#[test]
+fn nmi_handler_starts_a_fixed_number_of_cycles_after_vblank() {
+    run_with_big_stack(|| {
+        let mut cpu = cpu_with_nmis_enabled();
+        let (accesses, vblank, handler) = run_to_handler(&mut cpu);
+
+        // Two dummy reads of the interrupted JMP's opcode, the pc and status pushed, then the vector
+        let entry: Vec<(u16, bool)> = accesses[handler - 7..handler].iter().map(|access| (access.addr, access.write)).collect();
+        assert_eq!(entry, [(0xC000, false), (0xC000, false), (0x01FD, true), (0x01FC, true), (0x01FB, true), (0xFFFA, false), (0xFFFB, false)]);
+
+        // VBlank goes up at 241,1, here during the opcode fetch of a JMP, and the NMI line follows within the same cycle
+        // The 6502 polls for interrupts on an instruction's second to last cycle, so the JMP's two operand reads finish
+        // first and the 7 cycle entry sequence follows — the handler's first fetch is 1 + 2 + 7 = 10 cycles on
+        assert_eq!((accesses[vblank].scanline, accesses[vblank].dot), (241, 1));
+        assert_eq!((accesses[vblank].addr, accesses[vblank].value), (0xC000, JMP_SELF[0]));
+        assert_eq!(accesses[handler].cycle - accesses[vblank].cycle, 10);
+    });
+}
+
+#[test]
+fn a_longer_nmi_delay_moves_the_handler_by_the_same_time() {
+    run_with_big_stack(|| {
+        let mut cpu = cpu_with_nmis_enabled();
+        let default_delay = cpu.cpu_bus.ppu.nmi_delay();
+        let (accesses, vblank, handler) = run_to_handler(&mut cpu);
+        let default_start = accesses[handler].cycle - accesses[vblank].cycle;
+
+        // Whole JMPs (3 cycles, 9 dots) later, so the NMI lands at the same point in the loop and the handler moves by
+        // exactly the added time — 3 dots to a CPU cycle
+        for extra_dots in [9, 18, 27] {
+            let mut cpu = cpu_with_nmis_enabled();
+            cpu.cpu_bus.ppu.set_nmi_delay(default_delay + extra_dots);
+            let (accesses, vblank, handler) = run_to_handler(&mut cpu);
+
+            let start = accesses[handler].cycle - accesses[vblank].cycle;
+            assert_eq!(start - default_start, extra_dots as usize / 3, "With {} extra dots of delay", extra_dots);
+        }
+    });
+}
+
+#[test]
+fn each_frame_services_exactly_one_nmi() {
+    run_with_big_stack(|| {
+        let mut cpu = cpu_with_nmis_enabled();
+        let raised = cpu.cpu_bus.ppu.nmi_count();
+        let serviced = cpu.nmi_serviced_count();
+
+        for frame in 1..=10 {
+            let _ = cpu.run_frame();
+            assert_eq!(cpu.cpu_bus.ppu.nmi_count() - raised, frame, "The PPU raised the wrong number of NMIs");
+            assert_eq!(cpu.nmi_serviced_count() - serviced, frame, "The CPU serviced the wrong number of NMIs");
+        }
+    });
+}
+
+#[test]
+fn a_frame_with_extra_nmis_is_counted_and_reported_once() {
+    run_with_big_stack(|| {
+        let mut cpu = cpu_with_nmis_enabled();
+        let reported = Rc::new(RefCell::new(Vec::new()));
+        let recorder = reported.clone();
+        cpu.set_on_double_nmi(Some(Box::new(move |frame| recorder.borrow_mut().push(frame))));
+
+        cpu.run_to_vblank().unwrap();
+        let frame = cpu.cpu_bus.ppu.frame_count();
+        for _ in 0..10 {
+            cpu.decode().unwrap();
+        }
+        assert_eq!(cpu.double_nmi_frames(), 0, "The VBlank NMI on its own was counted");
+
+        // Turning NMIs back on with VBlank still set raises another one each time
+        for _ in 0..2 {
+            cpu.cpu_bus.mem_write(0x2000, 0x00);
+            cpu.cpu_bus.mem_write(0x2000, 0x80);
+            for _ in 0..10 {
+                cpu.decode().unwrap();
+            }
+        }
+
+        assert_eq!(cpu.double_nmi_frames(), 1);
+        assert_eq!(*reported.borrow(), [frame]);
+
+        // Frames after it are back to one NMI each
+        for _ in 0..3 {
+            let _ = cpu.run_frame();
+        }
+        assert_eq!(cpu.double_nmi_frames(), 1);
+    });
+}
+
+// Runs a frame, giving back every bus access, the index of the first one at or after VBlank and the index of the NMI
+// handler's first fetch
+fn run_to_handler(cpu: &mut CPU<CPUBus>) -> (Vec<BusAccess>, usize, usize) {
+    let accesses = Rc::new(RefCell::new(Vec::new()));
+    let recorder = accesses.clone();
+    cpu.set_on_bus_access(Some(Box::new(move |access: BusAccess| recorder.borrow_mut().push(access))));
+    let _ = cpu.run_frame();
+    cpu.set_on_bus_access(None);
+
+    let accesses = accesses.take();
+    let handler = accesses.iter().position(|access| access.addr == HANDLER).expect("The NMI handler never ran");
+    let vblank = accesses[..handler].iter().rposition(|access| (access.scanline, access.dot) < (241, 1)).unwrap() + 1;
+
+    (accesses, vblank, handler)
+}
+
+// Past the power on warm up, spinning in a JMP loop at $C000 with NMIs going to an RTI at $C010
+fn cpu_with_nmis_enabled() -> CPU<CPUBus> {
+    let mut program = vec![0; 0x4000];
+    program[0..3].copy_from_slice(&JMP_SELF);
+    program[0x10] = 0x40; // $C010: RTI
+    program[0x3FFA..0x3FFC].copy_from_slice(&[0x10, 0xC0]);
+    let mut cpu = booted_nrom(&program, vec![0; 0x2000], Mirroring::VERTICAL);
+
+    // Acknowledge any VBlank left over from the warm up so turning NMIs on doesn't raise one straight away
+    let _ = cpu.cpu_bus.mem_read(0x2002);
+    cpu.cpu_bus.mem_write(0x2000, 0x80);
+
+    cpu
+}
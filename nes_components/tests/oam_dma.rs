@@ -0,0 +1,93 @@
+// OAM DMA ($4014) — where the copied page lands in OAM, how long it takes, and how far the PPU moves on while the DMA has
+// the bus
+
+mod common;
+
+use common::*;
+use nes_components::*;
+
+// $C000 spins in place, $C003 copies page 2 into OAM and goes back to spinning
+const SPIN: u16 = 0xC000;
+const DMA_ROUTINE: u16 = 0xC003;
+const PROGRAM: [u8; 11] = [
+    0x4C, 0x00, 0xC0, // JMP $C000
+    0xA9, 0x02, // LDA #$02
+    0x8D, 0x14, 0x40, // STA $4014
+    0x4C, 0x00, 0xC0, // JMP $C000
+];
+
+#[test]
+fn oam_dma_starts_at_oam_addr() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&PROGRAM, vec![0; 0x2000], Mirroring::VERTICAL);
+        for i in 0..=255u8 {
+            cpu.cpu_bus.poke(0x0200 + i as u16, i);
+        }
+
+        // Rendering is off, so nothing else touches OAMADDR while the DMA runs
+        cpu.cpu_bus.mem_write(0x2003, 0x10);
+        run_dma_routine(&mut cpu);
+
+        let oam = cpu.cpu_bus.ppu.oam_snapshot();
+        for i in 0..=255u8 {
+            assert_eq!(oam[i.wrapping_add(0x10) as usize], i, "Page byte {:02X} landed in the wrong OAM slot", i);
+        }
+    });
+}
+
+#[test]
+fn oam_dma_mid_frame_advances_the_ppu_by_its_cycles() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&PROGRAM, vec![0; 0x2000], Mirroring::VERTICAL);
+        cpu.cpu_bus.mem_write(0x2001, 0b0001_1000);
+
+        while cpu.cpu_bus.ppu.state.scanline != 100 {
+            cpu.decode().expect("The spin loop stopped running");
+        }
+
+        // LDA, then STA $4014 (4 cycles) and the JMP the DMA halts the CPU on (3 cycles)
+        cpu.pc = DMA_ROUTINE;
+        cpu.decode().expect("LDA didn't run");
+
+        let (start_clk, start_dot) = (cpu.cycles(), cpu.cpu_bus.ppu.dot_count());
+        cpu.decode().expect("STA $4014 didn't run");
+        cpu.decode().expect("The JMP after the DMA didn't run");
+        let (clk, dots) = (cpu.cycles() - start_clk, cpu.cpu_bus.ppu.dot_count() - start_dot);
+
+        // 513 cycles for the halt and the 256 read/write pairs, plus one to line up when the DMA starts on an odd cycle
+        assert!(clk == 4 + 3 + 513 || clk == 4 + 3 + 514, "DMA took {} cycles", clk - 7);
+        assert_eq!(dots, clk as u64 * 3, "The PPU didn't keep pace with the DMA's cycles");
+        assert_eq!(cpu.pc, SPIN);
+    });
+}
+
+#[test]
+fn oam_dma_takes_513_or_514_cycles_depending_on_the_start_cycle() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&PROGRAM, vec![0; 0x2000], Mirroring::VERTICAL);
+
+        // The spin loop's JMP takes 3 cycles, so each spin flips which cycle the routine starts on
+        let mut lengths = Vec::new();
+        for start_parity in [0, 1] {
+            while cpu.cycles() % 2 != start_parity {
+                cpu.decode().expect("The spin loop stopped running");
+            }
+
+            // LDA (2 cycles) and STA $4014 (4) fix where the halt cycle lands, so the start cycle decides whether the copy
+            // needs an alignment read before its first get cycle
+            cpu.pc = DMA_ROUTINE;
+            let start_clk = cpu.cycles();
+            run_dma_routine(&mut cpu);
+            lengths.push(cpu.cycles() - start_clk - (2 + 4 + 3));
+        }
+
+        assert_eq!(lengths, [514, 513]);
+    });
+}
+
+fn run_dma_routine(cpu: &mut CPU<CPUBus>) {
+    cpu.pc = DMA_ROUTINE;
+    while cpu.pc != SPIN {
+        cpu.decode().expect("The DMA routine stopped running");
+    }
+}
@@ -0,0 +1,57 @@
+// Register bits nothing drives — they read back as whatever was last on the data bus
+// Bits 5-7 of the controller ports too, which usually read back as the $40 of the address
+// The PPU has its own bus — bits 0-4 of $2002 are whatever was last written to or read from a PPU register
+
+mod common;
+
+use common::*;
+use nes_components::*;
+
+#[test]
+fn the_unused_bit_of_apu_status_comes_from_open_bus() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+        cpu.cpu_bus.mem_write(0x0000, 0xFF);
+        cpu.cpu_bus.mem_write(0x0001, 0x00);
+
+        let _ = cpu.cpu_bus.mem_read(0x0000);
+        assert_eq!(cpu.cpu_bus.mem_read(0x4015) & 0b0010_0000, 0b0010_0000);
+        // The read happens inside the CPU, so it doesn't change what's on the bus either
+        assert_eq!(cpu.cpu_bus.mem_read(0x4015) & 0b0010_0000, 0b0010_0000);
+
+        let _ = cpu.cpu_bus.mem_read(0x0001);
+        assert_eq!(cpu.cpu_bus.mem_read(0x4015) & 0b0010_0000, 0);
+    });
+}
+
+#[test]
+fn the_top_three_bits_of_a_controller_read_come_from_open_bus() {
+    run_with_big_stack(|| {
+        // $C000: LDA $4016 — the last byte on the bus before the read is the $40 of the address
+        let mut cpu = booted_nrom(&[0xAD, 0x16, 0x40, 0x4C, 0x03, 0xC0], vec![0; 0x2000], Mirroring::VERTICAL);
+        cpu.pc = 0xC000;
+        cpu.decode().expect("LDA didn't run");
+        assert_eq!(cpu.accumulator & 0b1110_0000, 0b0100_0000);
+
+        // Whatever else was last on the bus shows through the same way, and bits 1-4 stay clear
+        cpu.cpu_bus.mem_write(0x0000, 0xFF);
+        let _ = cpu.cpu_bus.mem_read(0x0000);
+        assert_eq!(cpu.cpu_bus.mem_read(0x4016) & 0b1111_1110, 0b1110_0000);
+
+        cpu.cpu_bus.mem_write(0x0001, 0x00);
+        let _ = cpu.cpu_bus.mem_read(0x0001);
+        assert_eq!(cpu.cpu_bus.mem_read(0x4017) & 0b1111_1110, 0);
+    });
+}
+
+#[test]
+fn the_low_five_bits_of_ppustatus_come_from_the_ppu_latch() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+
+        for data in [0xF5, 0x0A] {
+            cpu.cpu_bus.mem_write(0x2003, data);
+            assert_eq!(cpu.cpu_bus.mem_read(0x2002) & 0b1_1111, data & 0b1_1111, "After writing {:02X}", data);
+        }
+    });
+}
@@ -0,0 +1,76 @@
+// System colors — the .pal file turned into RGB through PPU::color_for_index, and what's reported about a bad one
+// Palette RAM only holds the 6 bit index into them, which rendering maps to RGB
+// VS System PPUs have their own colors built in, and ignore the .pal file
+
+mod common;
+
+use common::*;
+use nes_components::*;
+use std::{cell::RefCell, rc::Rc};
+
+#[test]
+fn color_0f_is_black_in_the_standard_palette() {
+    run_with_big_stack(|| {
+        let palette = std::fs::read(frontend_file("palettes/ntsc_palette.pal")).expect("The standard palette is missing");
+        let ppu = PPU::init_ppu(blank_mapper(), palette, None);
+
+        assert!(ppu.palette_problems().is_empty(), "{:?}", ppu.palette_problems());
+        let (r, g, b) = ppu.color_for_index(0x0F);
+        assert!(r < 0x10 && g < 0x10 && b < 0x10, "$0F came out as ({}, {}, {})", r, g, b);
+
+        // Only the low 6 bits pick the color
+        assert_eq!(ppu.color_for_index(0x4F), (r, g, b));
+    });
+}
+
+#[test]
+fn short_palettes_are_reported_and_padded_with_black() {
+    run_with_big_stack(|| {
+        let ppu = PPU::init_ppu(blank_mapper(), vec![0xFF; 100], None);
+
+        assert_eq!(ppu.palette_problems(), [PaletteError::NotTriples(100), PaletteError::TooShort(100)]);
+        assert_eq!(ppu.color_for_index(0x20), (0xFF, 0xFF, 0xFF));
+        assert_eq!(ppu.color_for_index(0x3F), (0, 0, 0), "Colors past the end of the file should be black");
+    });
+}
+
+#[test]
+fn palette_ram_holds_an_index_that_rendering_maps_to_rgb() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+
+        // Only the low 6 bits are kept, and that's what $2007 reads back
+        write_vram(&mut cpu, 0x3F00, 0x6A);
+        cpu.cpu_bus.mem_write(0x2006, 0x3F);
+        cpu.cpu_bus.mem_write(0x2006, 0x00);
+        assert_eq!(cpu.cpu_bus.mem_read(0x2007), 0x2A);
+
+        // Every background pixel is transparent, so the whole screen is the backdrop — color $2A, which is (42, 42, 42)
+        cpu.cpu_bus.mem_write(0x2001, 0b0000_1010);
+        let _ = cpu.run_frame();
+        let _ = cpu.run_frame();
+
+        let pixels = cpu.cpu_bus.ppu.frame_buffer_as(PixelFormat::Xrgb);
+        assert_eq!(pixels[100 * 256 + 128] & 0xFF_FFFF, 0x2A_2A2A);
+    });
+}
+
+#[test]
+fn vs_mode_takes_colors_from_the_vs_palette() {
+    run_with_big_stack(|| {
+        let mut ppu = PPU::init_ppu(blank_mapper(), vec![0x11; 192], None);
+        assert_eq!(ppu.color_for_index(0x20), (0x11, 0x11, 0x11));
+
+        // $20 is 777 (white) in the VS palette's 3 bits per channel, and $01 is 014 — each level is n * 255 / 7
+        ppu.set_system(System::Vs);
+        assert_eq!(ppu.color_for_index(0x20), (0xFF, 0xFF, 0xFF));
+        assert_eq!(ppu.color_for_index(0x01), (0, 36, 145));
+
+        ppu.set_system(System::Nes);
+        assert_eq!(ppu.color_for_index(0x20), (0x11, 0x11, 0x11));
+    });
+}
+
+fn blank_mapper() -> Rc<RefCell<dyn Mapper>> {
+    Rom::from_parts(vec![0; 0x4000], vec![0; 0x2000], 0, Mirroring::VERTICAL).create_mapper().expect("Could not create the mapper")
+}
@@ -0,0 +1,101 @@
+// Debugger peeks and pokes — they reach the same memory as normal accesses, but registers, the mapper's A12 counter and
+// the illegal write counter never hear about them
+
+mod common;
+
+use common::*;
+use nes_components::*;
+use std::{cell::RefCell, rc::Rc};
+
+// CHR ROM that counts the A12 rises it's told about
+struct A12Counter {
+    chr: Vec<u8>,
+    rises: usize,
+}
+
+impl Mapper for A12Counter {
+    fn cpu_read(&self, _addr: u16) -> u8 { 0 }
+    fn cpu_write(&mut self, _addr: u16, _data: u8) {}
+    fn ppu_read(&self, addr: u16) -> u8 { self.chr[addr as usize] }
+    fn ppu_write(&mut self, _addr: u16, _data: u8) {}
+    fn mirroring(&self) -> Mirroring { Mirroring::VERTICAL }
+    fn prg_len(&self) -> usize { 0x8000 }
+    fn chr_len(&self) -> usize { self.chr.len() }
+    fn prg_bank(&self, _window: usize) -> usize { 0 }
+    fn chr_bank(&self, _window: usize) -> usize { 0 }
+    fn on_ppu_a12_rise(&mut self) { self.rises += 1; }
+}
+
+#[test]
+fn ppu_poke_skips_a12_and_the_illegal_write_counter() {
+    let mapper = Rc::new(RefCell::new(A12Counter { chr: vec![0; 0x2000], rises: 0 }));
+    let mut ppu = PPU::init_ppu(mapper.clone(), vec![0; 192], None);
+
+    // Rendering is off, so ticking only lets A12 sit low for longer than the mapper's filter
+    for _ in 0..100 {
+        ppu.ppu_tick();
+    }
+
+    ppu.set_on_illegal_ppu_write(Some(Box::new(|addr, _| panic!("Poke to {:04X} was reported", addr))));
+    for addr in [0x0000, 0x1000, 0x0FFF, 0x1FF0, 0x4000, 0xFFFF] {
+        ppu.poke_vram(addr, 0xAB);
+    }
+
+    assert_eq!(mapper.borrow().rises, 0, "A poke into the upper pattern table clocked the mapper");
+    assert_eq!(ppu.illegal_ppu_writes(), 0, "Pokes to CHR ROM or past $3FFF were counted as illegal");
+
+    // Nametables and palette RAM still take the data
+    ppu.poke_vram(0x2000, 0x12);
+    ppu.poke_vram(0x3F01, 0xFF);
+    assert_eq!(ppu.peek_vram(0x2000), 0x12);
+    assert_eq!(ppu.palette_ram()[1], 0x3F, "Palette entries only hold 6 bits");
+}
+
+#[test]
+fn peeking_status_leaves_vblank_set() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+        while cpu.cpu_bus.ppu.state.scanline != 241 || cpu.cpu_bus.ppu.state.dots < 10 {
+            cpu.cpu_bus.ppu.ppu_tick();
+        }
+
+        for _ in 0..3 {
+            assert_ne!(cpu.cpu_bus.peek(0x2002) & 0x80, 0, "Peeking $2002 cleared VBlank");
+        }
+
+        // A real read still acknowledges it
+        assert_ne!(cpu.cpu_bus.mem_read(0x2002) & 0x80, 0);
+        assert_eq!(cpu.cpu_bus.peek(0x2002) & 0x80, 0);
+    });
+}
+
+#[test]
+fn cpu_poke_reaches_prg_ram_but_not_registers_or_rom() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+
+        cpu.cpu_bus.poke(0x6000, 0x42);
+        cpu.cpu_bus.poke(0x7FFF, 0x99);
+        assert_eq!((cpu.cpu_bus.mem_read(0x6000), cpu.cpu_bus.mem_read(0x7FFF)), (0x42, 0x99));
+
+        // Turning on NMIs or overwriting the reset vector would both show
+        cpu.cpu_bus.poke(0x2000, 0x80);
+        cpu.cpu_bus.poke(0xFFFC, 0x12);
+        assert_eq!(cpu.cpu_bus.peek(0xFFFC), 0x00);
+        let _ = cpu.run_frame();
+        assert_eq!(cpu.cpu_bus.ppu.nmi_count(), 0, "Poking $2000 turned on NMIs");
+    });
+}
+
+#[test]
+fn read_range_over_the_ppu_registers_leaves_vblank_set() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+        tick_to(&mut cpu, 241, 10);
+
+        let registers = cpu.read_range(0x2000, 8);
+
+        assert_ne!(registers[2] & 0x80, 0, "read_range didn't see VBlank");
+        assert_ne!(cpu.cpu_bus.peek(0x2002) & 0x80, 0, "read_range cleared VBlank");
+    });
+}
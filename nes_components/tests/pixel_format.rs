@@ -0,0 +1,16 @@
+// Repacking pixels between the PPU's XRGB and the byte orders textures want
+
+use nes_components::*;
+
+#[test]
+fn xrgb_to_rgba_and_back_keeps_the_color() {
+    let xrgb = PixelFormat::Xrgb.pack(0x12, 0x34, 0x56);
+    assert_eq!(xrgb, 0x0012_3456);
+
+    // RGBA is about the order in memory, with alpha filled in
+    let rgba = PixelFormat::Xrgb.convert(xrgb, PixelFormat::Rgba);
+    assert_eq!(rgba.to_ne_bytes(), [0x12, 0x34, 0x56, 0xFF]);
+
+    assert_eq!(PixelFormat::Rgba.convert(rgba, PixelFormat::Xrgb), xrgb);
+    assert_eq!(PixelFormat::Bgra.convert(PixelFormat::Xrgb.convert(xrgb, PixelFormat::Bgra), PixelFormat::Xrgb), xrgb);
+}
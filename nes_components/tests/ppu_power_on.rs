@@ -0,0 +1,24 @@
+// A PPU as PPU::new builds it — at the very start of the first frame with nothing raised yet
+
+mod common;
+
+use common::*;
+use nes_components::*;
+
+#[test]
+fn a_fresh_ppu_starts_at_dot_0_of_scanline_0_outside_vblank() {
+    run_with_big_stack(|| {
+        let mapper = Rom::from_parts(vec![0; 0x4000], vec![0; 0x2000], 0, Mirroring::VERTICAL).create_mapper().expect("Could not create the mapper");
+        let ppu = PPU::new(PpuConfig { mapper: mapper.clone(), palette: vec![0; 192], window: None });
+
+        assert_eq!((ppu.state.scanline, ppu.state.dots), (0, 0));
+        assert_eq!((ppu.frame_count(), ppu.dot_count(), ppu.nmi_count()), (0, 0, 0));
+        assert!(!ppu.warmed_up());
+
+        let state = PpuState::new();
+        assert_eq!((state.scanline, state.dots), (0, 0));
+
+        let cpu = CPU::init_cpu(mapper, ppu);
+        assert_eq!(cpu.cpu_bus.peek(0x2002) & 0x80, 0, "VBlank is set at power on");
+    });
+}
@@ -0,0 +1,165 @@
+// The PPU registers as the CPU sees them — what a read or write of $2000-$2007 does to v, t, w and the read buffer
+
+mod common;
+
+use common::*;
+use nes_components::*;
+
+#[test]
+fn a_palette_read_buffers_the_nametable_byte_under_it() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+        cpu.cpu_bus.ppu.poke_vram(0x3F05, 0x21);
+        cpu.cpu_bus.ppu.poke_vram(0x2F05, 0x77);
+
+        // The palette comes straight back, while the buffer picks up the nametable mirror at $2F05
+        cpu.cpu_bus.mem_write(0x2006, 0x3F);
+        cpu.cpu_bus.mem_write(0x2006, 0x05);
+        assert_eq!(cpu.cpu_bus.mem_read(0x2007) & 0x3F, 0x21);
+
+        // The next read (from anywhere) hands that buffered byte over
+        cpu.cpu_bus.mem_write(0x2006, 0x20);
+        cpu.cpu_bus.mem_write(0x2006, 0x00);
+        assert_eq!(cpu.cpu_bus.mem_read(0x2007), 0x77);
+    });
+}
+
+#[test]
+fn a_ppuctrl_write_sets_the_nametable_bits_of_t() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+
+        // t = $2345 through $2006 (which copies it into v)
+        cpu.cpu_bus.mem_write(0x2006, 0x23);
+        cpu.cpu_bus.mem_write(0x2006, 0x45);
+
+        for nametable in 0..4u16 {
+            cpu.cpu_bus.mem_write(0x2000, nametable as u8);
+            assert_eq!(cpu.cpu_bus.ppu.temp_vram_addr(), (0x2345 & !0x0C00) | (nametable << 10), "Nametable {}", nametable);
+        }
+
+        assert_eq!(cpu.cpu_bus.ppu.vram_addr(), 0x2345, "A $2000 write moved v");
+    });
+}
+
+#[test]
+fn a_ppuaddr_pair_clears_bit_14_of_v() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+
+        // Fine Y 7 through $2005 puts bit 14 of t up
+        let _ = cpu.cpu_bus.mem_read(0x2002);
+        cpu.cpu_bus.mem_write(0x2005, 0x00);
+        cpu.cpu_bus.mem_write(0x2005, 0xFF);
+        assert_ne!(cpu.cpu_bus.ppu.temp_vram_addr() & 0x4000, 0);
+
+        // The first $2006 write only has room for 6 bits, and clears bit 14 with them
+        cpu.cpu_bus.mem_write(0x2006, 0xFF);
+        cpu.cpu_bus.mem_write(0x2006, 0xFF);
+
+        assert_eq!(cpu.cpu_bus.ppu.vram_addr(), 0x3FFF);
+        assert_eq!(cpu.cpu_bus.ppu.temp_vram_addr(), 0x3FFF);
+    });
+}
+
+#[test]
+fn scroll_and_address_writes_share_one_toggle_that_a_status_read_resets() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+        cpu.cpu_bus.mem_write(0x2006, 0x00);
+        cpu.cpu_bus.mem_write(0x2006, 0x00);
+
+        // A $2006 write after one $2005 write is taken as the second of the pair — the low byte, which copies t into v
+        cpu.cpu_bus.mem_write(0x2005, 0x7D);
+        assert!(cpu.cpu_bus.ppu.write_toggle());
+        cpu.cpu_bus.mem_write(0x2006, 0x45);
+        assert!(!cpu.cpu_bus.ppu.write_toggle());
+        assert_eq!((cpu.cpu_bus.ppu.temp_vram_addr(), cpu.cpu_bus.ppu.vram_addr()), (0x0045, 0x0045));
+
+        // Reading $2002 in between starts the $2006 pair over from the high byte
+        cpu.cpu_bus.mem_write(0x2005, 0x7D);
+        let _ = cpu.cpu_bus.mem_read(0x2002);
+        assert!(!cpu.cpu_bus.ppu.write_toggle());
+        cpu.cpu_bus.mem_write(0x2006, 0x21);
+        assert_eq!(cpu.cpu_bus.ppu.vram_addr(), 0x0045, "v changed on the first write of the pair");
+        cpu.cpu_bus.mem_write(0x2006, 0x08);
+        assert_eq!((cpu.cpu_bus.ppu.temp_vram_addr(), cpu.cpu_bus.ppu.vram_addr()), (0x2108, 0x2108));
+    });
+}
+
+#[test]
+fn a_whole_nametable_uploads_and_reads_back_in_forced_blank() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+        // 32x30 tiles, then the 64 attribute bytes straight after them
+        let nametable: Vec<u8> = (0..1024u16).map(|i| (i * 7 + i / 32) as u8).collect();
+
+        cpu.cpu_bus.mem_write(0x2006, 0x20);
+        cpu.cpu_bus.mem_write(0x2006, 0x00);
+        for byte in &nametable {
+            cpu.cpu_bus.mem_write(0x2007, *byte);
+        }
+        assert_eq!(cpu.cpu_bus.ppu.vram_addr(), 0x2400, "The upload should end at the start of the next nametable");
+
+        // The first read only fills the buffer
+        cpu.cpu_bus.mem_write(0x2006, 0x20);
+        cpu.cpu_bus.mem_write(0x2006, 0x00);
+        let _ = cpu.cpu_bus.mem_read(0x2007);
+        let read_back: Vec<u8> = (0..1024).map(|_| cpu.cpu_bus.mem_read(0x2007)).collect();
+
+        assert_eq!(read_back, nametable);
+        assert_eq!((cpu.cpu_bus.ppu.peek_vram(0x23BF), cpu.cpu_bus.ppu.peek_vram(0x23C0)), (nametable[959], nametable[960]));
+    });
+}
+
+#[test]
+fn ppuaddr_and_ppudata_writes_are_logged_with_v_and_t() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+        tick_to(&mut cpu, 245, 20);
+        cpu.cpu_bus.set_ppu_access_logging(true);
+
+        cpu.cpu_bus.mem_write(0x2006, 0x21);
+        cpu.cpu_bus.mem_write(0x2006, 0x08);
+        cpu.cpu_bus.mem_write(0x2007, 0x55);
+        // Mirrors are logged under the register they reach
+        cpu.cpu_bus.mem_write(0x3FFF, 0x66);
+
+        let access = |addr, value, v, t| PpuAccess { addr, value, write: true, scanline: 245, dot: 20, v, t };
+        assert_eq!(cpu.cpu_bus.take_ppu_access_log(), [
+            access(0x2006, 0x21, 0x0000, 0x2100),
+            access(0x2006, 0x08, 0x2108, 0x2108),
+            access(0x2007, 0x55, 0x2109, 0x2108),
+            access(0x2007, 0x66, 0x210A, 0x2108),
+        ]);
+        assert!(cpu.cpu_bus.ppu_access_log().is_empty(), "Taking the log didn't start a fresh one");
+
+        cpu.cpu_bus.set_ppu_access_logging(false);
+        cpu.cpu_bus.mem_write(0x2006, 0x20);
+        assert!(cpu.cpu_bus.ppu_access_log().is_empty());
+    });
+}
+
+#[test]
+fn ppuctrl_writes_are_ignored_until_the_ppu_warms_up() {
+    run_with_big_stack(|| {
+        let mut prg = vec![0; 0x4000];
+        prg[0..3].copy_from_slice(&JMP_SELF);
+        prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0xC0]);
+        let mapper = Rom::from_parts(prg, vec![0; 0x2000], 0, Mirroring::VERTICAL).create_mapper().expect("Could not create the mapper");
+        let ppu = PPU::init_ppu(mapper.clone(), vec![0; 192], None);
+        let mut cpu = CPU::init_cpu(mapper, ppu);
+
+        // Nametable 3 shows up in t's bits 10-11 once a write goes through
+        cpu.cpu_bus.mem_write(0x2000, 0x03);
+        assert!(!cpu.cpu_bus.ppu.warmed_up());
+        assert_eq!(cpu.cpu_bus.ppu.temp_vram_addr(), 0, "A PPUCTRL write before the warm up went through");
+
+        while !cpu.cpu_bus.ppu.warmed_up() {
+            cpu.decode().unwrap();
+        }
+
+        cpu.cpu_bus.mem_write(0x2000, 0x03);
+        assert_eq!(cpu.cpu_bus.ppu.temp_vram_addr(), 0x0C00);
+    });
+}
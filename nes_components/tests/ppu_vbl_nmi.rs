@@ -0,0 +1,122 @@
+// Checks the dots the VBlank flag goes up and comes down on, and races between $2002 reads and the flag/NMI
+// The full ppu_vbl_nmi suite is ignored by default since its ROMs aren't shipped with every checkout — put them in
+// frontend/tests/ppu_vbl_nmi/rom_singles and run cargo test -- --ignored
+
+mod common;
+
+use common::*;
+use nes_components::*;
+
+// The suite's tests each run for a few hundred frames at most
+const MAX_INSTRUCTIONS: usize = 50_000_000;
+
+#[test]
+#[ignore = "needs blargg's ppu_vbl_nmi ROMs in frontend/tests/ppu_vbl_nmi/rom_singles"]
+fn ppu_vbl_nmi_passes() {
+    run_with_big_stack(run_suite);
+}
+
+#[test]
+fn vblank_flag_is_readable_from_241_1_until_261_1() {
+    run_with_big_stack(|| {
+        let mut cpu = warmed_up_cpu();
+
+        tick_to(&mut cpu, 241, 0);
+        assert_eq!(cpu.cpu_bus.peek(0x2002) & 0x80, 0, "VBlank is set before 241,1");
+
+        tick_to(&mut cpu, 241, 1);
+        assert_ne!(cpu.cpu_bus.peek(0x2002) & 0x80, 0, "VBlank isn't set at 241,1");
+
+        tick_to(&mut cpu, 261, 0);
+        assert_ne!(cpu.cpu_bus.peek(0x2002) & 0x80, 0, "VBlank is cleared before 261,1");
+
+        tick_to(&mut cpu, 261, 1);
+        assert_eq!(cpu.cpu_bus.mem_read(0x2002) & 0x80, 0, "VBlank is still set at 261,1");
+    });
+}
+
+#[test]
+fn reading_status_just_before_vblank_suppresses_it() {
+    run_with_big_stack(|| {
+        let mut cpu = warmed_up_cpu();
+
+        tick_to(&mut cpu, 241, 0);
+        assert_eq!(cpu.cpu_bus.mem_read(0x2002) & 0x80, 0);
+
+        tick_to(&mut cpu, 241, 10);
+        assert_eq!(cpu.cpu_bus.peek(0x2002) & 0x80, 0, "VBlank went up after a read at 241,0");
+        assert!(!cpu.cpu_bus.nmi_pending(), "NMI raised after a read at 241,0");
+
+        // Only that frame is affected
+        tick_to(&mut cpu, 241, 1);
+        assert_ne!(cpu.cpu_bus.peek(0x2002) & 0x80, 0);
+    });
+}
+
+#[test]
+fn reading_status_as_vblank_starts_cancels_the_nmi() {
+    run_with_big_stack(|| {
+        for dot in 1..=3 {
+            let mut cpu = warmed_up_cpu();
+
+            tick_to(&mut cpu, 241, dot);
+            assert_ne!(cpu.cpu_bus.mem_read(0x2002) & 0x80, 0, "VBlank not readable at 241,{}", dot);
+
+            tick_to(&mut cpu, 241, 10);
+            assert_eq!(cpu.cpu_bus.nmi_pending(), dot == 3, "Wrong NMI after a read at 241,{}", dot);
+        }
+    });
+}
+
+#[test]
+fn scanlines_are_341_dots_and_odd_frames_are_one_shorter_while_rendering() {
+    run_with_big_stack(|| {
+        let mut cpu = warmed_up_cpu();
+
+        tick_to(&mut cpu, 100, 0);
+        let start = cpu.cpu_bus.ppu.dot_count();
+        tick_to(&mut cpu, 101, 0);
+        assert_eq!(cpu.cpu_bus.ppu.dot_count() - start, 341);
+
+        // Rendering off, every frame is the full 262 scanlines
+        assert_eq!(frame_lengths(&mut cpu), [89342, 89342]);
+
+        // Rendering on, every other frame skips a dot
+        cpu.cpu_bus.mem_write(0x2001, 0b0000_1000);
+        let mut lengths = frame_lengths(&mut cpu);
+        lengths.sort();
+        assert_eq!(lengths, [89341, 89342]);
+    });
+}
+
+fn run_suite() {
+    let (count, failures) = run_blargg_singles("tests/ppu_vbl_nmi/rom_singles", MAX_INSTRUCTIONS)
+        .expect("frontend/tests/ppu_vbl_nmi/rom_singles not found");
+
+    assert!(failures.is_empty(), "{} of {} ppu_vbl_nmi ROMs failed:\n{}", failures.len(), count, failures.join("\n"));
+}
+
+// A console with NMIs turned on, sitting in a JMP loop past the power on warm up
+fn warmed_up_cpu() -> CPU<CPUBus> {
+    let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+
+    // Acknowledge whatever VBlank the last frame ended on, so only the NMIs a test causes show up
+    let _ = cpu.cpu_bus.mem_read(0x2002);
+    cpu.cpu_bus.mem_write(0x2000, 0x80);
+    cpu.cpu_bus.take_nmi();
+
+    cpu
+}
+
+// How many dots the next two frames take, from VBlank to VBlank
+fn frame_lengths(cpu: &mut CPU<CPUBus>) -> [u64; 2] {
+    tick_to(cpu, 241, 1);
+    let start = cpu.cpu_bus.ppu.dot_count();
+    cpu.cpu_bus.ppu.ppu_tick();
+    tick_to(cpu, 241, 1);
+    let middle = cpu.cpu_bus.ppu.dot_count();
+    cpu.cpu_bus.ppu.ppu_tick();
+    tick_to(cpu, 241, 1);
+
+    [middle - start, cpu.cpu_bus.ppu.dot_count() - middle]
+}
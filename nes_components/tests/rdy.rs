@@ -0,0 +1,46 @@
+// The RDY line — while it's held low the CPU stalls on its next read, repeating that read (side effects included)
+// until it's released. DMC sample fetches take the bus the same way
+
+mod common;
+
+use common::*;
+use nes_components::*;
+
+#[test]
+fn holding_rdy_repeats_the_read_before_it_goes_through() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+        cpu.cpu_bus.input.controllers_mut()[0].set_buttons(BUTTON_START);
+        cpu.cpu_bus.mem_write(0x4016, 1);
+        cpu.cpu_bus.mem_write(0x4016, 0);
+
+        // Three stalled reads shift out A, B and SELECT, so the real read gets START
+        cpu.cpu_bus.hold_rdy(3);
+        let start = cpu.cycles();
+        let value = cpu.read_byte(0x4016);
+
+        assert_eq!(cpu.cycles() - start, 4, "Expected three stalled cycles and the read");
+        assert_eq!(value & 1, 1, "The stalled reads didn't shift the controller");
+        assert_eq!(cpu.cpu_bus.mem_read(0x4016) & 1, 0, "Shifted out past START");
+    });
+}
+
+#[test]
+fn a_dmc_fetch_on_a_controller_read_only_drops_a_bit_with_the_glitch_on() {
+    run_with_big_stack(|| {
+        for glitch in [false, true] {
+            let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+            cpu.set_dmc_read_glitch(glitch);
+            cpu.cpu_bus.input.controllers_mut()[0].set_buttons(BUTTON_A);
+            cpu.cpu_bus.mem_write(0x4016, 1);
+            cpu.cpu_bus.mem_write(0x4016, 0);
+
+            // The fetch halts the CPU on its read of $4016
+            cpu.cpu_bus.request_dmc_dma(0xC000);
+            let value = cpu.read_byte(0x4016);
+
+            assert_eq!(cpu.cpu_bus.take_dmc_sample(), Some(JMP_SELF[0]));
+            assert_eq!(value & 1, !glitch as u8, "With the glitch {} the read should see {}", if glitch { "on" } else { "off" }, if glitch { "B" } else { "A" });
+        }
+    });
+}
@@ -0,0 +1,36 @@
+// The resampler taking the mixer's CPU clock rate output down to an audio device's rate
+
+use nes_components::*;
+
+const CPU_CLOCK_RATE: f64 = 1_789_773.0;
+const OUTPUT_RATE: f64 = 44_100.0;
+
+#[test]
+fn a_tone_keeps_its_frequency_through_the_resampler() {
+    let tone = 1_000.0;
+    let mut resampler = Resampler::new(CPU_CLOCK_RATE, OUTPUT_RATE);
+
+    // A fifth of a second of the tone, one sample per CPU cycle
+    for cycle in 0..(CPU_CLOCK_RATE / 5.0) as usize {
+        let t = cycle as f64 / CPU_CLOCK_RATE;
+        resampler.push((2.0 * std::f64::consts::PI * tone * t).sin() as f32);
+    }
+
+    let output = resampler.take_output();
+    let expected_len = OUTPUT_RATE / 5.0;
+    assert!((output.len() as f64 - expected_len).abs() < 50.0, "{} samples out, expected about {}", output.len(), expected_len);
+
+    // The strongest frequency in the output, to the nearest 5Hz
+    let (peak, _) = (1..2_000)
+        .map(|step| {
+            let frequency = step as f64 * 5.0;
+            let (re, im) = output.iter().enumerate().fold((0.0, 0.0), |(re, im), (n, sample)| {
+                let angle = 2.0 * std::f64::consts::PI * frequency * n as f64 / OUTPUT_RATE;
+                (re + *sample as f64 * angle.cos(), im - *sample as f64 * angle.sin())
+            });
+            (frequency, re * re + im * im)
+        })
+        .fold((0.0, 0.0), |best, candidate| if candidate.1 > best.1 { candidate } else { best });
+
+    assert!((peak - tone).abs() <= 10.0, "The tone came out at {}Hz", peak);
+}
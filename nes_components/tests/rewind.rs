@@ -0,0 +1,55 @@
+// Rewinding — captured states come back newest first, each older one rebuilt from its delta
+
+mod common;
+
+use common::*;
+use nes_components::*;
+
+// $C000: INX, JMP $C000 — X keeps counting, so every capture has different registers
+const COUNT_LOOP: [u8; 4] = [0xE8, 0x4C, 0x00, 0xC0];
+
+#[test]
+fn rewinding_brings_back_each_captured_state() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&COUNT_LOOP, vec![0; 0x2000], Mirroring::VERTICAL);
+        cpu.set_rewind(0, 3);
+
+        let mut captured = Vec::new();
+        for _ in 0..3 {
+            let _ = cpu.run_frame();
+            cpu.capture_rewind_state();
+            captured.push((cpu.x, cpu.pc, cpu.cycles()));
+        }
+        assert_eq!(cpu.rewind_buffer().len(), 3);
+
+        let _ = cpu.run_frame();
+        for expected in captured.iter().rev() {
+            assert!(cpu.rewind_step(), "Ran out of states early");
+            assert_eq!((cpu.x, cpu.pc, cpu.cycles()), *expected);
+        }
+
+        assert!(!cpu.rewind_step());
+        assert!(cpu.rewind_buffer().is_empty());
+    });
+}
+
+#[test]
+fn past_capacity_the_oldest_state_is_dropped() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&COUNT_LOOP, vec![0; 0x2000], Mirroring::VERTICAL);
+        // One capture at the end of every frame
+        cpu.set_rewind(1, 2);
+
+        let mut cycles = Vec::new();
+        for _ in 0..3 {
+            let _ = cpu.run_frame();
+            cycles.push(cpu.cycles());
+        }
+        assert_eq!(cpu.rewind_buffer().len(), 2);
+
+        assert!(cpu.rewind_step());
+        assert!(cpu.rewind_step());
+        assert_eq!(cpu.cycles(), cycles[1]);
+        assert!(!cpu.rewind_step());
+    });
+}
@@ -0,0 +1,88 @@
+// ASL, LSR, ROL and ROR give the same result and flags on the accumulator as on memory, for every value and carry in
+// Every read-modify-write instruction, INC and DEC included, takes the same cycles in each memory addressing mode
+
+mod common;
+
+use common::*;
+use nes_components::*;
+
+// (name, accumulator opcode, absolute opcode, what the operation does to (value, carry in) as (result, carry out))
+type Operation = (&'static str, u8, u8, fn(u8, bool) -> (u8, bool));
+
+const OPERATIONS: [Operation; 4] = [
+    ("ASL", 0x0A, 0x0E, |value, _| (value << 1, value & 0x80 != 0)),
+    ("LSR", 0x4A, 0x4E, |value, _| (value >> 1, value & 0x01 != 0)),
+    ("ROL", 0x2A, 0x2E, |value, carry| ((value << 1) | carry as u8, value & 0x80 != 0)),
+    ("ROR", 0x6A, 0x6E, |value, carry| ((value >> 1) | ((carry as u8) << 7), value & 0x01 != 0)),
+];
+
+#[test]
+fn shifts_and_rotates_match_in_accumulator_and_absolute_modes() {
+    run_with_big_stack(|| {
+        for (name, accumulator_opcode, absolute_opcode, operation) in OPERATIONS {
+            let mut accumulator_cpu = cpu_with_program(&[accumulator_opcode]);
+            let mut absolute_cpu = cpu_with_program(&[absolute_opcode, 0x00, 0x02]); // $0200
+
+            for value in 0..=255u8 {
+                for carry in [false, true] {
+                    let (result, carry_out) = operation(value, carry);
+                    let flags = (if carry_out { CARRY } else { 0 }) | (if result == 0 { ZERO } else { 0 }) | (result & NEGATIVE);
+
+                    let status = if carry { CARRY } else { 0 };
+                    let accumulator = run(&mut accumulator_cpu, status, value, None);
+                    let memory = run(&mut absolute_cpu, status, 0x5A, Some(value));
+
+                    assert_eq!(accumulator, (result, flags), "{} A with {:02X}, carry {}", name, value, carry);
+                    assert_eq!(memory, (result, flags), "{} $0200 with {:02X}, carry {}", name, value, carry);
+                    assert_eq!(absolute_cpu.accumulator, 0x5A, "{} $0200 changed A", name);
+                }
+            }
+        }
+    });
+}
+
+#[test]
+fn inc_and_dec_take_as_long_as_the_shifts_in_every_mode() {
+    run_with_big_stack(|| {
+        // (mode, operand bytes, cycles) — the indexed modes always pay the dummy read, page crossed or not
+        let modes: [(&str, &[u8], usize); 4] = [
+            ("zp", &[0x10], 5),
+            ("abs", &[0x00, 0x02], 6),
+            ("zp,X", &[0x10], 6),
+            ("abs,X", &[0x00, 0x02], 7),
+        ];
+        // (name, opcodes for zp, abs, zp,X and abs,X)
+        let instructions = [
+            ("ASL", [0x06, 0x0E, 0x16, 0x1E]),
+            ("DEC", [0xC6, 0xCE, 0xD6, 0xDE]),
+            ("INC", [0xE6, 0xEE, 0xF6, 0xFE]),
+        ];
+
+        for (name, opcodes) in instructions {
+            for ((mode, operand, cycles), opcode) in modes.into_iter().zip(opcodes) {
+                let mut cpu = cpu_with_program(&[[opcode].as_slice(), operand].concat());
+                cpu.x = 1;
+
+                let start = cpu.cycles();
+                cpu.decode().unwrap_or_else(|e| panic!("{} {} didn't run: {}", name, mode, e));
+
+                assert_eq!(cpu.cycles() - start, cycles, "{} {} took the wrong number of cycles", name, mode);
+            }
+        }
+    });
+}
+
+// Runs the instruction at $0600 once, giving back the result (A, or the byte at $0200 when it's given) and the N, Z and C flags
+fn run(cpu: &mut CPU<CPUBus>, status: u8, accumulator: u8, memory: Option<u8>) -> (u8, u8) {
+    cpu.pc = PROGRAM_START;
+    cpu.status = status;
+    cpu.accumulator = accumulator;
+    if let Some(value) = memory {
+        cpu.cpu_bus.poke(0x0200, value);
+    }
+
+    cpu.decode().expect("The instruction didn't run");
+
+    let result = if memory.is_some() { cpu.cpu_bus.peek(0x0200) } else { cpu.accumulator };
+    (result, cpu.status & (NEGATIVE | ZERO | CARRY))
+}
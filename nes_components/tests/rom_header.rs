@@ -0,0 +1,29 @@
+// Parsing the iNES header — buffers too short to hold one are rejected before anything is read from them, and dumps
+// without one can still be loaded with the settings it would have held
+
+use nes_components::*;
+
+#[test]
+fn buffers_shorter_than_the_header_are_truncated() {
+    assert_eq!(Rom::new(&vec![0]).err(), Some(RomError::TruncatedHeader(1)));
+    assert_eq!(Rom::new(&Vec::new()).err(), Some(RomError::TruncatedHeader(0)));
+    assert_eq!(Rom::new(&b"NES\x1A".to_vec()).err(), Some(RomError::TruncatedHeader(4)));
+}
+
+#[test]
+fn a_full_header_without_the_tag_has_no_header() {
+    assert_eq!(Rom::new(&vec![0; 16]).err(), Some(RomError::NoHeader));
+}
+
+#[test]
+fn a_headerless_dump_loads_as_nrom_with_the_given_settings() {
+    // 16KB of PRG then 8KB of CHR, each filled with its own byte
+    let raw = [vec![0xEA; 0x4000], vec![0x3C; 0x2000]].concat();
+
+    let rom = Rom::from_headerless(&raw, 0, Mirroring::VERTICAL).expect("The headerless dump didn't load");
+
+    assert_eq!((rom.mapper, rom.screen_mirroring), (0, Mirroring::VERTICAL));
+    assert_eq!(rom.prg_rom, vec![0xEA; 0x4000]);
+    assert_eq!(rom.chr_rom, vec![0x3C; 0x2000]);
+    assert!(rom.create_mapper().is_ok());
+}
@@ -0,0 +1,28 @@
+// CPU::run_with_limit — a program that finishes with BRK comes back Ok, one that never finishes stops at the limit
+
+mod common;
+
+use common::*;
+use nes_components::*;
+
+#[test]
+fn a_program_ending_in_brk_finishes() {
+    run_with_big_stack(|| {
+        // LDA #$05, BRK
+        let mut cpu = cpu_with_program(&[0xA9, 0x05, 0x00]);
+
+        assert_eq!(cpu.run_with_limit(100), Ok(()));
+        assert_eq!(cpu.accumulator, 0x05);
+    });
+}
+
+#[test]
+fn jmp_to_itself_hits_the_instruction_limit() {
+    run_with_big_stack(|| {
+        // $0600: JMP $0600
+        let mut cpu = cpu_with_program(&[0x4C, 0x00, 0x06]);
+
+        assert_eq!(cpu.run_with_limit(1000), Err(RunError::InstructionLimit(1000)));
+        assert_eq!(cpu.pc, PROGRAM_START);
+    });
+}
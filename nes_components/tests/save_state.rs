@@ -0,0 +1,39 @@
+// Save state version checks — the major version in the header has to match, a different minor version still loads
+
+mod common;
+
+use common::*;
+use nes_components::*;
+
+// The header is the "NESS" tag followed by the major and minor version
+const MAJOR: usize = 4;
+const MINOR: usize = 5;
+
+#[test]
+fn states_from_another_major_version_are_incompatible() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+        let mut state = cpu.save_state();
+        let minor = state[MINOR];
+        state[MAJOR] = state[MAJOR].wrapping_add(1);
+
+        let _ = cpu.run_frame();
+        let cycles = cpu.cycles();
+        assert_eq!(cpu.load_state(&state), Err(StateError::Incompatible { major: state[MAJOR], minor }));
+        assert_eq!(cpu.cycles(), cycles, "A rejected state was partly loaded");
+    });
+}
+
+#[test]
+fn states_from_a_newer_minor_version_still_load() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+        let mut state = cpu.save_state();
+        state[MINOR] = 0xFF;
+        let cycles = cpu.cycles();
+
+        let _ = cpu.run_frame();
+        cpu.load_state(&state).expect("A newer minor version was rejected");
+        assert_eq!(cpu.cycles(), cycles);
+    });
+}
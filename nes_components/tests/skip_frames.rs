@@ -0,0 +1,47 @@
+// Checks that skipping frames at boot (the frontend's --skip-frames) runs exactly that many without presenting any
+// Frame skip while running (run_frame_skip) presents one frame out of every skip + 1
+
+mod common;
+
+use common::*;
+use nes_components::*;
+
+const FRAMES_TO_SKIP: usize = 120;
+
+#[test]
+fn skip_frames_runs_exactly_n_frames_before_presenting() {
+    run_with_big_stack(|| {
+        let mut prg = vec![0; 0x4000];
+        prg[0..3].copy_from_slice(&[0x4C, 0x00, 0xC0]); // JMP $C000
+        prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0xC0]);
+
+        let rom = Rom::from_parts(prg, vec![0; 0x2000], 0, Mirroring::VERTICAL);
+        let mapper = rom.create_mapper().expect("Could not create the mapper");
+        let ppu = PPU::init_ppu(mapper.clone(), vec![0; 192], None);
+        let mut cpu = CPU::init_cpu(mapper, ppu);
+
+        cpu.skip_frames(FRAMES_TO_SKIP).expect("The skipped frames didn't run");
+        assert_eq!(cpu.cpu_bus.ppu.frame_count(), FRAMES_TO_SKIP as u64);
+        assert_eq!(cpu.cpu_bus.ppu.presented_count(), 0, "A skipped frame was presented");
+
+        // Presenting picks up right after the skipped frames
+        cpu.run_frame().expect("The first presented frame didn't run");
+        assert_eq!(cpu.cpu_bus.ppu.frame_count(), FRAMES_TO_SKIP as u64 + 1);
+        assert_eq!(cpu.cpu_bus.ppu.presented_count(), 1);
+    });
+}
+
+#[test]
+fn a_frame_skip_of_one_presents_half_the_frames() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+        let (frames, presented) = (cpu.cpu_bus.ppu.frame_count(), cpu.cpu_bus.ppu.presented_count());
+
+        for _ in 0..10 {
+            cpu.run_frame_skip(1).expect("A frame didn't run");
+        }
+
+        assert_eq!(cpu.cpu_bus.ppu.frame_count() - frames, 20);
+        assert_eq!(cpu.cpu_bus.ppu.presented_count() - presented, 10);
+    });
+}
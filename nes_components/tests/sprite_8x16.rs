@@ -0,0 +1,43 @@
+// 8x16 sprites — the even tile on top and the one after it underneath, swapped over by a vertical flip
+
+mod common;
+
+use common::*;
+use nes_components::*;
+
+const SPRITE_Y: u8 = 50;
+const SPRITE_X: u8 = 100;
+const TOP_COLOR: u8 = 0x21;
+const BOTTOM_COLOR: u8 = 0x22;
+
+#[test]
+fn a_vertical_flip_swaps_the_top_and_bottom_tiles() {
+    run_with_big_stack(|| {
+        for (attributes, top, bottom) in [(0x00, TOP_COLOR, BOTTOM_COLOR), (0x80, BOTTOM_COLOR, TOP_COLOR)] {
+            // Tile 2 (the top) is solid color 1, tile 3 (the bottom) solid color 2
+            let mut chr = vec![0; 0x2000];
+            chr[0x20..0x28].fill(0xFF);
+            chr[0x38..0x40].fill(0xFF);
+            let mut cpu = booted_nrom(&JMP_SELF, chr, Mirroring::VERTICAL);
+            cpu.cpu_bus.ppu.poke_vram(0x3F11, TOP_COLOR);
+            cpu.cpu_bus.ppu.poke_vram(0x3F12, BOTTOM_COLOR);
+
+            let mut oam = [0xFF; 256];
+            oam[0..4].copy_from_slice(&[SPRITE_Y, 0x02, attributes, SPRITE_X]);
+            cpu.cpu_bus.ppu.set_oam(&oam);
+            cpu.cpu_bus.mem_write(0x2000, 0b0010_0000);
+            cpu.cpu_bus.mem_write(0x2001, 0b0001_0100);
+
+            let _ = cpu.run_frame();
+            let _ = cpu.run_frame();
+
+            // The sprite is drawn on the 16 scanlines below its y
+            let pixels = cpu.cpu_bus.ppu.frame_buffer_as(PixelFormat::Xrgb);
+            let color_at = |line: u8| pixels[line as usize * 256 + SPRITE_X as usize + 4] & 0xFF;
+            for row in 0..16 {
+                let expected = if row < 8 { top } else { bottom };
+                assert_eq!(color_at(SPRITE_Y + 1 + row), expected as u32, "Row {} with attributes {:02X}", row, attributes);
+            }
+        }
+    });
+}
@@ -0,0 +1,114 @@
+// Sprite evaluation finds sprites by their place in OAM, and picks them on the scanlines they're drawn below
+// $2004 reads see it working — $FF while secondary OAM is cleared
+// With the sprite limit lifted, every sprite on a line gets picked instead of the first 8
+// Starting to render with OAMADDR past 8 copies a row of OAM over sprites 0 and 1 before evaluation ever sees them
+
+mod common;
+
+use common::*;
+use nes_components::*;
+
+#[test]
+fn evaluation_finds_sprites_at_known_oam_offsets() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+
+        // Sprite 5 (bytes 20-23) and the last sprite (bytes 252-255), everything else off screen
+        let mut oam = [0xFF; 256];
+        oam[20..24].copy_from_slice(&[100, 0x42, 0x01, 80]);
+        oam[252..256].copy_from_slice(&[104, 0x17, 0x00, 200]);
+        cpu.cpu_bus.ppu.set_oam(&oam);
+        cpu.cpu_bus.mem_write(0x2001, 0b0001_1000);
+
+        for scanline in 90..120 {
+            tick_to(&mut cpu, scanline, 257);
+
+            // A sprite at y is drawn on scanlines y + 1 to y + 8, so it's picked while the PPU is on y to y + 7
+            let mut expected = vec![];
+            if (100..108).contains(&scanline) {
+                expected.push((80, 100, 0x42));
+            }
+            if (104..112).contains(&scanline) {
+                expected.push((200, 104, 0x17));
+            }
+
+            assert_eq!(cpu.cpu_bus.ppu.active_sprites(), expected, "Wrong sprites picked on scanline {}", scanline);
+        }
+    });
+}
+
+#[test]
+fn oamdata_reads_ff_while_secondary_oam_is_cleared() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+        cpu.cpu_bus.ppu.set_oam(&[0x42; 256]);
+        cpu.cpu_bus.mem_write(0x2001, 0b0001_1000);
+
+        // Dots 1-64 of a visible scanline fill secondary OAM with $FF, and that's what the bus sees
+        for dot in [1, 30, 64] {
+            tick_to(&mut cpu, 100, dot);
+            assert_eq!(cpu.cpu_bus.mem_read(0x2004), 0xFF, "$2004 read at dot {} of the clear", dot);
+        }
+
+        // With rendering off OAM reads back as it is
+        cpu.cpu_bus.mem_write(0x2001, 0);
+        tick_to(&mut cpu, 101, 30);
+        assert_eq!(cpu.cpu_bus.mem_read(0x2004), 0x42);
+    });
+}
+
+#[test]
+fn with_no_sprite_limit_ten_sprites_on_a_line_are_all_picked() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+        cpu.cpu_bus.ppu.set_sprite_limit(None);
+
+        // Ten sprites side by side on y = 100
+        let mut oam = [0xFF; 256];
+        for sprite in 0..10 {
+            oam[sprite * 4..sprite * 4 + 4].copy_from_slice(&[100, sprite as u8, 0x00, sprite as u8 * 10]);
+        }
+        cpu.cpu_bus.ppu.set_oam(&oam);
+        cpu.cpu_bus.mem_write(0x2001, 0b0001_1000);
+
+        tick_to(&mut cpu, 100, 257);
+
+        let expected: Vec<(u8, u8, u8)> = (0..10).map(|sprite| (sprite * 10, 100, sprite)).collect();
+        assert_eq!(cpu.cpu_bus.ppu.active_sprites(), expected);
+    });
+}
+
+#[test]
+fn rendering_from_a_high_oamaddr_copies_its_row_over_the_first_two_sprites() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+        let oam: [u8; 256] = std::array::from_fn(|i| i as u8);
+        cpu.cpu_bus.ppu.set_oam(&oam);
+        cpu.cpu_bus.mem_write(0x2001, 0b0001_1000);
+
+        // OAMADDR $2B is in the row starting at $28
+        tick_to(&mut cpu, 250, 0);
+        cpu.cpu_bus.mem_write(0x2003, 0x2B);
+        tick_to(&mut cpu, 261, 2);
+
+        let snapshot = cpu.cpu_bus.ppu.oam_snapshot();
+        assert_eq!(snapshot[0..8], oam[0x28..0x30]);
+        assert_eq!(snapshot[8..], oam[8..], "The copy reached past the first two sprites");
+    });
+}
+
+#[test]
+fn rendering_from_a_low_oamaddr_leaves_oam_alone() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+        let oam: [u8; 256] = std::array::from_fn(|i| i as u8);
+        cpu.cpu_bus.ppu.set_oam(&oam);
+        cpu.cpu_bus.mem_write(0x2001, 0b0001_1000);
+
+        tick_to(&mut cpu, 250, 0);
+        cpu.cpu_bus.mem_write(0x2003, 0x07);
+        tick_to(&mut cpu, 261, 2);
+
+        assert_eq!(cpu.cpu_bus.ppu.oam_snapshot(), oam);
+    });
+}
@@ -0,0 +1,37 @@
+// Sprite zero hit — set where an opaque sprite 0 pixel lands on an opaque background pixel, except at x = 255
+
+mod common;
+
+use common::*;
+use nes_components::*;
+
+const SPRITE_Y: u8 = 100;
+
+// Sprite 0 with only its rightmost column opaque, over a solid background, at x — whether the hit flag went up that frame
+fn hit_with_sprite_at(x: u8) -> bool {
+    // Tile 0 is solid (the background), tile 1 only has its rightmost column set
+    let mut chr = vec![0; 0x2000];
+    chr[0x00..0x08].fill(0xFF);
+    chr[0x10..0x18].fill(0x01);
+    let mut cpu = booted_nrom(&JMP_SELF, chr, Mirroring::VERTICAL);
+
+    let mut oam = [0xFF; 256];
+    oam[0..4].copy_from_slice(&[SPRITE_Y, 0x01, 0x00, x]);
+    cpu.cpu_bus.ppu.set_oam(&oam);
+    cpu.cpu_bus.mem_write(0x2001, 0b0001_1110);
+
+    // Skip the frame rendering was turned on in the middle of, then look before the pre-render line clears the flag
+    let _ = cpu.run_frame();
+    tick_to(&mut cpu, 240, 0);
+    cpu.cpu_bus.peek(0x2002) & 0x40 != 0
+}
+
+#[test]
+fn an_opaque_pixel_at_x_254_hits() {
+    run_with_big_stack(|| assert!(hit_with_sprite_at(247)));
+}
+
+#[test]
+fn an_opaque_pixel_at_x_255_never_hits() {
+    run_with_big_stack(|| assert!(!hit_with_sprite_at(248)));
+}
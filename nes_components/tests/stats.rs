@@ -0,0 +1,28 @@
+// Emulation stats — every frame run_frame finishes is counted, along with the CPU cycles it took
+
+mod common;
+
+use common::*;
+use nes_components::*;
+
+#[test]
+fn every_frame_run_is_counted() {
+    run_with_big_stack(|| {
+        // booted_nrom has already run its 3 warm up frames
+        let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+        assert_eq!(cpu.stats().frame_count, 3);
+        let boot_cycles = cpu.stats().total_cycles;
+
+        for _ in 0..10 {
+            let _ = cpu.run_frame();
+        }
+        cpu.skip_frames(2).unwrap();
+
+        let stats = cpu.stats();
+        assert_eq!(stats.frame_count, 15);
+
+        // An NTSC frame is 29780.5 CPU cycles, give or take the instruction that runs over the end of it
+        let average = (stats.total_cycles - boot_cycles) as f64 / 12.0;
+        assert!((average - 29780.5).abs() < 3.0, "{} cycles per frame", average);
+    });
+}
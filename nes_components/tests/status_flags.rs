@@ -0,0 +1,115 @@
+// How the status register goes onto the stack and comes back — bit 5 and the B flag (bit 4) only exist in the pushed byte,
+// and B is only set when the push comes from BRK or PHP
+
+mod common;
+
+use common::*;
+use nes_components::*;
+
+#[test]
+fn php_then_plp_restores_every_flag() {
+    run_with_big_stack(|| {
+        // PHP, CLC, CLD, CLI, CLV, LDA #$01 (clears Z and N), PLP
+        let mut cpu = cpu_with_program(&[0x08, 0x18, 0xD8, 0x58, 0xB8, 0xA9, 0x01, 0x28]);
+        cpu.status = 0xFF;
+
+        cpu.decode().unwrap();
+        assert_eq!(cpu.cpu_bus.peek(0x0100 + cpu.sp as u16 + 1), 0xFF, "PHP pushed the wrong status");
+
+        for _ in 0..5 {
+            cpu.decode().unwrap();
+        }
+        assert_eq!(cpu.status & !(BREAK | UNUSED), 0, "The flags weren't all cleared before PLP");
+
+        cpu.decode().unwrap();
+        assert_eq!(cpu.status, !BREAK);
+    });
+}
+
+#[test]
+fn plp_and_rti_force_bit_5_and_drop_the_b_flag() {
+    run_with_big_stack(|| {
+        for (name, opcode) in [("PLP", 0x28), ("RTI", 0x40)] {
+            let mut cpu = cpu_with_program(&[opcode]);
+
+            for pulled in 0..=255u8 {
+                // Status on top, then a return address back to the start for RTI
+                cpu.sp = 0xFA;
+                cpu.cpu_bus.poke(0x01FB, pulled);
+                cpu.cpu_bus.poke(0x01FC, (PROGRAM_START & 0xFF) as u8);
+                cpu.cpu_bus.poke(0x01FD, (PROGRAM_START >> 8) as u8);
+                cpu.pc = PROGRAM_START;
+                cpu.status = !pulled;
+
+                cpu.decode().unwrap();
+                assert_eq!(cpu.status, (pulled | UNUSED) & !BREAK, "{} pulled {:08b}", name, pulled);
+            }
+        }
+    });
+}
+
+#[test]
+fn every_push_sets_bit_5_and_only_brk_and_php_set_b() {
+    run_with_big_stack(|| {
+        // Interrupts go to a NOP at $0700
+        let flat_cpu = |program: &[u8]| {
+            let mut cpu = cpu_with_program(program);
+            cpu.cpu_bus.poke(0x0700, 0xEA);
+            cpu.cpu_bus.poke(0xFFFE, 0x00);
+            cpu.cpu_bus.poke(0xFFFF, 0x07);
+            cpu.status = 0b1100_0011; // N, V, Z and C, so they can be told apart from bits 4 and 5
+            cpu
+        };
+
+        let mut php = flat_cpu(&[0x08]);
+        php.decode().unwrap();
+        assert_eq!(pushed_status(&php), 0b1111_0011, "PHP");
+
+        let mut brk = flat_cpu(&[0x00, 0x00]);
+        brk.decode().unwrap();
+        assert_eq!(pushed_status(&brk), 0b1111_0011, "BRK");
+
+        // The IRQ is polled during the NOP and taken before the next instruction
+        let mut irq = flat_cpu(&[0xEA, 0xEA]);
+        irq.set_irq_line(true);
+        irq.decode().unwrap();
+        irq.decode().unwrap();
+        assert_eq!(irq.pc, 0x0701, "The IRQ wasn't taken");
+        assert_eq!(pushed_status(&irq), 0b1110_0011, "IRQ");
+
+        // The NMI handler at $C010 spins, leaving what it pushed on top of the stack
+        let mut program = vec![0; 0x4000];
+        program[0..3].copy_from_slice(&JMP_SELF);
+        program[0x10..0x13].copy_from_slice(&[0x4C, 0x10, 0xC0]); // $C010: JMP $C010
+        program[0x3FFA..0x3FFC].copy_from_slice(&[0x10, 0xC0]);
+        let mut nmi = booted_nrom(&program, vec![0; 0x2000], Mirroring::VERTICAL);
+
+        let _ = nmi.cpu_bus.mem_read(0x2002);
+        nmi.cpu_bus.mem_write(0x2000, 0x80);
+        nmi.status = 0b1100_0111; // I as well, keeping the APU's frame IRQ out
+        let _ = nmi.run_frame();
+        assert_eq!(nmi.cpu_bus.ppu.nmi_count(), 1, "The NMI wasn't taken");
+        assert_eq!(pushed_status(&nmi), 0b1110_0111, "NMI");
+    });
+}
+
+#[test]
+fn sed_then_brk_pushes_the_decimal_flag() {
+    run_with_big_stack(|| {
+        // SED, BRK — the 2A03 has no decimal mode, but the flag is still there to be pushed
+        let mut cpu = cpu_with_program(&[0xF8, 0x00, 0x00]);
+        cpu.cpu_bus.poke(0xFFFE, 0x00);
+        cpu.cpu_bus.poke(0xFFFF, 0x07);
+
+        cpu.decode().unwrap();
+        cpu.decode().unwrap();
+
+        assert_eq!(cpu.pc, 0x0700, "BRK didn't jump through the vector");
+        assert_eq!(pushed_status(&cpu) & DECIMAL, DECIMAL);
+    });
+}
+
+// The byte on top of the stack — where the status goes last in PHP and the interrupt sequences
+fn pushed_status<B: Bus>(cpu: &CPU<B>) -> u8 {
+    cpu.cpu_bus.peek(0x0100 + cpu.sp.wrapping_add(1) as u16)
+}
@@ -0,0 +1,70 @@
+// Debugger stepping by render granularity — step_scanline stops on the first instruction boundary past the end of the
+// scanline, and step_frame runs a whole frame
+// run_to_vblank stops once the VBlank flag is up, where games read their input
+
+mod common;
+
+use common::*;
+use nes_components::*;
+
+// JMP $C000 takes 3 cycles, the most a step can run past the end of a scanline
+const JMP_DOTS: u64 = 9;
+
+#[test]
+fn step_scanline_moves_one_scanlines_worth_of_dots() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+        while cpu.cpu_bus.ppu.state.scanline != 10 {
+            cpu.decode().unwrap();
+        }
+
+        for _ in 0..100 {
+            let (scanline, line_start) = scanline_start(&cpu);
+            cpu.step_scanline().unwrap();
+            let (next_scanline, next_line_start) = scanline_start(&cpu);
+
+            // Where the step lands inside the scanline wobbles by an instruction, but the scanline it lands on started
+            // exactly 341 dots after the last one
+            assert_eq!(next_scanline, scanline + 1, "A step didn't stop on the next scanline");
+            assert_eq!(next_line_start - line_start, 341);
+            assert!(u64::from(cpu.cpu_bus.ppu.state.dots) < JMP_DOTS, "Stopped {} dots into the scanline", cpu.cpu_bus.ppu.state.dots);
+        }
+    });
+}
+
+#[test]
+fn step_frame_runs_one_frame() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+        let frames = cpu.cpu_bus.ppu.frame_count();
+
+        cpu.step_frame().unwrap();
+
+        assert_eq!(cpu.cpu_bus.ppu.frame_count(), frames + 1);
+    });
+}
+
+#[test]
+fn run_to_vblank_stops_with_vblank_set() {
+    run_with_big_stack(|| {
+        let mut cpu = booted_nrom(&JMP_SELF, vec![0; 0x2000], Mirroring::VERTICAL);
+        tick_to(&mut cpu, 100, 0);
+
+        cpu.run_to_vblank().unwrap();
+        assert_eq!(cpu.cpu_bus.ppu.state.scanline, 241);
+        assert_ne!(cpu.cpu_bus.peek(0x2002) & 0x80, 0, "VBlank wasn't set yet");
+
+        // Already in VBlank, so the next call goes through a whole frame to the next one
+        let frames = cpu.cpu_bus.ppu.frame_count();
+        cpu.run_to_vblank().unwrap();
+        assert_eq!(cpu.cpu_bus.ppu.state.scanline, 241);
+        assert_ne!(cpu.cpu_bus.peek(0x2002) & 0x80, 0);
+        assert_eq!(cpu.cpu_bus.ppu.frame_count(), frames + 1);
+    });
+}
+
+// The scanline the PPU is on, and the dot count it started at
+fn scanline_start(cpu: &CPU<CPUBus>) -> (u16, u64) {
+    let ppu = &cpu.cpu_bus.ppu;
+    (ppu.state.scanline, ppu.dot_count() - u64::from(ppu.state.dots))
+}
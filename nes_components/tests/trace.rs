@@ -0,0 +1,58 @@
+// Instruction tracing — the sink gets one nestest style line per instruction, with the registers before it runs
+
+mod common;
+
+use common::*;
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+// set_trace_sink takes ownership of the sink, so the buffer is shared to read it back afterwards
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn each_instruction_writes_one_line() {
+    run_with_big_stack(|| {
+        // LDA #$42, TAX, INX
+        let mut cpu = cpu_with_program(&[0xA9, 0x42, 0xAA, 0xE8]);
+        let buffer = Rc::new(RefCell::new(vec![]));
+        cpu.set_trace_sink(Some(Box::new(SharedBuffer(buffer.clone()))));
+
+        for _ in 0..3 {
+            cpu.decode().expect("The program failed to run");
+        }
+
+        let trace = String::from_utf8(buffer.borrow().clone()).expect("The trace isn't UTF-8");
+        let lines: Vec<&str> = trace.lines().collect();
+        assert_eq!(lines.len(), 3, "Expected one line per instruction:\n{}", trace);
+
+        assert!(lines[0].starts_with("0600  A9  A:00 X:00"), "{}", lines[0]);
+        assert!(lines[1].starts_with("0602  AA  A:42 X:00"), "{}", lines[1]);
+        assert!(lines[2].starts_with("0603  E8  A:42 X:42"), "{}", lines[2]);
+    });
+}
+
+#[test]
+fn no_sink_stops_the_trace() {
+    run_with_big_stack(|| {
+        let mut cpu = cpu_with_program(&[0xEA, 0xEA]);
+        let buffer = Rc::new(RefCell::new(vec![]));
+        cpu.set_trace_sink(Some(Box::new(SharedBuffer(buffer.clone()))));
+
+        cpu.decode().expect("The program failed to run");
+        cpu.set_trace_sink(None);
+        cpu.decode().expect("The program failed to run");
+
+        assert_eq!(String::from_utf8(buffer.borrow().clone()).unwrap().lines().count(), 1);
+    });
+}
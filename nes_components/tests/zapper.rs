@@ -0,0 +1,67 @@
+// The Zapper's photodiode — how bright the PPU has drawn the pixel it's aimed at, and the light bit that gives $4017
+
+mod common;
+
+use common::*;
+use nes_components::*;
+
+// A white 8x8 tile in the middle of a black screen, at tile column 16 and row 12
+const WHITE: (usize, usize) = (131, 100);
+const BLACK: (usize, usize) = (40, 100);
+
+#[test]
+fn a_white_pixel_is_bright_and_a_black_one_is_dark() {
+    run_with_big_stack(|| {
+        let mut cpu = white_square();
+        tick_to(&mut cpu, 102, 0);
+
+        assert!(cpu.cpu_bus.ppu.pixel_brightness(WHITE.0, WHITE.1) > 0xF0, "White came out dark");
+        assert!(cpu.cpu_bus.ppu.pixel_brightness(BLACK.0, BLACK.1) < 0x10, "Black came out bright");
+    });
+}
+
+#[test]
+fn the_zapper_senses_light_only_when_aimed_at_the_white_pixel() {
+    run_with_big_stack(|| {
+        let mut cpu = white_square();
+        cpu.cpu_bus.input = InputBackend::Zapper(Controller::new(), Zapper::new());
+        tick_to(&mut cpu, 102, 0);
+
+        // Bit 3 of $4017 reads 0 while light is sensed
+        cpu.cpu_bus.input.zapper_mut().unwrap().set_aim(Some(WHITE));
+        assert_eq!(cpu.cpu_bus.mem_read(0x4017) & 0b0000_1000, 0);
+
+        cpu.cpu_bus.input.zapper_mut().unwrap().set_aim(Some(BLACK));
+        assert_eq!(cpu.cpu_bus.mem_read(0x4017) & 0b0000_1000, 0b0000_1000);
+    });
+}
+
+// NROM console whose system palette is all black except color $30, which is white, showing the white tile
+fn white_square() -> CPU<CPUBus> {
+    let mut prg = vec![0; 0x4000];
+    prg[..3].copy_from_slice(&JMP_SELF);
+    prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0xC0]);
+
+    let mut chr = vec![0; 0x2000];
+    chr[16..24].fill(0xFF); // Tile 1 is solid color 1
+
+    let mut palette = vec![0; 192];
+    palette[0x30 * 3..0x30 * 3 + 3].fill(0xFF);
+
+    let mapper = Rom::from_parts(prg, chr, 0, Mirroring::VERTICAL).create_mapper().expect("Could not create the mapper");
+    let ppu = PPU::init_ppu(mapper.clone(), palette, None);
+    let mut cpu = CPU::init_cpu(mapper, ppu);
+    for _ in 0..3 {
+        let _ = cpu.run_frame();
+    }
+
+    write_vram(&mut cpu, 0x2000 + 12 * 32 + 16, 0x01);
+    write_vram(&mut cpu, 0x3F00, 0x0F);
+    write_vram(&mut cpu, 0x3F01, 0x30);
+    cpu.cpu_bus.mem_write(0x2006, 0x00);
+    cpu.cpu_bus.mem_write(0x2006, 0x00);
+    cpu.cpu_bus.mem_write(0x2001, 0b0000_1010);
+    let _ = cpu.run_frame();
+
+    cpu
+}